@@ -0,0 +1,172 @@
+use log::{info, warn};
+use std::{
+    f32::consts::TAU,
+    io::Read,
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+const SAMPLE_RATE: u32 = 22050;
+// samples per analysis block, about 46 ms at the rate above
+const BLOCK: usize = 1024;
+
+// spectrum bands are spread logarithmically between these
+const LOW_HZ: f32 = 60.0;
+const HIGH_HZ: f32 = 8000.0;
+
+// loudness (rms) then one magnitude per band, all roughly 0-1. stored as f32 bits so the
+// capture thread can hand them over without a lock
+pub struct AudioLevels {
+    rms: AtomicU32,
+    bands: Vec<AtomicU32>,
+}
+
+impl AudioLevels {
+    fn new(bands: usize) -> AudioLevels {
+        AudioLevels {
+            rms: AtomicU32::new(0),
+            bands: (0..bands).map(|_| AtomicU32::new(0)).collect(),
+        }
+    }
+
+    pub fn rms(&self) -> f32 {
+        f32::from_bits(self.rms.load(Ordering::Relaxed))
+    }
+
+    pub fn bands(&self) -> Vec<f32> {
+        self.bands
+            .iter()
+            .map(|band| f32::from_bits(band.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    fn store(&self, samples: &[f32]) {
+        self.rms.store(rms(samples).to_bits(), Ordering::Relaxed);
+
+        for (idx, band) in self.bands.iter().enumerate() {
+            let freq = band_frequency(idx, self.bands.len());
+            band.store(magnitude(samples, freq).to_bits(), Ordering::Relaxed);
+        }
+    }
+}
+
+fn rms(samples: &[f32]) -> f32 {
+    let sum: f32 = samples.iter().map(|sample| sample * sample).sum();
+    (sum / samples.len().max(1) as f32).sqrt()
+}
+
+// center frequency of a band
+fn band_frequency(idx: usize, bands: usize) -> f32 {
+    let t = (idx as f32 + 0.5) / bands.max(1) as f32;
+    LOW_HZ * (HIGH_HZ / LOW_HZ).powf(t)
+}
+
+// goertzel, a single bin of a dft. a full sine at the frequency comes out around 0.5
+fn magnitude(samples: &[f32], freq: f32) -> f32 {
+    let coeff = 2.0 * (TAU * freq / SAMPLE_RATE as f32).cos();
+    let (mut prev, mut prev2) = (0.0, 0.0);
+
+    for sample in samples {
+        let next = sample + coeff * prev - prev2;
+        prev2 = prev;
+        prev = next;
+    }
+
+    let power = prev * prev + prev2 * prev2 - coeff * prev * prev2;
+    power.max(0.0).sqrt() / samples.len().max(1) as f32
+}
+
+// records from pulseaudio (or pipewire's pulse server) through parec on its own thread. the
+// source is a pulse source name, by default what's playing rather than the microphone. if
+// recording isn't possible this logs once and stops, and the levels stay at zero. that's
+// always the case off linux, where there's no parec to run.
+pub fn spawn_capture(bands: usize, source: &str) -> Arc<AudioLevels> {
+    let levels = Arc::new(AudioLevels::new(bands));
+    let shared = levels.clone();
+    let source = source.to_string();
+
+    thread::spawn(move || {
+        let child = Command::new("parec")
+            .args([
+                "--raw",
+                "--format=s16le",
+                "--channels=1",
+                "--latency-msec=20",
+            ])
+            .arg(format!("--rate={}", SAMPLE_RATE))
+            .arg(format!("--device={}", source))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn();
+
+        let mut child = match child {
+            Ok(child) => child,
+            Err(err) => {
+                warn!("audio capture unavailable, could not run parec: {}", err);
+                return;
+            }
+        };
+        let mut stdout = child.stdout.take().unwrap();
+
+        info!("capturing audio from {}", source);
+
+        let mut bytes = vec![0u8; BLOCK * 2];
+        let mut samples = vec![0.0; BLOCK];
+
+        // stops once the effect using the levels is gone
+        while Arc::strong_count(&shared) > 1 {
+            if let Err(err) = stdout.read_exact(&mut bytes) {
+                warn!("audio capture stopped: {}", err);
+                break;
+            }
+
+            for (sample, pair) in samples.iter_mut().zip(bytes.chunks_exact(2)) {
+                *sample = i16::from_le_bytes([pair[0], pair[1]]) as f32 / i16::MAX as f32;
+            }
+
+            shared.store(&samples);
+        }
+
+        child.kill().ok();
+        child.wait().ok();
+    });
+
+    levels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(freq: f32) -> Vec<f32> {
+        (0..BLOCK)
+            .map(|idx| (TAU * freq * idx as f32 / SAMPLE_RATE as f32).sin())
+            .collect()
+    }
+
+    #[test]
+    fn rms_of_a_sine() {
+        assert!((rms(&sine(440.0)) - 0.5f32.sqrt()).abs() < 0.01);
+        assert_eq!(rms(&[0.0; BLOCK]), 0.0);
+    }
+
+    #[test]
+    fn bands_pick_out_their_frequency() {
+        let bands = 8;
+        let freq = band_frequency(3, bands);
+        let levels = AudioLevels::new(bands);
+        levels.store(&sine(freq));
+
+        let bands = levels.bands();
+        let loudest = (0..bands.len())
+            .max_by(|a, b| bands[*a].total_cmp(&bands[*b]))
+            .unwrap();
+
+        assert_eq!(loudest, 3);
+        assert!((bands[3] - 0.5).abs() < 0.05);
+    }
+}
@@ -0,0 +1,155 @@
+use crate::{
+    config::KBConfig,
+    effects::{EffectContext, LedEffect, LedState},
+    threading::{self, FrameSync, KeyState},
+};
+use hidapi::HidDevice;
+use palette::Hsva;
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+// frame rates tried in order until one can't be sustained
+const RATES: &[f32] = &[
+    20.0, 30.0, 45.0, 60.0, 90.0, 120.0, 180.0, 240.0, 360.0, 480.0,
+];
+const STEP_SECS: f32 = 2.0;
+
+// a step is sustainable if it hits this fraction of the target rate without write failures
+const MIN_RATE_FRACTION: f32 = 0.95;
+
+// changes every led every frame so nothing is skipped by the diff
+#[derive(Default)]
+struct BenchEffect {
+    hue: f32,
+}
+
+impl LedEffect for BenchEffect {
+    fn name(&self) -> &'static str {
+        "bench"
+    }
+
+    fn update(&mut self, _delta: f32, state: &mut [LedState], _ctx: &EffectContext) {
+        self.hue = (self.hue + 137.0) % 360.0;
+
+        for led in state {
+            led.color = Hsva::new(self.hue, 1.0, 1.0, 1.0);
+        }
+    }
+}
+
+struct StepResult {
+    target: f32,
+    achieved: f32,
+    frames: u32,
+    writes: usize,
+    failures: usize,
+    late_frames: u32,
+    write_time: Duration,
+}
+
+impl StepResult {
+    fn sustainable(&self) -> bool {
+        self.failures == 0 && self.achieved >= self.target * MIN_RATE_FRACTION
+    }
+
+    fn avg_write_ms(&self) -> f32 {
+        if self.writes == 0 {
+            0.0
+        } else {
+            self.write_time.as_secs_f32() * 1000.0 / self.writes as f32
+        }
+    }
+}
+
+fn run_step(device: &HidDevice, kb_config: &KBConfig, target: f32) -> StepResult {
+    let budget = Duration::from_secs_f32(1.0 / target);
+    let matrix =
+        vec![vec![KeyState::default(); kb_config.columns() as usize]; kb_config.rows() as usize];
+    let ctx = EffectContext::at_rest(&matrix, kb_config);
+
+    let mut effect = BenchEffect::default();
+    let mut frame_sync = FrameSync::new(0.0, kb_config.host_config.stream_threshold);
+    let mut led_state = threading::led_state(kb_config);
+
+    let mut result = StepResult {
+        target,
+        achieved: 0.0,
+        frames: 0,
+        writes: 0,
+        failures: 0,
+        late_frames: 0,
+        write_time: Duration::ZERO,
+    };
+
+    let start = Instant::now();
+    while start.elapsed().as_secs_f32() < STEP_SECS {
+        let frame_start = Instant::now();
+
+        let pre_state = led_state.clone();
+        effect.update(budget.as_secs_f32(), &mut led_state, &ctx);
+        let stats = threading::send_colors(
+            device,
+            frame_sync.frame(&led_state, &pre_state),
+            &kb_config.host_config,
+        );
+
+        result.frames += 1;
+        result.writes += stats.writes;
+        result.failures += stats.failures;
+        result.write_time += stats.write_time;
+
+        let elapsed = frame_start.elapsed();
+        if elapsed > budget {
+            result.late_frames += 1;
+        } else {
+            thread::sleep(budget - elapsed);
+        }
+    }
+
+    result.achieved = result.frames as f32 / start.elapsed().as_secs_f32();
+    result
+}
+
+// ramps up the frame rate with every led changing each frame and reports what keeps up
+pub fn run(device: &HidDevice, kb_config: &KBConfig) {
+    println!(
+        "benchmarking {} leds, {:.0}s per step",
+        kb_config.led_count(),
+        STEP_SECS
+    );
+    println!(
+        "{:>8} {:>10} {:>8} {:>12} {:>9} {:>8}",
+        "target", "achieved", "writes", "avg write", "failures", "late"
+    );
+
+    let mut best: Option<f32> = None;
+
+    for target in RATES {
+        let result = run_step(device, kb_config, *target);
+
+        println!(
+            "{:>8.0} {:>10.1} {:>8} {:>10.2}ms {:>9} {:>8}",
+            result.target,
+            result.achieved,
+            result.writes,
+            result.avg_write_ms(),
+            result.failures,
+            result.late_frames
+        );
+
+        if !result.sustainable() {
+            break;
+        }
+
+        best = Some(result.achieved);
+    }
+
+    threading::clear_leds(device, kb_config.host_config.framing());
+
+    match best {
+        Some(fps) => println!("max sustainable frame rate: {:.1} fps", fps),
+        None => println!("could not sustain even {:.0} fps", RATES[0]),
+    }
+}
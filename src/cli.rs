@@ -0,0 +1,318 @@
+use crate::{
+    config::KBConfig,
+    reload::ReloadResult,
+    threading::{HIDCommand, HIDThreadState},
+};
+use crossbeam::channel::{Receiver, Sender};
+use log::{info, warn};
+use serde_json::json;
+use std::{io, thread, time::Duration};
+
+pub const USAGE: &str = "\
+usage: kb_host [validate | dump-geometry [--json]] [options]
+
+options:
+  --config <file>        host config, instead of kb_host/config.json in the config directory
+  --qmk-root <dir>       qmk_firmware checkout, instead of ~/qmk_firmware
+  --kb, --keyboard <kb>  keyboard to use instead of the config's
+  --keymap <keymap>      keymap to use instead of the config's
+  --serial <serial>      device to use when several match, by serial number
+  --device-index <n>     device to use when several match, counting from 0
+  --update-rate <hz>     effect updates per second (default 240)
+  --fps <hz>             frames sent to the keyboard per second (default 20)
+  --headless             drive the keyboards without opening a window
+  --bench                measure how fast the keyboard takes full-board updates
+  --mock                 drive a made up keyboard typing on its own instead of the real one
+  --log-level <level>    off, error, warn, info, debug or trace
+  -h, --help             print this and exit";
+
+const UPDATE_RATE: f32 = 240.0; // <5 ms per update
+const FPS: f32 = 20.0;
+
+// flags followed by a value. `Overrides` and `logging` pick the ones not handled here out of the
+// same args before this runs (logging has to start first), which is why this isn't clap
+const VALUE_FLAGS: [&str; 10] = [
+    "--config",
+    "--qmk-root",
+    "--kb",
+    "--keyboard",
+    "--keymap",
+    "--serial",
+    "--device-index",
+    "--log-level",
+    "--update-rate",
+    "--fps",
+];
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run,
+    Validate,
+    DumpGeometry { json: bool },
+    Bench,
+    Help,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub command: Command,
+    pub update_rate: f32,
+    pub fps: f32,
+    pub headless: bool,
+    pub mock: bool,
+}
+
+impl Options {
+    // the whole command line including the program name, rejecting anything it doesn't know
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut options = Options {
+            command: Command::Run,
+            update_rate: UPDATE_RATE,
+            fps: FPS,
+            headless: false,
+            mock: false,
+        };
+        let (mut bench, mut json) = (false, false);
+
+        let mut args = args.iter().skip(1);
+        while let Some(arg) = args.next() {
+            if VALUE_FLAGS.contains(&arg.as_str()) {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("{} needs a value", arg))?;
+
+                match arg.as_str() {
+                    "--update-rate" => options.update_rate = rate(arg, value)?,
+                    "--fps" => options.fps = rate(arg, value)?,
+                    "--device-index" if value.parse::<usize>().is_err() => {
+                        return Err(format!("--device-index needs a number, got {}", value))
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+
+            match arg.as_str() {
+                "-h" | "--help" => options.command = Command::Help,
+                "--headless" => options.headless = true,
+                "--mock" => options.mock = true,
+                "--bench" => bench = true,
+                "--json" => json = true,
+                "validate" if options.command == Command::Run => {
+                    options.command = Command::Validate
+                }
+                "dump-geometry" if options.command == Command::Run => {
+                    options.command = Command::DumpGeometry { json: false }
+                }
+                _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+                _ => return Err(format!("unexpected argument {}", arg)),
+            }
+        }
+
+        match &mut options.command {
+            Command::Help => {}
+            Command::Validate | Command::DumpGeometry { .. } if bench => {
+                return Err("--bench doesn't go with validate or dump-geometry".to_string())
+            }
+            Command::DumpGeometry { json: as_json } => *as_json = json,
+            _ if json => return Err("--json only goes with dump-geometry".to_string()),
+            Command::Run if bench && options.mock => {
+                return Err("--bench needs the real keyboard, not --mock".to_string())
+            }
+            Command::Run if bench => options.command = Command::Bench,
+            _ => {}
+        }
+
+        Ok(options)
+    }
+}
+
+fn rate(flag: &str, value: &str) -> Result<f32, String> {
+    value
+        .parse::<f32>()
+        .ok()
+        .filter(|rate| rate.is_finite() && *rate > 0.0)
+        .ok_or_else(|| format!("{} needs a positive number, got {}", flag, value))
+}
+
+// one keyboard's channels when there's no ui to read them
+pub struct Headless {
+    pub tx: Sender<HIDCommand>,
+    pub rx: Receiver<HIDThreadState>,
+    pub console: Receiver<String>,
+    pub reload_rx: Receiver<ReloadResult>,
+}
+
+// `kb_host --headless`: runs until killed, passing reloaded configs on to the threads like the
+// ui would and logging the keyboards' console
+pub fn headless(boards: &[Headless], fps: f32) -> ! {
+    loop {
+        for (index, board) in boards.iter().enumerate() {
+            board.rx.try_iter().for_each(drop);
+
+            for line in board.console.try_iter() {
+                info!("keyboard {} console: {}", index, line.trim_end());
+            }
+
+            for result in board.reload_rx.try_iter() {
+                match result {
+                    Ok(kb_config) => {
+                        board.tx.send(HIDCommand::Reload(kb_config)).ok();
+                    }
+                    Err(err) => warn!("keyboard {}: config not reloaded: {}", index, err),
+                }
+            }
+        }
+
+        thread::sleep(Duration::from_secs_f32(1.0 / fps));
+    }
+}
+
+// `kb_host validate`: the config has already been read and validated by the time this runs
+pub fn validate(kb_config: &KBConfig) {
+    println!(
+        "{} by {}: ok",
+        kb_config.qmk_info.keyboard_name, kb_config.qmk_info.manufacturer
+    );
+    println!("  layout   {}", kb_config.host_config.layout);
+    println!("  keymap   {}", kb_config.host_config.keymap);
+    println!("  leds     {}", kb_config.led_count());
+    println!(
+        "  matrix   {} rows x {} columns",
+        kb_config.rows(),
+        kb_config.columns()
+    );
+    println!(
+        "  size     {} x {} key units",
+        kb_config.width(),
+        kb_config.height()
+    );
+}
+
+// `kb_host dump-geometry [--json]`: every key of the layout with the led it drives
+pub fn dump_geometry(kb_config: &KBConfig, as_json: bool) -> Result<(), io::Error> {
+    let keys = &kb_config.layout().layout;
+    let led_index = |(row, col): (u8, u8)| {
+        let led_idx = kb_config.matrix[row as usize][col as usize];
+        (led_idx >= 0).then_some(led_idx)
+    };
+
+    if as_json {
+        let keys: Vec<_> = keys
+            .iter()
+            .map(|key| {
+                json!({
+                    "label": key.label,
+                    "x": key.x,
+                    "y": key.y,
+                    "w": key.w,
+                    "h": key.h,
+                    "r": key.r,
+                    "rx": key.rx,
+                    "ry": key.ry,
+                    "matrix": key.matrix,
+                    "led": led_index(key.matrix),
+                })
+            })
+            .collect();
+
+        println!("{}", serde_json::to_string_pretty(&keys)?);
+        return Ok(());
+    }
+
+    println!(
+        "{:<12} {:>6} {:>6} {:>5} {:>5} {:>8} {:>4}",
+        "label", "x", "y", "w", "h", "matrix", "led"
+    );
+
+    for key in keys {
+        let led = led_index(key.matrix).map_or("-".to_string(), |led_idx| led_idx.to_string());
+
+        println!(
+            "{:<12} {:>6.2} {:>6.2} {:>5.2} {:>5.2} {:>8} {:>4}",
+            key.label,
+            key.x,
+            key.y,
+            key.w,
+            key.h,
+            format!("{},{}", key.matrix.0, key.matrix.1),
+            led
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        Options::parse(&args)
+    }
+
+    #[test]
+    fn parses_options() {
+        let options = parse(&[
+            "kb_host",
+            "dump-geometry",
+            "--keyboard",
+            "test",
+            "--fps",
+            "30",
+            "--json",
+        ])
+        .unwrap();
+
+        assert_eq!(
+            options,
+            Options {
+                command: Command::DumpGeometry { json: true },
+                update_rate: UPDATE_RATE,
+                fps: 30.0,
+                headless: false,
+                mock: false,
+            }
+        );
+
+        assert_eq!(
+            parse(&["kb_host", "--bench"]).unwrap().command,
+            Command::Bench
+        );
+        assert!(parse(&["kb_host", "--headless"]).unwrap().headless);
+        assert!(parse(&["kb_host", "--mock"]).unwrap().mock);
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert_eq!(
+            parse(&["kb_host", "--frames", "3"]),
+            Err("unknown option --frames".to_string())
+        );
+        assert_eq!(
+            parse(&["kb_host", "--update-rate", "fast"]),
+            Err("--update-rate needs a positive number, got fast".to_string())
+        );
+        assert_eq!(
+            parse(&["kb_host", "--fps", "0"]),
+            Err("--fps needs a positive number, got 0".to_string())
+        );
+        assert_eq!(
+            parse(&["kb_host", "--kb"]),
+            Err("--kb needs a value".to_string())
+        );
+        assert_eq!(
+            parse(&["kb_host", "--device-index", "-1"]),
+            Err("--device-index needs a number, got -1".to_string())
+        );
+        assert!(parse(&["kb_host", "validate", "dump-geometry"]).is_err());
+        assert!(parse(&["kb_host", "--json"]).is_err());
+        assert!(parse(&["kb_host", "--bench", "--mock"]).is_err());
+        assert_eq!(
+            parse(&["kb_host", "validate", "--bench"]),
+            Err("--bench doesn't go with validate or dump-geometry".to_string())
+        );
+        assert!(parse(&["kb_host", "dump-geometry", "--json", "--bench"]).is_err());
+    }
+}
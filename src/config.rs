@@ -1,5 +1,18 @@
-use serde::{de::Visitor, Deserialize, Deserializer};
-use std::collections::HashMap;
+use crate::{
+    effects::{LayerTransitionEffect, ProgressEffect},
+    presets::PresetFile,
+    protocol::{ColorFormat, Framing, CHECKSUM_VERSION, DEFAULT_EPSIZE, MAX_EPSIZE, MIN_EPSIZE},
+    theme::{KeyColorTheme, ThemeColor},
+};
+use serde::{
+    de::{DeserializeOwned, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fmt, io,
+    path::{Path, PathBuf},
+};
 
 struct HexString;
 
@@ -14,7 +27,24 @@ impl<'de> Visitor<'de> for HexString {
     where
         E: serde::de::Error,
     {
-        u16::from_str_radix(v.trim_start_matches("0x"), 16).map_err(serde::de::Error::custom)
+        let digits = v.trim();
+        let digits = digits
+            .strip_prefix("0x")
+            .or_else(|| digits.strip_prefix("0X"))
+            .unwrap_or(digits);
+
+        if digits.is_empty() {
+            return Err(E::custom(format!(
+                "{:?} is empty, expected a hex id like 0x1234",
+                v
+            )));
+        }
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(E::custom(format!("{:?} is not a hex number", v)));
+        }
+
+        u16::from_str_radix(digits, 16)
+            .map_err(|_| E::custom(format!("{:?} is more than 16 bits (at most 0xFFFF)", v)))
     }
 }
 
@@ -25,16 +55,123 @@ where
     deserializer.deserialize_str(HexString)
 }
 
+// every problem found while checking a keyboard config, reported together
+#[derive(Debug)]
+pub struct ValidationError {
+    pub problems: Vec<String>,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid keyboard config ({} problems)",
+            self.problems.len()
+        )?;
+        for problem in &self.problems {
+            write!(f, "\n  - {}", problem)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+// why the host couldn't start or a config couldn't be read, each a line that says what to fix
+#[derive(Debug)]
+pub enum StartupError {
+    NotFound(PathBuf),
+    // the keyboard's folder, which has no info.json or keyboard.json in it or above it
+    NoInfo(PathBuf),
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, String),
+    Invalid(ValidationError),
+    // the keyboard's name
+    DeviceNotFound(String),
+    Hid(String),
+    // anything else, like failing to write the output of a subcommand
+    Io(io::Error),
+}
+
+impl StartupError {
+    pub fn read(path: &Path, err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::NotFound => StartupError::NotFound(path.to_path_buf()),
+            _ => StartupError::Read(path.to_path_buf(), err),
+        }
+    }
+
+    pub fn parse(path: &Path, err: impl fmt::Display) -> Self {
+        StartupError::Parse(path.to_path_buf(), err.to_string())
+    }
+}
+
+impl fmt::Display for StartupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StartupError::NotFound(path) => write!(f, "{} doesn't exist", path.display()),
+            StartupError::NoInfo(dir) => write!(
+                f,
+                "no info.json or keyboard.json in {} or the folders above it",
+                dir.display()
+            ),
+            StartupError::Read(path, err) => {
+                write!(f, "could not read {}: {}", path.display(), err)
+            }
+            StartupError::Parse(path, err) => write!(f, "{} is malformed: {}", path.display(), err),
+            StartupError::Invalid(err) => write!(f, "{}", err),
+            StartupError::DeviceNotFound(keyboard) => {
+                write!(f, "could not find {}, is it plugged in?", keyboard)
+            }
+            StartupError::Hid(err) => write!(f, "hid error: {}", err),
+            StartupError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for StartupError {}
+
+impl From<ValidationError> for StartupError {
+    fn from(err: ValidationError) -> Self {
+        StartupError::Invalid(err)
+    }
+}
+
+impl From<io::Error> for StartupError {
+    fn from(err: io::Error) -> Self {
+        StartupError::Io(err)
+    }
+}
+
+// for the reload watcher and the ui, which only show the message
+impl From<StartupError> for io::Error {
+    fn from(err: StartupError) -> Self {
+        let kind = match &err {
+            StartupError::NotFound(_)
+            | StartupError::NoInfo(_)
+            | StartupError::DeviceNotFound(_) => io::ErrorKind::NotFound,
+            StartupError::Parse(..) | StartupError::Invalid(_) => io::ErrorKind::InvalidData,
+            StartupError::Read(_, err) | StartupError::Io(err) => err.kind(),
+            StartupError::Hid(_) => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, err.to_string())
+    }
+}
+
+#[derive(Debug)]
 pub struct KBConfig {
     pub host_config: Config,
     pub qmk_info: QMKInfo,
     pub matrix: LEDMatrix,
     pub legends: KBLegends,
+    // the top left corner of the keys, some layouts start left of or above 0
+    origin: (f32, f32),
     width: f32,
     height: f32,
     rows: u8,
     columns: u8,
-    led_count: u8,
+    led_count: u16,
 }
 
 impl KBConfig {
@@ -43,39 +180,226 @@ impl KBConfig {
         qmk_info: QMKInfo,
         matrix: LEDMatrix,
         legends: KBLegends,
-    ) -> KBConfig {
+    ) -> Result<KBConfig, ValidationError> {
+        Self::validate(&host_config, &qmk_info, &matrix, &legends)?;
+
         let layout = Self::get_layout(&qmk_info, &host_config);
 
-        let mut width: f32 = 0.0;
-        let mut height: f32 = 0.0;
+        let mut min = (f32::MAX, f32::MAX);
+        let mut max = (f32::MIN, f32::MIN);
 
         let mut rows: u8 = 0;
         let mut columns: u8 = 0;
 
-        let mut led_count: u8 = 0;
-
         for key in &layout.layout {
-            width = width.max(key.x + key.w);
-            height = height.max(key.y + key.h);
+            for (x, y) in key.corners() {
+                min = (min.0.min(x), min.1.min(y));
+                max = (max.0.max(x), max.1.max(y));
+            }
 
             rows = rows.max(key.matrix.0 + 1);
             columns = columns.max(key.matrix.1 + 1);
-
-            if matrix[key.matrix.0 as usize][key.matrix.1 as usize] >= 0 {
-                led_count += 1;
-            }
         }
 
-        KBConfig {
+        let led_count = Self::count_leds(&qmk_info, &matrix) as u16;
+
+        Ok(KBConfig {
             host_config,
             qmk_info,
             matrix,
             legends,
-            width,
-            height,
+            origin: min,
+            width: max.0 - min.0,
+            height: max.1 - min.1,
             rows,
             columns,
             led_count,
+        })
+    }
+
+    // checks everything that would otherwise index out of bounds later on
+    fn validate(
+        host_config: &Config,
+        qmk_info: &QMKInfo,
+        matrix: &LEDMatrix,
+        legends: &KBLegends,
+    ) -> Result<(), ValidationError> {
+        let mut problems = Vec::new();
+
+        if !(MIN_EPSIZE..=MAX_EPSIZE).contains(&host_config.raw_epsize) {
+            problems.push(format!(
+                "raw_epsize {} is outside {}-{}, reports wouldn't fit",
+                host_config.raw_epsize, MIN_EPSIZE, MAX_EPSIZE
+            ));
+        }
+
+        // mqtt 3.1.1 doesn't allow a password without a username (3.1.2.9), brokers drop the
+        // connection
+        if let Some(mqtt) = &host_config.mqtt {
+            if mqtt.password.is_some() && mqtt.username.is_none() {
+                problems
+                    .push("mqtt has a password but no username, brokers refuse that".to_string());
+            }
+        }
+
+        let layout = match qmk_info.layouts.get(&host_config.layout) {
+            Some(layout) => layout,
+            None => {
+                problems.push(format!(
+                    "layout {} not found in info.json (available: {})",
+                    host_config.layout,
+                    qmk_info
+                        .layouts
+                        .keys()
+                        .cloned()
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+                return Err(ValidationError { problems });
+            }
+        };
+        if layout.layout.is_empty() {
+            problems.push(format!(
+                "layout {} has no keys, there'd be nothing to draw or light",
+                host_config.layout
+            ));
+        }
+
+        let matrix_cols = matrix.first().map_or(0, |row| row.len());
+        for (idx, row) in matrix.iter().enumerate() {
+            if row.len() != matrix_cols {
+                problems.push(format!(
+                    "matrix.json row {} has {} columns, expected {}",
+                    idx,
+                    row.len(),
+                    matrix_cols
+                ));
+            }
+        }
+
+        if let Some(size) = &qmk_info.matrix_size {
+            if matrix.len() != size.rows as usize || matrix_cols != size.cols as usize {
+                problems.push(format!(
+                    "matrix.json is {}x{} but info.json declares a {}x{} matrix",
+                    matrix.len(),
+                    matrix_cols,
+                    size.rows,
+                    size.cols
+                ));
+            }
+        }
+
+        let led_count = Self::count_leds(qmk_info, matrix);
+
+        if legends.0.is_empty() {
+            problems.push(
+                "the legends have no layers, there'd be nothing to label keys with".to_string(),
+            );
+        }
+
+        // the first key seen at each position, a second one there would never light or show
+        // presses of its own
+        let mut positions: HashMap<(u8, u8), &str> = HashMap::new();
+
+        for key in &layout.layout {
+            let (row, col) = key.matrix;
+
+            match positions.entry(key.matrix) {
+                Entry::Occupied(first) => problems.push(format!(
+                    "keys {} and {} are both at ({}, {}) in layout {}",
+                    first.get(),
+                    key.label,
+                    row,
+                    col,
+                    host_config.layout
+                )),
+                Entry::Vacant(slot) => {
+                    slot.insert(&key.label);
+                }
+            }
+
+            if let Some(size) = &qmk_info.matrix_size {
+                if row >= size.rows || col >= size.cols {
+                    problems.push(format!(
+                        "key {} at ({}, {}) is outside the declared {}x{} matrix",
+                        key.label, row, col, size.rows, size.cols
+                    ));
+                }
+            }
+
+            match matrix.get(row as usize).and_then(|r| r.get(col as usize)) {
+                None => problems.push(format!(
+                    "key {} at ({}, {}) is outside matrix.json",
+                    key.label, row, col
+                )),
+                Some(led) if *led >= 0 && *led as usize >= led_count => problems.push(format!(
+                    "key {} at ({}, {}) has led index {} but the board only has {} leds",
+                    key.label, row, col, led, led_count
+                )),
+                _ => {}
+            }
+
+            for (idx, layer) in legends.0.iter().enumerate() {
+                let in_bounds = layer
+                    .legends
+                    .get(row as usize)
+                    .is_some_and(|r| (col as usize) < r.len());
+
+                if !in_bounds {
+                    problems.push(format!(
+                        "key {} at ({}, {}) has no legend on layer {} ({})",
+                        key.label, row, col, idx, layer.layer_name
+                    ));
+                }
+            }
+        }
+
+        let by_sun = host_config
+            .schedule
+            .iter()
+            .any(|entry| matches!(entry.at, ScheduleTime::Sunrise | ScheduleTime::Sunset));
+        if by_sun && host_config.location.is_none() {
+            problems.push(
+                "schedule has entries at sunrise or sunset but no location to work them out for"
+                    .to_string(),
+            );
+        }
+
+        let openrgb_leds = host_config
+            .openrgb
+            .as_ref()
+            .and_then(|openrgb| openrgb.leds.as_ref());
+        for led in openrgb_leds.into_iter().flatten() {
+            if *led >= led_count {
+                problems.push(format!(
+                    "openrgb leds has led index {} but the board only has {} leds",
+                    led, led_count
+                ));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError { problems })
+        }
+    }
+
+    // as many leds as info.json lists if it does, otherwise up to the highest index in
+    // matrix.json. underglow and leds under other layouts' keys count too, with no key of their own
+    fn count_leds(qmk_info: &QMKInfo, matrix: &LEDMatrix) -> usize {
+        match qmk_info
+            .rgb_matrix
+            .as_ref()
+            .or(qmk_info.led_matrix.as_ref())
+        {
+            Some(leds) => leds.layout.len(),
+            None => matrix
+                .iter()
+                .flatten()
+                .map(|led| (*led + 1).max(0) as usize)
+                .max()
+                .unwrap_or(0),
         }
     }
 
@@ -90,10 +414,61 @@ impl KBConfig {
         Self::get_layout(&self.qmk_info, &self.host_config)
     }
 
+    // every layout info.json defines, sorted so the ui lists them in a stable order
+    pub fn layout_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .qmk_info
+            .layouts
+            .keys()
+            .map(|name| name.as_str())
+            .collect();
+        names.sort_unstable();
+        names
+    }
+
+    // the same keyboard resolved against another layout, validated like a freshly loaded one
+    pub fn with_layout(&self, layout: &str) -> Result<KBConfig, ValidationError> {
+        let host_config = Config {
+            layout: layout.to_string(),
+            ..self.host_config.clone()
+        };
+
+        KBConfig::new(
+            host_config,
+            self.qmk_info.clone(),
+            self.matrix.clone(),
+            self.legends.clone(),
+        )
+    }
+
     pub fn width(&self) -> f32 {
         self.width
     }
 
+    // layout coordinates of the board's top left corner, what's drawn at 0, 0
+    pub fn origin(&self) -> (f32, f32) {
+        self.origin
+    }
+
+    // where a key's center is drawn, in layout units from the board's top left corner
+    pub fn key_position(&self, key: &QMKKey) -> (f32, f32) {
+        let (x, y) = key.rotate((key.x + key.w / 2.0, key.y + key.h / 2.0));
+        (x - self.origin.0, y - self.origin.1)
+    }
+
+    // the same as fractions of the board's width and height
+    pub fn relative_position(&self, key: &QMKKey) -> (f32, f32) {
+        let (x, y) = self.key_position(key);
+        (x / self.width, y / self.height)
+    }
+
+    // where a key sits along the board's diagonal, 0 at the top left corner to 1 at the bottom
+    // right, for effects that move across the board
+    pub fn phase_offset(&self, key: &QMKKey) -> f32 {
+        let (x, y) = self.relative_position(key);
+        (x + y) / 2.0
+    }
+
     pub fn height(&self) -> f32 {
         self.height
     }
@@ -106,31 +481,536 @@ impl KBConfig {
         self.columns
     }
 
-    pub fn led_count(&self) -> u8 {
+    pub fn led_count(&self) -> u16 {
         self.led_count
     }
+
+    // the led under a matrix position, none for keys without one and positions off the matrix
+    pub fn led_at(&self, (row, col): (u8, u8)) -> Option<usize> {
+        let led_idx = *self.matrix.get(row as usize)?.get(col as usize)?;
+        usize::try_from(led_idx).ok()
+    }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct Config {
     pub kb: String,
+    // e.g. "rev2" for keyboards/<kb>/rev2, the same as putting it at the end of kb
+    #[serde(default)]
+    pub revision: Option<String>,
     pub keymap: String,
     pub layout: String,
     #[serde(deserialize_with = "deserialize_hex")]
     pub usage_page: u16,
     #[serde(deserialize_with = "deserialize_hex")]
     pub usage: u16,
+    // picks one of several devices matching the ids above, by serial number and then by
+    // position among the ones left
+    #[serde(default)]
+    pub serial: Option<String>,
+    #[serde(default)]
+    pub device_index: Option<usize>,
+    // applied in order, see `effects::build_effect`
+    #[serde(default = "default_effects")]
+    pub effects: Vec<EffectConfig>,
+    #[serde(default)]
+    pub key_colors: KeyColorTheme,
+    // seconds between full led resyncs, 0 to only send changes
+    #[serde(default = "default_resync_interval")]
+    pub resync_interval: f32,
+    // once at least this fraction of the leds change in a frame, the whole board is streamed in
+    // runs of consecutive leds instead, and below that long runs of changes are. off if unset,
+    // the firmware has to know KSK_RGB_STREAM
+    #[serde(default)]
+    pub stream_threshold: Option<f32>,
+    // local address for the control socket, see control.rs
+    #[serde(default)]
+    pub control_addr: Option<String>,
+    // address for the websocket server streaming the leds and keys, see websocket.rs
+    #[serde(default)]
+    pub websocket_addr: Option<String>,
+    // a local http api for scripts, see rest.rs. off if unset, only read at startup
+    #[serde(default)]
+    pub rest: Option<RestConfig>,
+    // effects, params, pinned keys and brightness picked while running are saved and restored
+    // on the next start, see profile.rs
+    #[serde(default = "default_remember_settings")]
+    pub remember_settings: bool,
+    // run the hid thread at real-time priority where allowed, see priority.rs. only read at
+    // startup
+    #[serde(default)]
+    pub realtime_priority: bool,
+    // colors for the bar shown by the control socket's progress command
+    #[serde(default)]
+    pub progress: ProgressEffect,
+    // a band in the new layer's color sweeps over the effects on every layer change, off if unset
+    #[serde(default)]
+    pub layer_transition: Option<LayerTransitionEffect>,
+    // applied to every channel sent to the keyboard, 1.0 sends colors as is
+    #[serde(default = "default_gamma")]
+    pub gamma: f32,
+    // how led colors are encoded, rgb unless the firmware was built to take qmk's hsv
+    #[serde(default)]
+    pub color_format: ColorFormat,
+    // first byte of every report written to the keyboard. 0 for boards without numbered
+    // reports, which is what QMK's raw hid uses
+    #[serde(default)]
+    pub report_id: u8,
+    // version of the ksk protocol the firmware speaks, see protocol.rs. 2 adds checksums
+    #[serde(default = "default_protocol_version")]
+    pub protocol_version: u8,
+    // RAW_EPSIZE the firmware was built with, every report is this many bytes
+    #[serde(default = "default_raw_epsize")]
+    pub raw_epsize: usize,
+    // scale led colors before sending them for the brightness slider, for firmware without
+    // the brightness message
+    #[serde(default)]
+    pub host_brightness: bool,
+    // keys kept in one color over the effects from startup, written like a preset
+    #[serde(default)]
+    pub pinned_colors: PresetFile,
+    // mirror every frame to an openrgb server, off if unset. needs the openrgb feature
+    #[serde(default)]
+    pub openrgb: Option<OpenRgbConfig>,
+    // publish activity to and take commands from an mqtt broker, see mqtt.rs. only read at
+    // startup, and needs the mqtt feature
+    #[serde(default)]
+    pub mqtt: Option<MqttConfig>,
+    // effects, colors and brightness switched to at times of day, see schedule.rs
+    #[serde(default)]
+    pub schedule: Vec<ScheduleEntry>,
+    // where sunrise and sunset are worked out for, needed by schedule entries at either
+    #[serde(default)]
+    pub location: Option<Location>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Location {
+    // degrees, north and east positive
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+// "HH:MM" in local time, "sunrise" or "sunset"
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum ScheduleTime {
+    // minutes after local midnight
+    Clock(u32),
+    Sunrise,
+    Sunset,
+}
+
+impl TryFrom<String> for ScheduleTime {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.trim() {
+            "sunrise" => return Ok(ScheduleTime::Sunrise),
+            "sunset" => return Ok(ScheduleTime::Sunset),
+            _ => {}
+        }
+
+        value
+            .trim()
+            .split_once(':')
+            .and_then(|(hour, minute)| {
+                Some((hour.parse::<u32>().ok()?, minute.parse::<u32>().ok()?))
+            })
+            .filter(|(hour, minute)| *hour < 24 && *minute < 60)
+            .map(|(hour, minute)| ScheduleTime::Clock(hour * 60 + minute))
+            .ok_or_else(|| format!("expected HH:MM, sunrise or sunset, got {:?}", value))
+    }
+}
+
+// from `at` until the next entry, whatever's given replaces what's running
+#[derive(Deserialize, Debug, Clone)]
+pub struct ScheduleEntry {
+    pub at: ScheduleTime,
+    // added to `at`, e.g. -30 for half an hour before sunset
+    #[serde(default)]
+    pub offset_minutes: i32,
+    #[serde(default)]
+    pub effects: Option<Vec<EffectConfig>>,
+    #[serde(default)]
+    pub color: Option<ThemeColor>,
+    #[serde(default)]
+    pub brightness: Option<u8>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct MqttConfig {
+    // host:port
+    pub broker: String,
+    // every topic starts with this
+    #[serde(default = "default_mqtt_topic")]
+    pub topic: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    // home assistant's discovery prefix, or null to not announce the keyboard there
+    #[serde(default = "default_discovery_prefix")]
+    pub discovery_prefix: Option<String>,
+}
+
+fn default_mqtt_topic() -> String {
+    "kb_host".to_string()
+}
+
+fn default_discovery_prefix() -> Option<String> {
+    Some("homeassistant".to_string())
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct OpenRgbConfig {
+    #[serde(default = "default_openrgb_addr")]
+    pub addr: String,
+    // the keyboard's index in openrgb's device list
+    #[serde(default)]
+    pub device: u32,
+    // which of our leds each of the openrgb device's leds shows, in openrgb's order. the same
+    // order as ours if unset
+    #[serde(default)]
+    pub leds: Option<Vec<usize>>,
+}
+
+fn default_openrgb_addr() -> String {
+    "127.0.0.1:6742".to_string()
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct RestConfig {
+    #[serde(default = "default_rest_addr")]
+    pub addr: String,
+}
+
+fn default_rest_addr() -> String {
+    "127.0.0.1:7072".to_string()
+}
+
+// the config fields the ui's settings can change, written over the ones in the file. the ids
+// are kept as written, so they're checked by reading the file back like any other
+#[derive(Serialize, Debug, Clone)]
+pub struct HostSettings {
+    pub kb: String,
+    pub keymap: String,
+    pub layout: String,
+    pub usage_page: String,
+    pub usage: String,
+    // the config's own effects are left alone unless these are given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub effects: Option<Vec<EffectConfig>>,
+    // the same for pinned_colors
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pinned_colors: Option<PresetFile>,
+}
+
+impl HostSettings {
+    pub fn new(config: &Config) -> HostSettings {
+        HostSettings {
+            kb: config.kb.clone(),
+            keymap: config.keymap.clone(),
+            layout: config.layout.clone(),
+            usage_page: format!("{:#06X}", config.usage_page),
+            usage: format!("{:#06X}", config.usage),
+            effects: None,
+            pinned_colors: None,
+        }
+    }
+}
+
+// a config file with several keyboards, each configured like a file with only one
+#[derive(Deserialize)]
+struct Keyboards {
+    keyboards: Vec<Config>,
+}
+
+impl Config {
+    // a .toml config is read as toml, anything else as json
+    fn deserialize<T: DeserializeOwned>(path: &Path, contents: &str) -> Result<T, io::Error> {
+        if path.extension().is_some_and(|ext| ext == "toml") {
+            toml::from_str(contents).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        } else {
+            Ok(serde_json::from_str(contents)?)
+        }
+    }
+
+    // every keyboard in the file, which is either one keyboard's config or a `keyboards` list of
+    // them
+    pub fn parse_all(path: &Path, contents: &str) -> Result<Vec<Config>, io::Error> {
+        #[derive(Deserialize)]
+        struct Shape {
+            keyboards: Option<serde::de::IgnoredAny>,
+        }
+
+        let configs = match Self::deserialize::<Shape>(path, contents)?.keyboards {
+            Some(_) => Self::deserialize::<Keyboards>(path, contents)?.keyboards,
+            None => vec![Self::deserialize(path, contents)?],
+        };
+
+        if configs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the config's keyboards list is empty",
+            ));
+        }
+        Ok(configs)
+    }
+
+    // the file's contents with the `settings` that differ from its `index`th keyboard written
+    // into it. everything else is kept where it is, toml comments included, though json comes
+    // back re-indented
+    pub fn patch(
+        path: &Path,
+        contents: &str,
+        index: usize,
+        settings: &HostSettings,
+    ) -> Result<String, io::Error> {
+        let invalid = |err: String| io::Error::new(io::ErrorKind::InvalidData, err);
+        let missing = || invalid(format!("keyboard {} is not in the config", index + 1));
+
+        let config = Self::parse_all(path, contents)?
+            .into_iter()
+            .nth(index)
+            .ok_or_else(missing)?;
+        let current = serde_json::to_value(HostSettings {
+            effects: settings.effects.as_ref().map(|_| config.effects.clone()),
+            pinned_colors: settings
+                .pinned_colors
+                .as_ref()
+                .map(|_| config.pinned_colors.clone()),
+            ..HostSettings::new(&config)
+        })?;
+        let changed: Vec<(String, serde_json::Value)> = match serde_json::to_value(settings)? {
+            serde_json::Value::Object(settings) => settings
+                .into_iter()
+                .filter(|(key, value)| current.get(key) != Some(value))
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        if path.extension().is_some_and(|ext| ext == "toml") {
+            let mut file: toml_edit::DocumentMut = contents
+                .parse()
+                .map_err(|err: toml_edit::TomlError| invalid(err.to_string()))?;
+            let entry = match file.get_mut("keyboards") {
+                Some(keyboards) => keyboards.get_mut(index).ok_or_else(missing)?,
+                None => file.as_item_mut(),
+            };
+            let inline = entry.is_inline_table();
+            let entry = entry.as_table_like_mut().ok_or_else(missing)?;
+
+            let changed: serde_json::Map<_, _> = changed.into_iter().collect();
+            let changed =
+                toml_edit::ser::to_document(&changed).map_err(|err| invalid(err.to_string()))?;
+            for (key, item) in changed.into_table() {
+                // an inline table can only hold values, and so does what's written into one
+                let item = if inline {
+                    item.into_value()
+                        .map_or_else(|item| item, toml_edit::Item::Value)
+                } else {
+                    item
+                };
+                match (entry.get_mut(&key), item) {
+                    // a changed value keeps its spacing and trailing comment
+                    (Some(toml_edit::Item::Value(old)), toml_edit::Item::Value(mut new)) => {
+                        *new.decor_mut() = old.decor().clone();
+                        *old = new;
+                    }
+                    (Some(old), item) => *old = item,
+                    (None, item) => {
+                        entry.insert(&key, item);
+                    }
+                }
+            }
+            Ok(file.to_string())
+        } else {
+            let mut file: serde_json::Value = Self::deserialize(path, contents)?;
+            let entry = match file.get_mut("keyboards") {
+                Some(keyboards) => keyboards.get_mut(index),
+                None => Some(&mut file),
+            };
+            let entry = entry
+                .and_then(serde_json::Value::as_object_mut)
+                .ok_or_else(missing)?;
+            entry.extend(changed);
+            Ok(serde_json::to_string_pretty(&file)? + "\n")
+        }
+    }
+
+    pub fn framing(&self) -> Framing {
+        Framing {
+            report_id: self.report_id,
+            checksum: self.protocol_version >= CHECKSUM_VERSION,
+            epsize: self.raw_epsize,
+        }
+    }
+}
+
+fn default_raw_epsize() -> usize {
+    DEFAULT_EPSIZE
+}
+
+fn default_remember_settings() -> bool {
+    true
+}
+
+fn default_protocol_version() -> u8 {
+    1
+}
+
+fn default_gamma() -> f32 {
+    2.2
+}
+
+fn default_resync_interval() -> f32 {
+    5.0
+}
+
+fn default_effects() -> Vec<EffectConfig> {
+    vec![EffectConfig {
+        name: "rainbow1".to_string(),
+        params: serde_json::Value::Null,
+    }]
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct EffectConfig {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    pub params: serde_json::Value,
+}
+
+impl EffectConfig {
+    // writes back a param tuned in the ui, into the wrapped effect's params for idle_dim and
+    // masked
+    pub fn set_param(&mut self, name: &str, value: f32) {
+        set_value(&mut self.params, name, number(value));
+    }
+
+    // the same for params that aren't a single number, like a picked color
+    pub fn set_value(&mut self, name: &str, value: serde_json::Value) {
+        set_value(&mut self.params, name, value);
+    }
+}
+
+// the shortest spelling of the f32, so 0.1 isn't saved as 0.10000000149011612
+pub fn number(value: f32) -> serde_json::Value {
+    let value: f64 = value.to_string().parse().unwrap_or(value as f64);
+    value.into()
+}
+
+fn set_value(params: &mut serde_json::Value, name: &str, value: serde_json::Value) {
+    if !params.is_object() {
+        *params = serde_json::Value::Object(Default::default());
+    }
+    let object = params
+        .as_object_mut()
+        .expect("params were just made an object");
+
+    match object
+        .get_mut("effect")
+        .and_then(serde_json::Value::as_object_mut)
+    {
+        Some(inner) => set_value(
+            inner.entry("params").or_insert(serde_json::Value::Null),
+            name,
+            value,
+        ),
+        None => {
+            object.insert(name.to_string(), value);
+        }
+    }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct QMKInfo {
     pub keyboard_name: String,
     pub manufacturer: String,
     pub usb: QMKUSBInfo,
+    #[serde(default)]
+    pub matrix_size: Option<QMKMatrixSize>,
     pub layouts: HashMap<String, QMKLayout>,
+    // qmk's g_led_config for boards set up in info.json, whichever lighting they use
+    #[serde(default)]
+    pub rgb_matrix: Option<QMKLedLayout>,
+    #[serde(default)]
+    pub led_matrix: Option<QMKLedLayout>,
+}
+
+impl QMKInfo {
+    // qmk splits info.json over a keyboard's folder and its parents, e.g. usb ids in
+    // keyboards/foo and the layouts in keyboards/foo/rev2. these are merged outermost first,
+    // objects key by key and anything else replaced by the inner file
+    pub fn merge(base: &mut serde_json::Value, inner: serde_json::Value) {
+        match (base, inner) {
+            (serde_json::Value::Object(base), serde_json::Value::Object(inner)) => {
+                for (key, value) in inner {
+                    Self::merge(base.entry(key).or_insert(serde_json::Value::Null), value);
+                }
+            }
+            (base, inner) => *base = inner,
+        }
+    }
+
+    // the led index of every matrix position from rgb_matrix or led_matrix, for boards without a
+    // matrix.json. leds that aren't under a key (underglow) keep their index but aren't mapped
+    pub fn led_matrix(&self) -> Option<LEDMatrix> {
+        let leds = &self
+            .rgb_matrix
+            .as_ref()
+            .or(self.led_matrix.as_ref())?
+            .layout;
+
+        let keys = self.layouts.values().flat_map(|layout| &layout.layout);
+        let positions = leds
+            .iter()
+            .filter_map(|led| led.matrix)
+            .chain(keys.map(|key| key.matrix));
+        let (rows, cols) = match &self.matrix_size {
+            Some(size) => (size.rows as usize, size.cols as usize),
+            None => positions.fold((0, 0), |(rows, cols), (row, col)| {
+                (rows.max(row as usize + 1), cols.max(col as usize + 1))
+            }),
+        };
+
+        let mut matrix = vec![vec![-1; cols]; rows];
+        for (idx, led) in leds.iter().enumerate() {
+            let Some((row, col)) = led.matrix else {
+                continue;
+            };
+            // keys outside the declared size get reported in KBConfig::validate
+            if let Some(cell) = matrix
+                .get_mut(row as usize)
+                .and_then(|r| r.get_mut(col as usize))
+            {
+                *cell = idx as i16;
+            }
+        }
+
+        Some(matrix)
+    }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
+pub struct QMKLedLayout {
+    pub layout: Vec<QMKLed>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct QMKLed {
+    #[serde(default)]
+    pub matrix: Option<(u8, u8)>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct QMKMatrixSize {
+    pub rows: u8,
+    pub cols: u8,
+}
+
+#[derive(Deserialize, Debug, Clone)]
 pub struct QMKUSBInfo {
     #[serde(deserialize_with = "deserialize_hex")]
     pub vid: u16,
@@ -139,7 +1019,7 @@ pub struct QMKUSBInfo {
     pub device_version: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct QMKLayout {
     pub layout: Vec<QMKKey>,
 }
@@ -148,7 +1028,7 @@ fn default_dim() -> f32 {
     1.0
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct QMKKey {
     pub label: String,
     pub x: f32,
@@ -158,46 +1038,125 @@ pub struct QMKKey {
     #[serde(default = "default_dim")]
     pub h: f32,
     pub matrix: (u8, u8),
+    // degrees clockwise about (rx, ry), for angled and split layouts
+    #[serde(default)]
+    pub r: f32,
+    #[serde(default)]
+    pub rx: f32,
+    #[serde(default)]
+    pub ry: f32,
+}
+
+impl QMKKey {
+    // a point in layout units turned with the key
+    pub fn rotate(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        if self.r == 0.0 {
+            return (x, y);
+        }
+        let (sin, cos) = self.r.to_radians().sin_cos();
+        let (dx, dy) = (x - self.rx, y - self.ry);
+        (self.rx + dx * cos - dy * sin, self.ry + dx * sin + dy * cos)
+    }
+
+    // clockwise from the top left, as drawn
+    pub fn corners(&self) -> [(f32, f32); 4] {
+        [
+            (self.x, self.y),
+            (self.x + self.w, self.y),
+            (self.x + self.w, self.y + self.h),
+            (self.x, self.y + self.h),
+        ]
+        .map(|corner| self.rotate(corner))
+    }
 }
 
 pub type LEDMatrix = Vec<Vec<i16>>;
 
-#[derive(Deserialize, Debug)]
+// layers past the 32 a layer state can hold are never active
+fn layer_active(layer_state: u32, idx: usize) -> bool {
+    idx < 32 && layer_state & (1 << idx) != 0
+}
+
+// indices of the layers on in `layer_state`, lowest first. the base layer is always one of them,
+// layers the legends don't have included
+pub fn active_layers(layer_state: u32) -> impl Iterator<Item = usize> {
+    (0..32).filter(move |idx| *idx == 0 || layer_active(layer_state, *idx))
+}
+
+#[derive(Deserialize, Debug, Clone)]
 pub struct KBLegends(Vec<LayerDef>);
 
 impl KBLegends {
-    pub fn get_key(&self, layer_state: u8, row: u8, col: u8) -> Option<&KeyDef> {
-        for (idx, layer) in self.0.iter().rev().enumerate() {
-            let idx = self.0.len() - idx - 1;
+    pub fn new(layers: Vec<LayerDef>) -> KBLegends {
+        KBLegends(layers)
+    }
 
-            if idx != 0 && layer_state & (1 << idx) == 0 {
-                continue;
-            }
+    // the definition that's in effect at this position: transparent keys fall through to the
+    // next active layer below them, down to the base layer
+    pub fn get_key(&self, layer_state: u32, row: u8, col: u8) -> Option<&KeyDef> {
+        let mut active = self
+            .0
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(idx, _)| *idx == 0 || layer_active(layer_state, *idx))
+            .map(|(_, layer)| &layer.legends[row as usize][col as usize]);
 
-            let def = &layer.legends[row as usize][col as usize];
-            if let KeyUsage::Passthrough = def.usage {
-                continue;
-            }
+        // even a transparent base key is shown as what it is
+        active.find(|def| !def.is_transparent()).or_else(|| {
+            self.0
+                .first()
+                .map(|layer| &layer.legends[row as usize][col as usize])
+        })
+    }
 
-            return Some(def);
-        }
+    // index of the highest active layer, the base layer is always active
+    pub fn top_layer(&self, layer_state: u32) -> usize {
+        (1..self.0.len())
+            .rev()
+            .find(|idx| layer_active(layer_state, *idx))
+            .unwrap_or(0)
+    }
 
-        None
+    // name of the highest layer on in `layer_state`, for logging
+    pub fn layer_name(&self, layer_state: u32) -> &str {
+        self.0
+            .get(self.top_layer(layer_state))
+            .map(|layer| layer.layer_name.as_str())
+            .unwrap_or("none")
+    }
+
+    // base layer first, only the ones a layer state can turn on
+    pub fn layer_names(&self) -> impl Iterator<Item = &str> {
+        self.0
+            .iter()
+            .take(32)
+            .map(|layer| layer.layer_name.as_str())
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct LayerDef {
     layer_name: String,
     legends: Vec<Vec<KeyDef>>,
 }
 
-#[derive(Deserialize, Debug)]
+impl LayerDef {
+    pub fn new(layer_name: String, legends: Vec<Vec<KeyDef>>) -> LayerDef {
+        LayerDef {
+            layer_name,
+            legends,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum KeyUsage {
     Removed,
     Unused,
     Passthrough,
+    #[default]
     Default,
     Modtap,
     Modifier,
@@ -207,15 +1166,796 @@ pub enum KeyUsage {
     Mouse,
 }
 
-impl Default for KeyUsage {
-    fn default() -> Self {
-        KeyUsage::Default
-    }
-}
-
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct KeyDef {
     #[serde(default)]
     pub usage: KeyUsage,
     pub label: Option<String>,
 }
+
+// how keymaps spell KC_TRNS when the legends are exported straight from them
+const TRANSPARENT_LABELS: &[&str] = &["KC_TRNS", "KC_TRANSPARENT", "_______"];
+
+impl KeyDef {
+    pub fn is_transparent(&self) -> bool {
+        matches!(self.usage, KeyUsage::Passthrough)
+            || self
+                .label
+                .as_deref()
+                .is_some_and(|label| TRANSPARENT_LABELS.contains(&label))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn host_config() -> Config {
+        serde_json::from_str(
+            r#"{"kb": "test", "keymap": "default", "layout": "LAYOUT", "usage_page": "0xFF60", "usage": "0x61"}"#,
+        )
+        .unwrap()
+    }
+
+    fn qmk_info(matrix_size: &str, keys: &str) -> QMKInfo {
+        serde_json::from_str(&format!(
+            r#"{{
+                "keyboard_name": "test",
+                "manufacturer": "test",
+                "usb": {{"vid": "0x1234", "pid": "0x5678", "device_version": "1.0.0"}},
+                {}
+                "layouts": {{"LAYOUT": {{"layout": [{}]}}}}
+            }}"#,
+            matrix_size, keys
+        ))
+        .unwrap()
+    }
+
+    fn legends(rows: usize, cols: usize) -> KBLegends {
+        let row = vec![r#"{"label": "x"}"#; cols].join(",");
+        let layer = vec![format!("[{}]", row); rows].join(",");
+        serde_json::from_str(&format!(
+            r#"[{{"layer_name": "base", "legends": [{}]}}]"#,
+            layer
+        ))
+        .unwrap()
+    }
+
+    const KEYS_2X2: &str = r#"
+        {"label": "A", "x": 0, "y": 0, "matrix": [0, 0]},
+        {"label": "B", "x": 1, "y": 0, "matrix": [0, 1]},
+        {"label": "C", "x": 0, "y": 1, "matrix": [1, 0]},
+        {"label": "D", "x": 1, "y": 1, "matrix": [1, 1]}
+    "#;
+
+    // a 2x2 board with keys A B / C D and one led per key, for tests elsewhere
+    pub(crate) fn kb_config_2x2() -> KBConfig {
+        KBConfig::new(
+            host_config(),
+            qmk_info(r#""matrix_size": {"rows": 2, "cols": 2},"#, KEYS_2X2),
+            vec![vec![0, 1], vec![2, 3]],
+            legends(2, 2),
+        )
+        .unwrap()
+    }
+
+    // led 1 is under no key, like underglow, and D has no led
+    pub(crate) fn kb_config_with_gaps() -> KBConfig {
+        KBConfig::new(
+            host_config(),
+            qmk_info(r#""matrix_size": {"rows": 2, "cols": 2},"#, KEYS_2X2),
+            vec![vec![0, 2], vec![3, -1]],
+            legends(2, 2),
+        )
+        .unwrap()
+    }
+
+    // the same keys without a single led, like a board without rgb
+    pub(crate) fn kb_config_without_leds() -> KBConfig {
+        KBConfig::new(
+            host_config(),
+            qmk_info(r#""matrix_size": {"rows": 2, "cols": 2},"#, KEYS_2X2),
+            vec![vec![-1, -1], vec![-1, -1]],
+            legends(2, 2),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn valid_config() {
+        let config = KBConfig::new(
+            host_config(),
+            qmk_info(r#""matrix_size": {"rows": 2, "cols": 2},"#, KEYS_2X2),
+            vec![vec![0, 1], vec![2, 3]],
+            legends(2, 2),
+        )
+        .unwrap();
+
+        assert_eq!(config.led_count(), 4);
+        assert_eq!(config.rows(), 2);
+        assert_eq!(config.columns(), 2);
+    }
+
+    #[test]
+    fn more_than_255_leds() {
+        let (rows, cols) = (2, 150);
+        let keys = (0..rows * cols)
+            .map(|idx| {
+                format!(
+                    r#"{{"label": "x", "x": {}, "y": {}, "matrix": [{}, {}]}}"#,
+                    idx % cols,
+                    idx / cols,
+                    idx / cols,
+                    idx % cols
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        let matrix = (0..rows)
+            .map(|row| (0..cols).map(|col| (row * cols + col) as i16).collect())
+            .collect();
+
+        let config = KBConfig::new(
+            host_config(),
+            qmk_info("", &keys),
+            matrix,
+            legends(rows, cols),
+        )
+        .unwrap();
+        assert_eq!(config.led_count(), 300);
+
+        let led_state = crate::threading::led_state(&config);
+        assert_eq!(led_state.len(), 300);
+        assert_eq!(led_state[299].key.unwrap().matrix, (1, 149));
+    }
+
+    #[test]
+    fn merges_parent_info() {
+        let mut info = serde_json::json!({
+            "keyboard_name": "foo",
+            "usb": {"vid": "0x1234", "pid": "0x0001", "device_version": "1.0.0"},
+            "layouts": {"LAYOUT": {"layout": []}}
+        });
+        QMKInfo::merge(
+            &mut info,
+            serde_json::json!({
+                "manufacturer": "bar",
+                "usb": {"pid": "0x0002"},
+                "layouts": {"LAYOUT": {"layout": [{"label": "A", "x": 0, "y": 0, "matrix": [0, 0]}]}}
+            }),
+        );
+
+        let info: QMKInfo = serde_json::from_value(info).unwrap();
+        assert_eq!(info.manufacturer, "bar");
+        assert_eq!((info.usb.vid, info.usb.pid), (0x1234, 0x0002));
+        assert_eq!(info.layouts["LAYOUT"].layout.len(), 1);
+    }
+
+    #[test]
+    fn reads_toml_and_json() {
+        let json = Config::parse_all(
+            Path::new("config.json"),
+            r#"{
+                "kb": "test", "keymap": "default", "layout": "LAYOUT",
+                "usage_page": "0xFF60", "usage": "0x61", "serial": "ksk-1",
+                "effects": [{"name": "rainbow1", "params": {"speed": 36}}]
+            }"#,
+        )
+        .unwrap();
+
+        let toml = Config::parse_all(
+            Path::new("config.toml"),
+            r#"
+                kb = "test"
+                keymap = "default"
+                layout = "LAYOUT"
+                # comments are why anyone would want this
+                usage_page = "0xFF60"
+                usage = "0x61"
+                serial = "ksk-1"
+
+                [[effects]]
+                name = "rainbow1"
+                params = { speed = 36 }
+            "#,
+        )
+        .unwrap();
+
+        for config in [&json, &toml] {
+            assert_eq!(config.len(), 1);
+            assert_eq!((config[0].usage_page, config[0].usage), (0xFF60, 0x61));
+            assert_eq!(config[0].serial.as_deref(), Some("ksk-1"));
+            assert_eq!(config[0].effects[0].params["speed"], 36);
+        }
+
+        let err = Config::parse_all(Path::new("config.toml"), "kb = ")
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn reads_several_keyboards() {
+        let toml = Config::parse_all(
+            Path::new("config.toml"),
+            r#"
+                [[keyboards]]
+                kb = "left"
+                keymap = "default"
+                layout = "LAYOUT"
+                usage_page = "0xFF60"
+                usage = "0x61"
+
+                [[keyboards]]
+                kb = "right"
+                keymap = "default"
+                layout = "LAYOUT"
+                usage_page = "0xFF60"
+                usage = "0x61"
+                gamma = 1.0
+            "#,
+        )
+        .unwrap();
+
+        let kbs: Vec<_> = toml.iter().map(|config| config.kb.as_str()).collect();
+        assert_eq!(kbs, ["left", "right"]);
+        assert_eq!(toml[1].gamma, 1.0);
+
+        // a bad keyboard is reported as such, not as the file being neither shape
+        let err = Config::parse_all(
+            Path::new("config.json"),
+            r#"{"keyboards": [{"kb": "left", "keymap": "default"}]}"#,
+        )
+        .err()
+        .unwrap();
+        assert!(
+            err.to_string().contains("missing field `layout`"),
+            "{}",
+            err
+        );
+
+        let empty = Config::parse_all(Path::new("config.json"), r#"{"keyboards": []}"#);
+        assert!(empty.is_err());
+    }
+
+    #[test]
+    fn patches_settings_in() {
+        let settings = HostSettings {
+            kb: "right".to_string(),
+            keymap: "mine".to_string(),
+            layout: "LAYOUT_iso".to_string(),
+            usage_page: "0xFF60".to_string(),
+            usage: "0x61".to_string(),
+            effects: Some(vec![EffectConfig {
+                name: "static".to_string(),
+                params: serde_json::Value::Null,
+            }]),
+            pinned_colors: Some([("0,1".to_string(), [0.0, 1.0, 1.0, 1.0])].into()),
+        };
+
+        let toml = r#"
+            [[keyboards]]
+            kb = "left"
+            keymap = "default"
+            layout = "LAYOUT"
+            usage_page = "0xFF60"
+            usage = "0x61"
+
+            # the one on the right
+            [[keyboards]]
+            kb = "right"
+            keymap = "default"
+            layout = "LAYOUT"
+            usage_page = "0xff60"
+            usage = "0x62" # the raw hid interface
+            gamma = 1.0
+        "#;
+        let path = Path::new("config.toml");
+        let patched = Config::patch(path, toml, 1, &settings).unwrap();
+
+        let configs = Config::parse_all(path, &patched).unwrap();
+        assert_eq!(configs[0].layout, "LAYOUT");
+        let config = &configs[1];
+        assert_eq!((config.keymap.as_str(), config.usage), ("mine", 0x61));
+        // what the settings don't cover is kept
+        assert_eq!(config.gamma, 1.0);
+        assert_eq!(config.effects[0].name, "static");
+        assert_eq!(config.pinned_colors["0,1"], [0.0, 1.0, 1.0, 1.0]);
+        // and so is how the rest of the file is written
+        assert!(patched.contains("# the one on the right"));
+        assert!(patched.contains(r#"usage = "0x61" # the raw hid interface"#));
+        assert!(patched.contains(r#"usage_page = "0xff60""#));
+
+        let inline = r#"keyboards = [{ kb = "left", keymap = "default", layout = "LAYOUT", usage_page = "0xFF60", usage = "0x61" }]"#;
+        let patched = Config::patch(path, inline, 0, &settings).unwrap();
+        let config = &Config::parse_all(path, &patched).unwrap()[0];
+        assert_eq!(config.pinned_colors["0,1"], [0.0, 1.0, 1.0, 1.0]);
+
+        let json = r#"{"kb": "left", "keymap": "default", "layout": "LAYOUT", "usage_page": "0xFF60", "usage": "0x61", "report_id": 3}"#;
+        let path = Path::new("config.json");
+        let patched = Config::patch(path, json, 0, &settings).unwrap();
+        let config = &Config::parse_all(path, &patched).unwrap()[0];
+        assert_eq!((config.kb.as_str(), config.report_id), ("right", 3));
+        // json keys stay in the order they were written
+        assert!(patched.find(r#""layout""#) < patched.find(r#""report_id""#));
+
+        assert!(Config::patch(path, json, 1, &settings).is_err());
+    }
+
+    #[test]
+    fn sets_effect_params() {
+        let mut config: EffectConfig = serde_json::from_str(r#"{"name": "rainbow1"}"#).unwrap();
+        config.set_param("speed", 0.1);
+        assert_eq!(config.params, serde_json::json!({"speed": 0.1}));
+
+        let mut wrapper: EffectConfig = serde_json::from_str(
+            r#"{"name": "masked", "params": {"effect": {"name": "wave"}, "keys": [[0, 0]]}}"#,
+        )
+        .unwrap();
+        wrapper.set_param("speed", 2.0);
+        assert_eq!(
+            wrapper.params,
+            serde_json::json!({"effect": {"name": "wave", "params": {"speed": 2.0}}, "keys": [[0, 0]]})
+        );
+    }
+
+    #[test]
+    fn parses_hex_ids() {
+        #[derive(Deserialize)]
+        struct Id(#[serde(deserialize_with = "deserialize_hex")] u16);
+        let parse = |json: &str| serde_json::from_str::<Id>(json).map(|id| id.0);
+
+        assert_eq!(parse(r#""0xFF60""#).unwrap(), 0xFF60);
+        assert_eq!(parse(r#"" 0X61 ""#).unwrap(), 0x61);
+        assert_eq!(parse(r#""feed""#).unwrap(), 0xFEED);
+
+        let err = |json: &str| parse(json).err().unwrap().to_string();
+        assert!(err(r#""0x""#).contains("is empty"));
+        assert!(err(r#""0xFF6O""#).contains("not a hex number"));
+        assert!(err(r#""0x12345""#).contains("\"0x12345\" is more than 16 bits"));
+    }
+
+    #[test]
+    fn led_matrix_from_info() {
+        let mut info = qmk_info(r#""matrix_size": {"rows": 2, "cols": 2},"#, KEYS_2X2);
+        assert!(info.led_matrix().is_none());
+
+        // C has no led, the last one is underglow
+        info.rgb_matrix = serde_json::from_str(
+            r#"{"layout": [
+                {"matrix": [0, 0], "x": 0, "y": 0, "flags": 4},
+                {"matrix": [0, 1], "x": 16, "y": 0, "flags": 4},
+                {"matrix": [1, 1], "x": 16, "y": 16, "flags": 4},
+                {"x": 8, "y": 32, "flags": 2}
+            ]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(info.led_matrix().unwrap(), vec![vec![0, 1], vec![-1, 2]]);
+    }
+
+    #[test]
+    fn missing_layout() {
+        let mut host_config = host_config();
+        host_config.layout = "LAYOUT_iso".to_string();
+
+        let err = KBConfig::new(
+            host_config,
+            qmk_info("", KEYS_2X2),
+            vec![vec![0, 1], vec![2, 3]],
+            legends(2, 2),
+        )
+        .err()
+        .unwrap();
+
+        assert_eq!(err.problems.len(), 1);
+        assert!(err.problems[0].contains("LAYOUT_iso"));
+    }
+
+    #[test]
+    fn switches_layouts() {
+        let mut qmk_info = qmk_info(r#""matrix_size": {"rows": 2, "cols": 2},"#, KEYS_2X2);
+        let layout = |keys: &str| -> QMKLayout {
+            serde_json::from_str(&format!(r#"{{"layout": [{}]}}"#, keys)).unwrap()
+        };
+        // a wider top row over the same matrix
+        qmk_info.layouts.insert(
+            "LAYOUT_wide".to_string(),
+            layout(
+                r#"
+                {"label": "A", "x": 0, "y": 0, "w": 1.5, "matrix": [0, 0]},
+                {"label": "B", "x": 1.5, "y": 0, "matrix": [0, 1]},
+                {"label": "C", "x": 0, "y": 1, "matrix": [1, 0]},
+                {"label": "D", "x": 1, "y": 1, "matrix": [1, 1]}
+            "#,
+            ),
+        );
+        qmk_info.layouts.insert(
+            "LAYOUT_big".to_string(),
+            layout(r#"{"label": "E", "x": 0, "y": 0, "matrix": [2, 0]}"#),
+        );
+
+        let config = KBConfig::new(
+            host_config(),
+            qmk_info,
+            vec![vec![0, 1], vec![2, 3]],
+            legends(2, 2),
+        )
+        .unwrap();
+        assert_eq!(
+            config.layout_names(),
+            vec!["LAYOUT", "LAYOUT_big", "LAYOUT_wide"]
+        );
+
+        let wide = config.with_layout("LAYOUT_wide").unwrap();
+        assert_eq!(wide.host_config.layout, "LAYOUT_wide");
+        assert_eq!(wide.led_count(), 4);
+        assert_eq!(wide.width(), 2.5);
+
+        let err = config.with_layout("LAYOUT_big").err().unwrap();
+        assert!(err
+            .problems
+            .iter()
+            .any(|p| p.contains("outside matrix.json")));
+        assert!(config.with_layout("LAYOUT_none").is_err());
+    }
+
+    #[test]
+    fn rejects_endpoint_sizes() {
+        for raw_epsize in [MIN_EPSIZE - 1, MAX_EPSIZE + 1] {
+            let mut host_config = host_config();
+            host_config.raw_epsize = raw_epsize;
+
+            let err = KBConfig::new(
+                host_config,
+                qmk_info("", KEYS_2X2),
+                vec![vec![0, 1], vec![2, 3]],
+                legends(2, 2),
+            )
+            .err()
+            .unwrap();
+
+            assert_eq!(err.problems.len(), 1);
+            assert!(err.problems[0].contains("raw_epsize"));
+        }
+    }
+
+    #[test]
+    fn rejects_mqtt_password_without_username() {
+        let mut host_config = host_config();
+        host_config.mqtt = Some(
+            serde_json::from_str(r#"{"broker": "localhost:1883", "password": "hunter2"}"#).unwrap(),
+        );
+
+        let err = KBConfig::new(
+            host_config,
+            qmk_info("", KEYS_2X2),
+            vec![vec![0, 1], vec![2, 3]],
+            legends(2, 2),
+        )
+        .err()
+        .unwrap();
+
+        assert_eq!(err.problems.len(), 1);
+        assert!(err.problems[0].contains("no username"));
+    }
+
+    #[test]
+    fn matrix_smaller_than_layout() {
+        // without a matrix_size in info.json only matrix.json can catch it
+        let err = KBConfig::new(
+            host_config(),
+            qmk_info("", KEYS_2X2),
+            vec![vec![0, 1]],
+            legends(2, 2),
+        )
+        .err()
+        .unwrap();
+
+        assert_eq!(
+            err.problems,
+            vec![
+                "key C at (1, 0) is outside matrix.json",
+                "key D at (1, 1) is outside matrix.json"
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_all_problems() {
+        let keys = r#"
+            {"label": "A", "x": 0, "y": 0, "matrix": [0, 0]},
+            {"label": "B", "x": 1, "y": 0, "matrix": [0, 2]},
+            {"label": "C", "x": 0, "y": 1, "matrix": [3, 0]}
+        "#;
+
+        let err = KBConfig::new(
+            host_config(),
+            qmk_info(r#""matrix_size": {"rows": 3, "cols": 2},"#, keys),
+            // ragged second row, only two rows
+            vec![vec![0, 1], vec![2]],
+            legends(2, 2),
+        )
+        .err()
+        .unwrap();
+
+        let has = |needle: &str| err.problems.iter().any(|p| p.contains(needle));
+
+        assert!(has("row 1 has 1 columns"));
+        assert!(has("declares a 3x2 matrix"));
+        assert!(has("key B at (0, 2) is outside the declared"));
+        assert!(has("key B at (0, 2) is outside matrix.json"));
+        assert!(has("key C at (3, 0) is outside matrix.json"));
+        assert!(has("key C at (3, 0) has no legend"));
+        assert!(has("key C at (3, 0) is outside the declared"));
+        assert_eq!(err.problems.len(), 8);
+    }
+
+    #[test]
+    fn phase_offset_follows_position() {
+        // B is stacked right on top of A, like alternate keys in a layout
+        let keys = r#"
+            {"label": "A", "x": 0, "y": 0, "matrix": [0, 0]},
+            {"label": "B", "x": 0, "y": 0, "matrix": [0, 1]},
+            {"label": "C", "x": 3, "y": 1, "matrix": [0, 2]}
+        "#;
+
+        let config = KBConfig::new(
+            host_config(),
+            qmk_info("", keys),
+            vec![vec![0, 1, 2]],
+            legends(1, 3),
+        )
+        .unwrap();
+
+        let layout = &config.layout().layout;
+        let offsets: Vec<f32> = layout.iter().map(|key| config.phase_offset(key)).collect();
+
+        assert_eq!(offsets[0], offsets[1]);
+        assert!(offsets[2] > offsets[0]);
+        assert!(offsets.iter().all(|offset| (0.0..=1.0).contains(offset)));
+    }
+
+    #[test]
+    fn led_index_out_of_range() {
+        let mut info = qmk_info("", KEYS_2X2);
+        info.rgb_matrix = serde_json::from_str(
+            r#"{"layout": [{"matrix": [0, 0]}, {"matrix": [0, 1]}, {"matrix": [1, 0]}, {}]}"#,
+        )
+        .unwrap();
+
+        let err = KBConfig::new(
+            host_config(),
+            info,
+            vec![vec![0, 1], vec![2, 9]],
+            legends(2, 2),
+        )
+        .err()
+        .unwrap();
+
+        assert_eq!(err.problems.len(), 1);
+        assert!(err.problems[0].contains("led index 9 but the board only has 4 leds"));
+    }
+
+    #[test]
+    fn legends_without_layers() {
+        let err = KBConfig::new(
+            host_config(),
+            qmk_info("", KEYS_2X2),
+            vec![vec![0, 1], vec![2, 3]],
+            serde_json::from_str("[]").unwrap(),
+        )
+        .err()
+        .unwrap();
+
+        assert_eq!(err.problems.len(), 1);
+        assert!(err.problems[0].contains("no layers"));
+    }
+
+    #[test]
+    fn led_indices_with_gaps() {
+        // two underglow leds before the keys', and none for D
+        let config = KBConfig::new(
+            host_config(),
+            qmk_info("", KEYS_2X2),
+            vec![vec![2, 3], vec![4, -1]],
+            legends(2, 2),
+        )
+        .unwrap();
+        assert_eq!(config.led_count(), 5);
+
+        let led_state = crate::threading::led_state(&config);
+        assert!(led_state[0].key.is_none() && led_state[1].key.is_none());
+        assert_eq!(led_state[4].key.unwrap().label, "C");
+    }
+
+    #[test]
+    fn duplicate_positions() {
+        let keys = r#"
+            {"label": "A", "x": 0, "y": 0, "matrix": [0, 0]},
+            {"label": "B", "x": 1, "y": 0, "matrix": [0, 1]},
+            {"label": "C", "x": 0, "y": 1, "matrix": [0, 1]},
+            {"label": "D", "x": 1, "y": 1, "matrix": [1, 1]},
+            {"label": "E", "x": 2, "y": 1, "matrix": [0, 1]}
+        "#;
+        let err = KBConfig::new(
+            host_config(),
+            qmk_info("", keys),
+            vec![vec![0, 1], vec![-1, 2]],
+            legends(2, 2),
+        )
+        .err()
+        .unwrap();
+
+        assert_eq!(
+            err.problems,
+            [
+                "keys B and C are both at (0, 1) in layout LAYOUT",
+                "keys B and E are both at (0, 1) in layout LAYOUT",
+            ]
+        );
+    }
+
+    #[test]
+    fn rotated_keys_fit() {
+        // B is 2 wide, turned a quarter to hang down from its top left corner
+        let keys = r#"
+            {"label": "A", "x": 0, "y": 0, "matrix": [0, 0]},
+            {"label": "B", "x": 1, "y": 0, "w": 2, "matrix": [0, 1], "r": 90, "rx": 1, "ry": 0},
+            {"label": "C", "x": 0, "y": 1, "matrix": [1, 0]},
+            {"label": "D", "x": 1, "y": 1, "matrix": [1, 1]}
+        "#;
+        let kb_config = KBConfig::new(
+            host_config(),
+            qmk_info("", keys),
+            vec![vec![0, 1], vec![2, 3]],
+            legends(2, 2),
+        )
+        .unwrap();
+
+        let key = &kb_config.layout().layout[1];
+        let corners = key.corners();
+        for (corner, expected) in
+            corners
+                .iter()
+                .zip([(1.0, 0.0), (1.0, 2.0), (0.0, 2.0), (0.0, 0.0)])
+        {
+            assert!((corner.0 - expected.0).abs() < 1e-5 && (corner.1 - expected.1).abs() < 1e-5);
+        }
+        assert!((kb_config.width() - 2.0).abs() < 1e-5);
+        assert!((kb_config.height() - 2.0).abs() < 1e-5);
+        assert_eq!(kb_config.layout().layout[0].r, 0.0);
+    }
+
+    #[test]
+    fn split_layouts_start_anywhere() {
+        // the left half starts half a key left of 0, the right one after a gap and half a
+        // key lower
+        let keys = r#"
+            {"label": "L1", "x": -0.5, "y": 0, "matrix": [0, 0]},
+            {"label": "L2", "x": 0.5, "y": 0, "matrix": [0, 1]},
+            {"label": "R1", "x": 3, "y": 0.5, "matrix": [0, 2]},
+            {"label": "R2", "x": 4, "y": 0.5, "matrix": [0, 3]},
+            {"label": "L3", "x": -0.5, "y": 1, "matrix": [1, 0]},
+            {"label": "L4", "x": 0.5, "y": 1, "matrix": [1, 1]},
+            {"label": "R3", "x": 3, "y": 1.5, "matrix": [1, 2]},
+            {"label": "R4", "x": 4, "y": 1.5, "matrix": [1, 3]}
+        "#;
+        let kb_config = KBConfig::new(
+            host_config(),
+            qmk_info("", keys),
+            vec![vec![0, 1, 2, 3], vec![4, 5, 6, 7]],
+            legends(2, 4),
+        )
+        .unwrap();
+
+        assert_eq!(kb_config.origin(), (-0.5, 0.0));
+        assert_eq!(kb_config.width(), 5.5);
+        assert_eq!(kb_config.height(), 2.5);
+
+        let keys = &kb_config.layout().layout;
+        assert_eq!(kb_config.key_position(&keys[0]), (0.5, 0.5));
+        assert_eq!(kb_config.key_position(&keys[7]), (5.0, 2.0));
+        let (x, y) = kb_config.relative_position(&keys[7]);
+        assert!((0.0..=1.0).contains(&x) && (0.0..=1.0).contains(&y));
+        assert!(keys
+            .iter()
+            .all(|key| (0.0..=1.0).contains(&kb_config.phase_offset(key))));
+    }
+
+    #[test]
+    fn empty_layout() {
+        let err = KBConfig::new(host_config(), qmk_info("", ""), vec![], legends(0, 0))
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.problems,
+            ["layout LAYOUT has no keys, there'd be nothing to draw or light"]
+        );
+
+        // keys without leds are fine though
+        assert_eq!(kb_config_without_leds().led_count(), 0);
+    }
+
+    #[test]
+    fn reads_legends_json() {
+        let legends: KBLegends = serde_json::from_str(
+            r#"[{"layer_name": "base", "legends": [[
+                {"label": "Esc", "usage": "default"},
+                {"label": "Ctrl", "usage": "modtap"},
+                {"usage": "removed"},
+                {"label": "Fn"}
+            ]]}]"#,
+        )
+        .unwrap();
+
+        let usage = |col| &legends.get_key(0, 0, col).unwrap().usage;
+        assert!(matches!(usage(0), KeyUsage::Default));
+        assert!(matches!(usage(1), KeyUsage::Modtap));
+        assert!(matches!(usage(2), KeyUsage::Removed));
+        // usage is optional
+        assert!(matches!(usage(3), KeyUsage::Default));
+        assert_eq!(legends.get_key(0, 0, 2).unwrap().label, None);
+    }
+
+    #[test]
+    fn transparent_keys_fall_through() {
+        // one key per layer: base, a layer that's transparent in both ways, and a layer on top
+        let legends: KBLegends = serde_json::from_str(
+            r#"[
+                {"layer_name": "base", "legends": [[{"label": "A"}]]},
+                {"layer_name": "nav", "legends": [[{"label": "B"}]]},
+                {"layer_name": "trns", "legends": [[{"usage": "passthrough"}]]},
+                {"layer_name": "kc", "legends": [[{"label": "KC_TRNS"}]]}
+            ]"#,
+        )
+        .unwrap();
+        let label = |layer_state| {
+            legends
+                .get_key(layer_state, 0, 0)
+                .and_then(|def| def.label.as_deref())
+        };
+
+        assert_eq!(label(0), Some("A"));
+        assert_eq!(label(0b0010), Some("B"));
+        // falls through both transparent layers to nav, skipping inactive ones down to base
+        assert_eq!(label(0b1110), Some("B"));
+        assert_eq!(label(0b1100), Some("A"));
+    }
+
+    #[test]
+    fn top_layer_of_a_mask() {
+        let legends: KBLegends = serde_json::from_str(
+            r#"[
+                {"layer_name": "base", "legends": []},
+                {"layer_name": "nav", "legends": []},
+                {"layer_name": "num", "legends": []}
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(legends.top_layer(0), 0);
+        assert_eq!(legends.top_layer(0b110), 2);
+        // layers the legends don't have are ignored
+        assert_eq!(legends.top_layer(1 << 31 | 0b10), 1);
+        assert_eq!(
+            active_layers(1 << 31 | 0b100).collect::<Vec<_>>(),
+            [0, 2, 31]
+        );
+        assert_eq!(legends.layer_name(0b100), "num");
+        assert_eq!(
+            legends.layer_names().collect::<Vec<_>>(),
+            ["base", "nav", "num"]
+        );
+    }
+
+    #[test]
+    fn transparent_base_key_is_still_found() {
+        let legends: KBLegends = serde_json::from_str(
+            r#"[{"layer_name": "base", "legends": [[{"usage": "passthrough"}]]}]"#,
+        )
+        .unwrap();
+
+        assert!(legends.get_key(0, 0, 0).unwrap().is_transparent());
+    }
+}
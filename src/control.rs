@@ -0,0 +1,220 @@
+use crate::{config::KBConfig, threading::HIDCommand};
+use crossbeam::channel::Sender;
+use log::{debug, info, warn};
+use palette::Hsva;
+use std::{
+    collections::HashSet,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+// a plain text control socket for scripts, one command per line:
+//
+//   notify <hue> <saturation> <value> <seconds> [row,col ...]
+//   progress <percent>|off
+//   flash <hue> <saturation> <value> <milliseconds>
+//   tap
+//   text <message>
+//
+// hue is in degrees, saturation and value in 0-1, and keys are matrix positions of the
+// layout the app started with. every line is answered with `ok` or `error: <reason>`.
+pub fn spawn(addr: &str, tx: Sender<HIDCommand>, kb_config: &KBConfig) {
+    let keys: Arc<HashSet<(u8, u8)>> = Arc::new(
+        kb_config
+            .layout()
+            .layout
+            .iter()
+            .map(|key| key.matrix)
+            .collect(),
+    );
+
+    let listener = match TcpListener::bind(addr) {
+        Ok(listener) => listener,
+        Err(err) => {
+            warn!("could not bind control socket to {}: {}", addr, err);
+            return;
+        }
+    };
+
+    info!("control socket listening on {}", addr);
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let (tx, keys) = (tx.clone(), keys.clone());
+            thread::spawn(move || handle_client(stream, tx, &keys));
+        }
+    });
+}
+
+fn handle_client(stream: TcpStream, tx: Sender<HIDCommand>, keys: &HashSet<(u8, u8)>) {
+    let peer = stream.peer_addr().ok();
+    debug!("control client connected from {:?}", peer);
+
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(err) => {
+            warn!("could not answer control client {:?}: {}", peer, err);
+            return;
+        }
+    };
+
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                debug!("control client {:?} dropped: {}", peer, err);
+                break;
+            }
+        };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let reply = match parse_command(&line, keys) {
+            Ok(command) => match tx.send(command) {
+                Ok(()) => "ok".to_string(),
+                Err(_) => "error: hid thread stopped".to_string(),
+            },
+            Err(err) => format!("error: {}", err),
+        };
+
+        debug!("control command {:?}: {}", line.trim(), reply);
+        if writeln!(writer, "{}", reply).is_err() {
+            break;
+        }
+    }
+
+    debug!("control client {:?} disconnected", peer);
+}
+
+fn parse_num(arg: Option<&str>, name: &str) -> Result<f32, String> {
+    arg.ok_or(format!("missing {}", name))?
+        .parse()
+        .map_err(|_| format!("invalid {}", name))
+}
+
+// the longest a notification or flash may last, anything longer is surely a mistake
+const MAX_DURATION: Duration = Duration::from_secs(24 * 60 * 60);
+
+// none for durations that aren't positive, aren't a number or are longer than MAX_DURATION
+pub fn duration_from_secs(secs: f32) -> Option<Duration> {
+    Duration::try_from_secs_f32(secs)
+        .ok()
+        .filter(|duration| !duration.is_zero() && *duration <= MAX_DURATION)
+}
+
+fn parse_duration(arg: Option<&str>, unit: f32) -> Result<Duration, String> {
+    duration_from_secs(parse_num(arg, "duration")? * unit).ok_or("invalid duration".to_string())
+}
+
+fn parse_keys<'a>(
+    args: impl Iterator<Item = &'a str>,
+    known: &HashSet<(u8, u8)>,
+) -> Result<Vec<(u8, u8)>, String> {
+    args.map(|arg| {
+        let key = arg
+            .split_once(',')
+            .and_then(|(row, col)| Some((row.parse().ok()?, col.parse().ok()?)))
+            .ok_or(format!("invalid key position {}", arg))?;
+        match known.contains(&key) {
+            true => Ok(key),
+            false => Err(format!("unknown key {}", arg)),
+        }
+    })
+    .collect()
+}
+
+pub fn parse_command(line: &str, keys: &HashSet<(u8, u8)>) -> Result<HIDCommand, String> {
+    let mut args = line.split_whitespace();
+
+    match args.next() {
+        Some("notify") => {
+            let hue = parse_num(args.next(), "hue")?;
+            let saturation = parse_num(args.next(), "saturation")?;
+            let value = parse_num(args.next(), "value")?;
+            let duration = parse_duration(args.next(), 1.0)?;
+            let keys = parse_keys(args, keys)?;
+
+            Ok(HIDCommand::Notify {
+                color: Hsva::new(hue, saturation, value, 1.0),
+                duration,
+                keys: (!keys.is_empty()).then_some(keys),
+            })
+        }
+        // tap tempo, for effects like bpm that keep time
+        Some("tap") => Ok(HIDCommand::TapTempo),
+        // the message for effects like text, the rest of the line with its spaces squeezed
+        Some("text") => Ok(HIDCommand::SetText(args.collect::<Vec<_>>().join(" "))),
+        Some("flash") => {
+            let hue = parse_num(args.next(), "hue")?;
+            let saturation = parse_num(args.next(), "saturation")?;
+            let value = parse_num(args.next(), "value")?;
+            let duration = parse_duration(args.next(), 0.001)?;
+
+            Ok(HIDCommand::Flash {
+                color: Hsva::new(hue, saturation, value, 1.0),
+                duration,
+            })
+        }
+        Some("progress") => match args.next() {
+            Some("off") => Ok(HIDCommand::SetProgress(None)),
+            arg => {
+                let percent = parse_num(arg, "percent")?;
+                if !(0.0..=100.0).contains(&percent) {
+                    return Err("percent must be between 0 and 100".to_string());
+                }
+
+                Ok(HIDCommand::SetProgress(Some(percent / 100.0)))
+            }
+        },
+        Some(command) => Err(format!("unknown command {}", command)),
+        None => Err("empty command".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> HashSet<(u8, u8)> {
+        [(0, 0), (0, 1), (1, 0), (1, 1)].into_iter().collect()
+    }
+
+    #[test]
+    fn notify_checks_keys_and_durations() {
+        assert!(matches!(
+            parse_command("notify 0 1 1 2 0,1 1,1", &keys()),
+            Ok(HIDCommand::Notify { duration, keys: Some(keys), .. })
+                if duration == Duration::from_secs(2) && keys == [(0, 1), (1, 1)]
+        ));
+
+        let error = |line: &str| parse_command(line, &keys()).unwrap_err();
+        assert_eq!(error("notify 0 1 1 2 99,99"), "unknown key 99,99");
+        assert_eq!(error("notify 0 1 1 2 0;1"), "invalid key position 0;1");
+        for duration in ["0", "-1", "inf", "NaN", "1e30"] {
+            let line = format!("notify 0 1 1 {}", duration);
+            assert_eq!(error(&line), "invalid duration", "{}", duration);
+        }
+    }
+
+    #[test]
+    fn flash_durations_are_milliseconds() {
+        assert!(matches!(
+            parse_command("flash 0 1 1 250", &keys()),
+            Ok(HIDCommand::Flash { duration, .. }) if duration == Duration::from_millis(250)
+        ));
+        for duration in ["0", "inf", "NaN", "3.4e38"] {
+            let line = format!("flash 0 1 1 {}", duration);
+            assert_eq!(
+                parse_command(&line, &keys()).unwrap_err(),
+                "invalid duration",
+                "{}",
+                duration
+            );
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use crate::{config::KBConfig, transport::HidTransport};
+use hidapi::{DeviceInfo, HidApi, HidDevice};
+use log::{debug, info, warn};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// a device has to be seen (or missed) this many polls in a row before connecting (or
+// disconnecting), so a flaky enumeration doesn't thrash the connection
+const DEBOUNCE_POLLS: u32 = 2;
+
+fn matches(device: &DeviceInfo, kb_config: &KBConfig) -> bool {
+    let config = &kb_config.host_config;
+    device.vendor_id() == kb_config.qmk_info.usb.vid
+        && device.product_id() == kb_config.qmk_info.usb.pid
+        && device.usage_page() == config.usage_page
+        && device.usage() == config.usage
+        && config
+            .serial
+            .as_ref()
+            .is_none_or(|serial| device.serial_number() == Some(serial.as_str()))
+}
+
+// hidapi can list the same interface more than once, those are only kept the first time
+fn distinct<'a>(devices: impl Iterator<Item = &'a DeviceInfo>) -> Vec<&'a DeviceInfo> {
+    let mut distinct: Vec<&DeviceInfo> = Vec::new();
+    for device in devices {
+        if distinct.iter().all(|seen| seen.path() != device.path()) {
+            distinct.push(device);
+        }
+    }
+    distinct
+}
+
+fn matching_devices<'a>(api: &'a HidApi, kb_config: &KBConfig) -> Vec<&'a DeviceInfo> {
+    distinct(
+        api.device_list()
+            .filter(|device| matches(device, kb_config)),
+    )
+}
+
+// the first match unless the config's `device_index` picks another
+pub fn find_device<'a>(api: &'a HidApi, kb_config: &KBConfig) -> Option<&'a DeviceInfo> {
+    let index = kb_config.host_config.device_index.unwrap_or(0);
+    matching_devices(api, kb_config).get(index).copied()
+}
+
+fn describe(device: &DeviceInfo) -> String {
+    format!(
+        "{:04x}:{:04x} usage page {:#06x} usage {:#04x}, {} by {}, serial {}",
+        device.vendor_id(),
+        device.product_id(),
+        device.usage_page(),
+        device.usage(),
+        device.product_string().unwrap_or("?"),
+        device.manufacturer_string().unwrap_or("?"),
+        device.serial_number().unwrap_or("none"),
+    )
+}
+
+fn device_list(devices: &[&DeviceInfo]) -> String {
+    devices
+        .iter()
+        .enumerate()
+        .map(|(index, device)| format!("\n  {}: {}", index, describe(device)))
+        .collect()
+}
+
+// says why nothing was opened: no device matching, which is most often wrong ids in the config
+// so every device is listed, or a `device_index` past the matching ones
+pub fn open_device(api: &HidApi, kb_config: &KBConfig) -> Option<HidDevice> {
+    let usb = &kb_config.qmk_info.usb;
+    let config = &kb_config.host_config;
+    let devices = matching_devices(api, kb_config);
+
+    if devices.is_empty() {
+        warn!(
+            "no hid device matches {:04x}:{:04x} usage page {:#06x} usage {:#04x}{}, connected \
+             are:{}",
+            usb.vid,
+            usb.pid,
+            config.usage_page,
+            config.usage,
+            config
+                .serial
+                .as_ref()
+                .map_or(String::new(), |serial| format!(" serial {}", serial)),
+            device_list(&distinct(api.device_list()))
+        );
+        return None;
+    }
+
+    if devices.len() > 1 && config.device_index.is_none() {
+        warn!(
+            "{} devices match, using the first. set serial or device_index in the config, or \
+             pass --serial or --device-index, to pick another:{}",
+            devices.len(),
+            device_list(&devices)
+        );
+    }
+
+    let Some(device_info) = devices.get(config.device_index.unwrap_or(0)) else {
+        warn!(
+            "device_index is {} but only {} devices match:{}",
+            config.device_index.unwrap_or(0),
+            devices.len(),
+            device_list(&devices)
+        );
+        return None;
+    };
+
+    info!(
+        "opening {} by {} (version {}) at {:?}",
+        kb_config.qmk_info.keyboard_name,
+        kb_config.qmk_info.manufacturer,
+        kb_config.qmk_info.usb.device_version,
+        device_info.path()
+    );
+
+    match api.open_path(device_info.path()) {
+        Ok(device) => Some(device),
+        Err(err) => {
+            warn!("could not open device: {}", err);
+            None
+        }
+    }
+}
+
+pub enum DeviceEvent {
+    Connected(Box<dyn HidTransport + Send>),
+    Disconnected,
+}
+
+// where the thread gets its keyboard from: the real one through hidapi, or a mock
+pub trait DeviceSource: Send {
+    fn open(&self, kb_config: &KBConfig) -> Option<Box<dyn HidTransport + Send>>;
+    // whether the keyboard came or went, given whether the thread has it open
+    fn poll(&mut self, kb_config: &KBConfig, connected: bool) -> Option<DeviceEvent>;
+}
+
+// polls the hid device list for the configured keyboard coming and going. hidapi can only be
+// opened once, so every keyboard's watcher shares it
+pub struct DeviceWatcher {
+    api: Arc<Mutex<HidApi>>,
+    last_poll: Instant,
+    seen: u32,
+    missing: u32,
+}
+
+impl DeviceWatcher {
+    pub fn new(api: Arc<Mutex<HidApi>>) -> DeviceWatcher {
+        DeviceWatcher {
+            api,
+            last_poll: Instant::now(),
+            seen: 0,
+            missing: 0,
+        }
+    }
+}
+
+impl DeviceSource for DeviceWatcher {
+    fn open(&self, kb_config: &KBConfig) -> Option<Box<dyn HidTransport + Send>> {
+        open_device(&self.api.lock().unwrap(), kb_config)
+            .map(|device| Box::new(device) as Box<dyn HidTransport + Send>)
+    }
+
+    fn poll(&mut self, kb_config: &KBConfig, connected: bool) -> Option<DeviceEvent> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return None;
+        }
+        self.last_poll = Instant::now();
+
+        let mut api = self.api.lock().unwrap();
+        if let Err(err) = api.refresh_devices() {
+            debug!("could not refresh hid devices: {}", err);
+            return None;
+        }
+
+        if find_device(&api, kb_config).is_some() {
+            self.missing = 0;
+            self.seen += 1;
+
+            if !connected && self.seen == 1 {
+                debug!("device showed up, opening it once it's stayed a moment");
+            }
+            if !connected && self.seen >= DEBOUNCE_POLLS {
+                debug!("opening the device");
+                return open_device(&api, kb_config)
+                    .map(|device| DeviceEvent::Connected(Box::new(device)));
+            }
+        } else {
+            self.seen = 0;
+            self.missing += 1;
+            if connected && self.missing == 1 {
+                debug!("device is missing from the device list");
+            }
+
+            if connected && self.missing >= DEBOUNCE_POLLS {
+                info!("device unplugged");
+                return Some(DeviceEvent::Disconnected);
+            }
+        }
+
+        None
+    }
+}
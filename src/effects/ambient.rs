@@ -0,0 +1,66 @@
+use super::{blend, EffectContext, LedEffect, LedState};
+use crate::screen::{self, ScreenColors};
+use serde::Deserialize;
+
+// tints the board with the screen's colors, like ambient tv lighting. each key takes the color
+// of the screen region at the same spot on the board, with 1x1 regions filling the whole board
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct AmbientEffect {
+    pub columns: usize,
+    pub rows: usize,
+    // screen samples per second
+    pub rate: f32,
+    // seconds to fade most of the way to a new color
+    pub fade: f32,
+    #[serde(skip)]
+    colors: Option<ScreenColors>,
+}
+
+impl Default for AmbientEffect {
+    fn default() -> Self {
+        AmbientEffect {
+            columns: 1,
+            rows: 1,
+            rate: 5.0,
+            fade: 0.3,
+            colors: None,
+        }
+    }
+}
+
+impl LedEffect for AmbientEffect {
+    fn name(&self) -> &'static str {
+        "ambient"
+    }
+
+    fn update(&mut self, delta: f32, state: &mut [LedState], ctx: &EffectContext) {
+        let (columns, rows) = (self.columns.max(1), self.rows.max(1));
+        let rate = self.rate.max(0.1);
+
+        // capture only starts once the effect actually runs
+        let colors = self
+            .colors
+            .get_or_insert_with(|| screen::spawn_sampler(columns, rows, rate));
+        let colors = colors.lock().unwrap().clone();
+
+        let amount = if self.fade > 0.0 {
+            (delta / self.fade).min(1.0)
+        } else {
+            1.0
+        };
+
+        for led in state {
+            let key = match led.key {
+                Some(key) => key,
+                None => continue,
+            };
+            let (x, y) = ctx.kb_config.relative_position(key);
+
+            let col = ((x * columns as f32) as usize).min(columns - 1);
+            let row = ((y * rows as f32) as usize).min(rows - 1);
+
+            led.color = blend(led.color, colors[row * columns + col], amount);
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use super::{EffectContext, LedEffect, LedState};
+use crate::audio::{self, AudioLevels};
+use palette::Hsva;
+use serde::Deserialize;
+use std::sync::Arc;
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioDisplay {
+    // the whole board pulses with the loudness
+    Level,
+    // bass on the left to treble on the right, each a bar rising from the bottom
+    Spectrum,
+}
+
+// drives the board from what's playing, or from the microphone with a different source
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct AudioEffect {
+    pub display: AudioDisplay,
+    // pulse source to record, e.g. a microphone's name from `pactl list short sources`
+    pub source: String,
+    pub bands: usize,
+    // levels are multiplied by this before they're shown
+    pub gain: f32,
+    // seconds to follow most of the way to a new level, higher flickers less
+    pub smoothing: f32,
+    pub hue: f32,
+    #[serde(skip)]
+    levels: Option<Arc<AudioLevels>>,
+    // rms first, then the bands, after gain and smoothing
+    #[serde(skip)]
+    shown: Vec<f32>,
+}
+
+impl Default for AudioEffect {
+    fn default() -> Self {
+        AudioEffect {
+            display: AudioDisplay::Spectrum,
+            source: "@DEFAULT_MONITOR@".to_string(),
+            bands: 12,
+            gain: 4.0,
+            smoothing: 0.1,
+            hue: 280.0,
+            levels: None,
+            shown: Vec::new(),
+        }
+    }
+}
+
+impl LedEffect for AudioEffect {
+    fn name(&self) -> &'static str {
+        "audio"
+    }
+
+    fn reset(&mut self) {
+        self.shown.clear();
+    }
+
+    fn update(&mut self, delta: f32, state: &mut [LedState], ctx: &EffectContext) {
+        let bands = self.bands.max(1);
+
+        // recording only starts once the effect actually runs
+        let levels = self
+            .levels
+            .get_or_insert_with(|| audio::spawn_capture(bands, &self.source));
+
+        let mut target = vec![levels.rms()];
+        target.extend(levels.bands());
+
+        self.shown.resize(target.len(), 0.0);
+        let amount = if self.smoothing > 0.0 {
+            (delta / self.smoothing).min(1.0)
+        } else {
+            1.0
+        };
+        for (shown, level) in self.shown.iter_mut().zip(target) {
+            let level = (level * self.gain).clamp(0.0, 1.0);
+            *shown += (level - *shown) * amount;
+        }
+
+        for led in state {
+            let key = match led.key {
+                Some(key) => key,
+                None => continue,
+            };
+
+            let value = match self.display {
+                AudioDisplay::Level => self.shown[0],
+                AudioDisplay::Spectrum => {
+                    let x = ctx.kb_config.relative_position(key).0;
+                    let height = 1.0 - ctx.kb_config.relative_position(key).1;
+                    let band = ((x * bands as f32) as usize).min(bands - 1);
+
+                    if height < self.shown[band + 1] {
+                        1.0
+                    } else {
+                        0.0
+                    }
+                }
+            };
+
+            led.color = Hsva::new(self.hue, 1.0, value, 1.0);
+        }
+    }
+}
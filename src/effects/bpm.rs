@@ -0,0 +1,151 @@
+use super::{easing, EffectContext, EffectParam, LedEffect, LedState};
+use palette::Hsva;
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+// taps further apart than this start counting the tempo over
+const TAP_RESET: Duration = Duration::from_secs(2);
+// how many of the last taps the tempo is averaged over
+const TAP_COUNT: usize = 8;
+
+// the whole board cycling hue in time with a tempo, flashing bright on every beat and fading
+// down to `floor` until the next. the tempo can be tapped in, see `LedEffect::tap`
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct BpmEffect {
+    pub bpm: f32,
+    // moves the beats later by this fraction of a beat, to line them up with the music
+    pub phase_offset: f32,
+    // hue degrees the board moves on per beat
+    pub hue_per_beat: f32,
+    // brightness just before a beat, 1 to not pulse at all
+    pub floor: f32,
+    // beats since the effect started, the fraction being how far into the current one
+    #[serde(skip)]
+    beats: f32,
+    #[serde(skip)]
+    taps: Vec<Instant>,
+}
+
+impl Default for BpmEffect {
+    fn default() -> Self {
+        BpmEffect {
+            bpm: 120.0,
+            phase_offset: 0.0,
+            hue_per_beat: 30.0,
+            floor: 0.3,
+            beats: 0.0,
+            taps: Vec::new(),
+        }
+    }
+}
+
+impl BpmEffect {
+    fn phase(&self) -> f32 {
+        self.beats - self.phase_offset
+    }
+
+    // full at the beat, falling off quickly so the beat reads as a flash
+    fn brightness(&self) -> f32 {
+        let into_beat = self.phase().rem_euclid(1.0);
+        let floor = self.floor.clamp(0.0, 1.0);
+        1.0 - (1.0 - floor) * easing::ease_out_cubic(into_beat)
+    }
+}
+
+impl LedEffect for BpmEffect {
+    fn name(&self) -> &'static str {
+        "bpm"
+    }
+
+    fn reset(&mut self) {
+        self.beats = 0.0;
+        self.taps.clear();
+    }
+
+    fn params(&self) -> Vec<EffectParam> {
+        vec![
+            EffectParam::new("bpm", self.bpm, 40.0..=240.0),
+            EffectParam::new("phase_offset", self.phase_offset, 0.0..=1.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            "bpm" => self.bpm = value,
+            "phase_offset" => self.phase_offset = value,
+            _ => return false,
+        }
+        true
+    }
+
+    // the tempo is the average time between the last taps, and every tap lands on a beat
+    fn tap(&mut self, at: Instant) -> bool {
+        if self
+            .taps
+            .last()
+            .is_some_and(|last| at.saturating_duration_since(*last) > TAP_RESET)
+        {
+            self.taps.clear();
+        }
+        self.taps.push(at);
+        if self.taps.len() > TAP_COUNT {
+            self.taps.remove(0);
+        }
+
+        if let [first, .., last] = self.taps[..] {
+            let interval = last.duration_since(first).as_secs_f32() / (self.taps.len() - 1) as f32;
+            if interval > 0.0 {
+                self.bpm = (60.0 / interval).clamp(20.0, 400.0);
+            }
+        }
+        self.beats = self.phase().round() + self.phase_offset;
+        true
+    }
+
+    fn update(&mut self, delta: f32, state: &mut [LedState], _ctx: &EffectContext) {
+        self.beats += delta * self.bpm.max(0.0) / 60.0;
+
+        let hue = (self.phase() * self.hue_per_beat).rem_euclid(360.0);
+        let color = Hsva::new(hue, 1.0, self.brightness(), 1.0);
+        for led in state {
+            led.color = color;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn taps_set_the_tempo() {
+        let mut effect = BpmEffect::default();
+        let start = Instant::now();
+        for beat in 0..4 {
+            effect.tap(start + Duration::from_millis(beat * 500 + 1));
+        }
+        assert!((effect.bpm - 120.0).abs() < 0.5);
+        // the taps land on beats
+        assert_eq!(effect.brightness(), 1.0);
+
+        // starting over after a pause
+        effect.tap(start + Duration::from_secs(10));
+        effect.tap(start + Duration::from_millis(10_750));
+        assert!((effect.bpm - 80.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn flashes_on_the_beat() {
+        let mut effect = BpmEffect {
+            floor: 0.2,
+            ..Default::default()
+        };
+        assert_eq!(effect.brightness(), 1.0);
+
+        effect.beats = 0.5;
+        assert!((effect.brightness() - 0.3).abs() < 1e-5);
+        effect.phase_offset = 0.5;
+        assert_eq!(effect.brightness(), 1.0);
+    }
+}
@@ -0,0 +1,65 @@
+use super::{easing::Easing, EffectContext, LedEffect, LedState};
+use crate::config;
+use palette::Hsva;
+use serde::Deserialize;
+use std::f32::consts::TAU;
+
+// the whole board fading in and out, all the way down to dark
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct BreathingEffect {
+    // seconds per breath
+    pub period_secs: f32,
+    // degrees
+    pub hue: f32,
+    pub saturation: f32,
+    // how each half of a breath ramps, the default following a sine
+    pub easing: Easing,
+    #[serde(skip)]
+    phase: f32,
+}
+
+impl Default for BreathingEffect {
+    fn default() -> Self {
+        BreathingEffect {
+            period_secs: 4.0,
+            hue: 200.0,
+            saturation: 1.0,
+            easing: Easing::EaseInOut,
+            phase: 0.0,
+        }
+    }
+}
+
+impl LedEffect for BreathingEffect {
+    fn name(&self) -> &'static str {
+        "breathing"
+    }
+
+    fn reset(&mut self) {
+        self.phase = 0.0;
+    }
+
+    // the brightness is the effect's own
+    fn set_color(&mut self, color: Hsva) -> Vec<(&'static str, serde_json::Value)> {
+        self.hue = color.hue.to_positive_degrees();
+        self.saturation = color.saturation;
+        vec![
+            ("hue", config::number(self.hue)),
+            ("saturation", config::number(self.saturation)),
+        ]
+    }
+
+    fn update(&mut self, delta: f32, state: &mut [LedState], _ctx: &EffectContext) {
+        // wrapped so the phase keeps its precision however long this runs
+        self.phase = (self.phase + delta * TAU / self.period_secs.max(0.01)) % TAU;
+
+        // up from dark and back down once per breath, halfway up when the phase starts
+        let cycle = (self.phase / TAU + 0.25) % 1.0;
+        let value = self.easing.apply(1.0 - (2.0 * cycle - 1.0).abs());
+
+        for led in state {
+            led.color = Hsva::new(self.hue, self.saturation, value, 1.0);
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use super::{blend, EffectContext, LedEffect, LedState};
+use crate::theme::{deserialize_hsva, ThemeColor};
+use egui::Color32;
+use log::warn;
+use palette::Hsva;
+use serde::Deserialize;
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+// shows the time as lit digit keys, hour digits in one color and minute digits in another
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ClockEffect {
+    pub twelve_hour: bool,
+    #[serde(deserialize_with = "deserialize_hsva")]
+    pub hour_color: Hsva,
+    #[serde(deserialize_with = "deserialize_hsva")]
+    pub minute_color: Hsva,
+    // the last seconds of every minute crossfade into the next one
+    pub transition_secs: f32,
+    // matrix positions of the keys for 0 through 9, otherwise found by their legend labels
+    pub digit_keys: Vec<(u8, u8)>,
+}
+
+impl Default for ClockEffect {
+    fn default() -> Self {
+        ClockEffect {
+            twelve_hour: false,
+            hour_color: ThemeColor(Color32::from_rgb(255, 120, 0)).to_hsva(),
+            minute_color: ThemeColor(Color32::from_rgb(0, 160, 255)).to_hsva(),
+            transition_secs: 2.0,
+            digit_keys: Vec::new(),
+        }
+    }
+}
+
+// hour and minute digits at this time of day, without a leading zero on the hour
+fn digits(secs_of_day: u64, twelve_hour: bool) -> Vec<(u8, bool)> {
+    let mut hour = secs_of_day / 3600 % 24;
+    let minute = secs_of_day / 60 % 60;
+
+    if twelve_hour {
+        hour = match hour % 12 {
+            0 => 12,
+            hour => hour,
+        };
+    }
+
+    let mut digits = Vec::with_capacity(4);
+    if hour >= 10 {
+        digits.push(((hour / 10) as u8, true));
+    }
+    digits.push(((hour % 10) as u8, true));
+    digits.push(((minute / 10) as u8, false));
+    digits.push(((minute % 10) as u8, false));
+    digits
+}
+
+// seconds since local midnight, falling back to utc where the offset isn't known
+fn local_secs_of_day() -> f64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    let offset = utc_offset(now.as_secs() as i64);
+    (now.as_secs_f64() + offset as f64).rem_euclid(SECS_PER_DAY as f64)
+}
+
+// seconds the local time is ahead of utc, utc with a warning the first time it can't be told
+pub(crate) fn utc_offset(unix_secs: i64) -> i64 {
+    static WARNED: AtomicBool = AtomicBool::new(false);
+
+    local_offset(unix_secs).unwrap_or_else(|| {
+        if !WARNED.swap(true, Ordering::Relaxed) {
+            warn!("local time zone unknown, clock and schedule times are in utc");
+        }
+        0
+    })
+}
+
+#[cfg(unix)]
+pub(crate) fn local_offset(unix_secs: i64) -> Option<i64> {
+    let time = unix_secs as libc::time_t;
+    // SAFETY: localtime_r only writes into the tm we hand it
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return None;
+    }
+
+    Some(tm.tm_gmtoff as i64)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn local_offset(_unix_secs: i64) -> Option<i64> {
+    None
+}
+
+impl ClockEffect {
+    // color of each digit key 0 through 9 at this time of day
+    fn digit_colors(&self, secs_of_day: u64) -> [Hsva; 10] {
+        let mut colors = [Hsva::new(0.0, 0.0, 0.0, 1.0); 10];
+        let mut lit = [(false, false); 10];
+
+        for (digit, is_hour) in digits(secs_of_day, self.twelve_hour) {
+            let lit = &mut lit[digit as usize];
+            if is_hour {
+                lit.0 = true;
+            } else {
+                lit.1 = true;
+            }
+        }
+
+        for (color, lit) in colors.iter_mut().zip(lit) {
+            *color = match lit {
+                (true, true) => blend(self.hour_color, self.minute_color, 0.5),
+                (true, false) => self.hour_color,
+                (false, true) => self.minute_color,
+                (false, false) => *color,
+            };
+        }
+
+        colors
+    }
+
+    fn digit_at(&self, led: &LedState) -> Option<usize> {
+        let key = led.key?;
+
+        if self.digit_keys.is_empty() {
+            key.label.parse().ok().filter(|digit| *digit < 10)
+        } else {
+            self.digit_keys.iter().position(|pos| *pos == key.matrix)
+        }
+    }
+}
+
+impl LedEffect for ClockEffect {
+    fn name(&self) -> &'static str {
+        "clock"
+    }
+
+    fn update(&mut self, _delta: f32, state: &mut [LedState], _ctx: &EffectContext) {
+        let now = local_secs_of_day();
+        let secs = now as u64;
+
+        let current = self.digit_colors(secs);
+        let next = self.digit_colors(secs - secs % 60 + 60);
+
+        // how far into the crossfade the current minute is
+        let left = 60.0 - now % 60.0;
+        let amount = (1.0 - left as f32 / self.transition_secs.max(0.01)).clamp(0.0, 1.0);
+
+        for led in state {
+            led.color = match self.digit_at(led) {
+                Some(digit) => blend(current[digit], next[digit], amount),
+                None => Hsva::new(0.0, 0.0, 0.0, 1.0),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(hour: u64, minute: u64) -> u64 {
+        hour * 3600 + minute * 60
+    }
+
+    #[test]
+    fn digits_24_hour() {
+        let digits = |secs| {
+            digits(secs, false)
+                .into_iter()
+                .map(|(d, _)| d)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(digits(at(13, 5)), [1, 3, 0, 5]);
+        assert_eq!(digits(at(0, 30)), [0, 3, 0]);
+        assert_eq!(digits(at(23, 59) + 59), [2, 3, 5, 9]);
+    }
+
+    #[test]
+    fn digits_12_hour() {
+        let digits = |secs| {
+            digits(secs, true)
+                .into_iter()
+                .map(|(d, _)| d)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(digits(at(13, 5)), [1, 0, 5]);
+        assert_eq!(digits(at(0, 30)), [1, 2, 3, 0]);
+        assert_eq!(digits(at(12, 0)), [1, 2, 0, 0]);
+    }
+
+    #[test]
+    fn shared_digits_blend() {
+        let effect = ClockEffect::default();
+        // 11:11 has both colors on the 1 key
+        let colors = effect.digit_colors(at(11, 11));
+
+        assert_eq!(colors[0].value, 0.0);
+        assert!(colors[1].value > 0.0);
+        assert_ne!(colors[1].hue, effect.hour_color.hue);
+        assert_ne!(colors[1].hue, effect.minute_color.hue);
+    }
+}
@@ -0,0 +1,68 @@
+use super::{EffectContext, LedEffect, LedState};
+use palette::Hsva;
+use serde::Deserialize;
+use std::time::Instant;
+
+// for tuning debounce: colors every key by how long its last press lasted, from blue for short
+// presses to green for long ones. presses shorter than `chatter_ms` are likely chatter and
+// blink red for a while.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct DebounceVizEffect {
+    pub chatter_ms: f32,
+    // presses this long or longer are fully green
+    pub max_dwell_ms: f32,
+    pub flash_secs: f32,
+}
+
+impl Default for DebounceVizEffect {
+    fn default() -> Self {
+        DebounceVizEffect {
+            chatter_ms: 15.0,
+            max_dwell_ms: 200.0,
+            flash_secs: 2.0,
+        }
+    }
+}
+
+impl LedEffect for DebounceVizEffect {
+    fn name(&self) -> &'static str {
+        "debounce_viz"
+    }
+
+    fn update(&mut self, _delta: f32, state: &mut [LedState], ctx: &EffectContext) {
+        let now = Instant::now();
+
+        for led in state {
+            let (row, col) = match led.key {
+                Some(key) => key.matrix,
+                None => continue,
+            };
+            let key_state = &ctx.key_state[row as usize][col as usize];
+
+            let down = match key_state.last_down {
+                Some(down) => down,
+                None => {
+                    led.color = Hsva::new(0.0, 0.0, 0.0, 1.0);
+                    continue;
+                }
+            };
+
+            // a key that's still held is measured up to now
+            let up = match key_state.last_up {
+                Some(up) if !key_state.is_pressed && up >= down => up,
+                _ => now,
+            };
+            let dwell_ms = (up - down).as_secs_f32() * 1000.0;
+
+            let released_secs = (now - up).as_secs_f32();
+            led.color = if dwell_ms < self.chatter_ms && released_secs < self.flash_secs {
+                let on = (released_secs * 4.0).fract() < 0.5;
+                Hsva::new(0.0, 1.0, if on { 1.0 } else { 0.0 }, 1.0)
+            } else {
+                let t = (dwell_ms / self.max_dwell_ms.max(1.0)).min(1.0);
+                Hsva::new(240.0 - 120.0 * t, 1.0, 1.0, 1.0)
+            };
+        }
+    }
+}
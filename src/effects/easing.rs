@@ -0,0 +1,135 @@
+use serde::Deserialize;
+use std::f32::consts::PI;
+
+// ramps for fades and pulses. every one takes how far along it is, 0-1, and goes from 0 at the
+// start to 1 at the end. t outside 0-1 is clamped
+
+pub fn linear(t: f32) -> f32 {
+    t.clamp(0.0, 1.0)
+}
+
+pub fn ease_in(t: f32) -> f32 {
+    linear(t).powi(2)
+}
+
+pub fn ease_out(t: f32) -> f32 {
+    1.0 - (1.0 - linear(t)).powi(2)
+}
+
+// along a half cosine, so it starts and ends at rest like a breath
+pub fn ease_in_out(t: f32) -> f32 {
+    (1.0 - (PI * linear(t)).cos()) / 2.0
+}
+
+pub fn ease_in_cubic(t: f32) -> f32 {
+    linear(t).powi(3)
+}
+
+pub fn ease_out_cubic(t: f32) -> f32 {
+    1.0 - (1.0 - linear(t)).powi(3)
+}
+
+pub fn ease_in_out_cubic(t: f32) -> f32 {
+    let t = linear(t);
+    if t < 0.5 {
+        4.0 * t.powi(3)
+    } else {
+        1.0 - (2.0 - 2.0 * t).powi(3) / 2.0
+    }
+}
+
+// falls onto 1 and bounces off it a few times, each bounce lower
+pub fn bounce(t: f32) -> f32 {
+    const N: f32 = 7.5625;
+    const D: f32 = 2.75;
+
+    let t = linear(t);
+    if t < 1.0 / D {
+        N * t * t
+    } else if t < 2.0 / D {
+        let t = t - 1.5 / D;
+        N * t * t + 0.75
+    } else if t < 2.5 / D {
+        let t = t - 2.25 / D;
+        N * t * t + 0.9375
+    } else {
+        let t = t - 2.625 / D;
+        N * t * t + 0.984375
+    }
+}
+
+// one of the above by name, for effects to take an `easing` in their params
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Easing {
+    #[default]
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+    EaseInCubic,
+    EaseOutCubic,
+    EaseInOutCubic,
+    Bounce,
+}
+
+impl Easing {
+    pub fn apply(self, t: f32) -> f32 {
+        match self {
+            Easing::Linear => linear(t),
+            Easing::EaseIn => ease_in(t),
+            Easing::EaseOut => ease_out(t),
+            Easing::EaseInOut => ease_in_out(t),
+            Easing::EaseInCubic => ease_in_cubic(t),
+            Easing::EaseOutCubic => ease_out_cubic(t),
+            Easing::EaseInOutCubic => ease_in_out_cubic(t),
+            Easing::Bounce => bounce(t),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Easing; 8] = [
+        Easing::Linear,
+        Easing::EaseIn,
+        Easing::EaseOut,
+        Easing::EaseInOut,
+        Easing::EaseInCubic,
+        Easing::EaseOutCubic,
+        Easing::EaseInOutCubic,
+        Easing::Bounce,
+    ];
+
+    fn samples() -> impl Iterator<Item = f32> {
+        (0..=100).map(|step| step as f32 / 100.0)
+    }
+
+    #[test]
+    fn start_at_0_and_end_at_1() {
+        for easing in ALL {
+            assert!(easing.apply(0.0).abs() < 1e-6, "{:?}", easing);
+            assert!((easing.apply(1.0) - 1.0).abs() < 1e-6, "{:?}", easing);
+
+            // clamped past the ends
+            assert_eq!(easing.apply(-1.0), easing.apply(0.0), "{:?}", easing);
+            assert_eq!(easing.apply(2.0), easing.apply(1.0), "{:?}", easing);
+        }
+    }
+
+    #[test]
+    fn only_bounce_turns_back() {
+        for easing in ALL {
+            let monotonic = samples()
+                .zip(samples().skip(1))
+                .all(|(a, b)| easing.apply(b) >= easing.apply(a) - 1e-6);
+            assert_eq!(monotonic, easing != Easing::Bounce, "{:?}", easing);
+        }
+
+        assert!(samples().all(|t| (0.0..=1.0 + 1e-6).contains(&bounce(t))));
+        assert!((ease_in_out(0.5) - 0.5).abs() < 1e-6);
+        assert!((ease_in_out_cubic(0.5) - 0.5).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,134 @@
+use super::{EffectContext, LedEffect, LedState};
+use crate::config::QMKKey;
+use palette::Hsva;
+use serde::Deserialize;
+
+// colors every key by the finger that should press it in touch typing
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct FingerZoneEffect {
+    // key center x positions (in key units) where one finger's zone ends and the next begins,
+    // left pinky through right pinky. the default fits a row-staggered ansi board
+    pub boundaries: [f32; 7],
+    // matrix rows pressed with the thumbs, otherwise the bottom row of the layout
+    pub thumb_rows: Vec<u8>,
+    // pinky, ring, middle, index and thumb, the same on both hands
+    pub hues: [f32; 5],
+    // the right hand is drawn at this brightness, to tell the hands apart
+    pub right_value: f32,
+}
+
+impl Default for FingerZoneEffect {
+    fn default() -> Self {
+        FingerZoneEffect {
+            boundaries: [2.75, 3.75, 4.75, 6.75, 8.75, 9.75, 10.75],
+            thumb_rows: Vec::new(),
+            hues: [0.0, 35.0, 60.0, 130.0, 220.0],
+            right_value: 0.6,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Hand {
+    Left,
+    Right,
+}
+
+impl FingerZoneEffect {
+    // the hand and finger (pinky 0 through thumb 4) for a key
+    fn zone(&self, key: &QMKKey, width: f32, height: f32) -> (Hand, usize) {
+        let x = key.x + key.w / 2.0;
+        let y = key.y + key.h / 2.0;
+
+        let thumb = if self.thumb_rows.is_empty() {
+            y > height - 1.0
+        } else {
+            self.thumb_rows.contains(&key.matrix.0)
+        };
+        if thumb {
+            let hand = if x < width / 2.0 {
+                Hand::Left
+            } else {
+                Hand::Right
+            };
+            return (hand, 4);
+        }
+
+        // zones 0-3 are the left pinky to index, 4-7 the right index to pinky
+        let zone = self.boundaries.iter().filter(|cut| x >= **cut).count();
+        if zone < 4 {
+            (Hand::Left, zone)
+        } else {
+            (Hand::Right, 7 - zone)
+        }
+    }
+}
+
+impl LedEffect for FingerZoneEffect {
+    fn name(&self) -> &'static str {
+        "finger_zone"
+    }
+
+    fn update(&mut self, _delta: f32, state: &mut [LedState], ctx: &EffectContext) {
+        let (width, height) = (ctx.kb_config.width(), ctx.kb_config.height());
+
+        for led in state {
+            let key = match led.key {
+                Some(key) => key,
+                None => continue,
+            };
+            let (hand, finger) = self.zone(key, width, height);
+            let value = match hand {
+                Hand::Left => 1.0,
+                Hand::Right => self.right_value,
+            };
+
+            led.color = Hsva::new(self.hues[finger], 1.0, value, 1.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(label: &str, x: f32, y: f32, w: f32) -> QMKKey {
+        QMKKey {
+            label: label.to_string(),
+            x,
+            y,
+            w,
+            h: 1.0,
+            matrix: (y as u8, 0),
+            r: 0.0,
+            rx: 0.0,
+            ry: 0.0,
+        }
+    }
+
+    #[test]
+    fn ansi_home_row() {
+        let effect = FingerZoneEffect::default();
+        let zone = |key: QMKKey| effect.zone(&key, 15.0, 5.0);
+
+        assert_eq!(zone(key("A", 1.75, 2.0, 1.0)), (Hand::Left, 0));
+        assert_eq!(zone(key("F", 4.75, 2.0, 1.0)), (Hand::Left, 3));
+        assert_eq!(zone(key("G", 5.75, 2.0, 1.0)), (Hand::Left, 3));
+        assert_eq!(zone(key("J", 7.75, 2.0, 1.0)), (Hand::Right, 3));
+        assert_eq!(zone(key("L", 9.75, 2.0, 1.0)), (Hand::Right, 1));
+        assert_eq!(zone(key("Enter", 12.75, 2.0, 2.25)), (Hand::Right, 0));
+        assert_eq!(zone(key("Space", 3.75, 4.0, 6.25)), (Hand::Left, 4));
+    }
+
+    #[test]
+    fn configured_thumb_rows() {
+        let effect = FingerZoneEffect {
+            thumb_rows: vec![3],
+            ..Default::default()
+        };
+
+        assert_eq!(effect.zone(&key("Space", 8.0, 3.0, 1.0), 15.0, 5.0).1, 4);
+        assert_eq!(effect.zone(&key("Ctrl", 0.0, 4.0, 1.0), 15.0, 5.0).1, 0);
+    }
+}
@@ -0,0 +1,189 @@
+use super::{EffectContext, LedEffect, LedState, PressTracker};
+use palette::Hsva;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use serde::Deserialize;
+
+// how far (in key units) a particle lights up around itself
+const PARTICLE_RADIUS: f32 = 0.8;
+
+// distance from the board edge (in key units) over which particles fade out
+const EDGE_FADE: f32 = 1.0;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FireworksTrigger {
+    // every nth keypress anywhere on the board
+    EveryNth(u32),
+    // every press of the key at this matrix position
+    Key(u8, u8),
+}
+
+struct Particle {
+    position: (f32, f32),
+    velocity: (f32, f32),
+    age: f32,
+    color: Hsva,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct FireworksEffect {
+    pub trigger: FireworksTrigger,
+    pub particle_count: usize,
+    // key units per second squared, pulling particles down the board
+    pub gravity: f32,
+    // seconds a particle lives for
+    pub lifetime: f32,
+    // key units per second
+    pub speed: f32,
+    #[serde(skip)]
+    particles: Vec<Particle>,
+    #[serde(skip)]
+    presses: PressTracker,
+    #[serde(skip)]
+    press_count: u32,
+    #[serde(skip, default = "rng")]
+    rng: SmallRng,
+}
+
+impl Default for FireworksEffect {
+    fn default() -> Self {
+        FireworksEffect {
+            trigger: FireworksTrigger::EveryNth(10),
+            particle_count: 16,
+            gravity: 3.0,
+            lifetime: 1.2,
+            speed: 5.0,
+            particles: Vec::new(),
+            presses: PressTracker::default(),
+            press_count: 0,
+            rng: rng(),
+        }
+    }
+}
+
+fn rng() -> SmallRng {
+    SmallRng::seed_from_u64(0)
+}
+
+impl FireworksEffect {
+    fn launch(&mut self, origin: (f32, f32)) {
+        let hue = self.rng.gen_range(0.0..360.0);
+
+        for _ in 0..self.particle_count {
+            let angle = self.rng.gen_range(0.0..std::f32::consts::TAU);
+            let speed = self.speed * self.rng.gen_range(0.5..1.0);
+
+            self.particles.push(Particle {
+                position: origin,
+                velocity: (angle.cos() * speed, angle.sin() * speed),
+                age: 0.0,
+                color: Hsva::new(hue + self.rng.gen_range(-20.0..20.0), 1.0, 1.0, 1.0),
+            });
+        }
+    }
+}
+
+impl LedEffect for FireworksEffect {
+    fn name(&self) -> &'static str {
+        "fireworks"
+    }
+
+    fn reset(&mut self) {
+        self.particles.clear();
+        self.press_count = 0;
+    }
+
+    fn update(&mut self, delta: f32, state: &mut [LedState], ctx: &EffectContext) {
+        for (row, col) in self.presses.poll(ctx.key_state) {
+            self.press_count += 1;
+
+            let triggered = match self.trigger {
+                FireworksTrigger::EveryNth(n) => n > 0 && self.press_count.is_multiple_of(n),
+                FireworksTrigger::Key(t_row, t_col) => (row, col) == (t_row, t_col),
+            };
+
+            if let (true, Some(origin)) = (triggered, ctx.key_center(row, col)) {
+                self.launch(origin);
+            }
+        }
+
+        // simulate
+        for particle in &mut self.particles {
+            particle.velocity.1 += self.gravity * delta;
+            particle.position.0 += particle.velocity.0 * delta;
+            particle.position.1 += particle.velocity.1 * delta;
+            particle.age += delta;
+        }
+
+        let lifetime = self.lifetime;
+        self.particles.retain(|particle| particle.age < lifetime);
+
+        // render
+        let (width, height) = (ctx.kb_config.width(), ctx.kb_config.height());
+
+        for led in state {
+            let key = match led.key {
+                Some(key) => key,
+                None => continue,
+            };
+            let center = ctx.kb_config.key_position(key);
+
+            // unlit keys stay see-through, so the effects below show between the sparks
+            let mut brightest = Hsva::new(0.0, 0.0, 0.0, 0.0);
+
+            for particle in &self.particles {
+                let (px, py) = particle.position;
+                let dist = ((px - center.0).powi(2) + (py - center.1).powi(2)).sqrt();
+                if dist > PARTICLE_RADIUS {
+                    continue;
+                }
+
+                // fade out near (and past) the edges instead of wrapping around
+                let edge_dist = px.min(width - px).min(py).min(height - py);
+                let edge = (edge_dist / EDGE_FADE).clamp(0.0, 1.0);
+
+                let life = 1.0 - particle.age / self.lifetime;
+                let value = (1.0 - dist / PARTICLE_RADIUS) * life * edge;
+
+                if value > brightest.alpha {
+                    brightest =
+                        Hsva::new(particle.color.hue, particle.color.saturation, 1.0, value);
+                }
+            }
+
+            led.color = brightest;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::tests::kb_config_2x2, threading::KeyState};
+    use std::time::Instant;
+
+    #[test]
+    fn unlit_keys_stay_transparent() {
+        let kb_config = kb_config_2x2();
+        let mut matrix = vec![vec![KeyState::default(); 2]; 2];
+        let mut effect = FireworksEffect {
+            trigger: FireworksTrigger::EveryNth(1),
+            ..Default::default()
+        };
+        let mut state = crate::threading::led_state(&kb_config);
+        let mut update = |matrix: &[Vec<KeyState>], state: &mut [LedState]| {
+            let ctx = EffectContext::at_rest(matrix, &kb_config);
+            effect.update(0.001, state, &ctx);
+        };
+
+        update(&matrix, &mut state);
+        assert!(state.iter().all(|led| led.color.alpha == 0.0));
+
+        // a burst on A, too fresh to have reached the other keys
+        matrix[0][0].last_down = Some(Instant::now());
+        update(&matrix, &mut state);
+        assert!(state[0].color.alpha > 0.0);
+        assert!(state[1..].iter().all(|led| led.color.alpha == 0.0));
+    }
+}
@@ -0,0 +1,66 @@
+use super::{EffectContext, LedEffect, LedState};
+use crate::theme::deserialize_hsva;
+use palette::Hsva;
+use serde::Deserialize;
+use std::time::Duration;
+
+// lights the whole board and fades out once, then removes itself
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct FlashEffect {
+    #[serde(deserialize_with = "deserialize_hsva")]
+    pub color: Hsva,
+    pub duration_ms: f32,
+    #[serde(skip)]
+    elapsed_ms: f32,
+}
+
+impl Default for FlashEffect {
+    fn default() -> Self {
+        FlashEffect {
+            color: Hsva::new(0.0, 0.0, 1.0, 1.0),
+            duration_ms: 300.0,
+            elapsed_ms: 0.0,
+        }
+    }
+}
+
+impl FlashEffect {
+    pub fn new(color: Hsva, duration: Duration) -> Self {
+        FlashEffect {
+            color,
+            duration_ms: duration.as_secs_f32() * 1000.0,
+            ..Default::default()
+        }
+    }
+}
+
+impl LedEffect for FlashEffect {
+    fn name(&self) -> &'static str {
+        "flash"
+    }
+
+    fn reset(&mut self) {
+        self.elapsed_ms = 0.0;
+    }
+
+    fn update(&mut self, delta: f32, state: &mut [LedState], _ctx: &EffectContext) {
+        let t = self.elapsed_ms / self.duration_ms.max(1.0);
+        let alpha = self.color.alpha * (1.0 - t).clamp(0.0, 1.0);
+
+        for led in state {
+            led.color = Hsva::new(
+                self.color.hue,
+                self.color.saturation,
+                self.color.value,
+                alpha,
+            );
+        }
+
+        self.elapsed_ms += delta * 1000.0;
+    }
+
+    fn finished(&self) -> bool {
+        self.elapsed_ms >= self.duration_ms
+    }
+}
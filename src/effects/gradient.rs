@@ -0,0 +1,155 @@
+use super::{EffectContext, LedEffect, LedState};
+use crate::theme::ThemeColor;
+use palette::{Hsva, Mix};
+use serde::{Deserialize, Deserializer};
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum GradientAxis {
+    X,
+    Y,
+    LedIndex,
+}
+
+// a fixed gradient over the board. stops are (position, color) with positions in 0-1 along the
+// axis, and colors in between are mixed in hsv
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct GradientEffect {
+    #[serde(deserialize_with = "deserialize_stops")]
+    stops: Vec<(f32, Hsva)>,
+    axis: GradientAxis,
+}
+
+// [[position, [r, g, b]], ...]
+fn deserialize_stops<'de, D>(deserializer: D) -> Result<Vec<(f32, Hsva)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut stops: Vec<_> = Vec::<(f32, ThemeColor)>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(position, color)| (position, color.to_hsva()))
+        .collect();
+
+    sort_stops(&mut stops);
+    Ok(stops)
+}
+
+fn sort_stops(stops: &mut [(f32, Hsva)]) {
+    stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+}
+
+impl Default for GradientEffect {
+    fn default() -> Self {
+        GradientEffect::new(
+            vec![
+                (0.0, Hsva::new(280.0, 1.0, 1.0, 1.0)),
+                (1.0, Hsva::new(180.0, 1.0, 1.0, 1.0)),
+            ],
+            GradientAxis::X,
+        )
+    }
+}
+
+impl GradientEffect {
+    pub fn new(mut stops: Vec<(f32, Hsva)>, axis: GradientAxis) -> GradientEffect {
+        sort_stops(&mut stops);
+        GradientEffect { stops, axis }
+    }
+
+    // positions before the first stop or after the last take that stop's color
+    fn sample(&self, position: f32) -> Hsva {
+        let (first, last) = match (self.stops.first(), self.stops.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Hsva::new(0.0, 0.0, 0.0, 1.0),
+        };
+
+        if position <= first.0 {
+            return first.1;
+        }
+        if position >= last.0 {
+            return last.1;
+        }
+
+        let next = self
+            .stops
+            .iter()
+            .position(|stop| stop.0 >= position)
+            .unwrap_or(self.stops.len() - 1);
+        let (start, end) = (self.stops[next - 1], self.stops[next]);
+
+        let span = end.0 - start.0;
+        let t = if span > 0.0 {
+            (position - start.0) / span
+        } else {
+            1.0
+        };
+
+        start.1.mix(&end.1, t)
+    }
+}
+
+impl LedEffect for GradientEffect {
+    fn name(&self) -> &'static str {
+        "gradient"
+    }
+
+    fn update(&mut self, _delta: f32, state: &mut [LedState], ctx: &EffectContext) {
+        let last_idx = (state.len().max(2) - 1) as f32;
+
+        for (idx, led) in state.iter_mut().enumerate() {
+            // leds without a key still have an index, underglow included
+            let position = match (self.axis, led.key) {
+                (GradientAxis::X, Some(key)) => ctx.kb_config.relative_position(key).0,
+                (GradientAxis::Y, Some(key)) => ctx.kb_config.relative_position(key).1,
+                (GradientAxis::LedIndex, _) => idx as f32 / last_idx,
+                (_, None) => continue,
+            };
+
+            led.color = self.sample(position);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn red() -> Hsva {
+        Hsva::new(0.0, 1.0, 1.0, 1.0)
+    }
+
+    fn blue() -> Hsva {
+        Hsva::new(240.0, 1.0, 1.0, 1.0)
+    }
+
+    #[test]
+    fn clamps_to_endpoints() {
+        let gradient = GradientEffect::new(vec![(0.75, blue()), (0.25, red())], GradientAxis::X);
+
+        assert_eq!(gradient.sample(0.0), red());
+        assert_eq!(gradient.sample(0.25), red());
+        assert_eq!(gradient.sample(1.0), blue());
+    }
+
+    #[test]
+    fn interpolates_between_stops() {
+        let gradient = GradientEffect::new(
+            vec![(0.0, Hsva::new(0.0, 0.0, 0.0, 1.0)), (1.0, red())],
+            GradientAxis::X,
+        );
+
+        let middle = gradient.sample(0.5);
+        assert!((middle.value - 0.5).abs() < 1e-5);
+        assert!((middle.saturation - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn single_stop_is_static() {
+        let gradient = GradientEffect::new(vec![(0.5, blue())], GradientAxis::Y);
+
+        for position in [0.0, 0.5, 1.0] {
+            assert_eq!(gradient.sample(position), blue());
+        }
+    }
+}
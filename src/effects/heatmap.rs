@@ -0,0 +1,67 @@
+use super::{EffectContext, LedEffect, LedState, PressTracker};
+use palette::Hsva;
+use serde::Deserialize;
+
+// colors keys from blue to red by how much they've been pressed lately
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct HeatmapEffect {
+    // seconds for a key's count to decay to half, so the map follows recent typing
+    pub half_life_secs: f32,
+    // presses are counted as floats so they can decay smoothly
+    #[serde(skip)]
+    counts: Vec<Vec<f32>>,
+    #[serde(skip)]
+    presses: PressTracker,
+}
+
+impl Default for HeatmapEffect {
+    fn default() -> Self {
+        HeatmapEffect {
+            half_life_secs: 120.0,
+            counts: Vec::new(),
+            presses: PressTracker::default(),
+        }
+    }
+}
+
+impl LedEffect for HeatmapEffect {
+    fn name(&self) -> &'static str {
+        "heatmap"
+    }
+
+    fn reset(&mut self) {
+        self.counts.clear();
+    }
+
+    fn update(&mut self, delta: f32, state: &mut [LedState], ctx: &EffectContext) {
+        if self.counts.is_empty() {
+            self.counts = ctx
+                .key_state
+                .iter()
+                .map(|row| vec![0.0; row.len()])
+                .collect();
+        }
+
+        let decay = 0.5f32.powf(delta / self.half_life_secs.max(0.01));
+        for count in self.counts.iter_mut().flatten() {
+            *count *= decay;
+        }
+
+        for (row, col) in self.presses.poll(ctx.key_state) {
+            self.counts[row as usize][col as usize] += 1.0;
+        }
+
+        let max = self.counts.iter().flatten().copied().fold(1.0, f32::max);
+
+        for led in state {
+            let (row, col) = match led.key {
+                Some(key) => key.matrix,
+                None => continue,
+            };
+            let heat = self.counts[row as usize][col as usize] / max;
+
+            led.color = Hsva::new(240.0 * (1.0 - heat), 1.0, 1.0, 1.0);
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use super::{build_effect, EffectContext, EffectParam, LedEffect, LedState};
+use crate::config::EffectConfig;
+use log::warn;
+use palette::Hsva;
+use serde::Deserialize;
+use std::time::Instant;
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct IdleDimParams {
+    effect: Option<EffectConfig>,
+    timeout_secs: f32,
+    floor: f32,
+    fade_secs: f32,
+}
+
+impl Default for IdleDimParams {
+    fn default() -> Self {
+        IdleDimParams {
+            effect: None,
+            timeout_secs: 60.0,
+            floor: 0.1,
+            fade_secs: 1.5,
+        }
+    }
+}
+
+// runs another effect and dims it once nothing has been pressed for a while
+pub struct IdleDimEffect {
+    inner: Box<dyn LedEffect>,
+    // seconds without a press before dimming
+    pub timeout_secs: f32,
+    // brightness scale when idle
+    pub floor: f32,
+    // seconds to go from full to the floor
+    pub fade_secs: f32,
+    level: f32,
+    started: Instant,
+    // what the inner effect drew last frame, before dimming
+    undimmed: Vec<Hsva>,
+}
+
+impl IdleDimEffect {
+    pub fn new(inner: Box<dyn LedEffect>, timeout_secs: f32, floor: f32) -> IdleDimEffect {
+        IdleDimEffect {
+            inner,
+            timeout_secs,
+            floor,
+            fade_secs: IdleDimParams::default().fade_secs,
+            level: 1.0,
+            started: Instant::now(),
+            undimmed: Vec::new(),
+        }
+    }
+
+    // {"effect": {"name": ..., "params": ...}, "timeout_secs": ..., "floor": ..., "fade_secs": ...}
+    pub fn from_params(params: &serde_json::Value) -> Option<Box<dyn LedEffect>> {
+        let params = if params.is_null() {
+            IdleDimParams::default()
+        } else {
+            match IdleDimParams::deserialize(params) {
+                Ok(params) => params,
+                Err(err) => {
+                    warn!("invalid idle_dim params {}: {}", params, err);
+                    return None;
+                }
+            }
+        };
+
+        let inner = match &params.effect {
+            Some(effect) => build_effect(&effect.name, &effect.params)?,
+            None => {
+                warn!("idle_dim needs an effect to wrap");
+                return None;
+            }
+        };
+
+        let mut effect = IdleDimEffect::new(inner, params.timeout_secs, params.floor);
+        effect.fade_secs = params.fade_secs;
+        Some(Box::new(effect))
+    }
+}
+
+impl LedEffect for IdleDimEffect {
+    fn name(&self) -> &'static str {
+        "idle_dim"
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset();
+        self.level = 1.0;
+        self.started = Instant::now();
+        self.undimmed.clear();
+    }
+
+    fn set_color(&mut self, color: Hsva) -> Vec<(&'static str, serde_json::Value)> {
+        self.inner.set_color(color)
+    }
+
+    fn set_text(&mut self, text: &str) -> bool {
+        self.inner.set_text(text)
+    }
+
+    fn tap(&mut self, at: Instant) -> bool {
+        self.inner.tap(at)
+    }
+
+    fn params(&self) -> Vec<EffectParam> {
+        self.inner.params()
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        self.inner.set_param(name, value)
+    }
+
+    fn update(&mut self, delta: f32, state: &mut [LedState], ctx: &EffectContext) {
+        // effects that build on the last frame shouldn't see it dimmed
+        if self.undimmed.len() == state.len() {
+            for (led, color) in state.iter_mut().zip(&self.undimmed) {
+                led.color = *color;
+            }
+        }
+
+        self.inner.update(delta, state, ctx);
+
+        self.undimmed.clear();
+        self.undimmed.extend(state.iter().map(|led| led.color));
+
+        // until the first press, idle time counts from startup
+        let last_active = ctx
+            .key_state
+            .iter()
+            .flatten()
+            .filter_map(|key| key.last_pressed)
+            .max()
+            .unwrap_or(self.started);
+        let pressed = ctx.key_state.iter().flatten().any(|key| key.is_pressed);
+
+        let idle = !pressed && last_active.elapsed().as_secs_f32() >= self.timeout_secs;
+
+        // dimming fades, waking up is instant
+        self.level = if idle {
+            let step = delta * (1.0 - self.floor) / self.fade_secs.max(0.01);
+            (self.level - step).max(self.floor)
+        } else {
+            1.0
+        };
+
+        for led in state {
+            led.color.value *= self.level;
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use super::{EffectContext, EffectParam, LedEffect, LedState};
+use log::warn;
+use palette::{IntoColor, Srgba};
+use serde::Deserialize;
+use std::{fs::File, io::Read};
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageFilter {
+    Nearest,
+    Bilinear,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct ImageParams {
+    // a png, relative to the working directory unless absolute
+    path: Option<String>,
+    filter: ImageFilter,
+    // image widths and heights per second, wrapping around
+    scroll_x: f32,
+    scroll_y: f32,
+}
+
+impl Default for ImageParams {
+    fn default() -> Self {
+        ImageParams {
+            path: None,
+            filter: ImageFilter::Bilinear,
+            scroll_x: 0.0,
+            scroll_y: 0.0,
+        }
+    }
+}
+
+// decoded pixels, row by row from the top left
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<Srgba>,
+}
+
+impl Image {
+    pub fn decode(reader: impl Read) -> Result<Image, String> {
+        let mut decoder = png::Decoder::new(reader);
+        // palettes and low bit depths come out as 8 bit rgb or gray
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info().map_err(|err| err.to_string())?;
+
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).map_err(|err| err.to_string())?;
+        let bytes = &buf[..info.buffer_size()];
+
+        let channel = |value: u8| value as f32 / 255.0;
+        let pixels: Vec<Srgba> = match info.color_type {
+            png::ColorType::Rgba => bytes
+                .chunks_exact(4)
+                .map(|px| {
+                    Srgba::new(
+                        channel(px[0]),
+                        channel(px[1]),
+                        channel(px[2]),
+                        channel(px[3]),
+                    )
+                })
+                .collect(),
+            png::ColorType::Rgb => bytes
+                .chunks_exact(3)
+                .map(|px| Srgba::new(channel(px[0]), channel(px[1]), channel(px[2]), 1.0))
+                .collect(),
+            png::ColorType::GrayscaleAlpha => bytes
+                .chunks_exact(2)
+                .map(|px| {
+                    Srgba::new(
+                        channel(px[0]),
+                        channel(px[0]),
+                        channel(px[0]),
+                        channel(px[1]),
+                    )
+                })
+                .collect(),
+            png::ColorType::Grayscale => bytes
+                .iter()
+                .map(|px| Srgba::new(channel(*px), channel(*px), channel(*px), 1.0))
+                .collect(),
+            png::ColorType::Indexed => return Err("the palette wasn't expanded".to_string()),
+        };
+
+        Image::new(info.width as usize, info.height as usize, pixels)
+    }
+
+    pub fn new(width: usize, height: usize, pixels: Vec<Srgba>) -> Result<Image, String> {
+        if width == 0 || height == 0 || pixels.len() != width * height {
+            return Err(format!(
+                "{} pixels don't make a {}x{} image",
+                pixels.len(),
+                width,
+                height
+            ));
+        }
+        Ok(Image {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    fn pixel(&self, x: isize, y: isize) -> Srgba {
+        let x = x.rem_euclid(self.width as isize) as usize;
+        let y = y.rem_euclid(self.height as isize) as usize;
+        self.pixels[y * self.width + x]
+    }
+
+    // u and v are 0-1 across and down the image, and wrap around past its edges. pixel centers
+    // sit at half steps, so bilinear blends between the nearest four
+    pub fn sample(&self, u: f32, v: f32, filter: ImageFilter) -> Srgba {
+        let x = u.rem_euclid(1.0) * self.width as f32;
+        let y = v.rem_euclid(1.0) * self.height as f32;
+
+        match filter {
+            ImageFilter::Nearest => self.pixel(x as isize, y as isize),
+            ImageFilter::Bilinear => {
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (left, top) = (x.floor() as isize, y.floor() as isize);
+                let (tx, ty) = (x - x.floor(), y - y.floor());
+
+                let lerp = |a: Srgba, b: Srgba, t: f32| {
+                    Srgba::new(
+                        a.red + (b.red - a.red) * t,
+                        a.green + (b.green - a.green) * t,
+                        a.blue + (b.blue - a.blue) * t,
+                        a.alpha + (b.alpha - a.alpha) * t,
+                    )
+                };
+                let upper = lerp(self.pixel(left, top), self.pixel(left + 1, top), tx);
+                let lower = lerp(self.pixel(left, top + 1), self.pixel(left + 1, top + 1), tx);
+                lerp(upper, lower, ty)
+            }
+        }
+    }
+}
+
+// paints the board from a png, stretched over the board whatever its aspect ratio, each led
+// taking the pixel under its key's center. scrolling pans the image across the board
+pub struct ImageEffect {
+    image: Image,
+    pub filter: ImageFilter,
+    pub scroll_x: f32,
+    pub scroll_y: f32,
+    offset: (f32, f32),
+}
+
+impl ImageEffect {
+    pub fn new(image: Image, filter: ImageFilter) -> ImageEffect {
+        ImageEffect {
+            image,
+            filter,
+            scroll_x: 0.0,
+            scroll_y: 0.0,
+            offset: (0.0, 0.0),
+        }
+    }
+
+    // {"path": ..., "filter": "nearest" | "bilinear", "scroll_x": ..., "scroll_y": ...}, the
+    // image is read once here
+    pub fn from_params(params: &serde_json::Value) -> Option<Box<dyn LedEffect>> {
+        let params = if params.is_null() {
+            ImageParams::default()
+        } else {
+            match ImageParams::deserialize(params) {
+                Ok(params) => params,
+                Err(err) => {
+                    warn!("invalid image params {}: {}", params, err);
+                    return None;
+                }
+            }
+        };
+
+        let Some(path) = &params.path else {
+            warn!("image needs the path of a png");
+            return None;
+        };
+        let image = File::open(path)
+            .map_err(|err| err.to_string())
+            .and_then(Image::decode);
+        let image = match image {
+            Ok(image) => image,
+            Err(err) => {
+                warn!("could not read image {}: {}", path, err);
+                return None;
+            }
+        };
+
+        let mut effect = ImageEffect::new(image, params.filter);
+        effect.scroll_x = params.scroll_x;
+        effect.scroll_y = params.scroll_y;
+        Some(Box::new(effect))
+    }
+}
+
+impl LedEffect for ImageEffect {
+    fn name(&self) -> &'static str {
+        "image"
+    }
+
+    fn reset(&mut self) {
+        self.offset = (0.0, 0.0);
+    }
+
+    fn params(&self) -> Vec<EffectParam> {
+        vec![
+            EffectParam::new("scroll_x", self.scroll_x, -1.0..=1.0),
+            EffectParam::new("scroll_y", self.scroll_y, -1.0..=1.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            "scroll_x" => self.scroll_x = value,
+            "scroll_y" => self.scroll_y = value,
+            _ => return false,
+        }
+        true
+    }
+
+    fn update(&mut self, delta: f32, state: &mut [LedState], ctx: &EffectContext) {
+        self.offset = (
+            (self.offset.0 + self.scroll_x * delta).rem_euclid(1.0),
+            (self.offset.1 + self.scroll_y * delta).rem_euclid(1.0),
+        );
+
+        for led in state {
+            let key = match led.key {
+                Some(key) => key,
+                None => continue,
+            };
+            let (u, v) = ctx.kb_config.relative_position(key);
+
+            let color = self
+                .image
+                .sample(u + self.offset.0, v + self.offset.1, self.filter);
+            led.color = color.into_color();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn red() -> Srgba {
+        Srgba::new(1.0, 0.0, 0.0, 1.0)
+    }
+
+    fn blue() -> Srgba {
+        Srgba::new(0.0, 0.0, 1.0, 1.0)
+    }
+
+    #[test]
+    fn samples_and_wraps() {
+        let image = Image::new(2, 1, vec![red(), blue()]).unwrap();
+
+        assert_eq!(image.sample(0.1, 0.5, ImageFilter::Nearest), red());
+        assert_eq!(image.sample(0.9, 0.5, ImageFilter::Nearest), blue());
+        assert_eq!(image.sample(1.1, 0.5, ImageFilter::Nearest), red());
+
+        // on a pixel's center it's that pixel, halfway between two it's both
+        assert_eq!(image.sample(0.25, 0.5, ImageFilter::Bilinear), red());
+        let between = image.sample(0.5, 0.5, ImageFilter::Bilinear);
+        assert!((between.red - 0.5).abs() < 1e-5 && (between.blue - 0.5).abs() < 1e-5);
+
+        assert!(Image::new(2, 2, vec![red()]).is_err());
+    }
+
+    #[test]
+    fn decodes_pngs() {
+        let mut png = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut png, 2, 1);
+            encoder.set_color(png::ColorType::Rgb);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&[255, 0, 0, 0, 0, 255]).unwrap();
+        }
+
+        let image = Image::decode(png.as_slice()).unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.pixels, [red(), blue()]);
+
+        assert!(Image::decode(&b"not a png"[..]).is_err());
+    }
+}
@@ -0,0 +1,53 @@
+use super::{EffectContext, LedEffect, LedState};
+use crate::theme::ThemeColor;
+use egui::Color32;
+use palette::Hsva;
+use serde::Deserialize;
+
+// lights the keys set for each lock while the host has it on, and leaves every other key
+// transparent so it sits on top of other effects
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct IndicatorEffect {
+    // matrix positions of the keys for each lock
+    pub caps_lock: Option<(u8, u8)>,
+    pub num_lock: Option<(u8, u8)>,
+    pub scroll_lock: Option<(u8, u8)>,
+    pub color: ThemeColor,
+}
+
+impl Default for IndicatorEffect {
+    fn default() -> Self {
+        IndicatorEffect {
+            caps_lock: None,
+            num_lock: None,
+            scroll_lock: None,
+            color: ThemeColor(Color32::RED),
+        }
+    }
+}
+
+impl LedEffect for IndicatorEffect {
+    fn name(&self) -> &'static str {
+        "indicator"
+    }
+
+    fn update(&mut self, _delta: f32, state: &mut [LedState], ctx: &EffectContext) {
+        let color = self.color.to_hsva();
+        let lit = [
+            (self.caps_lock, ctx.locks.caps_lock),
+            (self.num_lock, ctx.locks.num_lock),
+            (self.scroll_lock, ctx.locks.scroll_lock),
+        ];
+
+        for led in state {
+            let matrix = match led.key {
+                Some(key) => key.matrix,
+                None => continue,
+            };
+            let on = lit.iter().any(|(key, on)| *on && *key == Some(matrix));
+
+            led.color = Hsva::new(color.hue, color.saturation, color.value, on as u8 as f32);
+        }
+    }
+}
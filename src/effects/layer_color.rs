@@ -0,0 +1,45 @@
+use super::{EffectContext, LedEffect, LedState};
+use crate::theme::ThemeColor;
+use palette::Hsva;
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+
+// the whole board in one color per layer, off on layers without one
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct LayerColorEffect {
+    // {"<layer index>": [r, g, b], ...}
+    #[serde(deserialize_with = "deserialize_colors")]
+    pub colors: HashMap<u8, Hsva>,
+}
+
+fn deserialize_colors<'de, D>(deserializer: D) -> Result<HashMap<u8, Hsva>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let colors = HashMap::<u8, ThemeColor>::deserialize(deserializer)?;
+
+    Ok(colors
+        .into_iter()
+        .map(|(layer, color)| (layer, color.to_hsva()))
+        .collect())
+}
+
+impl LedEffect for LayerColorEffect {
+    fn name(&self) -> &'static str {
+        "layer_color"
+    }
+
+    fn update(&mut self, _delta: f32, state: &mut [LedState], ctx: &EffectContext) {
+        let layer = ctx.kb_config.legends.top_layer(ctx.layer_state) as u8;
+        let color = self
+            .colors
+            .get(&layer)
+            .copied()
+            .unwrap_or(Hsva::new(0.0, 0.0, 0.0, 1.0));
+
+        for led in state {
+            led.color = color;
+        }
+    }
+}
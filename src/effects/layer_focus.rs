@@ -0,0 +1,89 @@
+use super::{EffectContext, LedEffect, LedState};
+use crate::config::KeyUsage;
+use palette::Hsva;
+use serde::Deserialize;
+
+// lights the keys that do something on the active layer, tinted by usage. the rest are left to
+// the effects below unless `dim` is set, then they're turned down to that brightness
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct LayerFocusEffect {
+    // brightness of keys that do nothing on the active layer, 0 for off
+    pub dim: Option<f32>,
+}
+
+impl LedEffect for LayerFocusEffect {
+    fn name(&self) -> &'static str {
+        "layer_focus"
+    }
+
+    fn update(&mut self, _delta: f32, state: &mut [LedState], ctx: &EffectContext) {
+        for led in state {
+            let (row, col) = match led.key {
+                Some(key) => key.matrix,
+                None => continue,
+            };
+            led.color = match ctx.key_usage(row, col) {
+                None | Some(KeyUsage::Removed | KeyUsage::Unused | KeyUsage::Passthrough) => {
+                    match self.dim {
+                        Some(dim) => Hsva::new(0.0, 0.0, dim, 1.0),
+                        None => Hsva::new(0.0, 0.0, 0.0, 0.0),
+                    }
+                }
+                Some(usage) => {
+                    // same hue as the visualizer, but at full brightness
+                    let hsv = ctx
+                        .kb_config
+                        .host_config
+                        .key_colors
+                        .get(usage)
+                        .background
+                        .to_hsva();
+
+                    Hsva::new(hsv.hue, hsv.saturation, 1.0, 1.0)
+                }
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::tests::kb_config_2x2, threading::KeyState};
+
+    #[test]
+    fn only_dims_when_asked() {
+        let mut kb_config = kb_config_2x2();
+        kb_config.legends = serde_json::from_str(
+            r#"[{"layer_name": "base", "legends": [
+                [{"label": "A"}, {"label": "B", "usage": "unused"}],
+                [{"label": "C", "usage": "removed"}, {"label": "D", "usage": "modifier"}]
+            ]}]"#,
+        )
+        .unwrap();
+        let matrix = vec![vec![KeyState::default(); 2]; 2];
+        let ctx = EffectContext::at_rest(&matrix, &kb_config);
+
+        let update = |effect: &mut LayerFocusEffect| {
+            let mut state = crate::threading::led_state(&kb_config);
+            effect.update(0.1, &mut state, &ctx);
+            state
+                .iter()
+                .map(|led| (led.color.value, led.color.alpha))
+                .collect::<Vec<_>>()
+        };
+
+        // the keys that do nothing stay see-through
+        let lit = update(&mut LayerFocusEffect::default());
+        assert_eq!(lit[0], (1.0, 1.0));
+        assert_eq!(lit[1].1, 0.0);
+        assert_eq!(lit[2].1, 0.0);
+        assert_eq!(lit[3], (1.0, 1.0));
+
+        let dimmed = update(&mut LayerFocusEffect { dim: Some(0.2) });
+        assert_eq!(dimmed[1], (0.2, 1.0));
+        assert_eq!(dimmed[2], (0.2, 1.0));
+        assert_eq!(dimmed[3], (1.0, 1.0));
+    }
+}
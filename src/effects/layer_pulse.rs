@@ -0,0 +1,121 @@
+use super::{
+    default_layer_colors, easing::Easing, layer_color, EffectContext, EffectParam, LedEffect,
+    LedState,
+};
+use crate::theme::ThemeColor;
+use palette::Hsva;
+use serde::Deserialize;
+
+// the whole board in a dim version of the top layer's color, pulsing once in it whenever the top
+// layer changes. changes under a higher layer that stays on don't pulse
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct LayerPulseEffect {
+    // by layer index, layers past the end reuse the list from the start
+    pub colors: Vec<ThemeColor>,
+    // brightness at the start of a pulse, 0-1 of the layer's color
+    pub pulse: f32,
+    // brightness it settles at
+    pub settle: f32,
+    pub duration_ms: f32,
+    // how it falls from the pulse to the settled brightness
+    pub easing: Easing,
+    // time into the running pulse, if any
+    #[serde(skip)]
+    elapsed_ms: Option<f32>,
+}
+
+impl Default for LayerPulseEffect {
+    fn default() -> Self {
+        LayerPulseEffect {
+            colors: default_layer_colors(),
+            pulse: 1.0,
+            settle: 0.15,
+            duration_ms: 400.0,
+            easing: Easing::EaseOut,
+            elapsed_ms: None,
+        }
+    }
+}
+
+impl LayerPulseEffect {
+    // falls off quickly at first, then eases into the settled brightness
+    fn brightness(&self) -> f32 {
+        match self.elapsed_ms {
+            Some(elapsed) => {
+                let t = elapsed / self.duration_ms.max(1.0);
+                self.pulse + (self.settle - self.pulse) * self.easing.apply(t)
+            }
+            None => self.settle,
+        }
+    }
+}
+
+impl LedEffect for LayerPulseEffect {
+    fn name(&self) -> &'static str {
+        "layer_pulse"
+    }
+
+    fn reset(&mut self) {
+        self.elapsed_ms = None;
+    }
+
+    fn params(&self) -> Vec<EffectParam> {
+        vec![
+            EffectParam::new("pulse", self.pulse, 0.0..=1.0),
+            EffectParam::new("settle", self.settle, 0.0..=1.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            "pulse" => self.pulse = value,
+            "settle" => self.settle = value,
+            _ => return false,
+        }
+        true
+    }
+
+    fn update(&mut self, delta: f32, state: &mut [LedState], ctx: &EffectContext) {
+        let legends = &ctx.kb_config.legends;
+        let layer = legends.top_layer(ctx.layer_state);
+        if ctx.layer_changed && layer != legends.top_layer(ctx.previous_layer_state) {
+            self.elapsed_ms = Some(0.0);
+        }
+
+        let color = layer_color(&self.colors, layer);
+        let value = color.value * self.brightness().clamp(0.0, 1.0);
+
+        for led in state {
+            led.color = Hsva::new(color.hue, color.saturation, value, 1.0);
+        }
+
+        self.elapsed_ms = self
+            .elapsed_ms
+            .map(|elapsed| elapsed + delta * 1000.0)
+            .filter(|elapsed| *elapsed < self.duration_ms);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pulses_then_settles() {
+        let mut effect = LayerPulseEffect {
+            pulse: 1.0,
+            settle: 0.2,
+            duration_ms: 100.0,
+            ..Default::default()
+        };
+        assert_eq!(effect.brightness(), 0.2);
+
+        effect.elapsed_ms = Some(0.0);
+        assert_eq!(effect.brightness(), 1.0);
+        effect.elapsed_ms = Some(50.0);
+        assert!((effect.brightness() - 0.4).abs() < 1e-5);
+        effect.elapsed_ms = Some(100.0);
+        assert!((effect.brightness() - 0.2).abs() < 1e-5);
+    }
+}
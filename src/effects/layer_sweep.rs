@@ -0,0 +1,145 @@
+use super::{blend, EffectContext, LedEffect, LedState};
+use crate::{
+    config::{KBConfig, QMKKey},
+    theme::ThemeColor,
+};
+use egui::Color32;
+use palette::Hsva;
+use serde::Deserialize;
+
+// width of the bright wave front, as a fraction of the board
+const BAND: f32 = 0.25;
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SweepDirection {
+    LeftToRight,
+    RightToLeft,
+}
+
+impl SweepDirection {
+    // how far along the sweep a key is, 0 on the side it starts from and 1 on the other
+    pub fn position(self, key: &QMKKey, kb_config: &KBConfig) -> f32 {
+        let x = kb_config.relative_position(key).0;
+        match self {
+            SweepDirection::LeftToRight => x,
+            SweepDirection::RightToLeft => 1.0 - x,
+        }
+    }
+}
+
+// the colors the layer effects default to, by layer index
+pub fn default_layer_colors() -> Vec<ThemeColor> {
+    vec![
+        ThemeColor(Color32::from_rgb(0, 128, 255)),
+        ThemeColor(Color32::from_rgb(255, 64, 0)),
+        ThemeColor(Color32::from_rgb(0, 255, 96)),
+        ThemeColor(Color32::from_rgb(192, 0, 255)),
+    ]
+}
+
+// a layer's color from a list by layer index, layers past the end reusing it from the start.
+// white without any colors
+pub fn layer_color(colors: &[ThemeColor], layer: usize) -> Hsva {
+    match colors.len() {
+        0 => Hsva::new(0.0, 0.0, 1.0, 1.0),
+        len => colors[layer % len].to_hsva(),
+    }
+}
+
+// on every layer change, a wave of the new layer's color wipes across the board and leaves the
+// layer's color behind at `rest_value` brightness
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct LayerSweepEffect {
+    // by layer index, layers past the end reuse the list from the start
+    pub colors: Vec<ThemeColor>,
+    pub sweep_secs: f32,
+    pub rest_value: f32,
+    pub direction: SweepDirection,
+    #[serde(skip)]
+    layer: Option<usize>,
+    #[serde(skip)]
+    from: Hsva,
+    #[serde(skip)]
+    elapsed: f32,
+}
+
+impl Default for LayerSweepEffect {
+    fn default() -> Self {
+        LayerSweepEffect {
+            colors: default_layer_colors(),
+            sweep_secs: 0.4,
+            rest_value: 0.3,
+            direction: SweepDirection::LeftToRight,
+            layer: None,
+            from: Hsva::new(0.0, 0.0, 0.0, 1.0),
+            elapsed: 0.0,
+        }
+    }
+}
+
+impl LayerSweepEffect {
+    fn wave_color(&self, layer: usize) -> Hsva {
+        layer_color(&self.colors, layer)
+    }
+
+    fn rest_color(&self, layer: usize) -> Hsva {
+        let color = self.wave_color(layer);
+        Hsva::new(
+            color.hue,
+            color.saturation,
+            color.value * self.rest_value,
+            1.0,
+        )
+    }
+}
+
+impl LedEffect for LayerSweepEffect {
+    fn name(&self) -> &'static str {
+        "layer_sweep"
+    }
+
+    fn reset(&mut self) {
+        self.layer = None;
+    }
+
+    fn update(&mut self, delta: f32, state: &mut [LedState], ctx: &EffectContext) {
+        let layer = ctx.kb_config.legends.top_layer(ctx.layer_state);
+
+        match self.layer {
+            // the layer the board starts on doesn't sweep in
+            None => self.elapsed = self.sweep_secs,
+            // a new change restarts the sweep from the layer that was showing
+            Some(prev) if prev != layer => {
+                self.from = self.rest_color(prev);
+                self.elapsed = 0.0;
+            }
+            _ => self.elapsed += delta,
+        }
+        self.layer = Some(layer);
+
+        let wave = self.wave_color(layer);
+        let rest = self.rest_color(layer);
+
+        let t = (self.elapsed / self.sweep_secs.max(0.01)).min(1.0);
+        let front = t * (1.0 + BAND);
+
+        for led in state {
+            let key = match led.key {
+                Some(key) => key,
+                None => continue,
+            };
+            let x = self.direction.position(key, ctx.kb_config);
+
+            let behind = front - x;
+            led.color = if t >= 1.0 || behind >= BAND {
+                rest
+            } else if behind >= 0.0 {
+                blend(wave, rest, behind / BAND)
+            } else {
+                self.from
+            };
+        }
+    }
+}
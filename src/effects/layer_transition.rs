@@ -0,0 +1,107 @@
+use super::{
+    default_layer_colors, layer_color, EffectContext, LedEffect, LedState, SweepDirection,
+};
+use crate::theme::ThemeColor;
+use palette::Hsva;
+use serde::Deserialize;
+
+// a bright band in the new layer's color that crosses the board once on a layer change, drawn
+// over the effects. the hid thread starts one from the config's `layer_transition` on every
+// change, replacing any still running. it isn't in the registry, on the stack it'd sweep once
+// and be gone
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LayerTransitionEffect {
+    // by layer index, layers past the end reuse the list from the start
+    pub colors: Vec<ThemeColor>,
+    pub duration_ms: f32,
+    // width of the band, as a fraction of the board
+    pub band: f32,
+    pub direction: SweepDirection,
+    #[serde(skip)]
+    elapsed_ms: f32,
+}
+
+impl Default for LayerTransitionEffect {
+    fn default() -> Self {
+        LayerTransitionEffect {
+            colors: default_layer_colors(),
+            duration_ms: 300.0,
+            band: 0.2,
+            direction: SweepDirection::LeftToRight,
+            elapsed_ms: 0.0,
+        }
+    }
+}
+
+impl LedEffect for LayerTransitionEffect {
+    fn name(&self) -> &'static str {
+        "layer_transition"
+    }
+
+    fn reset(&mut self) {
+        self.elapsed_ms = 0.0;
+    }
+
+    fn update(&mut self, delta: f32, state: &mut [LedState], ctx: &EffectContext) {
+        let layer = ctx.kb_config.legends.top_layer(ctx.layer_state);
+        let color = layer_color(&self.colors, layer);
+
+        // the band starts fully off the board on one side and ends fully off the other
+        let band = self.band.max(0.01);
+        let t = (self.elapsed_ms / self.duration_ms.max(1.0)).min(1.0);
+        let center = t * (1.0 + band) - band / 2.0;
+
+        for led in state {
+            let key = match led.key {
+                Some(key) => key,
+                None => continue,
+            };
+            let x = self.direction.position(key, ctx.kb_config);
+
+            // brightest in the middle of the band, fading out to its edges
+            let alpha = (1.0 - (x - center).abs() / (band / 2.0)).max(0.0);
+            led.color = Hsva::new(color.hue, color.saturation, color.value, alpha);
+        }
+
+        self.elapsed_ms += delta * 1000.0;
+    }
+
+    fn finished(&self) -> bool {
+        self.elapsed_ms >= self.duration_ms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::tests::kb_config_2x2, threading};
+
+    #[test]
+    fn sweeps_once_and_finishes() {
+        let kb_config = kb_config_2x2();
+        let matrix = vec![vec![Default::default(); 2]; 2];
+        let ctx = EffectContext::at_rest(&matrix, &kb_config);
+        let mut state = threading::led_state(&kb_config);
+
+        let mut effect = LayerTransitionEffect {
+            duration_ms: 100.0,
+            band: 0.5,
+            ..Default::default()
+        };
+
+        // the band starts off the left edge
+        effect.update(0.025, &mut state, &ctx);
+        assert!(state.iter().all(|led| led.color.alpha == 0.0));
+        assert!(!effect.finished());
+
+        // a quarter of the way in it's over the left column only
+        effect.update(0.025, &mut state, &ctx);
+        let alphas: Vec<f32> = state.iter().map(|led| led.color.alpha).collect();
+        assert!(alphas[0] > 0.0 && alphas[2] > 0.0);
+        assert_eq!((alphas[1], alphas[3]), (0.0, 0.0));
+
+        effect.update(0.05, &mut state, &ctx);
+        assert!(effect.finished());
+    }
+}
@@ -0,0 +1,173 @@
+use super::{build_effect, EffectContext, EffectParam, LedEffect, LedState};
+use crate::config::{EffectConfig, QMKKey};
+use log::warn;
+use palette::Hsva;
+use serde::Deserialize;
+use std::{collections::HashSet, time::Instant};
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct MaskedParams {
+    effect: Option<EffectConfig>,
+    // matrix positions of the keys the effect is drawn on
+    keys: Vec<(u8, u8)>,
+    // and keys by their layout label, e.g. the alphas
+    labels: Vec<String>,
+}
+
+type KeyMask = Box<dyn Fn(&QMKKey) -> bool>;
+
+// runs another effect but only draws it on the keys that match, everything else keeps what
+// was there before
+pub struct MaskedEffect {
+    inner: Box<dyn LedEffect>,
+    mask: KeyMask,
+    // which leds match, worked out from the layout on the first frame
+    matches: Vec<bool>,
+    // everything the inner effect drew last frame, not just the masked part
+    drawn: Vec<Hsva>,
+}
+
+impl MaskedEffect {
+    pub fn new(inner: Box<dyn LedEffect>, mask: impl Fn(&QMKKey) -> bool + 'static) -> Self {
+        MaskedEffect {
+            inner,
+            mask: Box::new(mask),
+            matches: Vec::new(),
+            drawn: Vec::new(),
+        }
+    }
+
+    // {"effect": {"name": ..., "params": ...}, "keys": [[row, col], ...], "labels": [...]}
+    pub fn from_params(params: &serde_json::Value) -> Option<Box<dyn LedEffect>> {
+        let params = if params.is_null() {
+            MaskedParams::default()
+        } else {
+            match MaskedParams::deserialize(params) {
+                Ok(params) => params,
+                Err(err) => {
+                    warn!("invalid masked params {}: {}", params, err);
+                    return None;
+                }
+            }
+        };
+
+        let inner = match &params.effect {
+            Some(effect) => build_effect(&effect.name, &effect.params)?,
+            None => {
+                warn!("masked needs an effect to wrap");
+                return None;
+            }
+        };
+
+        let keys: HashSet<(u8, u8)> = params.keys.into_iter().collect();
+        let labels: HashSet<String> = params.labels.into_iter().collect();
+        if keys.is_empty() && labels.is_empty() {
+            warn!("masked effect has no keys or labels, it won't draw anything");
+        }
+
+        Some(Box::new(MaskedEffect::new(inner, move |key| {
+            keys.contains(&key.matrix) || labels.contains(&key.label)
+        })))
+    }
+}
+
+impl LedEffect for MaskedEffect {
+    fn name(&self) -> &'static str {
+        "masked"
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset();
+        self.drawn.clear();
+    }
+
+    fn set_color(&mut self, color: Hsva) -> Vec<(&'static str, serde_json::Value)> {
+        self.inner.set_color(color)
+    }
+
+    fn set_text(&mut self, text: &str) -> bool {
+        self.inner.set_text(text)
+    }
+
+    fn tap(&mut self, at: Instant) -> bool {
+        self.inner.tap(at)
+    }
+
+    fn params(&self) -> Vec<EffectParam> {
+        self.inner.params()
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        self.inner.set_param(name, value)
+    }
+
+    fn update(&mut self, delta: f32, state: &mut [LedState], ctx: &EffectContext) {
+        if self.matches.len() != state.len() {
+            self.matches = state
+                .iter()
+                .map(|led| led.key.is_some_and(|key| (self.mask)(key)))
+                .collect();
+        }
+
+        let mut canvas = state.to_vec();
+        if self.drawn.len() == canvas.len() {
+            for (led, color) in canvas.iter_mut().zip(&self.drawn) {
+                led.color = *color;
+            }
+        }
+
+        self.inner.update(delta, &mut canvas, ctx);
+
+        self.drawn.clear();
+        self.drawn.extend(canvas.iter().map(|led| led.color));
+
+        for ((led, color), matches) in state.iter_mut().zip(&self.drawn).zip(&self.matches) {
+            if *matches {
+                led.color = *color;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::tests::kb_config_2x2, effects::StaticEffect, threading};
+
+    #[test]
+    fn unmasked_leds_keep_their_color() {
+        let kb_config = kb_config_2x2();
+        let matrix = vec![vec![Default::default(); 2]; 2];
+        let ctx = EffectContext::at_rest(&matrix, &kb_config);
+
+        let before = Hsva::new(120.0, 1.0, 1.0, 0.5);
+        let mut state = threading::led_state(&kb_config);
+        for led in &mut state {
+            led.color = before;
+        }
+
+        let red = Hsva::new(0.0, 1.0, 1.0, 1.0);
+        let mut effect = MaskedEffect::new(Box::new(StaticEffect { color: red }), |key| {
+            key.label == "A" || key.matrix == (1, 1)
+        });
+
+        for _ in 0..2 {
+            effect.update(0.05, &mut state, &ctx);
+
+            let colors: Vec<Hsva> = state.iter().map(|led| led.color).collect();
+            assert_eq!(colors, [red, before, before, red]);
+        }
+    }
+
+    #[test]
+    fn builds_from_params() {
+        let params = serde_json::json!({
+            "effect": {"name": "static"},
+            "keys": [[0, 0]],
+            "labels": ["D"],
+        });
+        assert!(MaskedEffect::from_params(&params).is_some());
+        assert!(MaskedEffect::from_params(&serde_json::json!({"keys": [[0, 0]]})).is_none());
+    }
+}
@@ -1,21 +1,508 @@
-use crate::{config::QMKKey, threading::KeyState};
-use palette::Hsva;
+use crate::{
+    config::{KBConfig, KeyUsage, QMKKey},
+    protocol::{LockState, Mods},
+    threading::{EncoderState, KeyState},
+};
+use log::warn;
+use palette::{Hsva, IntoColor, LinSrgba, Mix, Srgba};
+use serde::de::DeserializeOwned;
+use std::{ops::RangeInclusive, time::Instant};
 
 #[derive(Default, Clone)]
 pub struct LedState<'key> {
     pub color: Hsva,
     pub key: Option<&'key QMKKey>,
+    // see `KBConfig::phase_offset`
+    pub phase_offset: f32,
 }
 
-impl<'key> LedState<'key> {
-    pub fn key(&self) -> &QMKKey {
-        self.key.unwrap()
+// mixes in linear rgb, since mixing hues goes around the color wheel
+pub fn blend(under: Hsva, over: Hsva, amount: f32) -> Hsva {
+    let under: Srgba = under.into_color();
+    let over: Srgba = over.into_color();
+
+    let mixed = under
+        .into_linear()
+        .mix(&over.into_linear(), amount.clamp(0.0, 1.0));
+
+    Srgba::from_linear(mixed).into_color()
+}
+
+// `over` on top of `under` by over's alpha (porter-duff over), computed in linear rgb and
+// converted back to hsv for sending
+pub fn alpha_over(under: Hsva, over: Hsva) -> Hsva {
+    let under: Srgba = under.into_color();
+    let over: Srgba = over.into_color();
+    let (under, over) = (under.into_linear(), over.into_linear());
+
+    let alpha = over.alpha + under.alpha * (1.0 - over.alpha);
+    if alpha <= 0.0 {
+        return Hsva::new(0.0, 0.0, 0.0, 0.0);
+    }
+
+    let mix = |u: f32, o: f32| (o * over.alpha + u * under.alpha * (1.0 - over.alpha)) / alpha;
+    let mixed = LinSrgba::new(
+        mix(under.red, over.red),
+        mix(under.green, over.green),
+        mix(under.blue, over.blue),
+        alpha,
+    );
+
+    Srgba::from_linear(mixed).into_color()
+}
+
+// everything an effect may read besides its own leds
+pub struct EffectContext<'a> {
+    pub key_state: &'a [Vec<KeyState>],
+    pub layer_state: u32,
+    // the layers on before the last change, the same as layer_state until there's been one
+    pub previous_layer_state: u32,
+    // whether the layers changed since the frame before, only true for one frame per change
+    pub layer_changed: bool,
+    pub locks: LockState,
+    pub mods: Mods,
+    pub encoders: &'a [EncoderState],
+    pub kb_config: &'a KBConfig,
+}
+
+impl<'a> EffectContext<'a> {
+    // the base layer with no locks, mods or encoders, for the bench and tests to start from
+    pub fn at_rest(key_state: &'a [Vec<KeyState>], kb_config: &'a KBConfig) -> Self {
+        EffectContext {
+            key_state,
+            layer_state: 0,
+            previous_layer_state: 0,
+            layer_changed: false,
+            locks: Default::default(),
+            mods: Default::default(),
+            encoders: &[],
+            kb_config,
+        }
+    }
+
+    // center of the key at this matrix position, in key units
+    pub fn key_center(&self, row: u8, col: u8) -> Option<(f32, f32)> {
+        self.kb_config
+            .layout()
+            .layout
+            .iter()
+            .find(|key| key.matrix == (row, col))
+            .map(|key| self.kb_config.key_position(key))
+    }
+
+    // what the key at this matrix position does on the active layers
+    pub fn key_usage(&self, row: u8, col: u8) -> Option<&'a KeyUsage> {
+        self.kb_config
+            .legends
+            .get_key(self.layer_state, row, col)
+            .map(|def| &def.usage)
+    }
+}
+
+// reports keys whose down event arrived since the last poll
+#[derive(Default)]
+pub struct PressTracker {
+    last_poll: Option<Instant>,
+}
+
+impl PressTracker {
+    pub fn poll(&mut self, key_state: &[Vec<KeyState>]) -> Vec<(u8, u8)> {
+        let now = Instant::now();
+        let mut pressed = Vec::new();
+
+        if let Some(last_poll) = self.last_poll {
+            for (row, cols) in key_state.iter().enumerate() {
+                for (col, key) in cols.iter().enumerate() {
+                    if key.last_down.is_some_and(|down| down > last_poll) {
+                        pressed.push((row as u8, col as u8));
+                    }
+                }
+            }
+        }
+
+        self.last_poll = Some(now);
+        pressed
+    }
+}
+
+// a number an effect lets the ui tune while it runs, named like its config param
+#[derive(Debug, Clone, PartialEq)]
+pub struct EffectParam {
+    pub name: &'static str,
+    pub value: f32,
+    // what the ui's slider offers, the config can go past it
+    pub range: RangeInclusive<f32>,
+}
+
+impl EffectParam {
+    pub fn new(name: &'static str, value: f32, range: RangeInclusive<f32>) -> EffectParam {
+        EffectParam { name, value, range }
     }
 }
 
 pub trait LedEffect {
-    fn update(&mut self, delta: f32, state: &mut Vec<LedState>, key_state: &[Vec<KeyState>]);
+    // the name it's registered under
+    fn name(&self) -> &'static str;
+
+    fn update(&mut self, delta: f32, state: &mut [LedState], ctx: &EffectContext);
+
+    // forget anything accumulated so far, as if the effect was just built
+    fn reset(&mut self) {}
+
+    // effects drawn in one color take this one from now on, returning the params that hold it
+    // so the config can keep them. empty for effects without a color
+    fn set_color(&mut self, _color: Hsva) -> Vec<(&'static str, serde_json::Value)> {
+        Vec::new()
+    }
+
+    // effects that show text show this from now on, returning whether they show any
+    fn set_text(&mut self, _text: &str) -> bool {
+        false
+    }
+
+    // a tap of tap tempo, for effects that keep time. returns whether the effect has a tempo
+    fn tap(&mut self, _at: Instant) -> bool {
+        false
+    }
+
+    // the params the ui shows a slider for, with their current values
+    fn params(&self) -> Vec<EffectParam> {
+        Vec::new()
+    }
+
+    // returns whether the effect has a param of that name
+    fn set_param(&mut self, _name: &str, _value: f32) -> bool {
+        false
+    }
+
+    // one-shot effects return true once they're done and get removed after the frame
+    fn finished(&self) -> bool {
+        false
+    }
 }
 
+// effects read their params straight from the config, anything missing keeps its default
+fn with_params<T>(params: &serde_json::Value) -> Option<Box<dyn LedEffect>>
+where
+    T: LedEffect + Default + DeserializeOwned + 'static,
+{
+    if params.is_null() {
+        return Some(Box::<T>::default());
+    }
+
+    match T::deserialize(params) {
+        Ok(effect) => Some(Box::new(effect)),
+        Err(err) => {
+            warn!("invalid effect params {}: {}", params, err);
+            None
+        }
+    }
+}
+
+type EffectConstructor = fn(&serde_json::Value) -> Option<Box<dyn LedEffect>>;
+
+// every effect the config can name, with how to build it from its params
+const REGISTRY: &[(&str, EffectConstructor)] = &[
+    ("ambient", with_params::<AmbientEffect>),
+    ("audio", with_params::<AudioEffect>),
+    ("bpm", with_params::<BpmEffect>),
+    ("breathing", with_params::<BreathingEffect>),
+    ("clock", with_params::<ClockEffect>),
+    ("debounce_viz", with_params::<DebounceVizEffect>),
+    ("finger_zone", with_params::<FingerZoneEffect>),
+    ("fireworks", with_params::<FireworksEffect>),
+    ("flash", with_params::<FlashEffect>),
+    ("gradient", with_params::<GradientEffect>),
+    ("heatmap", with_params::<HeatmapEffect>),
+    ("idle_dim", IdleDimEffect::from_params),
+    ("image", ImageEffect::from_params),
+    ("indicator", with_params::<IndicatorEffect>),
+    ("layer_color", with_params::<LayerColorEffect>),
+    ("layer_focus", with_params::<LayerFocusEffect>),
+    ("layer_pulse", with_params::<LayerPulseEffect>),
+    ("layer_sweep", with_params::<LayerSweepEffect>),
+    ("masked", MaskedEffect::from_params),
+    ("modifier_highlight", with_params::<ModifierHighlightEffect>),
+    ("progress", with_params::<ProgressEffect>),
+    ("rain", with_params::<RainEffect>),
+    ("rainbow1", with_params::<Rainbow1Effect>),
+    ("rainbow_angle", with_params::<RainbowAngleEffect>),
+    ("reactive", with_params::<ReactiveEffect>),
+    ("reactive_legend", with_params::<ReactiveLegendEffect>),
+    ("ripple", with_params::<RippleEffect>),
+    ("snake", with_params::<SnakeEffect>),
+    ("static", with_params::<StaticEffect>),
+    ("sustain", with_params::<SustainEffect>),
+    ("system_monitor", with_params::<SystemMonitorEffect>),
+    ("text", with_params::<TextEffect>),
+    ("twinkle", with_params::<TwinkleEffect>),
+    ("wave", with_params::<WaveEffect>),
+    ("wpm", with_params::<WpmEffect>),
+];
+
+// effects that `LedEffect::set_color` recolors, the ones the Effects window has a picker for
+pub const COLOR_EFFECTS: &[&str] = &["breathing", "static", "text"];
+
+pub fn effect_names() -> impl Iterator<Item = &'static str> {
+    REGISTRY.iter().map(|(name, _)| *name)
+}
+
+pub fn build_effect(name: &str, params: &serde_json::Value) -> Option<Box<dyn LedEffect>> {
+    REGISTRY
+        .iter()
+        .find(|(effect_name, _)| *effect_name == name)
+        .and_then(|(_, constructor)| constructor(params))
+}
+
+// effects that build with default params, the ones `HIDCommand::SetEffect` can switch to
+pub fn buildable_effects() -> Vec<&'static str> {
+    effect_names()
+        .filter(|name| build_effect(name, &serde_json::Value::Null).is_some())
+        .collect()
+}
+
+mod ambient;
+pub use ambient::*;
+
+mod audio;
+pub use audio::*;
+
+mod bpm;
+pub use bpm::*;
+
+mod breathing;
+pub use breathing::*;
+
+mod clock;
+pub use clock::*;
+
+mod debounce_viz;
+pub use debounce_viz::*;
+
+pub mod easing;
+
+mod finger_zone;
+pub use finger_zone::*;
+
+mod fireworks;
+pub use fireworks::*;
+
+mod flash;
+pub use flash::*;
+
+mod gradient;
+pub use gradient::*;
+
+mod heatmap;
+pub use heatmap::*;
+
+mod idle_dim;
+pub use idle_dim::*;
+
+mod image;
+pub use image::*;
+
+mod indicator;
+pub use indicator::*;
+
+mod layer_color;
+pub use layer_color::*;
+
+mod layer_focus;
+pub use layer_focus::*;
+
+mod layer_pulse;
+pub use layer_pulse::*;
+
+mod layer_sweep;
+pub use layer_sweep::*;
+
+mod layer_transition;
+pub use layer_transition::*;
+
+mod masked;
+pub use masked::*;
+
+mod modifier_highlight;
+pub use modifier_highlight::*;
+
+mod progress;
+pub use progress::*;
+
+mod rain;
+pub use rain::*;
+
 mod rainbow1;
 pub use rainbow1::*;
+
+mod rainbow_angle;
+pub use rainbow_angle::*;
+
+mod reactive;
+pub use reactive::*;
+
+mod reactive_legend;
+pub use reactive_legend::*;
+
+mod ripple;
+pub use ripple::*;
+
+mod snake;
+pub use snake::*;
+
+mod static_color;
+pub use static_color::*;
+
+mod sustain;
+pub use sustain::*;
+
+mod system_monitor;
+pub use system_monitor::*;
+
+mod text;
+pub use text::*;
+
+mod twinkle;
+pub use twinkle::*;
+
+mod wave;
+pub use wave::*;
+
+mod wpm;
+pub use wpm::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Hsva, b: Hsva) -> bool {
+        let (a, b): (Srgba, Srgba) = (a.into_color(), b.into_color());
+        (a.red - b.red).abs() < 1e-3
+            && (a.green - b.green).abs() < 1e-3
+            && (a.blue - b.blue).abs() < 1e-3
+            && (a.alpha - b.alpha).abs() < 1e-3
+    }
+
+    #[test]
+    fn alpha_over_composites() {
+        let red = Hsva::new(0.0, 1.0, 1.0, 1.0);
+        let blue = Hsva::new(240.0, 1.0, 1.0, 1.0);
+        let clear = Hsva::new(120.0, 1.0, 1.0, 0.0);
+
+        assert!(close(alpha_over(red, blue), blue));
+        assert!(close(alpha_over(red, clear), red));
+        assert!(close(alpha_over(clear, blue), blue));
+
+        // half of an opaque layer stays opaque
+        let half = alpha_over(red, Hsva::new(240.0, 1.0, 1.0, 0.5));
+        assert!((half.alpha - 1.0).abs() < 1e-5);
+        assert!(close(half, blend(red, blue, 0.5)));
+    }
+
+    // and boards without any leds get an empty state to update
+    #[test]
+    fn leds_without_keys_are_skipped() {
+        let gaps = crate::config::tests::kb_config_with_gaps();
+        assert!(crate::threading::led_state(&gaps)[1].key.is_none());
+
+        for kb_config in [
+            crate::config::tests::kb_config_with_gaps(),
+            crate::config::tests::kb_config_without_leds(),
+        ] {
+            update_every_effect(&kb_config);
+        }
+    }
+
+    fn update_every_effect(kb_config: &KBConfig) {
+        let mut matrix = vec![vec![KeyState::default(); 2]; 2];
+        matrix[0][1] = KeyState {
+            is_pressed: true,
+            last_down: Some(Instant::now()),
+            last_pressed: Some(Instant::now()),
+            ..Default::default()
+        };
+        let ctx = EffectContext {
+            layer_state: 1,
+            layer_changed: true,
+            ..EffectContext::at_rest(&matrix, kb_config)
+        };
+
+        // ambient and audio would start capturing the screen and sound
+        let effects = buildable_effects()
+            .into_iter()
+            .filter(|name| !matches!(*name, "ambient" | "audio"));
+        for name in effects {
+            let mut effect = build_effect(name, &serde_json::Value::Null).unwrap();
+            let mut state = crate::threading::led_state(kb_config);
+
+            for _ in 0..3 {
+                effect.update(0.1, &mut state, &ctx);
+            }
+        }
+    }
+
+    #[test]
+    fn registry_builds_defaults() {
+        for name in effect_names() {
+            let effect = build_effect(name, &serde_json::Value::Null);
+
+            // wrappers have nothing to wrap without params, and image nothing to show
+            let needs_params = matches!(name, "idle_dim" | "masked" | "image");
+            assert_eq!(effect.is_some(), !needs_params, "{}", name);
+            if let Some(mut effect) = effect {
+                assert_eq!(effect.name(), name);
+                assert_eq!(
+                    !effect.set_color(Hsva::new(0.0, 1.0, 1.0, 1.0)).is_empty(),
+                    COLOR_EFFECTS.contains(&name),
+                    "{}",
+                    name
+                );
+
+                for param in effect.params() {
+                    let value = *param.range.start();
+                    assert!(effect.set_param(param.name, value), "{}", name);
+                    assert!(effect.params().iter().any(|p| p
+                        == &EffectParam {
+                            value,
+                            ..param.clone()
+                        }));
+                }
+                assert!(!effect.set_param("nope", 1.0));
+            }
+        }
+
+        assert!(build_effect("nope", &serde_json::Value::Null).is_none());
+    }
+
+    #[test]
+    fn picked_colors_survive_a_rebuild() {
+        let kb_config = crate::config::tests::kb_config_2x2();
+        let matrix = vec![vec![KeyState::default(); 2]; 2];
+        let ctx = EffectContext::at_rest(&matrix, &kb_config);
+        let green = Hsva::new(120.0, 1.0, 1.0, 1.0);
+
+        for json in [
+            r#"{"name": "static"}"#,
+            r#"{"name": "breathing", "params": {"period_secs": 2}}"#,
+            r#"{"name": "masked", "params": {"effect": {"name": "static"}, "keys": [[0, 0]]}}"#,
+        ] {
+            let mut config: crate::config::EffectConfig = serde_json::from_str(json).unwrap();
+            let mut effect = build_effect(&config.name, &config.params).unwrap();
+            for (name, value) in effect.set_color(green) {
+                config.set_value(name, value);
+            }
+
+            let mut rebuilt = build_effect(&config.name, &config.params).unwrap();
+            let mut state = crate::threading::led_state(&kb_config);
+            rebuilt.update(0.1, &mut state, &ctx);
+            let hue = state[0].color.hue.to_positive_degrees();
+            assert!((hue - 120.0).abs() < 1.0, "{} {}", json, hue);
+        }
+    }
+
+    #[test]
+    fn registry_builds_wrapped_effects() {
+        let params = serde_json::json!({"effect": {"name": "static"}, "timeout_secs": 5});
+        assert!(build_effect("idle_dim", &params).is_some());
+    }
+}
@@ -0,0 +1,88 @@
+use super::{EffectContext, LedEffect, LedState};
+use crate::{config::KeyUsage, keycode, theme::ThemeColor};
+use egui::Color32;
+use palette::Hsva;
+use serde::Deserialize;
+
+// lights the modifiers that are currently held or active (e.g. one-shot), everything else stays
+// dim
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ModifierHighlightEffect {
+    pub color: ThemeColor,
+    // brightness of every other key
+    pub dim: f32,
+}
+
+impl Default for ModifierHighlightEffect {
+    fn default() -> Self {
+        ModifierHighlightEffect {
+            color: ThemeColor(Color32::from_rgb(255, 160, 0)),
+            dim: 0.0,
+        }
+    }
+}
+
+impl LedEffect for ModifierHighlightEffect {
+    fn name(&self) -> &'static str {
+        "modifier_highlight"
+    }
+
+    fn update(&mut self, _delta: f32, state: &mut [LedState], ctx: &EffectContext) {
+        let color = self.color.to_hsva();
+
+        for led in state {
+            let (row, col) = match led.key {
+                Some(key) => key.matrix,
+                None => continue,
+            };
+            let key_state = &ctx.key_state[row as usize][col as usize];
+            // firmware that reports mods says which keys' modifiers are on, even once released
+            let active = key_state
+                .keycode
+                .is_some_and(|code| keycode::modifier_bits(code) & ctx.mods.0 != 0);
+
+            // a held mod-tap is acting as its modifier
+            led.color = match ctx.key_usage(row, col) {
+                Some(KeyUsage::Modifier | KeyUsage::Modtap) if key_state.is_pressed => color,
+                _ if active => color,
+                _ => Hsva::new(0.0, 0.0, self.dim, 1.0),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::tests::kb_config_2x2, protocol::Mods, threading::KeyState};
+
+    #[test]
+    fn lights_active_mods() {
+        let kb_config = kb_config_2x2();
+        let mut matrix = vec![vec![KeyState::default(); 2]; 2];
+        // a released KC_LSFT and a released KC_A
+        matrix[0][0].keycode = Some(0xE1);
+        matrix[0][1].keycode = Some(0x04);
+
+        let mut state = crate::threading::led_state(&kb_config);
+        let mut effect = ModifierHighlightEffect::default();
+        let mut lit = |mods| {
+            let ctx = EffectContext {
+                mods,
+                ..EffectContext::at_rest(&matrix, &kb_config)
+            };
+            effect.update(0.1, &mut state, &ctx);
+            state
+                .iter()
+                .map(|led| led.color.value > 0.0)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(lit(Mods(0)), [false; 4]);
+        // one-shot shift still on
+        assert_eq!(lit(Mods(0b10)), [true, false, false, false]);
+        // right shift doesn't light the left one's key
+        assert_eq!(lit(Mods(0b10_0000)), [false; 4]);
+    }
+}
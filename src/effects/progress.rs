@@ -0,0 +1,48 @@
+use super::{blend, EffectContext, LedEffect, LedState};
+use crate::theme::ThemeColor;
+use egui::Color32;
+use serde::Deserialize;
+
+// a left to right bar over the whole board, for progress reported over the control socket
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ProgressEffect {
+    // 0-1
+    pub progress: f32,
+    pub filled: ThemeColor,
+    pub empty: ThemeColor,
+}
+
+impl Default for ProgressEffect {
+    fn default() -> Self {
+        ProgressEffect {
+            progress: 0.0,
+            filled: ThemeColor(Color32::from_rgb(0, 255, 64)),
+            empty: ThemeColor(Color32::from_rgb(0, 0, 0)),
+        }
+    }
+}
+
+impl LedEffect for ProgressEffect {
+    fn name(&self) -> &'static str {
+        "progress"
+    }
+
+    fn update(&mut self, _delta: f32, state: &mut [LedState], ctx: &EffectContext) {
+        let edge = self.progress.clamp(0.0, 1.0) * ctx.kb_config.width();
+        let filled = self.filled.to_hsva();
+        let empty = self.empty.to_hsva();
+
+        for led in state {
+            let key = match led.key {
+                Some(key) => key,
+                None => continue,
+            };
+
+            // the key the edge falls on is only partly filled
+            let left = key.x - ctx.kb_config.origin().0;
+            let amount = ((edge - left) / key.w).clamp(0.0, 1.0);
+            led.color = blend(empty, filled, amount);
+        }
+    }
+}
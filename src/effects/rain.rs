@@ -0,0 +1,86 @@
+use super::{EffectContext, LedEffect, LedState};
+use palette::Hsva;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use serde::Deserialize;
+
+// drops falling down the matrix columns with a fading trail, matrix movie style
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct RainEffect {
+    // matrix rows per second
+    pub speed: f32,
+    // rows of trail behind the head
+    pub trail: f32,
+    // chance per second that an empty column starts a new drop
+    pub spawn_rate: f32,
+    pub hue: f32,
+    // head row of the drop in every matrix column, if there is one
+    #[serde(skip)]
+    drops: Vec<Option<f32>>,
+    #[serde(skip, default = "rng")]
+    rng: SmallRng,
+}
+
+impl Default for RainEffect {
+    fn default() -> Self {
+        RainEffect {
+            speed: 8.0,
+            trail: 3.0,
+            spawn_rate: 0.6,
+            hue: 120.0,
+            drops: Vec::new(),
+            rng: rng(),
+        }
+    }
+}
+
+fn rng() -> SmallRng {
+    SmallRng::seed_from_u64(0)
+}
+
+impl LedEffect for RainEffect {
+    fn name(&self) -> &'static str {
+        "rain"
+    }
+
+    fn reset(&mut self) {
+        self.drops.clear();
+    }
+
+    fn update(&mut self, delta: f32, state: &mut [LedState], ctx: &EffectContext) {
+        let rows = ctx.kb_config.rows() as f32;
+        self.drops.resize(ctx.kb_config.columns() as usize, None);
+
+        let spawn_chance = (self.spawn_rate * delta).min(1.0);
+        for drop in &mut self.drops {
+            *drop = match *drop {
+                // gone once the end of the trail is past the last row
+                Some(head) if head - self.trail >= rows => None,
+                Some(head) => Some(head + self.speed * delta),
+                None if self.rng.gen::<f32>() < spawn_chance => Some(0.0),
+                None => None,
+            };
+        }
+
+        for led in state {
+            let (row, col) = match led.key {
+                Some(key) => key.matrix,
+                None => continue,
+            };
+
+            // distance behind the head, for leds inside the trail of the drop
+            let behind = self.drops[col as usize]
+                .map(|head| head - row as f32)
+                .filter(|behind| (0.0..self.trail).contains(behind));
+
+            let (saturation, value) = match behind {
+                // the head itself is washed out towards white
+                Some(behind) if behind < 1.0 => (0.3, 1.0),
+                Some(behind) => (1.0, 1.0 - behind / self.trail),
+                None => (1.0, 0.0),
+            };
+
+            led.color = Hsva::new(self.hue, saturation, value, 1.0);
+        }
+    }
+}
@@ -1,5 +1,6 @@
-use super::LedEffect;
+use super::{EffectContext, EffectParam, LedEffect, LedState};
 use palette::Hsva;
+use serde::Deserialize;
 
 // hue degrees per second
 const SPEED: f32 = 36.0;
@@ -7,26 +8,121 @@ const SPEED: f32 = 36.0;
 // hue degrees per key unit (kinda)
 const FACTOR: f32 = 4.0;
 
-#[derive(Default)]
+// hue degrees per encoder click
+const HUE_STEP: f32 = 10.0;
+
+#[derive(Deserialize)]
+#[serde(default)]
 pub struct Rainbow1Effect {
+    pub speed: f32,
+    pub factor: f32,
+    // turning this encoder shifts the rainbow by hue_step per click
+    pub encoder: Option<u8>,
+    pub hue_step: f32,
+    #[serde(skip)]
     base_hue: f32,
+    // encoder position as of the last frame
+    #[serde(skip)]
+    encoder_position: Option<i32>,
+}
+
+impl Default for Rainbow1Effect {
+    fn default() -> Self {
+        Rainbow1Effect {
+            speed: SPEED,
+            factor: FACTOR,
+            encoder: None,
+            hue_step: HUE_STEP,
+            base_hue: 0.0,
+            encoder_position: None,
+        }
+    }
 }
 
 impl LedEffect for Rainbow1Effect {
-    fn update(
-        &mut self,
-        delta: f32,
-        state: &mut Vec<super::LedState>,
-        _key_state: &[Vec<crate::threading::KeyState>],
-    ) {
+    fn name(&self) -> &'static str {
+        "rainbow1"
+    }
+
+    fn reset(&mut self) {
+        self.base_hue = 0.0;
+        self.encoder_position = None;
+    }
+
+    fn params(&self) -> Vec<EffectParam> {
+        vec![
+            EffectParam::new("speed", self.speed, -360.0..=360.0),
+            EffectParam::new("factor", self.factor, 0.0..=30.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            "speed" => self.speed = value,
+            "factor" => self.factor = value,
+            _ => return false,
+        }
+        true
+    }
+
+    fn update(&mut self, delta: f32, state: &mut [LedState], ctx: &EffectContext) {
+        // phase_offset goes corner to corner, so this is about factor degrees per key unit
+        if let Some(encoder) = self.encoder.and_then(|idx| ctx.encoders.get(idx as usize)) {
+            let last = self.encoder_position.replace(encoder.position);
+            let clicks = last.map_or(0, |last| encoder.position - last);
+            self.base_hue = (self.base_hue + clicks as f32 * self.hue_step).rem_euclid(360.0);
+        }
+
+        let spread = (ctx.kb_config.width() + ctx.kb_config.height()) * self.factor;
+
         for led in state {
-            let mut key_hue = self.base_hue + (led.key().x + led.key().y) * FACTOR;
+            let mut key_hue = self.base_hue + led.phase_offset * spread;
             key_hue %= 360.0;
 
             led.color = Hsva::new(key_hue, 1.0, 1.0, 1.0);
         }
 
-        self.base_hue += SPEED * delta;
+        self.base_hue += self.speed * delta;
         self.base_hue %= 360.0;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::tests::kb_config_2x2, effects::LedState, threading::EncoderState};
+
+    #[test]
+    fn encoder_shifts_hue() {
+        let kb_config = kb_config_2x2();
+        let matrix = vec![vec![Default::default(); 2]; 2];
+        let mut state = vec![LedState::default(); 4];
+        let mut effect = Rainbow1Effect {
+            speed: 0.0,
+            factor: 0.0,
+            encoder: Some(1),
+            ..Default::default()
+        };
+
+        let mut frame = |effect: &mut Rainbow1Effect, position| {
+            let encoders = [
+                EncoderState::default(),
+                EncoderState {
+                    position,
+                    clockwise: position >= 0,
+                },
+            ];
+            let ctx = EffectContext {
+                encoders: &encoders,
+                ..EffectContext::at_rest(&matrix, &kb_config)
+            };
+            effect.update(0.1, &mut state, &ctx);
+            state[0].color.hue.to_positive_degrees()
+        };
+
+        // turns before the effect started don't count
+        assert_eq!(frame(&mut effect, 5), 0.0);
+        assert_eq!(frame(&mut effect, 7), 20.0);
+        assert_eq!(frame(&mut effect, 4), 350.0);
+    }
+}
@@ -0,0 +1,129 @@
+use super::{EffectContext, EffectParam, LedEffect, LedState};
+use palette::Hsva;
+use serde::Deserialize;
+
+// a rainbow along any direction of the board, the hue following each key's position along it.
+// 0 degrees runs left to right and 90 top to bottom
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct RainbowAngleEffect {
+    // degrees, clockwise from pointing right
+    pub angle: f32,
+    // hue degrees per second
+    pub speed: f32,
+    // how many times the whole spectrum fits across the board along the angle
+    pub density: f32,
+    #[serde(skip)]
+    base_hue: f32,
+}
+
+impl Default for RainbowAngleEffect {
+    fn default() -> Self {
+        RainbowAngleEffect {
+            angle: 0.0,
+            speed: 36.0,
+            density: 1.0,
+            base_hue: 0.0,
+        }
+    }
+}
+
+impl RainbowAngleEffect {
+    // from 0 at the board's corner furthest behind the direction to 1 at the one furthest
+    // ahead, so the spectrum fits at any angle
+    fn position(angle: f32, x: f32, y: f32, width: f32, height: f32) -> f32 {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        let project = |x: f32, y: f32| x * cos + y * sin;
+
+        let corners =
+            [(0.0, 0.0), (width, 0.0), (0.0, height), (width, height)].map(|(x, y)| project(x, y));
+        let min = corners.iter().copied().fold(f32::INFINITY, f32::min);
+        let max = corners.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+
+        if max - min > 0.0 {
+            (project(x, y) - min) / (max - min)
+        } else {
+            0.0
+        }
+    }
+}
+
+impl LedEffect for RainbowAngleEffect {
+    fn name(&self) -> &'static str {
+        "rainbow_angle"
+    }
+
+    fn reset(&mut self) {
+        self.base_hue = 0.0;
+    }
+
+    fn params(&self) -> Vec<EffectParam> {
+        vec![
+            EffectParam::new("angle", self.angle, 0.0..=360.0),
+            EffectParam::new("speed", self.speed, -360.0..=360.0),
+            EffectParam::new("density", self.density, 0.0..=4.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            "angle" => self.angle = value,
+            "speed" => self.speed = value,
+            "density" => self.density = value,
+            _ => return false,
+        }
+        true
+    }
+
+    fn update(&mut self, delta: f32, state: &mut [LedState], ctx: &EffectContext) {
+        let (width, height) = (ctx.kb_config.width(), ctx.kb_config.height());
+
+        for led in state {
+            let key = match led.key {
+                Some(key) => key,
+                None => continue,
+            };
+            let (x, y) = ctx.kb_config.key_position(key);
+            let position = Self::position(self.angle, x, y, width, height);
+
+            let hue = (self.base_hue + position * self.density * 360.0).rem_euclid(360.0);
+            led.color = Hsva::new(hue, 1.0, 1.0, 1.0);
+        }
+
+        self.base_hue = (self.base_hue + self.speed * delta).rem_euclid(360.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::tests::kb_config_2x2, threading};
+
+    #[test]
+    fn follows_the_angle() {
+        let kb_config = kb_config_2x2();
+        let matrix = vec![vec![Default::default(); 2]; 2];
+        let ctx = EffectContext::at_rest(&matrix, &kb_config);
+
+        // keys A B / C D
+        let hues = |angle| {
+            let mut effect = RainbowAngleEffect {
+                angle,
+                speed: 0.0,
+                ..Default::default()
+            };
+            let mut state = threading::led_state(&kb_config);
+            effect.update(0.1, &mut state, &ctx);
+            state
+                .iter()
+                .map(|led| led.color.hue.to_positive_degrees().round())
+                .collect::<Vec<f32>>()
+        };
+
+        assert_eq!(hues(0.0), [90.0, 270.0, 90.0, 270.0]);
+        assert_eq!(hues(90.0), [90.0, 90.0, 270.0, 270.0]);
+        assert_eq!(hues(180.0), [270.0, 90.0, 270.0, 90.0]);
+        // along the diagonal B and C are level
+        assert_eq!(hues(45.0), [90.0, 180.0, 180.0, 270.0]);
+    }
+}
@@ -0,0 +1,89 @@
+use super::{easing::Easing, EffectContext, LedEffect, LedState};
+use crate::{theme::ThemeColor, threading::KeyState};
+use egui::Color32;
+use palette::Hsva;
+use serde::Deserialize;
+
+// lights a key when it's struck and fades it back to black
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ReactiveEffect {
+    // seconds from the release to fully dark
+    pub fade_secs: f32,
+    // how it fades out, see `Easing`
+    pub easing: Easing,
+    pub color: ThemeColor,
+}
+
+impl Default for ReactiveEffect {
+    fn default() -> Self {
+        ReactiveEffect {
+            fade_secs: 0.5,
+            easing: Easing::Linear,
+            color: ThemeColor(Color32::WHITE),
+        }
+    }
+}
+
+// 1 while the key is held, then down to 0 over fade_secs from its release
+pub(super) fn fade_alpha(key_state: &KeyState, fade_secs: f32, easing: Easing) -> f32 {
+    match key_state.last_up {
+        _ if key_state.is_pressed => 1.0,
+        Some(released) => {
+            1.0 - easing.apply(released.elapsed().as_secs_f32() / fade_secs.max(0.01))
+        }
+        None => 0.0,
+    }
+}
+
+impl LedEffect for ReactiveEffect {
+    fn name(&self) -> &'static str {
+        "reactive"
+    }
+
+    fn update(&mut self, _delta: f32, state: &mut [LedState], ctx: &EffectContext) {
+        let color = self.color.to_hsva();
+
+        for led in state {
+            // leds that aren't under a key have nothing to react to
+            let (row, col) = match led.key {
+                Some(key) => key.matrix,
+                None => continue,
+            };
+            let key_state = &ctx.key_state[row as usize][col as usize];
+            let alpha = fade_alpha(key_state, self.fade_secs, self.easing);
+
+            led.color = Hsva::new(color.hue, color.saturation, color.value, alpha);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn fades_from_the_release() {
+        let pressed = Instant::now() - Duration::from_secs(2);
+        let mut key_state = KeyState {
+            is_pressed: true,
+            last_down: Some(pressed),
+            last_pressed: Some(pressed),
+            ..Default::default()
+        };
+        assert_eq!(fade_alpha(&key_state, 0.5, Easing::Linear), 1.0);
+
+        // held for four times the fade, and only just let go
+        key_state.is_pressed = false;
+        key_state.last_up = Some(Instant::now());
+        assert!(fade_alpha(&key_state, 0.5, Easing::Linear) > 0.9);
+
+        key_state.last_up = Some(Instant::now() - Duration::from_secs_f32(0.25));
+        let half = fade_alpha(&key_state, 0.5, Easing::Linear);
+        assert!((half - 0.5).abs() < 0.1, "{}", half);
+
+        key_state.last_up = Some(pressed + Duration::from_secs(1));
+        assert_eq!(fade_alpha(&key_state, 0.5, Easing::Linear), 0.0);
+    }
+}
@@ -0,0 +1,138 @@
+use super::{easing::Easing, reactive::fade_alpha, EffectContext, LedEffect, LedState};
+use crate::theme::ThemeColor;
+use palette::Hsva;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+
+// like reactive, but each key flares in its own color: the one `keys` gives its matrix position,
+// or else its usage's color from the config's key_colors at full brightness, so modifiers and
+// layer keys flash in their category's color
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ReactiveLegendEffect {
+    // seconds from the release to fully dark
+    pub fade_secs: f32,
+    pub easing: Easing,
+    // "row,col" to [r, g, b], written like a preset's keys
+    #[serde(deserialize_with = "deserialize_keys")]
+    pub keys: HashMap<(u8, u8), Hsva>,
+    // the color each key got when it was last held, kept while it fades
+    #[serde(skip)]
+    flares: HashMap<(u8, u8), Hsva>,
+}
+
+fn deserialize_keys<'de, D>(deserializer: D) -> Result<HashMap<(u8, u8), Hsva>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    BTreeMap::<String, ThemeColor>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(position, color)| {
+            let matrix = position
+                .split_once(',')
+                .and_then(|(row, col)| Some((row.trim().parse().ok()?, col.trim().parse().ok()?)))
+                .ok_or_else(|| {
+                    serde::de::Error::custom(format!("invalid key position {:?}", position))
+                })?;
+            Ok((matrix, color.to_hsva()))
+        })
+        .collect()
+}
+
+impl Default for ReactiveLegendEffect {
+    fn default() -> Self {
+        ReactiveLegendEffect {
+            fade_secs: 0.5,
+            easing: Easing::Linear,
+            keys: HashMap::new(),
+            flares: HashMap::new(),
+        }
+    }
+}
+
+impl ReactiveLegendEffect {
+    // on the layers active right now, so a key held on another layer shows that layer's usage
+    fn key_color(&self, row: u8, col: u8, ctx: &EffectContext) -> Hsva {
+        if let Some(color) = self.keys.get(&(row, col)) {
+            return *color;
+        }
+
+        let usage = ctx.key_usage(row, col).cloned().unwrap_or_default();
+        let color = ctx.kb_config.host_config.key_colors.get(&usage).background;
+        let mut color = color.to_hsva();
+        color.value = 1.0;
+        color
+    }
+}
+
+impl LedEffect for ReactiveLegendEffect {
+    fn name(&self) -> &'static str {
+        "reactive_legend"
+    }
+
+    fn reset(&mut self) {
+        self.flares.clear();
+    }
+
+    fn update(&mut self, _delta: f32, state: &mut [LedState], ctx: &EffectContext) {
+        for led in state {
+            // leds that aren't under a key have nothing to react to
+            let (row, col) = match led.key {
+                Some(key) => key.matrix,
+                None => continue,
+            };
+            let key_state = &ctx.key_state[row as usize][col as usize];
+
+            if key_state.is_pressed {
+                let color = self.key_color(row, col, ctx);
+                self.flares.insert((row, col), color);
+            }
+
+            let alpha = fade_alpha(key_state, self.fade_secs, self.easing);
+            led.color = match self.flares.get(&(row, col)) {
+                Some(color) => Hsva::new(color.hue, color.saturation, color.value, alpha),
+                None => Hsva::new(0.0, 0.0, 0.0, 0.0),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::tests::kb_config_2x2, threading};
+    use std::time::Instant;
+
+    #[test]
+    fn flares_in_each_keys_color() {
+        let kb_config = kb_config_2x2();
+        let mut matrix = vec![vec![threading::KeyState::default(); 2]; 2];
+        for (row, col) in [(0, 0), (0, 1)] {
+            matrix[row][col].is_pressed = true;
+            matrix[row][col].last_pressed = Some(Instant::now());
+        }
+        let ctx = EffectContext::at_rest(&matrix, &kb_config);
+
+        let mut effect: ReactiveLegendEffect =
+            serde_json::from_value(serde_json::json!({"keys": {"0,0": [255, 0, 0]}})).unwrap();
+        let mut state = threading::led_state(&kb_config);
+        effect.update(0.05, &mut state, &ctx);
+
+        // A from `keys`, B in its usage's color made bright, the rest not pressed
+        assert_eq!(state[0].color, Hsva::new(0.0, 1.0, 1.0, 1.0));
+        let usage = kb_config
+            .host_config
+            .key_colors
+            .default
+            .background
+            .to_hsva();
+        assert_eq!(state[1].color.saturation, usage.saturation);
+        assert_eq!((state[1].color.value, state[1].color.alpha), (1.0, 1.0));
+        assert_eq!(state[2].color.alpha, 0.0);
+
+        assert!(serde_json::from_value::<ReactiveLegendEffect>(
+            serde_json::json!({"keys": {"A": [255, 0, 0]}})
+        )
+        .is_err());
+    }
+}
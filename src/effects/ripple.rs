@@ -0,0 +1,111 @@
+use super::{EffectContext, EffectParam, LedEffect, LedState, PressTracker};
+use crate::theme::ThemeColor;
+use egui::Color32;
+use palette::Hsva;
+use serde::Deserialize;
+
+struct Ripple {
+    origin: (f32, f32),
+    age: f32,
+}
+
+// rings of color expanding from every key that gets pressed
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct RippleEffect {
+    // key units per second
+    pub speed: f32,
+    // thickness of the ring in key units
+    pub width: f32,
+    // seconds until a ring has faded out completely
+    pub lifetime: f32,
+    pub color: ThemeColor,
+    #[serde(skip)]
+    ripples: Vec<Ripple>,
+    #[serde(skip)]
+    presses: PressTracker,
+}
+
+impl Default for RippleEffect {
+    fn default() -> Self {
+        RippleEffect {
+            speed: 12.0,
+            width: 1.5,
+            lifetime: 1.0,
+            color: ThemeColor(Color32::from_rgb(0, 160, 255)),
+            ripples: Vec::new(),
+            presses: PressTracker::default(),
+        }
+    }
+}
+
+impl LedEffect for RippleEffect {
+    fn name(&self) -> &'static str {
+        "ripple"
+    }
+
+    fn reset(&mut self) {
+        self.ripples.clear();
+    }
+
+    fn params(&self) -> Vec<EffectParam> {
+        vec![
+            EffectParam::new("speed", self.speed, 1.0..=50.0),
+            EffectParam::new("width", self.width, 0.1..=5.0),
+            EffectParam::new("lifetime", self.lifetime, 0.1..=5.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            "speed" => self.speed = value,
+            "width" => self.width = value,
+            "lifetime" => self.lifetime = value,
+            _ => return false,
+        }
+        true
+    }
+
+    fn update(&mut self, delta: f32, state: &mut [LedState], ctx: &EffectContext) {
+        for ripple in &mut self.ripples {
+            ripple.age += delta;
+        }
+        self.ripples.retain(|ripple| ripple.age < self.lifetime);
+
+        for (row, col) in self.presses.poll(ctx.key_state) {
+            if let Some(origin) = ctx.key_center(row, col) {
+                self.ripples.push(Ripple { origin, age: 0.0 });
+            }
+        }
+
+        let color = self.color.to_hsva();
+        let half_width = (self.width / 2.0).max(0.01);
+
+        for led in state {
+            let key = match led.key {
+                Some(key) => key,
+                None => continue,
+            };
+            let center = ctx.kb_config.key_position(key);
+
+            // overlapping rings take the brightest, not the sum
+            let brightness = self
+                .ripples
+                .iter()
+                .map(|ripple| {
+                    let distance = ((center.0 - ripple.origin.0).powi(2)
+                        + (center.1 - ripple.origin.1).powi(2))
+                    .sqrt();
+                    let radius = ripple.age * self.speed;
+
+                    let ring = 1.0 - ((distance - radius).abs() / half_width).min(1.0);
+                    let fade = 1.0 - ripple.age / self.lifetime;
+
+                    ring * fade
+                })
+                .fold(0.0, f32::max);
+
+            led.color = Hsva::new(color.hue, color.saturation, color.value, brightness);
+        }
+    }
+}
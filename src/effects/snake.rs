@@ -0,0 +1,81 @@
+use super::{EffectContext, LedEffect, LedState, PressTracker};
+use palette::Hsva;
+use serde::Deserialize;
+use std::{collections::VecDeque, time::Instant};
+
+// lights the last few keys pressed as a trail, newest brightest
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct SnakeEffect {
+    // how many presses the trail remembers
+    pub length: usize,
+    // brightness lost per press further back in the trail
+    pub fade_per_step: f32,
+    // presses older than this drop off the trail, so it empties once typing stops
+    pub lifetime_secs: f32,
+    pub hue: f32,
+    #[serde(skip)]
+    presses: PressTracker,
+    // newest at the front
+    #[serde(skip)]
+    trail: VecDeque<((u8, u8), Instant)>,
+}
+
+impl Default for SnakeEffect {
+    fn default() -> Self {
+        SnakeEffect {
+            length: 8,
+            fade_per_step: 0.12,
+            lifetime_secs: 3.0,
+            hue: 100.0,
+            presses: PressTracker::default(),
+            trail: VecDeque::new(),
+        }
+    }
+}
+
+impl LedEffect for SnakeEffect {
+    fn name(&self) -> &'static str {
+        "snake"
+    }
+
+    fn reset(&mut self) {
+        self.presses = PressTracker::default();
+        self.trail.clear();
+    }
+
+    fn update(&mut self, _delta: f32, state: &mut [LedState], ctx: &EffectContext) {
+        let now = Instant::now();
+        for matrix in self.presses.poll(ctx.key_state) {
+            self.trail.push_front((matrix, now));
+        }
+
+        self.trail.truncate(self.length);
+        while self
+            .trail
+            .back()
+            .is_some_and(|(_, pressed)| pressed.elapsed().as_secs_f32() > self.lifetime_secs)
+        {
+            self.trail.pop_back();
+        }
+
+        for led in state {
+            let key = match led.key {
+                Some(key) => key,
+                None => continue,
+            };
+            // a key pressed twice in the trail shows its newest step
+            let step = self
+                .trail
+                .iter()
+                .position(|(matrix, _)| *matrix == key.matrix);
+
+            let value = match step {
+                Some(step) => (1.0 - step as f32 * self.fade_per_step).max(0.0),
+                None => 0.0,
+            };
+
+            led.color = Hsva::new(self.hue, 1.0, value, 1.0);
+        }
+    }
+}
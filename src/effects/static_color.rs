@@ -0,0 +1,37 @@
+use super::{EffectContext, LedEffect, LedState};
+use crate::theme::{deserialize_hsva, hsva_value};
+use palette::Hsva;
+use serde::Deserialize;
+
+// every led one color. after the first frame nothing changes, so nothing more gets sent
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct StaticEffect {
+    #[serde(deserialize_with = "deserialize_hsva")]
+    pub color: Hsva,
+}
+
+impl Default for StaticEffect {
+    fn default() -> Self {
+        StaticEffect {
+            color: Hsva::new(0.0, 0.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl LedEffect for StaticEffect {
+    fn name(&self) -> &'static str {
+        "static"
+    }
+
+    fn set_color(&mut self, color: Hsva) -> Vec<(&'static str, serde_json::Value)> {
+        self.color = color;
+        vec![("color", hsva_value(color))]
+    }
+
+    fn update(&mut self, _delta: f32, state: &mut [LedState], _ctx: &EffectContext) {
+        for led in state {
+            led.color = self.color;
+        }
+    }
+}
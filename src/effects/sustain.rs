@@ -0,0 +1,89 @@
+use super::{EffectContext, LedEffect, LedState};
+use crate::theme::ThemeColor;
+use egui::Color32;
+use palette::Hsva;
+use serde::Deserialize;
+
+// keeps held keys glowing over the layers below, fading out slowly once they're released
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct SustainEffect {
+    // seconds for the glow to fade to half brightness after the key is released
+    pub half_life_secs: f32,
+    pub color: ThemeColor,
+}
+
+impl Default for SustainEffect {
+    fn default() -> Self {
+        SustainEffect {
+            half_life_secs: 1.5,
+            color: ThemeColor(Color32::from_rgb(255, 160, 64)),
+        }
+    }
+}
+
+impl LedEffect for SustainEffect {
+    fn name(&self) -> &'static str {
+        "sustain"
+    }
+
+    fn update(&mut self, _delta: f32, state: &mut [LedState], ctx: &EffectContext) {
+        let color = self.color.to_hsva();
+        let half_life = self.half_life_secs.max(0.01);
+
+        for led in state {
+            let (row, col) = match led.key {
+                Some(key) => key.matrix,
+                None => continue,
+            };
+            let key_state = &ctx.key_state[row as usize][col as usize];
+
+            // full for as long as the key is held, and only the latest release counts, so
+            // repeated presses can't add up past full
+            let glow = match key_state.last_up {
+                _ if key_state.is_pressed => 1.0,
+                Some(released) => 0.5f32.powf(released.elapsed().as_secs_f32() / half_life),
+                None => 0.0,
+            };
+
+            led.color = Hsva::new(color.hue, color.saturation, color.value, glow);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::tests::kb_config_2x2, threading::KeyState};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn fades_from_the_release() {
+        let kb_config = kb_config_2x2();
+        let pressed = Instant::now() - Duration::from_secs(10);
+        let mut matrix = vec![vec![KeyState::default(); 2]; 2];
+        matrix[0][0] = KeyState {
+            is_pressed: true,
+            last_down: Some(pressed),
+            last_pressed: Some(pressed),
+            ..Default::default()
+        };
+        let mut effect = SustainEffect::default();
+        let mut state = crate::threading::led_state(&kb_config);
+        let mut update = |matrix: &[Vec<KeyState>], state: &mut [LedState]| {
+            let ctx = EffectContext::at_rest(matrix, &kb_config);
+            effect.update(0.1, state, &ctx);
+        };
+
+        // held for well past the half life, still full
+        update(&matrix, &mut state);
+        assert_eq!(state[0].color.alpha, 1.0);
+        assert_eq!(state[1].color.alpha, 0.0);
+
+        // released a half life ago
+        matrix[0][0].is_pressed = false;
+        matrix[0][0].last_up = Some(Instant::now() - Duration::from_secs_f32(1.5));
+        update(&matrix, &mut state);
+        assert!((state[0].color.alpha - 0.5).abs() < 0.01);
+    }
+}
@@ -0,0 +1,188 @@
+use super::{EffectContext, LedEffect, LedState};
+use log::warn;
+use palette::Hsva;
+use serde::Deserialize;
+use std::{fs, time::Instant};
+
+const LOW_HUE: f32 = 120.0;
+const HIGH_HUE: f32 = 0.0;
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    Cpu,
+    Memory,
+}
+
+// a metric drawn as a bar along one matrix row
+#[derive(Deserialize, Clone, Copy)]
+pub struct MetricRow {
+    pub metric: Metric,
+    pub row: u8,
+}
+
+// bar graphs of cpu and memory use, each bar going from green to red as it fills. only
+// linux is supported, elsewhere the bars stay empty
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct SystemMonitorEffect {
+    pub rows: Vec<MetricRow>,
+    // seconds between reading the metrics, frames in between reuse the last reading
+    pub poll_secs: f32,
+    #[serde(skip)]
+    last_poll: Option<Instant>,
+    // (busy, total) jiffies from the last poll, cpu use is the change between polls
+    #[serde(skip)]
+    last_cpu: Option<(u64, u64)>,
+    #[serde(skip)]
+    cpu: f32,
+    #[serde(skip)]
+    memory: f32,
+    #[serde(skip)]
+    warned: bool,
+}
+
+impl Default for SystemMonitorEffect {
+    fn default() -> Self {
+        SystemMonitorEffect {
+            rows: vec![
+                MetricRow {
+                    metric: Metric::Cpu,
+                    row: 0,
+                },
+                MetricRow {
+                    metric: Metric::Memory,
+                    row: 1,
+                },
+            ],
+            poll_secs: 1.0,
+            last_poll: None,
+            last_cpu: None,
+            cpu: 0.0,
+            memory: 0.0,
+            warned: false,
+        }
+    }
+}
+
+// busy and total jiffies summed over every cpu, from the first line of /proc/stat
+fn parse_cpu(stat: &str) -> Option<(u64, u64)> {
+    let line = stat.lines().find(|line| line.starts_with("cpu "))?;
+    let times: Vec<u64> = line
+        .split_whitespace()
+        .skip(1)
+        .map(|field| field.parse().ok())
+        .collect::<Option<_>>()?;
+
+    // idle and iowait are the 4th and 5th fields
+    let idle = times.get(3)? + times.get(4).unwrap_or(&0);
+    let total: u64 = times.iter().sum();
+    Some((total - idle, total))
+}
+
+// fraction of memory in use, from /proc/meminfo
+fn parse_memory(meminfo: &str) -> Option<f32> {
+    let field = |name: &str| {
+        meminfo
+            .lines()
+            .find(|line| line.starts_with(name))?
+            .split_whitespace()
+            .nth(1)?
+            .parse::<f32>()
+            .ok()
+    };
+
+    let total = field("MemTotal:")?;
+    let available = field("MemAvailable:")?;
+    Some(1.0 - available / total.max(1.0))
+}
+
+impl SystemMonitorEffect {
+    fn poll(&mut self) {
+        let cpu = fs::read_to_string("/proc/stat")
+            .ok()
+            .and_then(|stat| parse_cpu(&stat));
+        let memory = fs::read_to_string("/proc/meminfo")
+            .ok()
+            .and_then(|meminfo| parse_memory(&meminfo));
+
+        if (cpu.is_none() || memory.is_none()) && !self.warned {
+            warn!("could not read system usage from /proc");
+            self.warned = true;
+        }
+
+        if let (Some((busy, total)), Some((last_busy, last_total))) = (cpu, self.last_cpu) {
+            let elapsed = total.saturating_sub(last_total);
+            if elapsed > 0 {
+                self.cpu = busy.saturating_sub(last_busy) as f32 / elapsed as f32;
+            }
+        }
+        self.last_cpu = cpu;
+        self.memory = memory.unwrap_or(0.0);
+    }
+
+    fn value(&self, metric: Metric) -> f32 {
+        match metric {
+            Metric::Cpu => self.cpu,
+            Metric::Memory => self.memory,
+        }
+    }
+}
+
+impl LedEffect for SystemMonitorEffect {
+    fn name(&self) -> &'static str {
+        "system_monitor"
+    }
+
+    fn reset(&mut self) {
+        self.last_poll = None;
+        self.last_cpu = None;
+        self.cpu = 0.0;
+        self.memory = 0.0;
+    }
+
+    fn update(&mut self, _delta: f32, state: &mut [LedState], ctx: &EffectContext) {
+        if self
+            .last_poll
+            .is_none_or(|last| last.elapsed().as_secs_f32() >= self.poll_secs)
+        {
+            self.poll();
+            self.last_poll = Some(Instant::now());
+        }
+
+        for led in state {
+            let key = match led.key {
+                Some(key) => key,
+                None => continue,
+            };
+            let metric = self.rows.iter().find(|row| row.row == key.matrix.0);
+
+            led.color = match metric.map(|row| self.value(row.metric)) {
+                Some(value) if ctx.kb_config.relative_position(key).0 < value => {
+                    Hsva::new(LOW_HUE + (HIGH_HUE - LOW_HUE) * value, 1.0, 1.0, 1.0)
+                }
+                _ => Hsva::new(0.0, 0.0, 0.0, 1.0),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_cpu_jiffies() {
+        let stat = "cpu  100 0 50 800 50 0 0 0 0 0\ncpu0 50 0 25 400 25 0 0 0 0 0\n";
+        assert_eq!(parse_cpu(stat), Some((150, 1000)));
+        assert_eq!(parse_cpu("intr 1 2 3"), None);
+    }
+
+    #[test]
+    fn reads_memory_use() {
+        let meminfo =
+            "MemTotal:       16000 kB\nMemFree:         1000 kB\nMemAvailable:    4000 kB\n";
+        assert_eq!(parse_memory(meminfo), Some(0.75));
+        assert_eq!(parse_memory("MemTotal: 16000 kB\n"), None);
+    }
+}
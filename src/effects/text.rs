@@ -0,0 +1,221 @@
+use super::{EffectContext, EffectParam, LedEffect, LedState};
+use crate::theme::{deserialize_hsva, hsva_value, ThemeColor};
+use egui::Color32;
+use palette::Hsva;
+use serde::Deserialize;
+
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+
+// rows from the top, the leftmost column in the highest of the 3 bits. lowercase is drawn as
+// uppercase, and anything else missing as '?'
+const FONT: &[(char, [u8; GLYPH_HEIGHT])] = &[
+    (' ', [0b000, 0b000, 0b000, 0b000, 0b000]),
+    ('A', [0b010, 0b101, 0b111, 0b101, 0b101]),
+    ('B', [0b110, 0b101, 0b110, 0b101, 0b110]),
+    ('C', [0b011, 0b100, 0b100, 0b100, 0b011]),
+    ('D', [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('E', [0b111, 0b100, 0b110, 0b100, 0b111]),
+    ('F', [0b111, 0b100, 0b110, 0b100, 0b100]),
+    ('G', [0b011, 0b100, 0b101, 0b101, 0b011]),
+    ('H', [0b101, 0b101, 0b111, 0b101, 0b101]),
+    ('I', [0b111, 0b010, 0b010, 0b010, 0b111]),
+    ('J', [0b001, 0b001, 0b001, 0b101, 0b010]),
+    ('K', [0b101, 0b101, 0b110, 0b101, 0b101]),
+    ('L', [0b100, 0b100, 0b100, 0b100, 0b111]),
+    ('M', [0b101, 0b111, 0b111, 0b101, 0b101]),
+    ('N', [0b110, 0b101, 0b101, 0b101, 0b101]),
+    ('O', [0b010, 0b101, 0b101, 0b101, 0b010]),
+    ('P', [0b110, 0b101, 0b110, 0b100, 0b100]),
+    ('Q', [0b010, 0b101, 0b101, 0b110, 0b011]),
+    ('R', [0b110, 0b101, 0b110, 0b101, 0b101]),
+    ('S', [0b011, 0b100, 0b010, 0b001, 0b110]),
+    ('T', [0b111, 0b010, 0b010, 0b010, 0b010]),
+    ('U', [0b101, 0b101, 0b101, 0b101, 0b111]),
+    ('V', [0b101, 0b101, 0b101, 0b101, 0b010]),
+    ('W', [0b101, 0b101, 0b111, 0b111, 0b101]),
+    ('X', [0b101, 0b101, 0b010, 0b101, 0b101]),
+    ('Y', [0b101, 0b101, 0b010, 0b010, 0b010]),
+    ('Z', [0b111, 0b001, 0b010, 0b100, 0b111]),
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b110, 0b001, 0b010, 0b100, 0b111]),
+    ('3', [0b110, 0b001, 0b010, 0b001, 0b110]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b110, 0b001, 0b110]),
+    ('6', [0b011, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b010, 0b010, 0b010]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b110]),
+    ('!', [0b010, 0b010, 0b010, 0b000, 0b010]),
+    ('?', [0b110, 0b001, 0b010, 0b000, 0b010]),
+    ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+    (',', [0b000, 0b000, 0b000, 0b010, 0b100]),
+    ('\'', [0b010, 0b010, 0b000, 0b000, 0b000]),
+    (':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+    ('-', [0b000, 0b000, 0b111, 0b000, 0b000]),
+    ('+', [0b000, 0b010, 0b111, 0b010, 0b000]),
+];
+
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    let find = |c: char| FONT.iter().find(|(glyph, _)| *glyph == c);
+    find(c.to_ascii_uppercase())
+        .or_else(|| find('?'))
+        .map(|(_, rows)| *rows)
+        .unwrap_or_default()
+}
+
+// the message as columns left to right, a bit per row from the top in the lowest bit, with a
+// blank column after every glyph
+fn render(message: &str) -> Vec<u8> {
+    let mut columns = Vec::with_capacity(message.len() * (GLYPH_WIDTH + 1));
+    for c in message.chars() {
+        let rows = glyph(c);
+        for x in 0..GLYPH_WIDTH {
+            let column = (0..GLYPH_HEIGHT)
+                .filter(|y| rows[*y] >> (GLYPH_WIDTH - 1 - x) & 1 == 1)
+                .fold(0, |column, y| column | 1 << y);
+            columns.push(column);
+        }
+        columns.push(0);
+    }
+    columns
+}
+
+// a message scrolling right to left over the matrix, one key per pixel, rows centered on
+// boards taller than the font. the keys' matrix positions are the pixels, so it reads best on
+// boards wired in a straight grid
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct TextEffect {
+    pub message: String,
+    #[serde(deserialize_with = "deserialize_hsva")]
+    pub color: Hsva,
+    // matrix columns per second
+    pub speed: f32,
+    // how far the message has moved in, in columns
+    #[serde(skip)]
+    scroll: f32,
+    // the message rendered, see `render`
+    #[serde(skip)]
+    columns: Option<Vec<u8>>,
+}
+
+impl Default for TextEffect {
+    fn default() -> Self {
+        TextEffect {
+            message: "HELLO".to_string(),
+            color: ThemeColor(Color32::WHITE).to_hsva(),
+            speed: 6.0,
+            scroll: 0.0,
+            columns: None,
+        }
+    }
+}
+
+impl LedEffect for TextEffect {
+    fn name(&self) -> &'static str {
+        "text"
+    }
+
+    fn reset(&mut self) {
+        self.scroll = 0.0;
+    }
+
+    fn set_color(&mut self, color: Hsva) -> Vec<(&'static str, serde_json::Value)> {
+        self.color = color;
+        vec![("color", hsva_value(color))]
+    }
+
+    // starts over from the right with the new message
+    fn set_text(&mut self, text: &str) -> bool {
+        self.message = text.to_string();
+        self.columns = None;
+        self.scroll = 0.0;
+        true
+    }
+
+    fn params(&self) -> Vec<EffectParam> {
+        vec![EffectParam::new("speed", self.speed, 0.5..=30.0)]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            "speed" => self.speed = value,
+            _ => return false,
+        }
+        true
+    }
+
+    fn update(&mut self, delta: f32, state: &mut [LedState], ctx: &EffectContext) {
+        let columns = self.columns.get_or_insert_with(|| render(&self.message));
+        let board_cols = ctx.kb_config.columns() as usize;
+        let top = (ctx.kb_config.rows() as usize).saturating_sub(GLYPH_HEIGHT) / 2;
+
+        // a board's width of blank columns ahead of the message, so it comes in from the right
+        // and is gone before it comes around again
+        let period = columns.len() + board_cols;
+        self.scroll = (self.scroll + delta * self.speed).rem_euclid(period as f32);
+
+        for led in state {
+            let lit = led.key.is_some_and(|key| {
+                let (row, col) = (key.matrix.0 as usize, key.matrix.1 as usize);
+                let x = (self.scroll as usize + col) % period;
+                (top..top + GLYPH_HEIGHT).contains(&row)
+                    && x >= board_cols
+                    && columns[x - board_cols] >> (row - top) & 1 == 1
+            });
+
+            led.color = if lit {
+                self.color
+            } else {
+                Hsva::new(0.0, 0.0, 0.0, 1.0)
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::tests::kb_config_2x2, threading};
+
+    #[test]
+    fn renders_glyphs_as_columns() {
+        // the two sides and the bar of an H, the middle only on the bar, then the gap
+        assert_eq!(render("H"), [0b11111, 0b00100, 0b11111, 0]);
+        assert_eq!(render("h"), render("H"));
+        assert_eq!(render("~"), render("?"));
+        assert_eq!(render("I ").len(), 8);
+    }
+
+    #[test]
+    fn scrolls_in_from_the_right() {
+        let kb_config = kb_config_2x2();
+        let matrix = vec![vec![Default::default(); 2]; 2];
+        let ctx = EffectContext::at_rest(&matrix, &kb_config);
+        let mut effect = TextEffect {
+            message: "H".to_string(),
+            speed: 1.0,
+            ..Default::default()
+        };
+        let mut state = threading::led_state(&kb_config);
+        let lit = |state: &[LedState]| {
+            state
+                .iter()
+                .map(|led| led.color.value > 0.0)
+                .collect::<Vec<_>>()
+        };
+
+        // keys A B / C D, a board's width of blank first
+        effect.update(1.0, &mut state, &ctx);
+        assert_eq!(lit(&state), [false, true, false, true]);
+        // the H's left side, then its middle that's only lit further down
+        effect.update(1.0, &mut state, &ctx);
+        assert_eq!(lit(&state), [true, false, true, false]);
+
+        effect.set_text("");
+        effect.update(1.0, &mut state, &ctx);
+        assert_eq!(lit(&state), [false; 4]);
+    }
+}
@@ -0,0 +1,134 @@
+use super::{EffectContext, LedEffect, LedState};
+use palette::Hsva;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use serde::Deserialize;
+
+// random leds flare up and fade out like stars
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct TwinkleEffect {
+    // new twinkles per led per second
+    pub density: f32,
+    // never more than this many leds lit at once
+    pub max_lit: usize,
+    // seconds for a twinkle to fade out
+    pub fade_secs: f32,
+    pub hue: f32,
+    pub saturation: f32,
+    pub seed: u64,
+    // brightness per led, sized on the first frame
+    #[serde(skip)]
+    brightness: Vec<f32>,
+    // fractional twinkles carried over, so the rate doesn't depend on the frame rate
+    #[serde(skip)]
+    pending: f32,
+    #[serde(skip)]
+    rng: Option<SmallRng>,
+}
+
+impl Default for TwinkleEffect {
+    fn default() -> Self {
+        TwinkleEffect {
+            density: 0.05,
+            max_lit: 12,
+            fade_secs: 1.0,
+            hue: 50.0,
+            saturation: 0.3,
+            seed: 0,
+            brightness: Vec::new(),
+            pending: 0.0,
+            rng: None,
+        }
+    }
+}
+
+impl TwinkleEffect {
+    fn step(&mut self, delta: f32, led_count: usize) {
+        if self.brightness.len() != led_count {
+            self.brightness = vec![0.0; led_count];
+        }
+        let rng = self
+            .rng
+            .get_or_insert_with(|| SmallRng::seed_from_u64(self.seed));
+
+        let fade = delta / self.fade_secs.max(0.01);
+        for brightness in &mut self.brightness {
+            *brightness = (*brightness - fade).max(0.0);
+        }
+
+        self.pending += self.density * led_count as f32 * delta;
+
+        let mut lit = self.brightness.iter().filter(|b| **b > 0.0).count();
+        while self.pending >= 1.0 {
+            self.pending -= 1.0;
+
+            if lit >= self.max_lit || led_count == 0 {
+                continue;
+            }
+
+            // landing on a star that's already lit just restarts it
+            let idx = rng.gen_range(0..led_count);
+            if self.brightness[idx] == 0.0 {
+                lit += 1;
+            }
+            self.brightness[idx] = 1.0;
+        }
+    }
+}
+
+impl LedEffect for TwinkleEffect {
+    fn name(&self) -> &'static str {
+        "twinkle"
+    }
+
+    fn reset(&mut self) {
+        self.brightness.clear();
+        self.pending = 0.0;
+        self.rng = None;
+    }
+
+    fn update(&mut self, delta: f32, state: &mut [LedState], _ctx: &EffectContext) {
+        self.step(delta, state.len());
+
+        for (led, brightness) in state.iter_mut().zip(&self.brightness) {
+            led.color = Hsva::new(self.hue, self.saturation, *brightness, 1.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(effect: &mut TwinkleEffect, frames: usize, delta: f32) -> Vec<Vec<f32>> {
+        (0..frames)
+            .map(|_| {
+                effect.step(delta, 100);
+                effect.brightness.clone()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_same_twinkles() {
+        let mut a = TwinkleEffect::default();
+        let mut b = TwinkleEffect::default();
+
+        assert_eq!(run(&mut a, 200, 0.05), run(&mut b, 200, 0.05));
+    }
+
+    #[test]
+    fn lit_count_is_bounded() {
+        let mut effect = TwinkleEffect {
+            density: 10.0,
+            max_lit: 5,
+            ..Default::default()
+        };
+
+        for delta in [0.001, 0.05, 1.0] {
+            for frame in run(&mut effect, 50, delta) {
+                assert!(frame.iter().filter(|b| **b > 0.0).count() <= 5);
+            }
+        }
+    }
+}
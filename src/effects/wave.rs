@@ -0,0 +1,81 @@
+use super::{EffectContext, EffectParam, LedEffect, LedState};
+use palette::Hsva;
+use serde::Deserialize;
+use std::f32::consts::PI;
+
+// a band of color travelling left to right across the board and wrapping around
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct WaveEffect {
+    // length of the band, as a fraction of the board width
+    pub wavelength: f32,
+    // board widths per second
+    pub speed: f32,
+    // degrees at the front of the band
+    pub hue: f32,
+    // degrees the hue shifts from the front of the band to its tail
+    pub spread: f32,
+    #[serde(skip)]
+    phase: f32,
+}
+
+impl Default for WaveEffect {
+    fn default() -> Self {
+        WaveEffect {
+            wavelength: 0.3,
+            speed: 0.5,
+            hue: 180.0,
+            spread: 60.0,
+            phase: 0.0,
+        }
+    }
+}
+
+impl LedEffect for WaveEffect {
+    fn name(&self) -> &'static str {
+        "wave"
+    }
+
+    fn reset(&mut self) {
+        self.phase = 0.0;
+    }
+
+    fn params(&self) -> Vec<EffectParam> {
+        vec![
+            EffectParam::new("speed", self.speed, 0.0..=3.0),
+            EffectParam::new("wavelength", self.wavelength, 0.01..=1.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            "speed" => self.speed = value,
+            "wavelength" => self.wavelength = value,
+            _ => return false,
+        }
+        true
+    }
+
+    fn update(&mut self, delta: f32, state: &mut [LedState], ctx: &EffectContext) {
+        self.phase = (self.phase + self.speed * delta).rem_euclid(1.0);
+        let wavelength = self.wavelength.clamp(0.01, 1.0);
+
+        for led in state {
+            let key = match led.key {
+                Some(key) => key,
+                None => continue,
+            };
+            let x = ctx.kb_config.relative_position(key).0;
+
+            // how far behind the front of the band this key is
+            let behind = (self.phase - x).rem_euclid(1.0);
+
+            led.color = if behind < wavelength {
+                let t = behind / wavelength;
+                Hsva::new(self.hue + self.spread * t, 1.0, (t * PI).sin(), 1.0)
+            } else {
+                Hsva::new(self.hue, 1.0, 0.0, 1.0)
+            };
+        }
+    }
+}
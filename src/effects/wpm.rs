@@ -0,0 +1,150 @@
+use super::{EffectContext, LedEffect, LedState, PressTracker};
+use palette::Hsva;
+use serde::Deserialize;
+use std::{collections::VecDeque, time::Instant};
+
+// characters in an average word, the usual convention for typing speed
+const CHARS_PER_WORD: f32 = 5.0;
+
+const SLOW_HUE: f32 = 240.0;
+const FAST_HUE: f32 = 0.0;
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum WpmDisplay {
+    // lights keys from the left edge out to the current speed
+    Bar,
+    // tints the whole board from blue when slow to red when fast
+    Hue,
+}
+
+// shows the current typing speed on the board
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct WpmEffect {
+    // how far back presses count towards the speed
+    pub window_secs: f32,
+    // speeds mapped onto the empty and the full bar
+    pub min_wpm: f32,
+    pub max_wpm: f32,
+    pub display: WpmDisplay,
+    // limits the bar to one matrix row, otherwise it spans the whole board
+    pub row: Option<u8>,
+    pub hue: f32,
+    #[serde(skip)]
+    presses: PressTracker,
+    #[serde(skip)]
+    window: VecDeque<Instant>,
+}
+
+impl Default for WpmEffect {
+    fn default() -> Self {
+        WpmEffect {
+            window_secs: 10.0,
+            min_wpm: 0.0,
+            max_wpm: 120.0,
+            display: WpmDisplay::Bar,
+            row: None,
+            hue: 120.0,
+            presses: PressTracker::default(),
+            window: VecDeque::new(),
+        }
+    }
+}
+
+pub fn words_per_minute(presses: usize, window_secs: f32) -> f32 {
+    presses as f32 / CHARS_PER_WORD / window_secs.max(0.1) * 60.0
+}
+
+impl WpmEffect {
+    // where the current speed falls between min_wpm and max_wpm
+    fn fraction(&self) -> f32 {
+        let wpm = words_per_minute(self.window.len(), self.window_secs);
+        let range = (self.max_wpm - self.min_wpm).max(1.0);
+
+        ((wpm - self.min_wpm) / range).clamp(0.0, 1.0)
+    }
+}
+
+impl LedEffect for WpmEffect {
+    fn name(&self) -> &'static str {
+        "wpm"
+    }
+
+    fn reset(&mut self) {
+        self.presses = PressTracker::default();
+        self.window.clear();
+    }
+
+    fn update(&mut self, _delta: f32, state: &mut [LedState], ctx: &EffectContext) {
+        let now = Instant::now();
+        for _ in self.presses.poll(ctx.key_state) {
+            self.window.push_back(now);
+        }
+
+        while self
+            .window
+            .front()
+            .is_some_and(|pressed| pressed.elapsed().as_secs_f32() > self.window_secs)
+        {
+            self.window.pop_front();
+        }
+
+        let fraction = self.fraction();
+
+        for led in state {
+            let key = match led.key {
+                Some(key) => key,
+                None => continue,
+            };
+
+            led.color = match self.display {
+                WpmDisplay::Bar => {
+                    let in_row = self.row.is_none_or(|row| row == key.matrix.0);
+                    let position = ctx.kb_config.relative_position(key).0;
+
+                    if in_row && position < fraction {
+                        Hsva::new(self.hue, 1.0, 1.0, 1.0)
+                    } else {
+                        Hsva::new(0.0, 0.0, 0.0, 1.0)
+                    }
+                }
+                WpmDisplay::Hue => {
+                    Hsva::new(SLOW_HUE + (FAST_HUE - SLOW_HUE) * fraction, 1.0, 1.0, 1.0)
+                }
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn five_chars_per_word() {
+        // 50 presses in 10 seconds is 10 words in a sixth of a minute
+        assert_eq!(words_per_minute(50, 10.0), 60.0);
+        assert_eq!(words_per_minute(0, 10.0), 0.0);
+    }
+
+    #[test]
+    fn fraction_clamps_to_range() {
+        let mut effect = WpmEffect {
+            min_wpm: 20.0,
+            max_wpm: 80.0,
+            ..Default::default()
+        };
+        let now = Instant::now();
+
+        // 50 presses in the default window is 60 wpm
+        effect.window.extend(std::iter::repeat_n(now, 50));
+        assert!((effect.fraction() - 40.0 / 60.0).abs() < 1e-5);
+
+        effect.window.extend(std::iter::repeat_n(now, 500));
+        assert_eq!(effect.fraction(), 1.0);
+
+        effect.window.clear();
+        assert_eq!(effect.fraction(), 0.0);
+    }
+}
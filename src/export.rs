@@ -0,0 +1,192 @@
+// the keyboard as drawn in the keyboard window, written out as an svg for sharing layouts
+use egui::{emath::Rot2, Color32, Pos2, Rect, Vec2};
+
+// one key's outline and text, relative to the board's top left corner. everything is laid out
+// as if the key wasn't rotated, then turned `rotation` radians clockwise about `pivot`
+pub struct KeyShape {
+    pub matrix: (u8, u8),
+    pub rect: Rect,
+    pub rounding: f32,
+    pub fill: Color32,
+    pub border_width: f32,
+    // the key's led, transparent for keys without one
+    pub border: Color32,
+    pub text_color: Color32,
+    pub font_size: f32,
+    // the legend wraps to `legend_width` from its top left corner at `legend_pos`
+    pub legend: Option<String>,
+    pub legend_pos: Pos2,
+    pub legend_width: f32,
+    // the usage letter, drawn smaller in the bottom right corner
+    pub letter: Option<&'static str>,
+    pub text_margin: f32,
+    pub rotation: f32,
+    pub pivot: Pos2,
+}
+
+impl KeyShape {
+    // a point laid out with the key, where it's drawn
+    pub fn turn(&self, pos: Pos2) -> Pos2 {
+        self.pivot + Rot2::from_angle(self.rotation) * (pos - self.pivot)
+    }
+
+    // clockwise from the top left
+    pub fn corners(&self) -> [Pos2; 4] {
+        let rect = self.rect;
+        [
+            rect.left_top(),
+            rect.right_top(),
+            rect.right_bottom(),
+            rect.left_bottom(),
+        ]
+        .map(|corner| self.turn(corner))
+    }
+
+    // the smallest upright rect around the key as drawn
+    pub fn bounds(&self) -> Rect {
+        Rect::from_points(&self.corners())
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// `fill="..." fill-opacity="..."` for the given attribute
+fn paint(attr: &str, color: Color32) -> String {
+    let [r, g, b, a] = color.to_srgba_unmultiplied();
+    format!(
+        r##"{attr}="#{r:02x}{g:02x}{b:02x}" {attr}-opacity="{:.3}""##,
+        a as f32 / 255.0
+    )
+}
+
+// legends aren't wrapped like in the ui, long ones run past their key
+pub fn to_svg(shapes: &[KeyShape], size: Vec2) -> String {
+    let mut lines = vec![format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w:.1}" height="{h:.1}" viewBox="0 0 {w:.1} {h:.1}">"#,
+        w = size.x,
+        h = size.y
+    )];
+
+    for shape in shapes {
+        let rect = shape.rect;
+        let rotated = shape.rotation != 0.0;
+        if rotated {
+            lines.push(format!(
+                r#"  <g transform="rotate({:.2} {:.2} {:.2})">"#,
+                shape.rotation.to_degrees(),
+                shape.pivot.x,
+                shape.pivot.y
+            ));
+        }
+        lines.push(format!(
+            r#"  <rect x="{:.2}" y="{:.2}" width="{:.2}" height="{:.2}" rx="{:.2}" {} {} stroke-width="{:.2}"/>"#,
+            rect.min.x,
+            rect.min.y,
+            rect.width(),
+            rect.height(),
+            shape.rounding,
+            paint("fill", shape.fill),
+            paint("stroke", shape.border),
+            shape.border_width,
+        ));
+
+        if let Some(legend) = &shape.legend {
+            lines.push(format!(
+                r#"  <text x="{:.2}" y="{:.2}" font-family="sans-serif" font-size="{:.2}" dominant-baseline="hanging" {}>{}</text>"#,
+                shape.legend_pos.x,
+                shape.legend_pos.y,
+                shape.font_size,
+                paint("fill", shape.text_color),
+                escape(legend),
+            ));
+        }
+
+        if let Some(letter) = shape.letter {
+            let corner = rect.right_bottom() - Vec2::splat(shape.text_margin);
+            lines.push(format!(
+                r#"  <text x="{:.2}" y="{:.2}" font-family="monospace" font-size="{:.2}" text-anchor="end" {}>{}</text>"#,
+                corner.x,
+                corner.y,
+                shape.font_size * 0.7,
+                paint("fill", shape.text_color),
+                letter,
+            ));
+        }
+
+        if rotated {
+            lines.push("  </g>".to_string());
+        }
+    }
+
+    lines.push("</svg>\n".to_string());
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_keys_and_legends() {
+        let shape = KeyShape {
+            matrix: (0, 0),
+            rect: Rect::from_min_size(Pos2::new(1.0, 2.0), Vec2::new(40.0, 40.0)),
+            rounding: 4.0,
+            fill: Color32::from_rgb(0x20, 0x30, 0x40),
+            border_width: 2.0,
+            border: Color32::TRANSPARENT,
+            text_color: Color32::WHITE,
+            font_size: 14.0,
+            legend: Some("<&>".to_string()),
+            legend_pos: Pos2::new(5.0, 6.0),
+            legend_width: 32.0,
+            letter: None,
+            text_margin: 4.0,
+            rotation: 0.0,
+            pivot: Pos2::ZERO,
+        };
+
+        let svg = to_svg(&[shape], Vec2::new(45.0, 45.0));
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains(r##"<rect x="1.00" y="2.00" width="40.00" height="40.00" rx="4.00" fill="#203040" fill-opacity="1.000" stroke="#000000" stroke-opacity="0.000""##));
+        assert!(svg.contains(">&lt;&amp;&gt;</text>"));
+        assert!(!svg.contains("<g"));
+        assert!(svg.trim_end().ends_with("</svg>"));
+    }
+
+    #[test]
+    fn rotated_keys_turn_about_their_pivot() {
+        let shape = KeyShape {
+            matrix: (0, 0),
+            rect: Rect::from_min_size(Pos2::new(10.0, 0.0), Vec2::new(20.0, 10.0)),
+            rounding: 0.0,
+            fill: Color32::WHITE,
+            border_width: 0.0,
+            border: Color32::TRANSPARENT,
+            text_color: Color32::BLACK,
+            font_size: 14.0,
+            legend: None,
+            legend_pos: Pos2::new(12.0, 2.0),
+            legend_width: 16.0,
+            letter: None,
+            text_margin: 2.0,
+            rotation: std::f32::consts::FRAC_PI_2,
+            pivot: Pos2::new(10.0, 0.0),
+        };
+
+        // a quarter turn clockwise hangs the key down from its pivot, on the left of it
+        let bounds = shape.bounds();
+        assert!((bounds.min - Pos2::new(0.0, 0.0)).length() < 1e-4);
+        assert!((bounds.max - Pos2::new(10.0, 20.0)).length() < 1e-4);
+        assert!((shape.turn(shape.legend_pos) - Pos2::new(8.0, 2.0)).length() < 1e-4);
+
+        let svg = to_svg(&[shape], Vec2::new(30.0, 30.0));
+        assert!(svg.contains(r#"<g transform="rotate(90.00 10.00 0.00)">"#));
+        assert!(svg.contains("  </g>"));
+    }
+}
@@ -0,0 +1,280 @@
+// names for qmk keycodes as the firmware reports them in press messages. the ranges follow
+// qmk's keycodes.h as of 0.19
+
+// basic keycodes (hid keyboard usages) from KC_NO, by code
+const BASIC: &[&str] = &[
+    "KC_NO",
+    "KC_TRNS",
+    "",
+    "",
+    "KC_A",
+    "KC_B",
+    "KC_C",
+    "KC_D",
+    "KC_E",
+    "KC_F",
+    "KC_G",
+    "KC_H",
+    "KC_I",
+    "KC_J",
+    "KC_K",
+    "KC_L",
+    "KC_M",
+    "KC_N",
+    "KC_O",
+    "KC_P",
+    "KC_Q",
+    "KC_R",
+    "KC_S",
+    "KC_T",
+    "KC_U",
+    "KC_V",
+    "KC_W",
+    "KC_X",
+    "KC_Y",
+    "KC_Z",
+    "KC_1",
+    "KC_2",
+    "KC_3",
+    "KC_4",
+    "KC_5",
+    "KC_6",
+    "KC_7",
+    "KC_8",
+    "KC_9",
+    "KC_0",
+    "KC_ENT",
+    "KC_ESC",
+    "KC_BSPC",
+    "KC_TAB",
+    "KC_SPC",
+    "KC_MINS",
+    "KC_EQL",
+    "KC_LBRC",
+    "KC_RBRC",
+    "KC_BSLS",
+    "KC_NUHS",
+    "KC_SCLN",
+    "KC_QUOT",
+    "KC_GRV",
+    "KC_COMM",
+    "KC_DOT",
+    "KC_SLSH",
+    "KC_CAPS",
+    "KC_F1",
+    "KC_F2",
+    "KC_F3",
+    "KC_F4",
+    "KC_F5",
+    "KC_F6",
+    "KC_F7",
+    "KC_F8",
+    "KC_F9",
+    "KC_F10",
+    "KC_F11",
+    "KC_F12",
+    "KC_PSCR",
+    "KC_SCRL",
+    "KC_PAUS",
+    "KC_INS",
+    "KC_HOME",
+    "KC_PGUP",
+    "KC_DEL",
+    "KC_END",
+    "KC_PGDN",
+    "KC_RGHT",
+    "KC_LEFT",
+    "KC_DOWN",
+    "KC_UP",
+    "KC_NUM",
+    "KC_PSLS",
+    "KC_PAST",
+    "KC_PMNS",
+    "KC_PPLS",
+    "KC_PENT",
+    "KC_P1",
+    "KC_P2",
+    "KC_P3",
+    "KC_P4",
+    "KC_P5",
+    "KC_P6",
+    "KC_P7",
+    "KC_P8",
+    "KC_P9",
+    "KC_P0",
+    "KC_PDOT",
+    "KC_NUBS",
+    "KC_APP",
+    "KC_KB_POWER",
+    "KC_PEQL",
+    "KC_F13",
+    "KC_F14",
+    "KC_F15",
+    "KC_F16",
+    "KC_F17",
+    "KC_F18",
+    "KC_F19",
+    "KC_F20",
+    "KC_F21",
+    "KC_F22",
+    "KC_F23",
+    "KC_F24",
+];
+
+// KC_LCTL through KC_RGUI, 0xE0-0xE7
+const MODIFIERS: &[&str] = &[
+    "KC_LCTL", "KC_LSFT", "KC_LALT", "KC_LGUI", "KC_RCTL", "KC_RSFT", "KC_RALT", "KC_RGUI",
+];
+
+// keycodes that carry a parameter (a layer, modifiers or a basic keycode): first code, last
+// code and the macro name
+const RANGES: &[(u16, u16, &str)] = &[
+    (0x0100, 0x1FFF, "MODS"),
+    (0x2000, 0x3FFF, "MT"),
+    (0x4000, 0x4FFF, "LT"),
+    (0x5000, 0x51FF, "LM"),
+    (0x5200, 0x521F, "TO"),
+    (0x5220, 0x523F, "MO"),
+    (0x5240, 0x525F, "DF"),
+    (0x5260, 0x527F, "TG"),
+    (0x5280, 0x529F, "OSL"),
+    (0x52A0, 0x52BF, "OSM"),
+    (0x52C0, 0x52DF, "TT"),
+];
+
+// the name of a basic or modifier keycode, or the macro name (e.g. "LT") of a keycode with a
+// parameter
+pub fn keycode_name(code: u16) -> Option<&'static str> {
+    match code {
+        0x00..=0xDF => BASIC
+            .get(code as usize)
+            .copied()
+            .filter(|name| !name.is_empty()),
+        0xE0..=0xE7 => Some(MODIFIERS[code as usize - 0xE0]),
+        _ => RANGES
+            .iter()
+            .find(|(first, last, _)| (*first..=*last).contains(&code))
+            .map(|(_, _, name)| *name),
+    }
+}
+
+// the modifier bits used by MT, LM, OSM and the modifier keycodes: ctrl, shift, alt and gui,
+// with bit 4 switching all of them to the right hand side
+fn mods_name(mods: u16) -> String {
+    let side = if mods & 0x10 != 0 { "R" } else { "L" };
+    let names = ["CTL", "SFT", "ALT", "GUI"]
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| mods & (1 << bit) != 0)
+        .map(|(_, name)| format!("MOD_{}{}", side, name))
+        .collect::<Vec<_>>();
+
+    if names.is_empty() {
+        "0".to_string()
+    } else {
+        names.join(" | ")
+    }
+}
+
+// wraps a keycode in the modifier macros held with it, e.g. LCTL(LSFT(KC_A))
+fn with_mods(mods: u16, keycode: String) -> String {
+    let side = if mods & 0x10 != 0 { "R" } else { "L" };
+
+    ["CTL", "SFT", "ALT", "GUI"]
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(bit, _)| mods & (1 << bit) != 0)
+        .fold(keycode, |inner, (_, name)| {
+            format!("{}{}({})", side, name, inner)
+        })
+}
+
+// the modifiers a keycode holds or applies, in the 8 bit layout firmware reports them in (see
+// protocol::Mods). 0 for keycodes without any
+pub fn modifier_bits(code: u16) -> u8 {
+    // the 5 bit form, bit 4 for the right hand side
+    let spread = |mods: u16| {
+        let bits = (mods & 0xF) as u8;
+        if mods & 0x10 != 0 {
+            bits << 4
+        } else {
+            bits
+        }
+    };
+
+    if (0xE0..=0xE7).contains(&code) {
+        return 1 << (code - 0xE0);
+    }
+
+    match keycode_name(code) {
+        Some("MODS" | "MT") => spread(code >> 8 & 0x1F),
+        Some("LM" | "OSM") => spread(code & 0x1F),
+        _ => 0,
+    }
+}
+
+fn basic_name(code: u16) -> String {
+    keycode_name(code).map_or_else(|| format!("{:#04x}", code), str::to_string)
+}
+
+// the keycode the way a keymap would spell it, e.g. `LT(1, KC_SPC)`, or its hex value if
+// it's not known
+pub fn describe(code: u16) -> String {
+    let layer = code & 0x1F;
+
+    match keycode_name(code) {
+        Some("MODS") => with_mods(code >> 8 & 0x1F, basic_name(code & 0xFF)),
+        Some("MT") => format!(
+            "MT({}, {})",
+            mods_name(code >> 8 & 0x1F),
+            basic_name(code & 0xFF)
+        ),
+        Some("LT") => format!("LT({}, {})", code >> 8 & 0xF, basic_name(code & 0xFF)),
+        Some("LM") => format!("LM({}, {})", code >> 5 & 0xF, mods_name(code & 0x1F)),
+        Some("OSM") => format!("OSM({})", mods_name(code & 0x1F)),
+        Some(name @ ("TO" | "MO" | "DF" | "TG" | "OSL" | "TT")) => format!("{}({})", name, layer),
+        Some(name) => name.to_string(),
+        None => format!("{:#06x}", code),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn basic_and_modifier_names() {
+        assert_eq!(keycode_name(0x04), Some("KC_A"));
+        assert_eq!(keycode_name(0x27), Some("KC_0"));
+        assert_eq!(keycode_name(0x2C), Some("KC_SPC"));
+        assert_eq!(keycode_name(0x73), Some("KC_F24"));
+        assert_eq!(keycode_name(0xE1), Some("KC_LSFT"));
+        assert_eq!(keycode_name(0x02), None);
+        assert_eq!(keycode_name(0x74), None);
+        assert_eq!(keycode_name(0xFFFF), None);
+    }
+
+    #[test]
+    fn parameterized_keycodes() {
+        assert_eq!(describe(0x412C), "LT(1, KC_SPC)");
+        assert_eq!(describe(0x5221), "MO(1)");
+        assert_eq!(describe(0x5263), "TG(3)");
+        assert_eq!(describe(0x2104), "MT(MOD_LCTL, KC_A)");
+        assert_eq!(describe(0x3204), "MT(MOD_RSFT, KC_A)");
+        assert_eq!(describe(0x0304), "LCTL(LSFT(KC_A))");
+        assert_eq!(describe(0x04), "KC_A");
+        assert_eq!(describe(0x7E00), "0x7e00");
+    }
+
+    #[test]
+    fn modifier_bits_of_keycodes() {
+        assert_eq!(modifier_bits(0xE0), 0b0000_0001);
+        assert_eq!(modifier_bits(0xE5), 0b0010_0000);
+        // MT(MOD_RCTL | MOD_RSFT, KC_A)
+        assert_eq!(modifier_bits(0x3304), 0b0011_0000);
+        // OSM(MOD_LGUI)
+        assert_eq!(modifier_bits(0x52A8), 0b0000_1000);
+        assert_eq!(modifier_bits(0x04), 0);
+    }
+}
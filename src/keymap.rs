@@ -0,0 +1,357 @@
+// legends derived from qmk's keymap.json or a keymap saved from via, for keymaps without a hand
+// written legends.json. the keycodes in every layer of keymap.json are in the order of the layout
+// macro, which is the order of the layout's keys in info.json. via saves every matrix position
+// instead, row by row
+use crate::config::{KBLegends, KeyDef, KeyUsage, LayerDef, QMKLayout};
+use serde::Deserialize;
+
+// how legends are derived from either file's contents, for the layout and matrix size
+pub type Derive = fn(&str, &QMKLayout, usize, usize) -> Result<KBLegends, String>;
+
+// both files have more, only the layers matter here
+#[derive(Deserialize)]
+struct QMKKeymap {
+    layers: Vec<Vec<String>>,
+}
+
+// modifier keycodes and their long spellings
+const MODIFIERS: &[&str] = &[
+    "KC_LCTL",
+    "KC_LSFT",
+    "KC_LALT",
+    "KC_LGUI",
+    "KC_RCTL",
+    "KC_RSFT",
+    "KC_RALT",
+    "KC_RGUI",
+    "KC_LCTRL",
+    "KC_LSHIFT",
+    "KC_LOPT",
+    "KC_LCMD",
+    "KC_LWIN",
+    "KC_RCTRL",
+    "KC_RSHIFT",
+    "KC_ROPT",
+    "KC_RCMD",
+    "KC_RWIN",
+    "KC_ALGR",
+];
+
+// macros that take a layer and switch to it one way or another
+const LAYER_MACROS: &[&str] = &["MO", "TG", "TO", "DF", "OSL", "TT", "LM"];
+
+// what's printed on keys whose keycode name doesn't say it well
+const SYMBOLS: &[(&str, &str)] = &[
+    ("MINS", "-"),
+    ("EQL", "="),
+    ("LBRC", "["),
+    ("RBRC", "]"),
+    ("BSLS", "\\"),
+    ("SCLN", ";"),
+    ("QUOT", "'"),
+    ("GRV", "`"),
+    ("COMM", ","),
+    ("DOT", "."),
+    ("SLSH", "/"),
+    ("LEFT", "←"),
+    ("RGHT", "→"),
+    ("UP", "↑"),
+    ("DOWN", "↓"),
+];
+
+// splits `LT(1, KC_SPC)` into `LT` and its arguments, or None for a plain keycode
+fn split_call(keycode: &str) -> Option<(&str, Vec<&str>)> {
+    let open = keycode.find('(')?;
+    let inner = keycode[open + 1..].strip_suffix(')')?;
+
+    let mut args = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (idx, c) in inner.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                args.push(inner[start..idx].trim());
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    args.push(inner[start..].trim());
+
+    Some((keycode[..open].trim(), args))
+}
+
+// the legend for a keycode without a macro around it, e.g. KC_SCLN as ;
+fn basic_label(keycode: &str) -> String {
+    let name = keycode.strip_prefix("KC_").unwrap_or(keycode);
+
+    SYMBOLS
+        .iter()
+        .find(|(symbol, _)| *symbol == name)
+        .map_or_else(|| name.to_string(), |(_, label)| label.to_string())
+}
+
+fn basic_usage(keycode: &str) -> KeyUsage {
+    let name = keycode.strip_prefix("KC_").unwrap_or(keycode);
+    let function_key = name
+        .strip_prefix('F')
+        .is_some_and(|num| !num.is_empty() && num.chars().all(|c| c.is_ascii_digit()));
+
+    if MODIFIERS.contains(&keycode) {
+        KeyUsage::Modifier
+    } else if keycode.starts_with("KC_MS_")
+        || keycode.starts_with("KC_BTN")
+        || keycode.starts_with("KC_WH_")
+        || keycode.starts_with("MS_")
+    {
+        KeyUsage::Mouse
+    } else if function_key || keycode.starts_with("QK_") || keycode.starts_with("RGB_") {
+        KeyUsage::Function
+    } else {
+        KeyUsage::Default
+    }
+}
+
+// the number of a numbered keycode in any of its spellings, e.g. MACRO01, MACRO(1) or
+// QK_MACRO_1 for the `["MACRO", "QK_MACRO_"]` prefixes
+fn numbered(keycode: &str, prefixes: &[&str]) -> Option<u32> {
+    prefixes.iter().find_map(|prefix| {
+        let rest = keycode.strip_prefix(prefix)?;
+        let num = rest
+            .strip_prefix('(')
+            .and_then(|rest| rest.strip_suffix(')'))
+            .unwrap_or(rest);
+        num.trim().parse().ok()
+    })
+}
+
+// what a keycode string from keymap.json or via looks like on the key and how it's used. macros
+// and the keyboard's custom keycodes only get their number, what they do isn't in the keymap
+pub fn key_def(keycode: &str) -> KeyDef {
+    let keycode = keycode.trim();
+    let def = |usage, label: Option<String>| KeyDef { usage, label };
+
+    match keycode {
+        "KC_TRNS" | "KC_TRANSPARENT" | "_______" => return def(KeyUsage::Passthrough, None),
+        "KC_NO" | "XXXXXXX" => return def(KeyUsage::Unused, None),
+        _ => {}
+    }
+
+    if let Some(num) = numbered(keycode, &["MACRO", "QK_MACRO_"]) {
+        return def(KeyUsage::Default, Some(format!("Macro {}", num)));
+    }
+    if let Some(num) = numbered(keycode, &["USER", "CUSTOM", "QK_KB_", "QK_USER_"]) {
+        return def(KeyUsage::Default, Some(format!("Custom {}", num)));
+    }
+    // via writes keycodes it has no name for as numbers, e.g. 0x7e40
+    if keycode.starts_with("0x") {
+        return def(KeyUsage::Default, Some(keycode.to_string()));
+    }
+
+    match split_call(keycode) {
+        Some(("LT", args)) if args.len() == 2 => {
+            def(KeyUsage::Layertap, Some(basic_label(args[1])))
+        }
+        // older via exports spell LT(1, KC_A) as LT1(KC_A)
+        Some((name, args))
+            if args.len() == 1
+                && name
+                    .strip_prefix("LT")
+                    .is_some_and(|layer| layer.parse::<u8>().is_ok()) =>
+        {
+            def(KeyUsage::Layertap, Some(basic_label(args[0])))
+        }
+        Some(("MT", args)) if args.len() == 2 => def(KeyUsage::Modtap, Some(basic_label(args[1]))),
+        // LCTL_T(KC_A) and friends
+        Some((name, args)) if name.ends_with("_T") && args.len() == 1 => {
+            def(KeyUsage::Modtap, Some(basic_label(args[0])))
+        }
+        Some((name, _)) if LAYER_MACROS.contains(&name) => {
+            def(KeyUsage::Layer, Some(keycode.to_string()))
+        }
+        Some(("OSM", _)) => def(KeyUsage::Modifier, Some(keycode.to_string())),
+        // modifiers held with a key, e.g. LCTL(KC_C)
+        Some((name, args)) if args.len() == 1 => def(
+            basic_usage(args[0]),
+            Some(format!("{}+{}", name, basic_label(args[0]))),
+        ),
+        Some(_) => def(KeyUsage::Default, Some(keycode.to_string())),
+        None => def(basic_usage(keycode), Some(basic_label(keycode))),
+    }
+}
+
+// a legends grid of `rows` x `cols` for every layer of the keymap, positions the layout doesn't
+// use are left removed
+pub fn legends(
+    keymap_json: &str,
+    layout: &QMKLayout,
+    rows: usize,
+    cols: usize,
+) -> Result<KBLegends, String> {
+    let keymap: QMKKeymap =
+        serde_json::from_str(keymap_json).map_err(|err| format!("invalid keymap.json: {}", err))?;
+
+    let mut layers = Vec::new();
+    for (idx, keycodes) in keymap.layers.iter().enumerate() {
+        if keycodes.len() != layout.layout.len() {
+            return Err(format!(
+                "keymap.json layer {} has {} keycodes but the layout has {} keys",
+                idx,
+                keycodes.len(),
+                layout.layout.len()
+            ));
+        }
+
+        let keycode = |key: usize, _: (usize, usize)| keycodes[key].as_str();
+        layers.push(layer(idx, layout, rows, cols, keycode));
+    }
+
+    Ok(KBLegends::new(layers))
+}
+
+// the same from a keymap saved in via, which has every matrix position whether the layout uses
+// it or not
+pub fn via_legends(
+    via_json: &str,
+    layout: &QMKLayout,
+    rows: usize,
+    cols: usize,
+) -> Result<KBLegends, String> {
+    let keymap: QMKKeymap =
+        serde_json::from_str(via_json).map_err(|err| format!("invalid via keymap: {}", err))?;
+
+    let mut layers = Vec::new();
+    for (idx, keycodes) in keymap.layers.iter().enumerate() {
+        if keycodes.len() != rows * cols {
+            return Err(format!(
+                "via layer {} has {} keycodes but the matrix has {} positions, is it for this keyboard?",
+                idx,
+                keycodes.len(),
+                rows * cols
+            ));
+        }
+
+        let keycode = |_: usize, (row, col): (usize, usize)| keycodes[row * cols + col].as_str();
+        layers.push(layer(idx, layout, rows, cols, keycode));
+    }
+
+    Ok(KBLegends::new(layers))
+}
+
+// layer `number` with the keycode `keycode` gives every key of the layout, by its index there and
+// its matrix position. positions the layout doesn't use are left removed
+fn layer<'a>(
+    number: usize,
+    layout: &QMKLayout,
+    rows: usize,
+    cols: usize,
+    keycode: impl Fn(usize, (usize, usize)) -> &'a str,
+) -> LayerDef {
+    let removed = KeyDef {
+        usage: KeyUsage::Removed,
+        label: None,
+    };
+    let mut grid = vec![vec![removed; cols]; rows];
+    for (idx, key) in layout.layout.iter().enumerate() {
+        let (row, col) = (key.matrix.0 as usize, key.matrix.1 as usize);
+        // out of bounds keys are reported by KBConfig::validate
+        if let Some(cell) = grid.get_mut(row).and_then(|r| r.get_mut(col)) {
+            *cell = key_def(keycode(idx, (row, col)));
+        }
+    }
+
+    LayerDef::new(format!("layer {}", number), grid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(keycode: &str) -> String {
+        format!("{:?}", key_def(keycode).usage)
+    }
+
+    fn label(keycode: &str) -> Option<String> {
+        key_def(keycode).label
+    }
+
+    #[test]
+    fn classifies_keycodes() {
+        assert_eq!(usage("MO(1)"), "Layer");
+        assert_eq!(usage("LT(2, KC_A)"), "Layertap");
+        assert_eq!(usage("MT(MOD_LCTL | MOD_LSFT, KC_ESC)"), "Modtap");
+        assert_eq!(usage("LSFT_T(KC_Z)"), "Modtap");
+        assert_eq!(usage("KC_LSFT"), "Modifier");
+        assert_eq!(usage("OSM(MOD_LGUI)"), "Modifier");
+        assert_eq!(usage("KC_F11"), "Function");
+        assert_eq!(usage("QK_BOOT"), "Function");
+        assert_eq!(usage("KC_MS_UP"), "Mouse");
+        assert_eq!(usage("_______"), "Passthrough");
+        assert_eq!(usage("XXXXXXX"), "Unused");
+        assert_eq!(usage("KC_FIND"), "Default");
+        assert_eq!(usage("KC_A"), "Default");
+    }
+
+    #[test]
+    fn labels_keycodes() {
+        assert_eq!(label("KC_A").as_deref(), Some("A"));
+        assert_eq!(label("KC_SCLN").as_deref(), Some(";"));
+        assert_eq!(label("LT(2, KC_SPC)").as_deref(), Some("SPC"));
+        assert_eq!(label("LCTL(KC_C)").as_deref(), Some("LCTL+C"));
+        assert_eq!(label("TG(3)").as_deref(), Some("TG(3)"));
+        assert_eq!(label("KC_TRNS"), None);
+
+        // via's spellings
+        assert_eq!(label("MACRO02").as_deref(), Some("Macro 2"));
+        assert_eq!(label("MACRO(2)").as_deref(), Some("Macro 2"));
+        assert_eq!(label("CUSTOM(0)").as_deref(), Some("Custom 0"));
+        assert_eq!(label("USER01").as_deref(), Some("Custom 1"));
+        assert_eq!(label("0x7e40").as_deref(), Some("0x7e40"));
+        assert_eq!(usage("LT1(KC_SPC)"), "Layertap");
+        assert_eq!(label("LT1(KC_SPC)").as_deref(), Some("SPC"));
+    }
+
+    #[test]
+    fn maps_layout_order_to_matrix() {
+        // the layout macro lists B before A
+        let layout: QMKLayout = serde_json::from_str(
+            r#"{"layout": [
+                {"label": "B", "x": 1, "y": 0, "matrix": [0, 1]},
+                {"label": "A", "x": 0, "y": 0, "matrix": [0, 0]}
+            ]}"#,
+        )
+        .unwrap();
+
+        let keymap = r#"{"layers": [["KC_B", "KC_A"], ["_______", "MO(1)"]]}"#;
+        let legends = legends(keymap, &layout, 2, 2).unwrap();
+
+        let get = |layer_state, row, col| legends.get_key(layer_state, row, col).unwrap();
+        assert_eq!(get(0, 0, 0).label.as_deref(), Some("A"));
+        assert_eq!(get(0, 0, 1).label.as_deref(), Some("B"));
+        // transparent falls through to the base layer
+        assert_eq!(get(0b10, 0, 1).label.as_deref(), Some("B"));
+        assert_eq!(get(0b10, 0, 0).label.as_deref(), Some("MO(1)"));
+        assert!(matches!(get(0, 1, 0).usage, KeyUsage::Removed));
+
+        let short = r#"{"layers": [["KC_A"]]}"#;
+        assert!(super::legends(short, &layout, 2, 2).is_err());
+
+        // via has every position of the 2x2 matrix, row by row
+        let via = r#"{"name": "test", "vendorProductId": 1, "macros": [], "layers": [
+            ["KC_A", "KC_B", "KC_NO", "KC_NO"],
+            ["MACRO(0)", "KC_TRNS", "KC_NO", "KC_NO"]
+        ]}"#;
+        let legends = via_legends(via, &layout, 2, 2).unwrap();
+
+        let get = |layer_state, row, col| legends.get_key(layer_state, row, col).unwrap();
+        assert_eq!(get(0, 0, 0).label.as_deref(), Some("A"));
+        assert_eq!(get(0, 0, 1).label.as_deref(), Some("B"));
+        assert_eq!(get(0b10, 0, 0).label.as_deref(), Some("Macro 0"));
+        assert_eq!(get(0b10, 0, 1).label.as_deref(), Some("B"));
+        // the layout doesn't use the second row
+        assert!(matches!(get(0, 1, 0).usage, KeyUsage::Removed));
+
+        assert!(via_legends(short, &layout, 2, 2).is_err());
+    }
+}
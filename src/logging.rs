@@ -0,0 +1,63 @@
+use log::{LevelFilter, Log, Metadata, Record};
+use std::{io::Write, str::FromStr, sync::OnceLock, time::Instant};
+
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Info;
+
+struct Logger {
+    start: Instant,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        // e.g. [   1.234s DEBUG kb_host::threading] message
+        let elapsed = self.start.elapsed().as_secs_f32();
+        let mut stderr = std::io::stderr().lock();
+        writeln!(
+            stderr,
+            "[{:>8.3}s {:<5} {}] {}",
+            elapsed,
+            record.level(),
+            record.target(),
+            record.args()
+        )
+        .ok();
+    }
+
+    fn flush(&self) {
+        std::io::stderr().flush().ok();
+    }
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+// --log-level if it's given, then RUST_LOG, then the default
+pub fn level_from_env(args: &[String]) -> LevelFilter {
+    let arg_level = args
+        .iter()
+        .position(|arg| arg == "--log-level")
+        .and_then(|idx| args.get(idx + 1))
+        .cloned();
+
+    arg_level
+        .or_else(|| std::env::var("RUST_LOG").ok())
+        .and_then(|level| LevelFilter::from_str(level.trim()).ok())
+        .unwrap_or(DEFAULT_LEVEL)
+}
+
+pub fn init(level: LevelFilter) {
+    let logger = LOGGER.get_or_init(|| Logger {
+        start: Instant::now(),
+    });
+
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(level);
+    }
+}
@@ -1,5 +1,12 @@
 use hidapi::HidApi;
-use std::{fs, io, sync::Arc};
+use log::{debug, info};
+use serde::de::DeserializeOwned;
+use std::{
+    fs,
+    io::{self, IsTerminal},
+    path::Path,
+    sync::{Arc, Mutex},
+};
 
 mod config;
 use config::*;
@@ -13,77 +20,305 @@ mod protocol;
 
 mod effects;
 
-const CONFIG_PATH: &str = "kb_host/config.json";
-const UPDATE_RATE: f32 = 240.0; // <5 ms per update
-const FPS: f32 = 20.0;
+mod bench;
 
-fn read_config() -> Result<KBConfig, io::Error> {
-    // host configuration
-    let config_contents = fs::read_to_string(
-        dirs::config_dir()
-            .expect("no config directory")
-            .join(CONFIG_PATH),
-    )?;
-    let config: Config = serde_json::from_str(&config_contents)?;
+mod cli;
+use cli::{Command, Options};
 
-    // info.json and matrix.json
-    let keyboard_path = dirs::home_dir()
-        .expect("no home directory")
-        .join("qmk_firmware/keyboards")
-        .join(&config.kb);
+mod control;
 
-    let qmk_info_contents = fs::read_to_string(keyboard_path.join("info.json"))?;
-    let qmk_info: QMKInfo = serde_json::from_str(&qmk_info_contents)?;
+mod device;
+use device::{DeviceSource, DeviceWatcher};
 
-    let matrix_contents = fs::read_to_string(keyboard_path.join("matrix.json"))?;
-    let matrix: LEDMatrix = serde_json::from_str(&matrix_contents)?;
+mod export;
 
-    // legends.json
-    let keymap_path = keyboard_path.join("keymaps").join(&config.keymap);
+mod screen;
 
-    let legends_contents = fs::read_to_string(keymap_path.join("legends.json"))?;
-    let legends: KBLegends = serde_json::from_str(&legends_contents)?;
+mod audio;
 
-    Ok(KBConfig::new(config, qmk_info, matrix, legends))
+mod keycode;
+
+mod keymap;
+
+mod logging;
+
+#[cfg(feature = "mqtt")]
+mod mqtt;
+
+#[cfg(feature = "openrgb")]
+mod openrgb;
+
+mod paths;
+use paths::{ConfigSource, Overrides};
+
+mod presets;
+
+mod priority;
+
+mod profile;
+
+mod reload;
+
+mod theme;
+
+mod transport;
+
+mod rest;
+
+mod schedule;
+
+mod websocket;
+
+mod wizard;
+
+fn read_configs(overrides: &Overrides) -> Result<Vec<KBConfig>, StartupError> {
+    overrides
+        .host_configs()?
+        .into_iter()
+        .map(|config| read_keyboard(overrides, config))
+        .collect()
+}
+
+fn read_file(path: &Path) -> Result<String, StartupError> {
+    debug!("reading {}", path.display());
+    fs::read_to_string(path).map_err(|err| StartupError::read(path, err))
+}
+
+fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T, StartupError> {
+    serde_json::from_str(&read_file(path)?).map_err(|err| StartupError::parse(path, err))
+}
+
+// info.json (or keyboard.json), merged from the keyboard's folder and its parents
+fn read_info(overrides: &Overrides, config: &Config) -> Result<QMKInfo, StartupError> {
+    let info_paths = overrides.info_paths(config);
+    if info_paths.is_empty() {
+        return Err(StartupError::NoInfo(overrides.keyboard_path(config)));
+    }
+
+    let mut qmk_info = serde_json::Value::Null;
+    for path in &info_paths {
+        QMKInfo::merge(&mut qmk_info, read_json(path)?);
+    }
+    // the merged info only turns out malformed here, the innermost file is the likeliest culprit
+    serde_json::from_value(qmk_info)
+        .map_err(|err| StartupError::parse(&info_paths[info_paths.len() - 1], err))
+}
+
+// the keyboard's qmk files for its host config
+fn read_keyboard(overrides: &Overrides, config: Config) -> Result<KBConfig, StartupError> {
+    info!("loaded host config for {} ({})", config.kb, config.keymap);
+
+    // info.json, and matrix.json unless info.json has the leds
+    let qmk_info = read_info(overrides, &config)?;
+
+    let matrix_path = overrides.matrix_path(&config);
+    let matrix: LEDMatrix = match qmk_info.led_matrix() {
+        Some(matrix) if !matrix_path.is_file() => {
+            info!("no matrix.json, taking the leds from info.json");
+            matrix
+        }
+        _ => read_json(&matrix_path)?,
+    };
+
+    // legends.json, or what keymap.json or a keymap saved from via says if there isn't one
+    let legends_path = overrides.legends_path(&config);
+    let keymap_path = overrides.keymap_path(&config);
+    let via_path = overrides.via_path(&config);
+
+    let derive = if legends_path.is_file() {
+        None
+    } else if keymap_path.is_file() {
+        Some((&keymap_path, keymap::legends as keymap::Derive))
+    } else if via_path.is_file() {
+        Some((&via_path, keymap::via_legends as keymap::Derive))
+    } else {
+        None
+    };
+
+    let legends: KBLegends = if let Some((path, derive)) = derive {
+        info!("no legends.json, deriving legends from {}", path.display());
+        let layout = qmk_info
+            .layouts
+            .get(&config.layout)
+            .ok_or_else(|| ValidationError {
+                problems: vec![format!("layout {} not found in info.json", config.layout)],
+            })?;
+        let cols = matrix.first().map_or(0, |row| row.len());
+
+        derive(&read_file(path)?, layout, matrix.len(), cols)
+            .map_err(|err| StartupError::parse(path, err))?
+    } else {
+        read_json(&legends_path)?
+    };
+
+    Ok(KBConfig::new(config, qmk_info, matrix, legends)?)
 }
 
-fn main() -> Result<(), io::Error> {
-    let kb_config = Arc::new(read_config()?);
-
-    let device = match HidApi::new() {
-        Ok(api) => {
-            let device_info = api
-                .device_list()
-                .find(|device| {
-                    device.vendor_id() == kb_config.qmk_info.usb.vid
-                        && device.product_id() == kb_config.qmk_info.usb.pid
-                        && device.usage_page() == kb_config.host_config.usage_page
-                        && device.usage() == kb_config.host_config.usage
-                })
-                .expect("could not find device");
-
-            api.open_path(device_info.path())
-                .expect("could not open device")
+// bad arguments exit with 2 and the usage, anything failing after that with 1
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let options = match Options::parse(&args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, cli::USAGE);
+            std::process::exit(2);
         }
-        Err(err) => panic!("could not list hid devices: {}", err),
     };
 
-    // thread
-    let mut thread = HIDThread::new(kb_config.clone());
-    thread.start(UPDATE_RATE, FPS, device);
+    if options.command == Command::Help {
+        println!("{}", cli::USAGE);
+        return;
+    }
+
+    logging::init(logging::level_from_env(&args));
+
+    if let Err(err) = run(&options, &Overrides::from_env(&args)) {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn run(options: &Options, overrides: &Overrides) -> Result<(), StartupError> {
+    if options.command == Command::Validate {
+        read_configs(overrides)?.iter().for_each(cli::validate);
+        return Ok(());
+    }
+
+    // only asked when someone is there to answer
+    if options.command == Command::Run
+        && !overrides.config_path().exists()
+        && io::stdin().is_terminal()
+    {
+        wizard::run(overrides)?;
+    }
+
+    let kb_configs: Vec<Arc<KBConfig>> =
+        read_configs(overrides)?.into_iter().map(Arc::new).collect();
+
+    // geometry and benchmarks are for the first keyboard in the config
+    if let Command::DumpGeometry { json } = options.command {
+        return Ok(cli::dump_geometry(&kb_configs[0], json)?);
+    }
+
+    // a mock keyboard doesn't need hidapi, which might not even work where the demo runs
+    let api = match options.mock {
+        true => None,
+        false => Some(
+            HidApi::new()
+                .map_err(|err| StartupError::Hid(format!("could not list devices: {}", err)))?,
+        ),
+    };
+
+    if options.command == Command::Bench {
+        let device = api
+            .as_ref()
+            .and_then(|api| device::open_device(api, &kb_configs[0]));
+        let Some(device) = device else {
+            return Err(StartupError::DeviceNotFound(
+                kb_configs[0].qmk_info.keyboard_name.clone(),
+            ));
+        };
+        bench::run(&device, &kb_configs[0]);
+        return Ok(());
+    }
+
+    // a thread, reload watcher and control socket for every keyboard, the threads kept until the
+    // ui closes (or forever without one)
+    let api = api.map(|api| Arc::new(Mutex::new(api)));
+    let mut threads = Vec::with_capacity(kb_configs.len());
+    let mut boards = Vec::with_capacity(kb_configs.len());
+    let mut headless = Vec::with_capacity(kb_configs.len());
+
+    for (index, kb_config) in kb_configs.into_iter().enumerate() {
+        let mut thread = HIDThread::new(kb_config.clone());
+
+        #[cfg(feature = "mqtt")]
+        if let Some(mqtt) = &kb_config.host_config.mqtt {
+            mqtt::spawn(mqtt, kb_config.clone(), thread.tx(), thread.telemetry());
+        }
+        #[cfg(not(feature = "mqtt"))]
+        if kb_config.host_config.mqtt.is_some() {
+            log::warn!("built without the mqtt feature, not connecting to mqtt");
+        }
+
+        if let Some(addr) = &kb_config.host_config.websocket_addr {
+            websocket::spawn(addr, thread.live_frames());
+        }
+
+        if let Some(rest) = &kb_config.host_config.rest {
+            rest::spawn(rest, thread.tx(), thread.telemetry());
+        }
+
+        // a mock keyboard's settings would end up over the real one's
+        let profile = profile::profile_path(&kb_config.host_config)
+            .filter(|_| kb_config.host_config.remember_settings && !options.mock)
+            .map(profile::ProfileStore::open);
+        // the ui starts from the same settings as the thread
+        let saved = profile
+            .as_ref()
+            .and_then(|profile| profile.saved().cloned());
+        if let Some(profile) = profile {
+            thread.remember(profile);
+        }
+
+        let source: Box<dyn DeviceSource> = match &api {
+            Some(api) => Box::new(DeviceWatcher::new(api.clone())),
+            None => Box::new(transport::spawn_demo(&kb_config)),
+        };
+        thread.start(options.update_rate, options.fps, source);
+
+        let tx = thread.tx();
+
+        let (reload_tx, reload_rx) = crossbeam::channel::unbounded();
+        let source = ConfigSource::new(overrides.clone(), index, read_keyboard);
+        let (read, files) = (source.clone(), source.clone());
+        reload::spawn(
+            &kb_config,
+            move || read.read(),
+            move |kb_config| files.config_files(kb_config),
+            reload_tx,
+        );
+
+        if let Some(addr) = &kb_config.host_config.control_addr {
+            control::spawn(addr, tx.clone(), &kb_config);
+        }
+
+        if options.headless {
+            headless.push(cli::Headless {
+                tx,
+                rx: thread.rx(),
+                console: thread.console(),
+                reload_rx,
+            });
+        } else {
+            boards.push(ui::Board::new(
+                thread.rx(),
+                tx,
+                thread.console(),
+                reload_rx,
+                source,
+                kb_config,
+                saved.as_ref(),
+            ));
+        }
+        threads.push(thread);
+    }
+
+    if options.headless {
+        cli::headless(&headless, options.fps);
+    }
 
     // egui
-    let options = eframe::NativeOptions {
+    let native_options = eframe::NativeOptions {
         maximized: true,
         ..Default::default()
     };
 
-    let rx = thread.rx();
-
+    let fps = options.fps;
     eframe::run_native(
         "ksk QMK keyboard host",
-        options,
-        Box::new(move |_cc| Box::new(ui::App::new(rx, kb_config))),
+        native_options,
+        Box::new(move |cc| Box::new(ui::App::new(boards, fps, cc.storage))),
     );
 
     Ok(())
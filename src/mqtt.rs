@@ -0,0 +1,517 @@
+// publishes the keyboard's activity to an mqtt broker and takes commands from it, and announces
+// both to home assistant. under the configured topic:
+//
+//   available        online, or offline once the host is gone
+//   connected        ON or OFF, whether the keyboard is plugged in
+//   layer            the highest layer on
+//   wpm              words per minute
+//   set              ON or OFF, the leds on at the last brightness or off
+//   brightness/set   0-255
+//   color/set        r,g,b in 0-255, recolors the effects like the color picker
+//   effect/set       an effect's name, run on its own with default params
+//
+// this is a minimal mqtt 3.1.1 client, qos 0 only. losing the broker is logged once and
+// connecting is tried again every few seconds
+use crate::{
+    config::{KBConfig, MqttConfig},
+    effects,
+    threading::{HIDCommand, Telemetry},
+};
+use crossbeam::channel::{Receiver, RecvTimeoutError, Sender, TryRecvError};
+use log::{debug, info, warn};
+use palette::{Hsv, Hsva, IntoColor, Srgb};
+use serde_json::json;
+use std::{
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const SUBSCRIBE: u8 = 0x82;
+const PINGREQ: u8 = 0xC0;
+
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+// how long a read waits before going back to publishing
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+fn push_str(buf: &mut Vec<u8>, string: &[u8]) {
+    buf.extend_from_slice(&(string.len() as u16).to_be_bytes());
+    buf.extend_from_slice(string);
+}
+
+// the header byte, the body's length in 7 bit groups lowest first, then the body
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut buf = vec![header];
+    let mut len = body.len();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        if len == 0 {
+            buf.push(byte);
+            break;
+        }
+        buf.push(byte | 0x80);
+    }
+    buf.extend_from_slice(body);
+    buf
+}
+
+// a clean session, with the broker saying `offline` on `will_topic` for us once we're gone
+fn connect_packet(config: &MqttConfig, client_id: &str, will_topic: &str) -> Vec<u8> {
+    let mut flags = 0x02 | 0x04 | 0x20;
+    if config.username.is_some() {
+        flags |= 0x80;
+    }
+    if config.password.is_some() {
+        flags |= 0x40;
+    }
+
+    let mut body = Vec::new();
+    push_str(&mut body, b"MQTT");
+    body.push(4);
+    body.push(flags);
+    body.extend_from_slice(&(KEEP_ALIVE.as_secs() as u16).to_be_bytes());
+    push_str(&mut body, client_id.as_bytes());
+    push_str(&mut body, will_topic.as_bytes());
+    push_str(&mut body, b"offline");
+    for field in [&config.username, &config.password].into_iter().flatten() {
+        push_str(&mut body, field.as_bytes());
+    }
+    packet(CONNECT, &body)
+}
+
+fn publish_packet(topic: &str, payload: &str, retain: bool) -> Vec<u8> {
+    let mut body = Vec::new();
+    push_str(&mut body, topic.as_bytes());
+    body.extend_from_slice(payload.as_bytes());
+    packet(PUBLISH | retain as u8, &body)
+}
+
+fn subscribe_packet(id: u16, filters: &[String]) -> Vec<u8> {
+    let mut body = id.to_be_bytes().to_vec();
+    for filter in filters {
+        push_str(&mut body, filter.as_bytes());
+        body.push(0);
+    }
+    packet(SUBSCRIBE, &body)
+}
+
+// the header byte, body and whole length of the packet `buf` starts with, once all of it is there
+fn split_packet(buf: &[u8]) -> Option<(u8, &[u8], usize)> {
+    let mut len = 0;
+    let mut idx = 1;
+    for shift in [0, 7, 14, 21] {
+        let byte = *buf.get(idx)?;
+        len |= ((byte & 0x7F) as usize) << shift;
+        idx += 1;
+        if byte & 0x80 == 0 {
+            let body = buf.get(idx..idx + len)?;
+            return Some((buf[0], body, idx + len));
+        }
+    }
+    None
+}
+
+// the topic and payload of a publish, skipping the packet id qos 1 and 2 have
+fn parse_publish(header: u8, body: &[u8]) -> Option<(&str, &[u8])> {
+    let len = u16::from_be_bytes([*body.first()?, *body.get(1)?]) as usize;
+    let topic = std::str::from_utf8(body.get(2..2 + len)?).ok()?;
+    let id_len = if (header >> 1) & 0x03 > 0 { 2 } else { 0 };
+    Some((topic, body.get(2 + len + id_len..)?))
+}
+
+// a message on one of the command topics, the topic without the prefix. `brightness` is the last
+// brightness above 0, which ON goes back to
+fn command(topic: &str, payload: &str, brightness: &mut u8) -> Result<HIDCommand, String> {
+    let payload = payload.trim();
+    match topic {
+        "set" => match payload {
+            "ON" => Ok(HIDCommand::SetBrightness(*brightness)),
+            "OFF" => Ok(HIDCommand::SetBrightness(0)),
+            _ => Err(format!("set takes ON or OFF, not {:?}", payload)),
+        },
+        "brightness/set" => {
+            let value: u8 = payload
+                .parse()
+                .map_err(|_| format!("brightness takes 0-255, not {:?}", payload))?;
+            if value > 0 {
+                *brightness = value;
+            }
+            Ok(HIDCommand::SetBrightness(value))
+        }
+        "color/set" => {
+            let channels: Vec<u8> = payload
+                .split(',')
+                .map(|channel| channel.trim().parse())
+                .collect::<Result<_, _>>()
+                .map_err(|_| format!("color takes r,g,b in 0-255, not {:?}", payload))?;
+            let [red, green, blue] = channels[..] else {
+                return Err(format!("color takes r,g,b in 0-255, not {:?}", payload));
+            };
+
+            let hsv: Hsv = Srgb::new(red, green, blue)
+                .into_format::<f32>()
+                .into_color();
+            Ok(HIDCommand::SetColor(Hsva::new(
+                hsv.hue,
+                hsv.saturation,
+                hsv.value,
+                1.0,
+            )))
+        }
+        "effect/set" if effects::buildable_effects().contains(&payload) => {
+            Ok(HIDCommand::SetEffect(payload.to_string()))
+        }
+        "effect/set" => Err(format!("no effect called {:?}", payload)),
+        _ => Err(format!("nothing listens on {}", topic)),
+    }
+}
+
+// home assistant's discovery topics and configs, a light for the leds and sensors for the rest
+fn discovery(config: &MqttConfig, prefix: &str, kb_config: &KBConfig) -> Vec<(String, String)> {
+    let topic = &config.topic;
+    let id = topic.replace('/', "_");
+    let device = json!({
+        "identifiers": [id],
+        "name": kb_config.qmk_info.keyboard_name,
+        "manufacturer": kb_config.qmk_info.manufacturer,
+    });
+
+    let entity = |component: &str, key: &str, mut fields: serde_json::Value| {
+        fields["unique_id"] = json!(format!("{}_{}", id, key));
+        fields["availability_topic"] = json!(format!("{}/available", topic));
+        fields["device"] = device.clone();
+        (
+            format!("{}/{}/{}_{}/config", prefix, component, id, key),
+            fields.to_string(),
+        )
+    };
+
+    vec![
+        entity(
+            "light",
+            "leds",
+            json!({
+                "name": "Leds",
+                "command_topic": format!("{}/set", topic),
+                "brightness_command_topic": format!("{}/brightness/set", topic),
+                "rgb_command_topic": format!("{}/color/set", topic),
+                "effect_command_topic": format!("{}/effect/set", topic),
+                "effect_list": effects::buildable_effects(),
+            }),
+        ),
+        entity(
+            "sensor",
+            "layer",
+            json!({"name": "Layer", "state_topic": format!("{}/layer", topic)}),
+        ),
+        entity(
+            "sensor",
+            "wpm",
+            json!({
+                "name": "Typing speed",
+                "state_topic": format!("{}/wpm", topic),
+                "unit_of_measurement": "WPM",
+            }),
+        ),
+        entity(
+            "binary_sensor",
+            "connected",
+            json!({
+                "name": "Connected",
+                "state_topic": format!("{}/connected", topic),
+                "device_class": "connectivity",
+            }),
+        ),
+    ]
+}
+
+// only what changed since `last`, everything without it
+fn publish_telemetry(
+    stream: &mut TcpStream,
+    topic: &str,
+    telemetry: &Telemetry,
+    last: Option<&Telemetry>,
+) -> io::Result<()> {
+    if last.is_none_or(|last| last.connected != telemetry.connected) {
+        let state = if telemetry.connected { "ON" } else { "OFF" };
+        stream.write_all(&publish_packet(
+            &format!("{}/connected", topic),
+            state,
+            true,
+        ))?;
+    }
+    if last.is_none_or(|last| last.layer != telemetry.layer) {
+        let layer = telemetry.layer.to_string();
+        stream.write_all(&publish_packet(&format!("{}/layer", topic), &layer, true))?;
+    }
+    if last.is_none_or(|last| last.wpm != telemetry.wpm) {
+        let wpm = telemetry.wpm.to_string();
+        stream.write_all(&publish_packet(&format!("{}/wpm", topic), &wpm, true))?;
+    }
+    Ok(())
+}
+
+pub fn spawn(
+    config: &MqttConfig,
+    kb_config: Arc<KBConfig>,
+    tx: Sender<HIDCommand>,
+    telemetry: Receiver<Telemetry>,
+) {
+    let config = config.clone();
+    thread::spawn(move || run(&config, &kb_config, &tx, &telemetry));
+}
+
+// runs until the hid thread is gone
+fn run(
+    config: &MqttConfig,
+    kb_config: &KBConfig,
+    tx: &Sender<HIDCommand>,
+    telemetry: &Receiver<Telemetry>,
+) {
+    let mut client = Client {
+        config,
+        kb_config,
+        tx,
+        telemetry,
+        last: None,
+        brightness: u8::MAX,
+        reported: false,
+    };
+
+    loop {
+        match client.session() {
+            Ok(()) => return,
+            Err(err) if !client.reported => {
+                warn!(
+                    "mqtt broker {} unavailable: {}, trying again every {}s",
+                    config.broker,
+                    err,
+                    RETRY_INTERVAL.as_secs()
+                );
+                client.reported = true;
+            }
+            Err(err) => debug!("mqtt broker still unavailable: {}", err),
+        }
+
+        // whatever changes meanwhile is published after reconnecting
+        let deadline = Instant::now() + RETRY_INTERVAL;
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            match telemetry.recv_timeout(remaining) {
+                Ok(latest) => client.last = Some(latest),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    }
+}
+
+struct Client<'a> {
+    config: &'a MqttConfig,
+    kb_config: &'a KBConfig,
+    tx: &'a Sender<HIDCommand>,
+    telemetry: &'a Receiver<Telemetry>,
+    // the last telemetry published, or to publish on connecting
+    last: Option<Telemetry>,
+    brightness: u8,
+    // whether losing the broker was logged
+    reported: bool,
+}
+
+impl Client<'_> {
+    // one connection to the broker, an error once it's lost and Ok once the hid thread is
+    fn session(&mut self) -> io::Result<()> {
+        let topic = &self.config.topic;
+        let addr = self
+            .config
+            .broker
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, "the address resolved to nothing")
+            })?;
+
+        let mut stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+        stream.set_read_timeout(Some(POLL_INTERVAL))?;
+        let client_id = format!("kb_host-{}", std::process::id());
+        let available = format!("{}/available", topic);
+        stream.write_all(&connect_packet(self.config, &client_id, &available))?;
+
+        let started = Instant::now();
+        let mut last_ping = Instant::now();
+        let mut ready = false;
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 1024];
+
+        loop {
+            match stream.read(&mut chunk) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionAborted,
+                        "the broker closed the connection",
+                    ))
+                }
+                Ok(size) => buf.extend_from_slice(&chunk[..size]),
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) => {}
+                Err(err) => return Err(err),
+            }
+
+            while let Some((header, body, size)) = split_packet(&buf) {
+                let body = body.to_vec();
+                buf.drain(..size);
+
+                match header & 0xF0 {
+                    CONNACK => {
+                        let code = body.get(1).copied().unwrap_or(u8::MAX);
+                        if code != 0 {
+                            return Err(io::Error::new(
+                                io::ErrorKind::ConnectionRefused,
+                                format!("the broker refused the connection (code {})", code),
+                            ));
+                        }
+                        self.connected(&mut stream)?;
+                        ready = true;
+                    }
+                    PUBLISH => {
+                        let Some((received, payload)) = parse_publish(header, &body) else {
+                            continue;
+                        };
+                        let Some(suffix) = received.strip_prefix(&format!("{}/", topic)) else {
+                            continue;
+                        };
+
+                        let payload = String::from_utf8_lossy(payload);
+                        match command(suffix, &payload, &mut self.brightness) {
+                            Ok(command) => {
+                                debug!("mqtt {}: {}", received, payload);
+                                if self.tx.send(command).is_err() {
+                                    return Ok(());
+                                }
+                            }
+                            Err(err) => warn!("ignoring mqtt message on {}: {}", received, err),
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            if !ready {
+                if started.elapsed() >= CONNECT_TIMEOUT {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "the broker didn't answer",
+                    ));
+                }
+                continue;
+            }
+
+            loop {
+                match self.telemetry.try_recv() {
+                    Ok(latest) => {
+                        publish_telemetry(&mut stream, topic, &latest, self.last.as_ref())?;
+                        self.last = Some(latest);
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            }
+
+            if last_ping.elapsed() >= KEEP_ALIVE / 2 {
+                stream.write_all(&[PINGREQ, 0])?;
+                last_ping = Instant::now();
+            }
+        }
+    }
+
+    // subscribes, announces the keyboard and publishes where it's at
+    fn connected(&mut self, stream: &mut TcpStream) -> io::Result<()> {
+        let topic = &self.config.topic;
+        info!("connected to mqtt broker {}", self.config.broker);
+        self.reported = false;
+
+        let filters = [format!("{}/set", topic), format!("{}/+/set", topic)];
+        stream.write_all(&subscribe_packet(1, &filters))?;
+
+        if let Some(prefix) = &self.config.discovery_prefix {
+            for (config_topic, config) in discovery(self.config, prefix, self.kb_config) {
+                stream.write_all(&publish_packet(&config_topic, &config, true))?;
+            }
+        }
+
+        let available = format!("{}/available", topic);
+        stream.write_all(&publish_packet(&available, "online", true))?;
+        if let Some(last) = &self.last {
+            publish_telemetry(stream, topic, last, None)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_and_splits_packets() {
+        let payload = "x".repeat(200);
+        let publish = publish_packet("kb/wpm", &payload, true);
+        // 2 + 6 + 200 bytes need two length bytes
+        assert_eq!(publish[..3], [PUBLISH | 1, 208 | 0x80, 1]);
+
+        let mut buf = publish.clone();
+        buf.extend_from_slice(&[PINGREQ, 0]);
+        let (header, body, size) = split_packet(&buf).unwrap();
+        assert_eq!((header, size), (PUBLISH | 1, publish.len()));
+        assert_eq!(
+            parse_publish(header, body),
+            Some(("kb/wpm", payload.as_bytes()))
+        );
+
+        assert_eq!(split_packet(&buf[size..]), Some((PINGREQ, &[][..], 2)));
+        assert_eq!(split_packet(&publish[..100]), None);
+    }
+
+    #[test]
+    fn parses_commands() {
+        let mut brightness = u8::MAX;
+
+        assert!(matches!(
+            command("brightness/set", "100", &mut brightness),
+            Ok(HIDCommand::SetBrightness(100))
+        ));
+        assert!(matches!(
+            command("set", "OFF", &mut brightness),
+            Ok(HIDCommand::SetBrightness(0))
+        ));
+        // back to where it was before going off
+        assert!(matches!(
+            command("set", "ON", &mut brightness),
+            Ok(HIDCommand::SetBrightness(100))
+        ));
+
+        let Ok(HIDCommand::SetColor(color)) = command("color/set", "0,255,0", &mut brightness)
+        else {
+            panic!("not a color");
+        };
+        assert_eq!(color.hue.to_positive_degrees().round(), 120.0);
+
+        assert!(matches!(
+            command("effect/set", "rainbow1", &mut brightness),
+            Ok(HIDCommand::SetEffect(name)) if name == "rainbow1"
+        ));
+        assert!(command("effect/set", "nope", &mut brightness).is_err());
+        assert!(command("color/set", "1,2", &mut brightness).is_err());
+        assert!(command("brightness/set", "300", &mut brightness).is_err());
+    }
+}
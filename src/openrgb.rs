@@ -0,0 +1,152 @@
+// mirrors the frames sent to the keyboard to an openrgb server over its sdk protocol, so the
+// board can follow the rest of the room's lighting. openrgb not running is only logged, and
+// connecting is tried again every few seconds
+use crate::{config::OpenRgbConfig, protocol};
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use log::{debug, info, warn};
+use palette::{rgb::Rgb, Hsv, IntoColor};
+use std::{
+    io::{self, Write},
+    net::{TcpStream, ToSocketAddrs},
+    thread,
+    time::{Duration, Instant},
+};
+
+const MAGIC: &[u8; 4] = b"ORGB";
+const SET_CLIENT_NAME: u32 = 50;
+const UPDATE_LEDS: u32 = 1050;
+// direct control, which openrgb calls the custom mode
+const SET_CUSTOM_MODE: u32 = 1100;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+// every packet is the magic, the device index, the packet id and the data's length, then data
+fn packet(device: u32, id: u32, data: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(16 + data.len());
+    buf.extend_from_slice(MAGIC);
+    buf.extend_from_slice(&device.to_le_bytes());
+    buf.extend_from_slice(&id.to_le_bytes());
+    buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    buf.extend_from_slice(data);
+    buf
+}
+
+// the data starts over with its own length, then the color count and a red, green, blue and
+// padding byte per led. leds `leds` names past the end of the frame are sent black
+fn update_leds(device: u32, frame: &[Hsv], leds: Option<&[usize]>) -> Vec<u8> {
+    let count = leds.map_or(frame.len(), <[usize]>::len);
+    let size = 4 + 2 + count * 4;
+
+    let mut data = Vec::with_capacity(size);
+    data.extend_from_slice(&(size as u32).to_le_bytes());
+    data.extend_from_slice(&(count as u16).to_le_bytes());
+    for idx in 0..count {
+        let led = leds.map_or(idx, |leds| leds[idx]);
+        let [red, green, blue] = frame.get(led).map_or([0; 3], |color| {
+            let rgb: Rgb = (*color).into_color();
+            // openrgb devices apply their own curves
+            [rgb.red, rgb.green, rgb.blue].map(|value| protocol::channel(value, 1.0))
+        });
+        data.extend_from_slice(&[red, green, blue, 0]);
+    }
+
+    packet(device, UPDATE_LEDS, &data)
+}
+
+fn connect(config: &OpenRgbConfig) -> io::Result<TcpStream> {
+    let addr = config.addr.to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "the address resolved to nothing")
+    })?;
+
+    let mut stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+    stream.set_nodelay(true)?;
+    stream.set_write_timeout(Some(Duration::from_secs(1)))?;
+    stream.write_all(&packet(0, SET_CLIENT_NAME, b"kb_host\0"))?;
+    stream.write_all(&packet(config.device, SET_CUSTOM_MODE, &[]))?;
+    Ok(stream)
+}
+
+// frames are handed to a thread of its own, so a slow or missing server never holds up the
+// keyboard. the thread stops with the sink
+pub struct OpenRgbSink {
+    tx: Sender<Vec<Hsv>>,
+}
+
+impl OpenRgbSink {
+    pub fn spawn(config: &OpenRgbConfig) -> OpenRgbSink {
+        let (tx, rx) = unbounded();
+        let config = config.clone();
+        thread::spawn(move || run(&config, &rx));
+        OpenRgbSink { tx }
+    }
+
+    pub fn send(&self, frame: Vec<Hsv>) {
+        self.tx.send(frame).ok();
+    }
+}
+
+// only the newest frame is sent when the server falls behind
+fn run(config: &OpenRgbConfig, rx: &Receiver<Vec<Hsv>>) {
+    let mut stream: Option<TcpStream> = None;
+    let mut last_attempt: Option<Instant> = None;
+    let mut reported = false;
+
+    while let Ok(frame) = rx.recv() {
+        let frame = rx.try_iter().last().unwrap_or(frame);
+
+        if stream.is_none() && last_attempt.is_none_or(|at| at.elapsed() >= RETRY_INTERVAL) {
+            last_attempt = Some(Instant::now());
+            match connect(config) {
+                Ok(connected) => {
+                    info!(
+                        "mirroring leds to openrgb device {} at {}",
+                        config.device, config.addr
+                    );
+                    reported = false;
+                    stream = Some(connected);
+                }
+                Err(err) if !reported => {
+                    warn!(
+                        "openrgb isn't reachable at {}: {}, trying again every {}s",
+                        config.addr,
+                        err,
+                        RETRY_INTERVAL.as_secs()
+                    );
+                    reported = true;
+                }
+                Err(err) => debug!("openrgb still unreachable: {}", err),
+            }
+        }
+
+        if let Some(connected) = &mut stream {
+            let packet = update_leds(config.device, &frame, config.leds.as_deref());
+            if let Err(err) = connected.write_all(&packet) {
+                warn!("lost the connection to openrgb: {}", err);
+                stream = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_leds_in_openrgb_order() {
+        let frame = [Hsv::new(0.0, 1.0, 1.0), Hsv::new(120.0, 1.0, 1.0)];
+        let packet = update_leds(3, &frame, Some(&[1, 0, 5]));
+
+        assert_eq!(&packet[..4], b"ORGB");
+        assert_eq!(packet[4..8], 3u32.to_le_bytes());
+        assert_eq!(packet[8..12], UPDATE_LEDS.to_le_bytes());
+        assert_eq!(packet[12..16], 18u32.to_le_bytes());
+
+        let data = &packet[16..];
+        assert_eq!(data[..4], 18u32.to_le_bytes());
+        assert_eq!(data[4..6], 3u16.to_le_bytes());
+        // green, red, then black for an led the frame doesn't have
+        assert_eq!(data[6..], [0, 255, 0, 0, 255, 0, 0, 0, 0, 0, 0, 0]);
+    }
+}
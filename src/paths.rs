@@ -0,0 +1,506 @@
+use crate::config::{Config, HostSettings, KBConfig, StartupError};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+const CONFIG_PATH: &str = "kb_host/config.json";
+const TOML_CONFIG_PATH: &str = "kb_host/config.toml";
+const QMK_ROOT: &str = "qmk_firmware";
+
+// the same schema under its old and new name, the new one winning if a folder has both
+const INFO_FILES: [&str; 2] = ["info.json", "keyboard.json"];
+
+// where to find the config and keyboard files, for anything not given the defaults are the
+// config directory's kb_host/config.json, ~/qmk_firmware, and the kb/keymap in the config
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Overrides {
+    pub config: Option<PathBuf>,
+    pub qmk_root: Option<PathBuf>,
+    pub kb: Option<String>,
+    pub keymap: Option<String>,
+    pub serial: Option<String>,
+    pub device_index: Option<usize>,
+}
+
+impl Overrides {
+    // --config, --qmk-root, --kb (or --keyboard), --keymap, --serial and --device-index, each
+    // falling back to its KB_HOST_ variable
+    pub fn from_env(args: &[String]) -> Overrides {
+        Self::parse(args, |name| std::env::var(name).ok())
+    }
+
+    fn parse(args: &[String], env: impl Fn(&str) -> Option<String>) -> Overrides {
+        let get = |flag: &str, var: &str| {
+            args.iter()
+                .position(|arg| flag.split('|').any(|flag| arg == flag))
+                .and_then(|idx| args.get(idx + 1))
+                .cloned()
+                .or_else(|| env(var))
+                .filter(|value| !value.is_empty())
+        };
+
+        Overrides {
+            config: get("--config", "KB_HOST_CONFIG").map(PathBuf::from),
+            qmk_root: get("--qmk-root", "KB_HOST_QMK_ROOT").map(PathBuf::from),
+            kb: get("--kb|--keyboard", "KB_HOST_KB"),
+            keymap: get("--keymap", "KB_HOST_KEYMAP"),
+            serial: get("--serial", "KB_HOST_SERIAL"),
+            device_index: get("--device-index", "KB_HOST_DEVICE_INDEX")
+                .and_then(|index| index.parse().ok()),
+        }
+    }
+
+    // config.json, or config.toml if that's the only one there
+    pub fn config_path(&self) -> PathBuf {
+        self.config.clone().unwrap_or_else(|| {
+            let config_dir = dirs::config_dir().expect("no config directory");
+            let (json, toml) = (
+                config_dir.join(CONFIG_PATH),
+                config_dir.join(TOML_CONFIG_PATH),
+            );
+
+            if !json.is_file() && toml.is_file() {
+                toml
+            } else {
+                json
+            }
+        })
+    }
+
+    // the host configuration of every keyboard in the config file
+    pub fn host_configs(&self) -> Result<Vec<Config>, StartupError> {
+        let config_path = self.config_path();
+        let config_contents = fs::read_to_string(&config_path)
+            .map_err(|err| StartupError::read(&config_path, err))?;
+        let mut configs = Config::parse_all(&config_path, &config_contents)
+            .map_err(|err| StartupError::parse(&config_path, err))?;
+        for config in &mut configs {
+            self.apply(config);
+        }
+        Ok(configs)
+    }
+
+    fn host_config(&self, index: usize) -> Result<Config, io::Error> {
+        self.host_configs()?.into_iter().nth(index).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("keyboard {} is no longer in the config", index + 1),
+            )
+        })
+    }
+
+    // the keyboard, keymap and device given here win over the ones in the config
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(kb) = &self.kb {
+            config.kb = kb.clone();
+        }
+        if let Some(keymap) = &self.keymap {
+            config.keymap = keymap.clone();
+        }
+        if let Some(serial) = &self.serial {
+            config.serial = Some(serial.clone());
+        }
+        if let Some(index) = self.device_index {
+            config.device_index = Some(index);
+        }
+    }
+
+    fn keyboards_path(&self) -> PathBuf {
+        self.qmk_root
+            .clone()
+            .unwrap_or_else(|| dirs::home_dir().expect("no home directory").join(QMK_ROOT))
+            .join("keyboards")
+    }
+
+    pub fn keyboard_path(&self, config: &Config) -> PathBuf {
+        let path = self.keyboards_path().join(&config.kb);
+        match &config.revision {
+            Some(revision) => path.join(revision),
+            None => path,
+        }
+    }
+
+    // the keyboard's folder and every folder above it up to keyboards/, outermost first
+    pub fn keyboard_dirs(&self, config: &Config) -> Vec<PathBuf> {
+        let keyboards = self.keyboards_path();
+        let keyboard_path = self.keyboard_path(config);
+
+        let mut dirs: Vec<PathBuf> = keyboard_path
+            .ancestors()
+            .take_while(|dir| *dir != keyboards)
+            .map(Path::to_path_buf)
+            .collect();
+        dirs.reverse();
+        dirs
+    }
+
+    // every info.json, and keyboard.json which newer qmk replaces it with, from the outermost
+    // folder in, to be merged with `QMKInfo::merge`
+    pub fn info_paths(&self, config: &Config) -> Vec<PathBuf> {
+        self.keyboard_dirs(config)
+            .into_iter()
+            .flat_map(|dir| INFO_FILES.map(|file| dir.join(file)))
+            .filter(|path| path.is_file())
+            .collect()
+    }
+
+    // the innermost folder that has `file`, like qmk looks for keymaps and the rest. falls back
+    // to the keyboard's own folder so a missing file is reported there
+    fn find(&self, config: &Config, file: &Path) -> PathBuf {
+        self.keyboard_dirs(config)
+            .into_iter()
+            .rev()
+            .map(|dir| dir.join(file))
+            .find(|path| path.is_file())
+            .unwrap_or_else(|| self.keyboard_path(config).join(file))
+    }
+
+    pub fn matrix_path(&self, config: &Config) -> PathBuf {
+        self.find(config, Path::new("matrix.json"))
+    }
+
+    pub fn legends_path(&self, config: &Config) -> PathBuf {
+        self.find(config, &Self::legends_file(config))
+    }
+
+    fn legends_file(config: &Config) -> PathBuf {
+        Path::new("keymaps")
+            .join(&config.keymap)
+            .join("legends.json")
+    }
+
+    // qmk's own keymap, which legends are derived from if there's no legends.json
+    pub fn keymap_path(&self, config: &Config) -> PathBuf {
+        self.find(config, &Self::keymap_file(config))
+    }
+
+    fn keymap_file(config: &Config) -> PathBuf {
+        Path::new("keymaps")
+            .join(&config.keymap)
+            .join("keymap.json")
+    }
+
+    // a keymap saved from via, the last place legends are derived from
+    pub fn via_path(&self, config: &Config) -> PathBuf {
+        self.find(config, &Self::via_file(config))
+    }
+
+    fn via_file(config: &Config) -> PathBuf {
+        Path::new("keymaps").join(&config.keymap).join("via.json")
+    }
+
+    // keymaps of the keyboard and its parent folders that have legends to show, sorted
+    pub fn keymap_names(&self, config: &Config) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .keyboard_dirs(config)
+            .iter()
+            .filter_map(|dir| fs::read_dir(dir.join("keymaps")).ok())
+            .flatten()
+            .flatten()
+            .filter(|entry| {
+                let path = entry.path();
+                ["legends.json", "keymap.json", "via.json"]
+                    .iter()
+                    .any(|file| path.join(file).is_file())
+            })
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect();
+
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    // every folder under keyboards/ with an info.json or keyboard.json, as `kb` would name it.
+    // parents of revisions are listed too, even if only the revisions are whole keyboards
+    pub fn keyboard_names(&self) -> Vec<String> {
+        let keyboards = self.keyboards_path();
+        let mut names = Vec::new();
+        let mut dirs = vec![keyboards.clone()];
+
+        while let Some(dir) = dirs.pop() {
+            if INFO_FILES.iter().any(|file| dir.join(file).is_file()) {
+                if let Ok(name) = dir.strip_prefix(&keyboards) {
+                    names.push(name.to_string_lossy().replace('\\', "/"));
+                }
+            }
+
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            dirs.extend(
+                entries
+                    .flatten()
+                    .map(|entry| entry.path())
+                    .filter(|path| path.is_dir() && !path.ends_with("keymaps")),
+            );
+        }
+
+        names.sort_unstable();
+        names
+    }
+
+    // every file a config is or could be read from, to reload when one of them changes
+    pub fn config_files(&self, kb_config: &KBConfig) -> Vec<PathBuf> {
+        let config = &kb_config.host_config;
+
+        let mut files = vec![self.config_path()];
+        for dir in self.keyboard_dirs(config) {
+            files.extend(INFO_FILES.map(|file| dir.join(file)));
+            files.push(dir.join("matrix.json"));
+            files.push(dir.join(Self::legends_file(config)));
+            files.push(dir.join(Self::keymap_file(config)));
+            files.push(dir.join(Self::via_file(config)));
+        }
+        files
+    }
+}
+
+pub type LoadKeyboard = fn(&Overrides, Config) -> Result<KBConfig, StartupError>;
+
+// how to read one keyboard's config again, shared by its reload watcher and the ui so that a
+// keymap picked in the ui is kept by later reloads
+#[derive(Clone)]
+pub struct ConfigSource {
+    overrides: Arc<Mutex<Overrides>>,
+    // which of the config's keyboards
+    index: usize,
+    // the keyboard's files for its host config
+    load: LoadKeyboard,
+}
+
+impl ConfigSource {
+    pub fn new(overrides: Overrides, index: usize, load: LoadKeyboard) -> Self {
+        ConfigSource {
+            overrides: Arc::new(Mutex::new(overrides)),
+            index,
+            load,
+        }
+    }
+
+    fn read_with(&self, overrides: &Overrides) -> Result<KBConfig, io::Error> {
+        Ok((self.load)(overrides, overrides.host_config(self.index)?)?)
+    }
+
+    fn overrides(&self) -> Overrides {
+        self.overrides.lock().unwrap().clone()
+    }
+
+    pub fn read(&self) -> Result<KBConfig, io::Error> {
+        self.read_with(&self.overrides())
+    }
+
+    pub fn config_files(&self, kb_config: &KBConfig) -> Vec<PathBuf> {
+        self.overrides().config_files(kb_config)
+    }
+
+    pub fn keymap_names(&self, config: &Config) -> Vec<String> {
+        self.overrides().keymap_names(config)
+    }
+
+    // reads the config again with another keymap, which is only kept if it reads fine
+    pub fn with_keymap(&self, keymap: &str) -> Result<KBConfig, io::Error> {
+        let mut overrides = self.overrides();
+        overrides.keymap = Some(keymap.to_string());
+
+        let kb_config = self.read_with(&overrides)?;
+        *self.overrides.lock().unwrap() = overrides;
+        Ok(kb_config)
+    }
+
+    // writes `settings` into this keyboard's part of the config file, only if the config they
+    // make reads fine. the reload watcher picks the change up from there. a kb or keymap given
+    // on the command line or picked in the ui would win over the saved ones, so they're dropped
+    pub fn save(&self, settings: &HostSettings) -> Result<(), io::Error> {
+        let mut overrides = self.overrides();
+        overrides.kb = None;
+        overrides.keymap = None;
+
+        let path = overrides.config_path();
+        let contents = fs::read_to_string(&path).map_err(|err| StartupError::read(&path, err))?;
+        let patched = Config::patch(&path, &contents, self.index, settings)?;
+        let config = Config::parse_all(&path, &patched)
+            .map_err(|err| StartupError::parse(&path, err))?
+            .into_iter()
+            .nth(self.index)
+            .expect("patched keyboard is in the config");
+        (self.load)(&overrides, config)?;
+
+        fs::write(&path, patched)?;
+        *self.overrides.lock().unwrap() = overrides;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn args_win_over_env() {
+        let env = |name: &str| match name {
+            "KB_HOST_KB" => Some("env_kb".to_string()),
+            "KB_HOST_KEYMAP" => Some("env_keymap".to_string()),
+            "KB_HOST_QMK_ROOT" => Some(String::new()),
+            _ => None,
+        };
+
+        let overrides = Overrides::parse(
+            &args(&["kb_host", "--kb", "arg_kb", "--config", "/tmp/kb.json"]),
+            env,
+        );
+
+        assert_eq!(
+            overrides,
+            Overrides {
+                config: Some(PathBuf::from("/tmp/kb.json")),
+                // empty variables count as unset
+                qmk_root: None,
+                kb: Some("arg_kb".to_string()),
+                keymap: Some("env_keymap".to_string()),
+                serial: None,
+                device_index: None,
+            }
+        );
+
+        let overrides = Overrides::parse(&args(&["kb_host", "--keyboard", "other_kb"]), env);
+        assert_eq!(overrides.kb, Some("other_kb".to_string()));
+    }
+
+    #[test]
+    fn finds_keyboard_json() {
+        let root = std::env::temp_dir().join(format!("kb_host_info_{}", std::process::id()));
+        let keyboard = root.join("keyboards/test");
+        fs::create_dir_all(keyboard.join("rev1")).unwrap();
+        fs::write(keyboard.join("info.json"), "{}").unwrap();
+        fs::write(keyboard.join("rev1/keyboard.json"), "{}").unwrap();
+
+        let config: Config = serde_json::from_str(
+            r#"{"kb": "test/rev1", "keymap": "default", "layout": "LAYOUT", "usage_page": "0xFF60", "usage": "0x61"}"#,
+        )
+        .unwrap();
+        let overrides = Overrides {
+            qmk_root: Some(root.clone()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            overrides.info_paths(&config),
+            vec![
+                keyboard.join("info.json"),
+                keyboard.join("rev1/keyboard.json")
+            ]
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn config_errors_name_the_file() {
+        let root = std::env::temp_dir().join(format!("kb_host_errors_{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let path = root.join("config.json");
+        let overrides = Overrides {
+            config: Some(path.clone()),
+            ..Default::default()
+        };
+
+        let err = overrides.host_configs().err().unwrap();
+        assert!(matches!(&err, StartupError::NotFound(missing) if *missing == path));
+
+        fs::write(&path, "{").unwrap();
+        let err = overrides.host_configs().err().unwrap();
+        assert!(matches!(err, StartupError::Parse(..)));
+        assert!(err
+            .to_string()
+            .starts_with(&format!("{} is malformed", path.display())));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn lists_keyboards() {
+        let root = std::env::temp_dir().join(format!("kb_host_keyboards_{}", std::process::id()));
+        let keyboards = root.join("keyboards");
+        for dir in ["foo/rev1", "foo/rev2", "bar/keymaps/default", "baz"] {
+            fs::create_dir_all(keyboards.join(dir)).unwrap();
+        }
+        fs::write(keyboards.join("foo/info.json"), "{}").unwrap();
+        fs::write(keyboards.join("foo/rev2/keyboard.json"), "{}").unwrap();
+        fs::write(keyboards.join("bar/info.json"), "{}").unwrap();
+        fs::write(keyboards.join("bar/keymaps/default/info.json"), "{}").unwrap();
+
+        let overrides = Overrides {
+            qmk_root: Some(root.clone()),
+            ..Default::default()
+        };
+        assert_eq!(overrides.keyboard_names(), vec!["bar", "foo", "foo/rev2"]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn lists_keymaps() {
+        let root = std::env::temp_dir().join(format!("kb_host_keymaps_{}", std::process::id()));
+        let keymaps = root.join("keyboards/test/keymaps");
+        for (keymap, file) in [
+            ("gaming", "legends.json"),
+            ("default", "keymap.json"),
+            ("empty", "readme.md"),
+        ] {
+            fs::create_dir_all(keymaps.join(keymap)).unwrap();
+            fs::write(keymaps.join(keymap).join(file), "{}").unwrap();
+        }
+
+        let config: Config = serde_json::from_str(
+            r#"{"kb": "test", "revision": "rev1", "keymap": "default", "layout": "LAYOUT", "usage_page": "0xFF60", "usage": "0x61"}"#,
+        )
+        .unwrap();
+        let overrides = Overrides {
+            qmk_root: Some(root.clone()),
+            ..Default::default()
+        };
+
+        assert_eq!(overrides.keymap_names(&config), vec!["default", "gaming"]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn paths_follow_overrides() {
+        let mut config: Config = serde_json::from_str(
+            r#"{"kb": "test", "keymap": "default", "layout": "LAYOUT", "usage_page": "0xFF60", "usage": "0x61"}"#,
+        )
+        .unwrap();
+
+        let overrides = Overrides {
+            qmk_root: Some(PathBuf::from("/src/qmk")),
+            keymap: Some("mine".to_string()),
+            ..Default::default()
+        };
+        overrides.apply(&mut config);
+
+        assert_eq!(config.kb, "test");
+        assert_eq!(
+            overrides.legends_path(&config),
+            PathBuf::from("/src/qmk/keyboards/test/keymaps/mine/legends.json")
+        );
+
+        config.kb = "handwired/test".to_string();
+        config.revision = Some("rev2".to_string());
+        assert_eq!(
+            overrides.keyboard_dirs(&config),
+            vec![
+                PathBuf::from("/src/qmk/keyboards/handwired"),
+                PathBuf::from("/src/qmk/keyboards/handwired/test"),
+                PathBuf::from("/src/qmk/keyboards/handwired/test/rev2"),
+            ]
+        );
+    }
+}
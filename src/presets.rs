@@ -0,0 +1,136 @@
+use crate::config::KBConfig;
+use log::warn;
+use palette::Hsva;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+const PRESETS_PATH: &str = "kb_host/presets";
+
+pub type KeyColors = HashMap<(u8, u8), Hsva>;
+
+// presets are json objects of "row,col": [hue degrees, saturation, value, alpha], the same as a
+// config's pinned_colors
+pub type PresetFile = BTreeMap<String, [f32; 4]>;
+
+pub fn presets_dir() -> Result<PathBuf, io::Error> {
+    dirs::config_dir()
+        .map(|dir| dir.join(PRESETS_PATH))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no config directory"))
+}
+
+// where the preset called `name` is saved, refusing names that would put it anywhere but
+// straight in the presets directory
+pub fn preset_path(name: &str) -> Result<PathBuf, io::Error> {
+    let name = name.trim();
+    if name.is_empty() || name.contains(['/', '\\']) || name.contains("..") {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{:?} isn't a preset name, leave out slashes and ..", name),
+        ));
+    }
+
+    Ok(presets_dir()?.join(format!("{}.json", name)))
+}
+
+// empty without a config directory to keep them in
+pub fn list_presets() -> Vec<PathBuf> {
+    let mut presets: Vec<PathBuf> = presets_dir()
+        .and_then(fs::read_dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    presets.sort();
+    presets
+}
+
+pub fn to_preset(colors: &KeyColors) -> PresetFile {
+    colors
+        .iter()
+        .map(|((row, col), color)| {
+            (
+                format!("{},{}", row, col),
+                [
+                    color.hue.to_positive_degrees(),
+                    color.saturation,
+                    color.value,
+                    color.alpha,
+                ],
+            )
+        })
+        .collect()
+}
+
+pub fn save_preset(path: &Path, colors: &KeyColors) -> Result<(), io::Error> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    fs::write(path, serde_json::to_string_pretty(&to_preset(colors))?)
+}
+
+pub fn load_preset(path: &Path, kb_config: &KBConfig) -> Result<KeyColors, io::Error> {
+    let preset: PresetFile = serde_json::from_str(&fs::read_to_string(path)?)?;
+
+    Ok(from_preset(
+        &preset,
+        kb_config,
+        &format!("preset {:?}", path),
+    ))
+}
+
+// the config's pinned_colors, which the thread starts with
+pub fn pinned_colors(kb_config: &KBConfig) -> KeyColors {
+    from_preset(
+        &kb_config.host_config.pinned_colors,
+        kb_config,
+        "pinned_colors",
+    )
+}
+
+// keys that don't exist on this board are skipped, with a warning naming `origin`
+pub fn from_preset(preset: &PresetFile, kb_config: &KBConfig, origin: &str) -> KeyColors {
+    let layout = kb_config.layout();
+
+    let mut colors = KeyColors::new();
+
+    for (pos, [hue, saturation, value, alpha]) in preset {
+        let parsed = pos
+            .split_once(',')
+            .and_then(|(row, col)| Some((row.trim().parse().ok()?, col.trim().parse().ok()?)));
+
+        match parsed {
+            Some(matrix) if layout.layout.iter().any(|key| key.matrix == matrix) => {
+                colors.insert(matrix, Hsva::new(*hue, *saturation, *value, *alpha));
+            }
+            Some(_) => warn!("{}: no key at {} on this board", origin, pos),
+            None => warn!("{}: invalid key position {}", origin, pos),
+        }
+    }
+
+    colors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preset_names_stay_in_the_presets_dir() {
+        if let Ok(dir) = presets_dir() {
+            assert_eq!(preset_path(" lights ").unwrap(), dir.join("lights.json"));
+        }
+
+        for name in ["", "  ", "a/b", "a\\b", "..", "../x", "/etc/x"] {
+            let err = preset_path(name).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{:?}", name);
+        }
+    }
+}
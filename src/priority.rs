@@ -0,0 +1,59 @@
+use log::{info, warn};
+use std::io;
+
+// runs the calling thread ahead of everything at normal priority, so a busy machine doesn't
+// preempt it mid-frame. that's also how it can starve the rest of the machine if it never
+// sleeps, which the hid thread always does between updates
+pub fn raise_current_thread() {
+    match raise() {
+        Ok(how) => info!("hid thread raised to {}", how),
+        Err(err) => warn!(
+            "couldn't raise the hid thread's priority, running it as usual: {}",
+            err
+        ),
+    }
+}
+
+// the lowest real-time priority, still ahead of every normal thread. linux only allows it with
+// CAP_SYS_NICE or an rtprio limit (e.g. in /etc/security/limits.conf)
+#[cfg(unix)]
+fn raise() -> io::Result<&'static str> {
+    // SAFETY: sched_param is plain data, and the calls only touch the current thread
+    let err = unsafe {
+        let mut param: libc::sched_param = std::mem::zeroed();
+        param.sched_priority = libc::sched_get_priority_min(libc::SCHED_FIFO);
+        libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_FIFO, &param)
+    };
+
+    match err {
+        0 => Ok("real-time (fifo) scheduling"),
+        err => Err(io::Error::from_raw_os_error(err)),
+    }
+}
+
+#[cfg(windows)]
+fn raise() -> io::Result<&'static str> {
+    use std::ffi::c_void;
+
+    const THREAD_PRIORITY_TIME_CRITICAL: i32 = 15;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetCurrentThread() -> *mut c_void;
+        fn SetThreadPriority(thread: *mut c_void, priority: i32) -> i32;
+    }
+
+    // SAFETY: the current thread's pseudo handle is always valid and never needs closing
+    if unsafe { SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_TIME_CRITICAL) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok("time critical priority")
+}
+
+#[cfg(not(any(unix, windows)))]
+fn raise() -> io::Result<&'static str> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "not supported on this platform",
+    ))
+}
@@ -0,0 +1,178 @@
+use crate::{
+    config::{Config, EffectConfig},
+    presets::{to_preset, KeyColors, PresetFile},
+};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+const PROFILES_PATH: &str = "kb_host/profiles";
+// changes are looked for this often, so a dragged slider is written once it settles
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+// what was picked for a keyboard while running: the effect stack with its tuned params, the
+// pinned keys and the brightness. written whenever they change and read back at startup
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Profile {
+    pub effects: Vec<EffectConfig>,
+    pub pinned_colors: PresetFile,
+    pub brightness: Option<u8>,
+    // the config's effects and pinned_colors as of saving. the saved ones are only restored
+    // while these are unchanged, so edits to the config still win
+    config_effects: Vec<EffectConfig>,
+    config_pinned_colors: PresetFile,
+}
+
+impl Profile {
+    pub fn new(
+        config: &Config,
+        effects: &[EffectConfig],
+        key_colors: &KeyColors,
+        brightness: Option<u8>,
+    ) -> Profile {
+        Profile {
+            effects: effects.to_vec(),
+            pinned_colors: to_preset(key_colors),
+            brightness,
+            config_effects: config.effects.clone(),
+            config_pinned_colors: config.pinned_colors.clone(),
+        }
+    }
+
+    // whether the effects and pinned keys apply over `config`'s, the brightness always does
+    pub fn applies(&self, config: &Config) -> bool {
+        self.config_effects == config.effects && self.config_pinned_colors == config.pinned_colors
+    }
+}
+
+// the effects and pinned keys to start with, the saved ones where they apply
+pub fn restored<'a>(
+    saved: Option<&'a Profile>,
+    config: &'a Config,
+) -> (&'a [EffectConfig], &'a PresetFile) {
+    match saved.filter(|saved| saved.applies(config)) {
+        Some(saved) => (&saved.effects, &saved.pinned_colors),
+        None => (&config.effects, &config.pinned_colors),
+    }
+}
+
+// one file per keyboard, told apart by serial when the config gives one
+pub fn profile_path(config: &Config) -> Option<PathBuf> {
+    let mut name = config.kb.replace('/', "_");
+    if let Some(serial) = &config.serial {
+        name = format!("{}-{}", name, serial);
+    }
+    dirs::config_dir().map(|dir| dir.join(PROFILES_PATH).join(name + ".json"))
+}
+
+// the profile last written to `path`, keeping what was written so only changes are written again
+pub struct ProfileStore {
+    path: PathBuf,
+    saved: Option<Profile>,
+    last_check: Option<Instant>,
+}
+
+impl ProfileStore {
+    pub fn open(path: PathBuf) -> ProfileStore {
+        let saved = match load(&path) {
+            Ok(profile) => {
+                info!("restoring settings from {:?}", path);
+                Some(profile)
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => {
+                warn!("could not read saved settings from {:?}: {}", path, err);
+                None
+            }
+        };
+
+        ProfileStore {
+            path,
+            saved,
+            last_check: None,
+        }
+    }
+
+    pub fn saved(&self) -> Option<&Profile> {
+        self.saved.as_ref()
+    }
+
+    // writes the current profile if it changed, checked about once a second
+    pub fn poll(&mut self, current: impl FnOnce() -> Profile) {
+        if self
+            .last_check
+            .is_some_and(|last| last.elapsed() < SAVE_INTERVAL)
+        {
+            return;
+        }
+        self.last_check = Some(Instant::now());
+
+        let profile = current();
+        if self.saved.as_ref() == Some(&profile) {
+            return;
+        }
+
+        debug!("saving settings to {:?}", self.path);
+        if let Err(err) = save(&self.path, &profile) {
+            warn!("could not save settings to {:?}: {}", self.path, err);
+        }
+        // not retried every second if it failed, only on the next change
+        self.saved = Some(profile);
+    }
+}
+
+fn load(path: &Path) -> Result<Profile, io::Error> {
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+fn save(path: &Path, profile: &Profile) -> Result<(), io::Error> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    fs::write(path, serde_json::to_string_pretty(profile)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saves_changes_and_restores_them() {
+        let path = std::env::temp_dir()
+            .join(format!("kb_host_profile_{}", std::process::id()))
+            .join("test.json");
+        let mut config: Config = serde_json::from_str(
+            r#"{"kb": "test", "keymap": "default", "layout": "LAYOUT", "usage_page": "0xFF60", "usage": "0x61"}"#,
+        )
+        .unwrap();
+        let effects = vec![EffectConfig {
+            name: "breathing".to_string(),
+            params: serde_json::json!({"speed": 2.0}),
+        }];
+        let key_colors = KeyColors::from([((0, 1), palette::Hsva::new(120.0, 1.0, 1.0, 1.0))]);
+
+        let mut store = ProfileStore::open(path.clone());
+        assert!(store.saved().is_none());
+        store.poll(|| Profile::new(&config, &effects, &key_colors, Some(80)));
+
+        let store = ProfileStore::open(path.clone());
+        let profile = store.saved().unwrap();
+        let (restored_effects, pins) = restored(Some(profile), &config);
+        assert_eq!(restored_effects, effects);
+        assert_eq!(pins["0,1"], [120.0, 1.0, 1.0, 1.0]);
+        assert_eq!(profile.brightness, Some(80));
+
+        // the config's effects were edited since
+        config.effects.clear();
+        assert!(!profile.applies(&config));
+        assert!(restored(Some(profile), &config).0.is_empty());
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -1,12 +1,105 @@
-use hidapi::{HidDevice, HidError};
+use crate::transport::HidTransport;
+use hidapi::HidError;
+use log::{debug, trace};
 use palette::{rgb::Rgb, Hsv, IntoColor};
-use std::collections::HashMap;
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    time::{Duration, Instant},
+};
 
 pub const KSK_PRESS: u8 = 0;
 pub const KSK_LAYER: u8 = 1;
 pub const KSK_RGB_SET: u8 = 2;
+pub const KSK_GET_LAYER: u8 = 3;
+// same as KSK_RGB_SET, but with 16 bit (little endian) led indices
+pub const KSK_RGB_SET_WIDE: u8 = 4;
+// text from the firmware, low header bit set if the text continues in the next report
+pub const KSK_CONSOLE: u8 = 5;
+// host lock leds changed, one byte in qmk's led_t layout
+pub const KSK_LOCKS: u8 = 6;
+// global brightness scale on the firmware, one byte (0-255) after the header
+pub const KSK_BRIGHTNESS: u8 = 7;
+// asks for the colors the firmware is showing, answered with the same opcode: a 16 bit (little
+// endian) start index, a count byte, then that many leds of 3 color bytes. a full board takes
+// many answers, each continuing where the last left off
+pub const KSK_RGB_STATE: u8 = 8;
+// battery level of wireless boards, one percent byte, low header bit set while charging
+pub const KSK_BATTERY: u8 = 9;
+// asks for the firmware's version, answered with the same opcode and major, minor and patch
+// bytes. the major version is the ksk protocol version
+pub const KSK_VERSION: u8 = 10;
+// acknowledges a report, the opcode it acknowledges in the low header nibble. firmware only
+// sends these from protocol version ACK_VERSION on, and only for reports sent with send_acked
+pub const KSK_ACK: u8 = 11;
+// a rotary encoder clicked once, the encoder's index byte after the header, low header bit set
+// if it turned clockwise
+pub const KSK_ENCODER: u8 = 12;
+// a run of consecutive leds: a 16 bit (little endian) start index, a count byte, then that many
+// leds of 3 color bytes, like KSK_RGB_STATE answers
+pub const KSK_RGB_STREAM: u8 = 13;
+// one piece of a message too long for a single report: the header byte of the message it's part
+// of, the message's total payload length (16 bit, little endian), then as much of the payload as
+// fits. the pieces come in order and stand for a report with that header and the whole payload
+pub const KSK_FRAGMENT: u8 = 14;
 
-pub const RAW_EPSIZE: usize = 32;
+// RAW_EPSIZE in qmk, the size of every report. 32 unless the firmware was built with a bigger
+// raw hid endpoint, full speed usb can't go past 64
+pub const DEFAULT_EPSIZE: usize = 32;
+pub const MIN_EPSIZE: usize = 16;
+pub const MAX_EPSIZE: usize = 64;
+
+// every report starts with the magic `k s k`, then a header byte with the opcode in the high
+// nibble and opcode specific data in the low nibble, then the payload. from protocol version 2
+// on, the last byte of every report (reports are zero padded to the full endpoint size) is a
+// crc-8 (polynomial 0x07, initial value 0) over the bytes before it, and reports that don't
+// match are dropped
+pub const CHECKSUM_VERSION: u8 = 2;
+pub const ACK_VERSION: u8 = 2;
+
+// how reports are framed on the wire, the same for every report to one keyboard
+#[derive(Debug, Clone, Copy)]
+pub struct Framing {
+    // first byte of every write, see Config::report_id
+    pub report_id: u8,
+    pub checksum: bool,
+    // see Config::raw_epsize
+    pub epsize: usize,
+}
+
+impl Default for Framing {
+    fn default() -> Self {
+        Framing {
+            report_id: 0,
+            checksum: false,
+            epsize: DEFAULT_EPSIZE,
+        }
+    }
+}
+
+impl Framing {
+    // bytes of a report left for the header and payload
+    pub const fn report_size(self) -> usize {
+        if self.checksum {
+            self.epsize - 1
+        } else {
+            self.epsize
+        }
+    }
+}
+
+pub fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                crc << 1 ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
 
 #[derive(Debug)]
 pub struct PressMessage {
@@ -14,21 +107,166 @@ pub struct PressMessage {
     pub keycode: u16,
     pub col: u8,
     pub row: u8,
+    // active right after this event, the byte after the row. firmware from before it leaves 0
+    pub mods: Mods,
 }
 
 #[derive(Debug)]
 pub struct LayerMessage {
-    pub layer_state: u8,
+    // qmk's layer_state_t, one bit per active layer
+    pub layer_state: u32,
+}
+
+// how the 3 bytes of every led color are sent, and read back. the firmware has to be built for
+// the same one
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorFormat {
+    // red, green, blue, gamma corrected on the host
+    #[default]
+    Rgb,
+    // qmk's hue, saturation, value bytes (hue 0-255 around the wheel), the firmware applies its
+    // own curve, so gamma is ignored
+    Hsv,
 }
 
 #[derive(Debug)]
 pub struct RgbSetMessage {
-    pub colors: HashMap<u8, Hsv>,
+    pub colors: HashMap<u16, Hsv>,
+    pub gamma: f32,
+    pub format: ColorFormat,
+    // sent as KSK_RGB_SET_WIDE, needed for led indices past 255
+    pub wide: bool,
+}
+
+impl RgbSetMessage {
+    // leds that fit in one report after the 4 byte header, each is an index plus 3 bytes of color.
+    // the count goes in the low header nibble, so never more than 15
+    pub const fn capacity(wide: bool, framing: Framing) -> usize {
+        let index_size = if wide { 2 } else { 1 };
+        let fits = (framing.report_size() - 4) / (index_size + 3);
+        if fits < 15 {
+            fits
+        } else {
+            15
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct RgbSetFullMessage {
     pub color: Hsv,
+    pub gamma: f32,
+    pub format: ColorFormat,
+}
+
+#[derive(Debug)]
+pub struct RgbStreamMessage {
+    pub start: u16,
+    pub colors: Vec<Hsv>,
+    pub gamma: f32,
+    pub format: ColorFormat,
+}
+
+impl RgbStreamMessage {
+    // leds that fit in one report after the 4 byte header, the start index and the count
+    pub const fn capacity(framing: Framing) -> usize {
+        (framing.report_size() - 7) / 3
+    }
+}
+
+// a run of led colors read back from the firmware, as the raw channel bytes
+#[derive(Debug)]
+pub struct RgbStateMessage {
+    pub start: u16,
+    pub colors: Vec<[u8; 3]>,
+}
+
+// turns a channel byte the firmware reports back into the color value it was sent from
+pub fn channel_value(byte: u8, gamma: f32) -> f32 {
+    (byte as f32 / 255.0).powf(1.0 / gamma)
+}
+
+// the color 3 bytes the firmware reports back stand for
+pub fn color_value(bytes: [u8; 3], gamma: f32, format: ColorFormat) -> Hsv {
+    match format {
+        ColorFormat::Rgb => {
+            let [red, green, blue] = bytes.map(|byte| channel_value(byte, gamma));
+            Rgb::new(red, green, blue).into_color()
+        }
+        ColorFormat::Hsv => Hsv::new(
+            bytes[0] as f32 / 256.0 * 360.0,
+            bytes[1] as f32 / 255.0,
+            bytes[2] as f32 / 255.0,
+        ),
+    }
+}
+
+// a piece of console text, `more` if the next report continues it. the firmware splits long
+// text at utf-8 character boundaries
+#[derive(Debug)]
+pub struct ConsoleMessage {
+    pub text: String,
+    pub more: bool,
+}
+
+// lock leds the host has turned on, as the firmware reports them
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LockState {
+    pub num_lock: bool,
+    pub caps_lock: bool,
+    pub scroll_lock: bool,
+}
+
+impl LockState {
+    // bit order of qmk's led_t
+    pub fn from_bits(bits: u8) -> Self {
+        LockState {
+            num_lock: bits & 1 != 0,
+            caps_lock: bits & 1 << 1 != 0,
+            scroll_lock: bits & 1 << 2 != 0,
+        }
+    }
+}
+
+// active modifiers in qmk's 8 bit layout: left ctrl, shift, alt, gui, then the same on the right
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Mods(pub u8);
+
+impl Mods {
+    // either side
+    fn has(self, bit: u8) -> bool {
+        self.0 & (1 << bit | 1 << (bit + 4)) != 0
+    }
+
+    pub fn has_ctrl(self) -> bool {
+        self.has(0)
+    }
+
+    pub fn has_shift(self) -> bool {
+        self.has(1)
+    }
+
+    pub fn has_alt(self) -> bool {
+        self.has(2)
+    }
+
+    pub fn has_gui(self) -> bool {
+        self.has(3)
+    }
+
+    pub fn names(self) -> Vec<&'static str> {
+        [
+            (self.has_ctrl(), "Ctrl"),
+            (self.has_shift(), "Shift"),
+            (self.has_alt(), "Alt"),
+            (self.has_gui(), "Gui"),
+        ]
+        .iter()
+        .filter(|(on, _)| *on)
+        .map(|(_, name)| *name)
+        .collect()
+    }
 }
 
 #[derive(Debug)]
@@ -37,25 +275,151 @@ pub enum ProtocolMessage {
     Layer(LayerMessage),
     RgbSet(RgbSetMessage),
     RgbSetFull(RgbSetFullMessage),
+    RgbStream(RgbStreamMessage),
+    // asks the firmware to answer with a KSK_LAYER message
+    GetLayer,
+    Console(ConsoleMessage),
+    Locks(LockState),
+    // scales every led on the firmware, 255 is full brightness
+    SetBrightness(u8),
+    // asks the firmware to answer with KSK_RGB_STATE messages for every led
+    RgbQuery,
+    RgbState(RgbStateMessage),
+    Battery { percent: u8, charging: bool },
+    // asks the firmware to answer with a KSK_VERSION message
+    VersionQuery,
+    Version(FirmwareVersion),
+    Ack { opcode: u8 },
+    Encoder { index: u8, clockwise: bool },
+}
+
+#[derive(Debug)]
+pub enum ProtocolError {
+    // more leds than RgbSetMessage::capacity
+    TooManyColors(usize),
+    // led index past 255 in a compact rgb set message
+    IndexTooLarge(u16),
+    // bytes the report would take
+    MessageTooLarge(usize),
+    // only the firmware sends this kind of message
+    NotSendable,
+    Hid(HidError),
+    // no ack arrived for any of this many attempts
+    NoAck(u32),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProtocolError::TooManyColors(count) => {
+                write!(f, "cannot set {} rgb pixels at once", count)
+            }
+            ProtocolError::IndexTooLarge(idx) => {
+                write!(f, "led index {} needs the wide rgb set message", idx)
+            }
+            ProtocolError::MessageTooLarge(size) => {
+                write!(f, "{} byte message exceeds the endpoint size", size)
+            }
+            ProtocolError::NotSendable => write!(f, "this message cannot be sent"),
+            ProtocolError::Hid(err) => write!(f, "{}", err),
+            ProtocolError::NoAck(attempts) => write!(f, "no ack after {} attempts", attempts),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+impl From<HidError> for ProtocolError {
+    fn from(err: HidError) -> Self {
+        ProtocolError::Hid(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FirmwareVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+}
+
+impl FirmwareVersion {
+    // what firmware from before the version query is taken to be
+    pub const LEGACY: FirmwareVersion = FirmwareVersion {
+        major: 1,
+        minor: 0,
+        patch: 0,
+    };
+}
+
+impl std::fmt::Display for FirmwareVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
 }
 
-const K: u8 = 0x6b;
-const S: u8 = 0x73;
+pub const K: u8 = 0x6b;
+pub const S: u8 = 0x73;
+
+// blending and brightness scaling can push a channel slightly past 1.0, which would wrap
+// around in the cast without the clamp
+pub fn channel(value: f32, gamma: f32) -> u8 {
+    (value.clamp(0.0, 1.0).powf(gamma) * 255.0).round() as u8
+}
+
+// a color component in 0-1, NaN (from a division by 0 in some effect) counting as 0
+pub fn unit(value: f32) -> f32 {
+    if value.is_nan() {
+        0.0
+    } else {
+        value.clamp(0.0, 1.0)
+    }
+}
 
-fn push_color(buf: &mut Vec<u8>, color: &Hsv) {
-    let rgb: Rgb = (*color).into_color();
-    buf.push((rgb.red * 255.0) as u8);
-    buf.push((rgb.green * 255.0) as u8);
-    buf.push((rgb.blue * 255.0) as u8);
+fn push_color(buf: &mut Vec<u8>, color: &Hsv, gamma: f32, format: ColorFormat) {
+    // clamped before converting, a saturation past 1 would otherwise shift the hue
+    let color = Hsv::new(color.hue, unit(color.saturation), unit(color.value));
+    match format {
+        ColorFormat::Rgb => {
+            let rgb: Rgb = color.into_color();
+            buf.push(channel(rgb.red, gamma));
+            buf.push(channel(rgb.green, gamma));
+            buf.push(channel(rgb.blue, gamma));
+        }
+        ColorFormat::Hsv => {
+            // 360 degrees wraps around to 0
+            let hue = color.hue.to_positive_degrees() / 360.0 * 256.0;
+            buf.push((hue.round() as u32 % 256) as u8);
+            buf.push(channel(color.saturation, 1.0));
+            buf.push(channel(color.value, 1.0));
+        }
+    }
 }
 
 fn read_u16(buf: &[u8], beg_index: usize) -> u16 {
-    (buf[beg_index + 1] as u16) << 4 | buf[beg_index] as u16
+    (buf[beg_index + 1] as u16) << 8 | buf[beg_index] as u16
 }
 
 impl ProtocolMessage {
-    pub fn read_buffer(buf: &[u8; RAW_EPSIZE], size: usize) -> Option<ProtocolMessage> {
+    // whether a report has the ksk header and, when checked, the right checksum
+    fn valid_report(buf: &[u8], size: usize, framing: Framing) -> bool {
         if size < 4 || buf[0] != K || buf[1] != S || buf[2] != K {
+            trace!("ignoring {} byte report without ksk header", size);
+            return false;
+        }
+
+        if framing.checksum {
+            let end = framing.report_size();
+            if size < framing.epsize || crc8(&buf[..end]) != buf[end] {
+                debug!("dropping report with a bad checksum");
+                return false;
+            }
+        }
+
+        true
+    }
+
+    pub fn read_buffer(buf: &[u8], size: usize, framing: Framing) -> Option<ProtocolMessage> {
+        if !Self::valid_report(buf, size, framing) {
             return None;
         }
 
@@ -68,41 +432,1000 @@ impl ProtocolMessage {
                 keycode: read_u16(buf, 4),
                 col: buf[6],
                 row: buf[7],
+                mods: Mods(if size > 8 { buf[8] } else { 0 }),
             })),
             KSK_LAYER => Some(ProtocolMessage::Layer(LayerMessage {
-                layer_state: buf[4],
+                layer_state: u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]),
+            })),
+            KSK_LOCKS => Some(ProtocolMessage::Locks(LockState::from_bits(buf[4]))),
+            KSK_VERSION => Some(ProtocolMessage::Version(FirmwareVersion {
+                major: buf[4],
+                minor: buf[5],
+                patch: buf[6],
             })),
-            _ => None,
+            KSK_ENCODER => Some(ProtocolMessage::Encoder {
+                index: buf[4],
+                clockwise: header_data & 1 == 1,
+            }),
+            KSK_ACK => Some(ProtocolMessage::Ack {
+                opcode: header_data,
+            }),
+            KSK_BATTERY => Some(ProtocolMessage::Battery {
+                percent: buf[4].min(100),
+                charging: header_data & 1 == 1,
+            }),
+            KSK_RGB_STATE => {
+                let count = buf[6] as usize;
+                let end = 7 + count * 3;
+                if end > size.min(framing.report_size()) {
+                    debug!("ignoring rgb state report with {} bytes of {}", size, end);
+                    return None;
+                }
+
+                Some(ProtocolMessage::RgbState(RgbStateMessage {
+                    start: read_u16(buf, 4),
+                    colors: buf[7..end]
+                        .chunks_exact(3)
+                        .map(|rgb| [rgb[0], rgb[1], rgb[2]])
+                        .collect(),
+                }))
+            }
+            KSK_CONSOLE => {
+                // one length byte, then that much text
+                let len = buf[4] as usize;
+                let end = 5 + len;
+                if end > size.min(framing.report_size()) {
+                    debug!("ignoring console report with {} bytes of {}", size, end);
+                    return None;
+                }
+
+                Some(ProtocolMessage::Console(ConsoleMessage {
+                    text: String::from_utf8_lossy(&buf[5..end]).into_owned(),
+                    more: header_data & 1 == 1,
+                }))
+            }
+            _ => {
+                debug!("ignoring report with unknown opcode {}", op);
+                None
+            }
         }
     }
 
-    pub fn send(&self, device: &HidDevice) -> Result<usize, HidError> {
-        let mut buf: Vec<u8> = vec![0x00, K, S, K];
+    // hidapi takes the report id as the first byte of every write, ahead of the report itself,
+    // so a full buffer is one byte longer than the endpoint
+    pub fn serialize(&self, framing: Framing) -> Result<Vec<u8>, ProtocolError> {
+        let mut buf: Vec<u8> = vec![framing.report_id, K, S, K];
 
         match self {
             ProtocolMessage::RgbSet(msg) => {
-                if msg.colors.len() > u8::MAX.into() {
-                    panic!("cannot set this many rgb pixels at once!");
+                if msg.colors.len() > RgbSetMessage::capacity(msg.wide, framing) {
+                    return Err(ProtocolError::TooManyColors(msg.colors.len()));
                 }
 
-                buf.push(KSK_RGB_SET << 4 | msg.colors.len() as u8);
+                let op = if msg.wide {
+                    KSK_RGB_SET_WIDE
+                } else {
+                    KSK_RGB_SET
+                };
+                buf.push(op << 4 | msg.colors.len() as u8);
 
                 for (idx, color) in &msg.colors {
-                    buf.push(*idx);
-                    push_color(&mut buf, color);
+                    if msg.wide {
+                        buf.extend(idx.to_le_bytes());
+                    } else {
+                        let idx =
+                            u8::try_from(*idx).map_err(|_| ProtocolError::IndexTooLarge(*idx))?;
+                        buf.push(idx);
+                    }
+                    push_color(&mut buf, color, msg.gamma, msg.format);
+                }
+            }
+            ProtocolMessage::RgbStream(msg) => {
+                if msg.colors.len() > RgbStreamMessage::capacity(framing) {
+                    return Err(ProtocolError::TooManyColors(msg.colors.len()));
+                }
+
+                buf.push(KSK_RGB_STREAM << 4);
+                buf.extend(msg.start.to_le_bytes());
+                buf.push(msg.colors.len() as u8);
+                for color in &msg.colors {
+                    push_color(&mut buf, color, msg.gamma, msg.format);
                 }
             }
             ProtocolMessage::RgbSetFull(msg) => {
                 buf.push(KSK_RGB_SET << 4);
-                push_color(&mut buf, &msg.color);
+                push_color(&mut buf, &msg.color, msg.gamma, msg.format);
+            }
+            ProtocolMessage::GetLayer => {
+                buf.push(KSK_GET_LAYER << 4);
+            }
+            ProtocolMessage::VersionQuery => {
+                buf.push(KSK_VERSION << 4);
             }
-            _ => panic!("this message cannot be sent!"),
+            ProtocolMessage::RgbQuery => {
+                buf.push(KSK_RGB_STATE << 4);
+            }
+            ProtocolMessage::SetBrightness(value) => {
+                buf.push(KSK_BRIGHTNESS << 4);
+                buf.push(*value);
+            }
+            _ => return Err(ProtocolError::NotSendable),
         }
 
-        if buf.len() > RAW_EPSIZE + 1 {
-            panic!("message size exceeded RAW_EPSIZE ({})", RAW_EPSIZE);
+        // the report id byte doesn't count towards the endpoint size
+        if buf.len() > framing.report_size() + 1 {
+            return Err(ProtocolError::MessageTooLarge(buf.len() - 1));
         }
 
-        device.write(&buf)
+        if framing.checksum {
+            buf.resize(framing.epsize, 0);
+            let crc = crc8(&buf[1..]);
+            buf.push(crc);
+        }
+
+        Ok(buf)
+    }
+
+    pub fn send(
+        &self,
+        device: &dyn HidTransport,
+        framing: Framing,
+    ) -> Result<usize, ProtocolError> {
+        let buf = self.serialize(framing)?;
+        trace!("writing {:?} in {} bytes", self, buf.len());
+        Ok(device.write(&buf)?)
+    }
+
+    // sends until the firmware acks it, waiting up to `timeout` for each of `attempts` tries.
+    // anything else read while waiting is pushed onto `received` for the caller to handle
+    pub fn send_acked(
+        &self,
+        device: &dyn HidTransport,
+        reader: &mut ProtocolReader,
+        timeout: Duration,
+        attempts: u32,
+        received: &mut VecDeque<ProtocolMessage>,
+    ) -> Result<(), ProtocolError> {
+        let buf = self.serialize(reader.framing)?;
+        // after the report id and the magic
+        let opcode = buf[4] >> 4;
+        let mut recv_buffer = vec![0u8; reader.framing.epsize];
+
+        for attempt in 1..=attempts {
+            device.write(&buf)?;
+
+            let deadline = Instant::now() + timeout;
+            while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+                let size = device.read_timeout(&mut recv_buffer, remaining.as_millis() as i32)?;
+                if size == 0 {
+                    break;
+                }
+
+                match reader.read(&recv_buffer, size) {
+                    Some(ProtocolMessage::Ack { opcode: acked }) if acked == opcode => {
+                        return Ok(());
+                    }
+                    Some(message) => received.push_back(message),
+                    None => {}
+                }
+            }
+
+            debug!(
+                "no ack for opcode {}, attempt {} of {}",
+                opcode, attempt, attempts
+            );
+        }
+
+        Err(ProtocolError::NoAck(attempts))
+    }
+}
+
+// fragments that stop coming for this long are dropped
+const FRAGMENT_TIMEOUT: Duration = Duration::from_millis(500);
+// longest payload put back together, anything claiming more is dropped
+const MAX_FRAGMENTED: usize = 1024;
+
+struct Fragments {
+    header: u8,
+    total: usize,
+    payload: Vec<u8>,
+    last: Instant,
+}
+
+// reads every report from the keyboard, putting KSK_FRAGMENT pieces back together into the
+// message they're part of
+pub struct ProtocolReader {
+    framing: Framing,
+    pending: Option<Fragments>,
+}
+
+impl ProtocolReader {
+    pub fn new(framing: Framing) -> Self {
+        ProtocolReader {
+            framing,
+            pending: None,
+        }
+    }
+
+    pub fn read(&mut self, buf: &[u8], size: usize) -> Option<ProtocolMessage> {
+        if self
+            .pending
+            .as_ref()
+            .is_some_and(|pending| pending.last.elapsed() >= FRAGMENT_TIMEOUT)
+        {
+            debug!("dropping a fragmented message that stopped halfway");
+            self.pending = None;
+        }
+
+        if !ProtocolMessage::valid_report(buf, size, self.framing) {
+            return None;
+        }
+
+        if buf[3] >> 4 == KSK_FRAGMENT {
+            self.fragment(buf, size)
+        } else {
+            ProtocolMessage::read_buffer(buf, size, self.framing)
+        }
+    }
+
+    fn fragment(&mut self, buf: &[u8], size: usize) -> Option<ProtocolMessage> {
+        let end = size.min(self.framing.report_size());
+        if end < 7 {
+            debug!("ignoring {} byte fragment", size);
+            return None;
+        }
+
+        let header = buf[4];
+        let total = read_u16(buf, 5) as usize;
+        if total > MAX_FRAGMENTED {
+            debug!("ignoring fragment of a {} byte message", total);
+            self.pending = None;
+            return None;
+        }
+
+        let mut pending = match self.pending.take() {
+            Some(pending) if pending.header == header && pending.total == total => pending,
+            other => {
+                if other.is_some() {
+                    debug!("dropping a fragmented message cut off by another");
+                }
+
+                Fragments {
+                    header,
+                    total,
+                    payload: Vec::with_capacity(total),
+                    last: Instant::now(),
+                }
+            }
+        };
+
+        let missing = pending.total - pending.payload.len();
+        pending
+            .payload
+            .extend_from_slice(&buf[7..end.min(7 + missing)]);
+        pending.last = Instant::now();
+
+        if pending.payload.len() < pending.total {
+            self.pending = Some(pending);
+            return None;
+        }
+
+        // the whole message as one long report, padded so fixed offsets stay in bounds
+        let mut whole = vec![K, S, K, pending.header];
+        whole.extend(pending.payload);
+        let size = whole.len();
+        whole.resize(size.max(DEFAULT_EPSIZE), 0);
+
+        let framing = Framing {
+            checksum: false,
+            epsize: size,
+            ..self.framing
+        };
+        ProtocolMessage::read_buffer(&whole, size, framing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pushed(color: Hsv, gamma: f32) -> Vec<u8> {
+        let mut buf = Vec::new();
+        push_color(&mut buf, &color, gamma, ColorFormat::Rgb);
+        buf
+    }
+
+    #[test]
+    fn channels_clamp_instead_of_wrapping() {
+        assert_eq!(pushed(Hsv::new(0.0, 0.0, 1.2), 1.0), vec![255, 255, 255]);
+        assert_eq!(pushed(Hsv::new(0.0, 0.0, 1.2), 2.2), vec![255, 255, 255]);
+        assert_eq!(pushed(Hsv::new(0.0, 0.0, -0.1), 2.2), vec![0, 0, 0]);
+    }
+
+    #[test]
+    fn hsv_format_sends_qmk_bytes() {
+        let hsv = |color: Hsv| {
+            let mut buf = Vec::new();
+            push_color(&mut buf, &color, 2.2, ColorFormat::Hsv);
+            buf
+        };
+
+        assert_eq!(hsv(Hsv::new(0.0, 1.0, 1.0)), vec![0, 255, 255]);
+        assert_eq!(hsv(Hsv::new(180.0, 0.5, 0.0)), vec![128, 128, 0]);
+        // just short of a full turn rounds up and wraps
+        assert_eq!(hsv(Hsv::new(359.9, 1.0, 1.0)), vec![0, 255, 255]);
+
+        let back = color_value([128, 255, 51], 2.2, ColorFormat::Hsv);
+        assert!((back.hue.to_positive_degrees() - 180.0).abs() < 1e-3);
+        assert!((back.value - 0.2).abs() < 1e-3);
+    }
+
+    #[test]
+    fn gamma_one_is_linear() {
+        for step in 0..=20 {
+            let value = step as f32 / 20.0;
+            let expected = (value * 255.0).round() as u8;
+            assert_eq!(pushed(Hsv::new(0.0, 0.0, value), 1.0), vec![expected; 3]);
+        }
+    }
+
+    #[test]
+    fn buffer_starts_with_report_id() {
+        for report_id in [0x00, 0x01, 0xff] {
+            let buf = ProtocolMessage::GetLayer
+                .serialize(Framing {
+                    report_id,
+                    checksum: false,
+                    epsize: DEFAULT_EPSIZE,
+                })
+                .unwrap();
+            assert_eq!(buf, vec![report_id, K, S, K, KSK_GET_LAYER << 4]);
+        }
+
+        let mut colors = HashMap::new();
+        colors.insert(3, Hsv::new(0.0, 0.0, 1.0));
+        let buf = ProtocolMessage::RgbSet(RgbSetMessage {
+            colors,
+            gamma: 1.0,
+            format: ColorFormat::Rgb,
+            wide: false,
+        })
+        .serialize(Framing {
+            report_id: 0x02,
+            checksum: false,
+            epsize: DEFAULT_EPSIZE,
+        })
+        .unwrap();
+        assert_eq!(
+            buf,
+            vec![0x02, K, S, K, KSK_RGB_SET << 4 | 1, 3, 255, 255, 255]
+        );
+    }
+
+    #[test]
+    fn out_of_range_colors_are_clamped() {
+        let send = |color: Hsv, format: ColorFormat| {
+            let buf = ProtocolMessage::RgbSetFull(RgbSetFullMessage {
+                color,
+                gamma: 1.0,
+                format,
+            })
+            .serialize(Framing::default())
+            .unwrap();
+            buf[buf.len() - 3..].to_vec()
+        };
+
+        // orange stays orange, rather than losing green to an overshooting saturation
+        assert_eq!(
+            send(Hsv::new(30.0, 1.5, 1.0), ColorFormat::Rgb),
+            [255, 128, 0]
+        );
+        assert_eq!(send(Hsv::new(0.0, 0.0, 3.0), ColorFormat::Rgb), [255; 3]);
+        assert_eq!(send(Hsv::new(0.0, 1.0, -0.5), ColorFormat::Rgb), [0; 3]);
+        assert_eq!(
+            send(Hsv::new(0.0, f32::NAN, 1.0), ColorFormat::Rgb),
+            [255; 3]
+        );
+        assert_eq!(
+            send(Hsv::new(180.0, 2.0, 1.5), ColorFormat::Hsv),
+            [128, 255, 255]
+        );
+    }
+
+    fn rgb_set(idx: u16, wide: bool) -> Vec<u8> {
+        let mut colors = HashMap::new();
+        colors.insert(idx, Hsv::new(0.0, 1.0, 1.0));
+
+        ProtocolMessage::RgbSet(RgbSetMessage {
+            colors,
+            gamma: 1.0,
+            format: ColorFormat::Rgb,
+            wide,
+        })
+        .serialize(Framing::default())
+        .unwrap()
+    }
+
+    #[test]
+    fn compact_led_index() {
+        assert_eq!(
+            rgb_set(255, false),
+            vec![0, K, S, K, KSK_RGB_SET << 4 | 1, 255, 255, 0, 0]
+        );
+    }
+
+    #[test]
+    fn wide_led_index() {
+        assert_eq!(
+            rgb_set(255, true),
+            vec![0, K, S, K, KSK_RGB_SET_WIDE << 4 | 1, 0xff, 0x00, 255, 0, 0]
+        );
+        assert_eq!(
+            rgb_set(256, true),
+            vec![0, K, S, K, KSK_RGB_SET_WIDE << 4 | 1, 0x00, 0x01, 255, 0, 0]
+        );
+    }
+
+    #[test]
+    fn unsendable_messages_are_errors() {
+        let mut colors = HashMap::new();
+        colors.insert(256, Hsv::new(0.0, 1.0, 1.0));
+        let compact = ProtocolMessage::RgbSet(RgbSetMessage {
+            colors,
+            gamma: 1.0,
+            format: ColorFormat::Rgb,
+            wide: false,
+        });
+        assert!(matches!(
+            compact.serialize(Framing::default()),
+            Err(ProtocolError::IndexTooLarge(256))
+        ));
+
+        let colors = (0..8).map(|idx| (idx, Hsv::new(0.0, 0.0, 1.0))).collect();
+        let overfull = ProtocolMessage::RgbSet(RgbSetMessage {
+            colors,
+            gamma: 1.0,
+            format: ColorFormat::Rgb,
+            wide: false,
+        });
+        assert!(matches!(
+            overfull.serialize(Framing::default()),
+            Err(ProtocolError::TooManyColors(8))
+        ));
+
+        assert!(matches!(
+            ProtocolMessage::Ack { opcode: 0 }.serialize(Framing::default()),
+            Err(ProtocolError::NotSendable)
+        ));
+    }
+
+    #[test]
+    fn full_reports_fit() {
+        for wide in [false, true] {
+            let colors = (0..RgbSetMessage::capacity(wide, Framing::default()) as u16)
+                .map(|idx| (idx, Hsv::new(0.0, 0.0, 1.0)))
+                .collect();
+
+            let buf = ProtocolMessage::RgbSet(RgbSetMessage {
+                colors,
+                gamma: 1.0,
+                format: ColorFormat::Rgb,
+                wide,
+            })
+            .serialize(Framing::default())
+            .unwrap();
+            assert!(buf.len() <= DEFAULT_EPSIZE + 1);
+        }
+    }
+
+    #[test]
+    fn chunk_limit_layout() {
+        let capacity = RgbSetMessage::capacity(false, Framing::default());
+        let colors = (0..capacity as u16)
+            .map(|idx| (idx, Hsv::new(0.0, 0.0, 1.0)))
+            .collect();
+
+        let buf = ProtocolMessage::RgbSet(RgbSetMessage {
+            colors,
+            gamma: 1.0,
+            format: ColorFormat::Rgb,
+            wide: false,
+        })
+        .serialize(Framing::default())
+        .unwrap();
+
+        assert_eq!(buf[..5], [0, K, S, K, KSK_RGB_SET << 4 | capacity as u8]);
+        assert_eq!(buf.len(), 5 + capacity * 4);
+
+        // indices come in hash order, every one exactly once and followed by white
+        let mut indices: Vec<u8> = buf[5..].chunks_exact(4).map(|led| led[0]).collect();
+        indices.sort_unstable();
+        assert_eq!(indices, (0..capacity as u8).collect::<Vec<_>>());
+        assert!(buf[5..]
+            .chunks_exact(4)
+            .all(|led| led[1..] == [255, 255, 255]));
+    }
+
+    #[test]
+    fn stream_layout() {
+        let capacity = RgbStreamMessage::capacity(Framing::default());
+        assert_eq!(capacity, 8);
+
+        let buf = ProtocolMessage::RgbStream(RgbStreamMessage {
+            start: 300,
+            colors: vec![Hsv::new(0.0, 1.0, 1.0), Hsv::new(0.0, 0.0, 1.0)],
+            gamma: 1.0,
+            format: ColorFormat::Rgb,
+        })
+        .serialize(Framing::default())
+        .unwrap();
+        assert_eq!(
+            buf,
+            [
+                0,
+                K,
+                S,
+                K,
+                KSK_RGB_STREAM << 4,
+                0x2c,
+                0x01,
+                2,
+                255,
+                0,
+                0,
+                255,
+                255,
+                255
+            ]
+        );
+
+        let full = ProtocolMessage::RgbStream(RgbStreamMessage {
+            start: 0,
+            colors: vec![Hsv::new(0.0, 0.0, 1.0); capacity],
+            gamma: 1.0,
+            format: ColorFormat::Rgb,
+        })
+        .serialize(CHECKED)
+        .unwrap();
+        assert_eq!(full.len(), DEFAULT_EPSIZE + 1);
+
+        let overfull = ProtocolMessage::RgbStream(RgbStreamMessage {
+            start: 0,
+            colors: vec![Hsv::new(0.0, 0.0, 1.0); capacity + 1],
+            gamma: 1.0,
+            format: ColorFormat::Rgb,
+        });
+        assert!(matches!(
+            overfull.serialize(Framing::default()),
+            Err(ProtocolError::TooManyColors(_))
+        ));
+    }
+
+    #[test]
+    fn full_set_layout() {
+        let buf = ProtocolMessage::RgbSetFull(RgbSetFullMessage {
+            color: Hsv::new(0.0, 0.0, 0.0),
+            gamma: 2.2,
+            format: ColorFormat::Rgb,
+        })
+        .serialize(Framing::default())
+        .unwrap();
+        assert_eq!(buf, [0, K, S, K, KSK_RGB_SET << 4, 0, 0, 0]);
+    }
+
+    #[test]
+    fn query_layouts() {
+        let serialize = |message: ProtocolMessage| message.serialize(Framing::default()).unwrap();
+
+        assert_eq!(
+            serialize(ProtocolMessage::GetLayer),
+            [0, K, S, K, KSK_GET_LAYER << 4]
+        );
+        assert_eq!(
+            serialize(ProtocolMessage::RgbQuery),
+            [0, K, S, K, KSK_RGB_STATE << 4]
+        );
+        assert_eq!(
+            serialize(ProtocolMessage::VersionQuery),
+            [0, K, S, K, KSK_VERSION << 4]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_reports() {
+        let mut buf = [0u8; DEFAULT_EPSIZE];
+        buf[..8].copy_from_slice(&[K, S, K, KSK_PRESS << 4 | 1, 0xff, 0xff, 3, 2]);
+        assert!(ProtocolMessage::read_buffer(&buf, DEFAULT_EPSIZE, Framing::default()).is_some());
+
+        // shorter than the header
+        assert!(ProtocolMessage::read_buffer(&buf, 3, Framing::default()).is_none());
+
+        for idx in 0..3 {
+            let mut bad_magic = buf;
+            bad_magic[idx] ^= 0xff;
+            assert!(
+                ProtocolMessage::read_buffer(&bad_magic, DEFAULT_EPSIZE, Framing::default())
+                    .is_none()
+            );
+        }
+
+        let mut unknown = buf;
+        unknown[3] = 0xf0;
+        assert!(
+            ProtocolMessage::read_buffer(&unknown, DEFAULT_EPSIZE, Framing::default()).is_none()
+        );
+    }
+
+    #[test]
+    fn reads_press_mods() {
+        let mut buf = [0u8; DEFAULT_EPSIZE];
+        buf[..9].copy_from_slice(&[K, S, K, KSK_PRESS << 4 | 1, 0x04, 0, 0, 0, 0b0100_0001]);
+
+        match ProtocolMessage::read_buffer(&buf, DEFAULT_EPSIZE, Framing::default()) {
+            Some(ProtocolMessage::Press(msg)) => {
+                assert!(msg.mods.has_ctrl());
+                assert!(msg.mods.has_alt());
+                assert!(!msg.mods.has_shift() && !msg.mods.has_gui());
+                assert_eq!(msg.mods.names(), ["Ctrl", "Alt"]);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // older firmware ends the report after the row
+        match ProtocolMessage::read_buffer(&buf, 8, Framing::default()) {
+            Some(ProtocolMessage::Press(msg)) => assert_eq!(msg.mods, Mods(0)),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reads_largest_keycode() {
+        let mut buf = [0u8; DEFAULT_EPSIZE];
+        buf[..8].copy_from_slice(&[K, S, K, KSK_PRESS << 4, 0xff, 0xff, 0, 0]);
+
+        match ProtocolMessage::read_buffer(&buf, DEFAULT_EPSIZE, Framing::default()) {
+            Some(ProtocolMessage::Press(msg)) => {
+                assert_eq!(msg.keycode, 0xffff);
+                assert!(!msg.pressed);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    fn console_report(more: bool, text: &str) -> [u8; DEFAULT_EPSIZE] {
+        let mut buf = [0u8; DEFAULT_EPSIZE];
+        buf[..4].copy_from_slice(&[K, S, K, KSK_CONSOLE << 4 | more as u8]);
+        buf[4] = text.len() as u8;
+        buf[5..5 + text.len()].copy_from_slice(text.as_bytes());
+        buf
+    }
+
+    #[test]
+    fn reads_console_text() {
+        let buf = console_report(true, "hello ");
+
+        match ProtocolMessage::read_buffer(&buf, DEFAULT_EPSIZE, Framing::default()) {
+            Some(ProtocolMessage::Console(msg)) => {
+                assert_eq!(msg.text, "hello ");
+                assert!(msg.more);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_console_text() {
+        let mut buf = console_report(false, "hello");
+        buf[4] = 40;
+
+        assert!(ProtocolMessage::read_buffer(&buf, DEFAULT_EPSIZE, Framing::default()).is_none());
+        assert!(ProtocolMessage::read_buffer(
+            &console_report(false, "hello"),
+            8,
+            Framing::default()
+        )
+        .is_none());
+    }
+
+    const CHECKED: Framing = Framing {
+        report_id: 0,
+        checksum: true,
+        epsize: DEFAULT_EPSIZE,
+    };
+
+    #[test]
+    fn crc8_check_value() {
+        // the standard check input for crc-8 with polynomial 0x07
+        assert_eq!(crc8(b"123456789"), 0xF4);
+    }
+
+    #[test]
+    fn checksummed_reports_are_padded() {
+        let buf = ProtocolMessage::SetBrightness(128)
+            .serialize(CHECKED)
+            .unwrap();
+
+        assert_eq!(buf.len(), DEFAULT_EPSIZE + 1);
+        assert_eq!(buf[..6], [0, K, S, K, KSK_BRIGHTNESS << 4, 128]);
+        assert_eq!(buf[DEFAULT_EPSIZE], crc8(&buf[1..DEFAULT_EPSIZE]));
+    }
+
+    #[test]
+    fn checksummed_rgb_reports_fit() {
+        for wide in [false, true] {
+            let colors = (0..RgbSetMessage::capacity(wide, CHECKED) as u16)
+                .map(|idx| (idx, Hsv::new(0.0, 0.0, 1.0)))
+                .collect();
+
+            let buf = ProtocolMessage::RgbSet(RgbSetMessage {
+                colors,
+                gamma: 1.0,
+                format: ColorFormat::Rgb,
+                wide,
+            })
+            .serialize(CHECKED)
+            .unwrap();
+            assert_eq!(buf.len(), DEFAULT_EPSIZE + 1);
+        }
+    }
+
+    #[test]
+    fn bigger_endpoints_fit_more_leds() {
+        let framing = Framing {
+            report_id: 0,
+            checksum: true,
+            epsize: MAX_EPSIZE,
+        };
+
+        assert_eq!(RgbSetMessage::capacity(false, Framing::default()), 7);
+        assert_eq!(RgbSetMessage::capacity(false, framing), 14);
+        assert_eq!(RgbSetMessage::capacity(true, framing), 11);
+
+        // past 15 leds the count wouldn't fit in the header
+        let unchecked = Framing {
+            checksum: false,
+            ..framing
+        };
+        assert_eq!(RgbSetMessage::capacity(false, unchecked), 15);
+
+        let buf = ProtocolMessage::SetBrightness(128)
+            .serialize(framing)
+            .unwrap();
+        assert_eq!(buf.len(), MAX_EPSIZE + 1);
+        assert_eq!(buf[MAX_EPSIZE], crc8(&buf[1..MAX_EPSIZE]));
+    }
+
+    #[test]
+    fn checksum_round_trip() {
+        let mut buf = console_report(false, "hi");
+        buf[DEFAULT_EPSIZE - 1] = crc8(&buf[..DEFAULT_EPSIZE - 1]);
+
+        match ProtocolMessage::read_buffer(&buf, DEFAULT_EPSIZE, CHECKED) {
+            Some(ProtocolMessage::Console(msg)) => assert_eq!(msg.text, "hi"),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // a flipped payload bit, or a short read, is dropped instead of decoded
+        let mut corrupt = buf;
+        corrupt[5] ^= 0x01;
+        assert!(ProtocolMessage::read_buffer(&corrupt, DEFAULT_EPSIZE, CHECKED).is_none());
+        assert!(ProtocolMessage::read_buffer(&buf, DEFAULT_EPSIZE - 1, CHECKED).is_none());
+    }
+
+    #[test]
+    fn reads_rgb_state() {
+        let mut buf = [0u8; DEFAULT_EPSIZE];
+        buf[..13].copy_from_slice(&[
+            K,
+            S,
+            K,
+            KSK_RGB_STATE << 4,
+            0x2C,
+            0x01,
+            2,
+            255,
+            0,
+            0,
+            0,
+            0,
+            128,
+        ]);
+
+        match ProtocolMessage::read_buffer(&buf, DEFAULT_EPSIZE, Framing::default()) {
+            Some(ProtocolMessage::RgbState(msg)) => {
+                assert_eq!(msg.start, 300);
+                assert_eq!(msg.colors, [[255, 0, 0], [0, 0, 128]]);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // more leds than the report holds
+        buf[6] = 9;
+        assert!(ProtocolMessage::read_buffer(&buf, DEFAULT_EPSIZE, Framing::default()).is_none());
+    }
+
+    #[test]
+    fn reads_battery() {
+        let mut buf = [0u8; DEFAULT_EPSIZE];
+        buf[..5].copy_from_slice(&[K, S, K, KSK_BATTERY << 4 | 1, 87]);
+
+        match ProtocolMessage::read_buffer(&buf, DEFAULT_EPSIZE, Framing::default()) {
+            Some(ProtocolMessage::Battery { percent, charging }) => {
+                assert_eq!(percent, 87);
+                assert!(charging);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reads_version() {
+        let mut buf = [0u8; DEFAULT_EPSIZE];
+        buf[..7].copy_from_slice(&[K, S, K, KSK_VERSION << 4, 2, 1, 3]);
+
+        match ProtocolMessage::read_buffer(&buf, DEFAULT_EPSIZE, Framing::default()) {
+            Some(ProtocolMessage::Version(version)) => assert_eq!(version.to_string(), "2.1.3"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reads_encoder() {
+        let mut buf = [0u8; DEFAULT_EPSIZE];
+        buf[..5].copy_from_slice(&[K, S, K, KSK_ENCODER << 4 | 1, 2]);
+
+        assert!(matches!(
+            ProtocolMessage::read_buffer(&buf, DEFAULT_EPSIZE, Framing::default()),
+            Some(ProtocolMessage::Encoder {
+                index: 2,
+                clockwise: true
+            })
+        ));
+
+        buf[3] = KSK_ENCODER << 4;
+        assert!(matches!(
+            ProtocolMessage::read_buffer(&buf, DEFAULT_EPSIZE, Framing::default()),
+            Some(ProtocolMessage::Encoder {
+                index: 2,
+                clockwise: false
+            })
+        ));
+    }
+
+    fn fragment(header: u8, total: u16, payload: &[u8]) -> [u8; DEFAULT_EPSIZE] {
+        let mut buf = [0u8; DEFAULT_EPSIZE];
+        buf[..7].copy_from_slice(&[K, S, K, KSK_FRAGMENT << 4, header, 0, 0]);
+        buf[5..7].copy_from_slice(&total.to_le_bytes());
+        buf[7..7 + payload.len()].copy_from_slice(payload);
+        buf
+    }
+
+    #[test]
+    fn reassembles_fragments() {
+        let text = "a console line longer than one report";
+        let mut payload = vec![text.len() as u8];
+        payload.extend(text.as_bytes());
+        let (first, rest) = payload.split_at(DEFAULT_EPSIZE - 7);
+
+        let header = KSK_CONSOLE << 4;
+        let total = payload.len() as u16;
+        let mut reader = ProtocolReader::new(Framing::default());
+
+        assert!(reader
+            .read(&fragment(header, total, first), DEFAULT_EPSIZE)
+            .is_none());
+        match reader.read(&fragment(header, total, rest), DEFAULT_EPSIZE) {
+            Some(ProtocolMessage::Console(msg)) => assert_eq!(msg.text, text),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // everything else passes straight through
+        assert!(matches!(
+            reader.read(&console_report(false, "hi"), DEFAULT_EPSIZE),
+            Some(ProtocolMessage::Console(_))
+        ));
+    }
+
+    #[test]
+    fn drops_interrupted_fragments() {
+        let mut reader = ProtocolReader::new(Framing::default());
+        let header = KSK_CONSOLE << 4;
+
+        assert!(reader
+            .read(&fragment(header, 40, &[39; 25]), DEFAULT_EPSIZE)
+            .is_none());
+        // a different message starts over, so it doesn't get the first one's bytes
+        assert!(reader
+            .read(&fragment(header, 30, &[29; 25]), DEFAULT_EPSIZE)
+            .is_none());
+        match reader.read(&fragment(header, 30, &[b'x'; 5]), DEFAULT_EPSIZE) {
+            Some(ProtocolMessage::Console(msg)) => assert_eq!(msg.text.len(), 29),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        assert!(reader
+            .read(&fragment(header, 4000, &[0; 25]), DEFAULT_EPSIZE)
+            .is_none());
+        assert!(reader.pending.is_none());
+    }
+
+    #[test]
+    fn reads_ack() {
+        let mut buf = [0u8; DEFAULT_EPSIZE];
+        buf[..4].copy_from_slice(&[K, S, K, KSK_ACK << 4 | KSK_BRIGHTNESS]);
+
+        assert!(matches!(
+            ProtocolMessage::read_buffer(&buf, DEFAULT_EPSIZE, Framing::default()),
+            Some(ProtocolMessage::Ack {
+                opcode: KSK_BRIGHTNESS
+            })
+        ));
+    }
+
+    #[test]
+    fn channel_value_undoes_gamma() {
+        for value in [0.0, 0.25, 0.5, 1.0] {
+            let byte = channel(value, 2.2);
+            assert!((channel_value(byte, 2.2) - value).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn brightness_report() {
+        let buf = ProtocolMessage::SetBrightness(128)
+            .serialize(Framing::default())
+            .unwrap();
+        assert_eq!(buf, [0, K, S, K, KSK_BRIGHTNESS << 4, 128]);
+    }
+
+    #[test]
+    fn reads_press_keycode() {
+        let mut buf = [0u8; DEFAULT_EPSIZE];
+        buf[..8].copy_from_slice(&[K, S, K, KSK_PRESS << 4 | 1, 0xA3, 0x04, 3, 2]);
+
+        match ProtocolMessage::read_buffer(&buf, DEFAULT_EPSIZE, Framing::default()) {
+            Some(ProtocolMessage::Press(msg)) => {
+                assert_eq!(msg.keycode, 0x04A3);
+                assert!(msg.pressed);
+                assert_eq!((msg.row, msg.col), (2, 3));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reads_full_layer_mask() {
+        let mut buf = [0u8; DEFAULT_EPSIZE];
+        buf[..8].copy_from_slice(&[K, S, K, KSK_LAYER << 4, 0x05, 0x00, 0x01, 0x80]);
+
+        match ProtocolMessage::read_buffer(&buf, DEFAULT_EPSIZE, Framing::default()) {
+            Some(ProtocolMessage::Layer(msg)) => assert_eq!(msg.layer_state, 0x8001_0005),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reads_lock_bits() {
+        let mut buf = [0u8; DEFAULT_EPSIZE];
+        buf[..5].copy_from_slice(&[K, S, K, KSK_LOCKS << 4, 0b110]);
+
+        match ProtocolMessage::read_buffer(&buf, DEFAULT_EPSIZE, Framing::default()) {
+            Some(ProtocolMessage::Locks(locks)) => assert_eq!(
+                locks,
+                LockState {
+                    num_lock: false,
+                    caps_lock: true,
+                    scroll_lock: true,
+                }
+            ),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn gamma_darkens_midtones() {
+        let linear = pushed(Hsv::new(0.0, 0.0, 0.5), 1.0);
+        let corrected = pushed(Hsv::new(0.0, 0.0, 0.5), 2.2);
+        assert!(corrected[0] < linear[0]);
     }
 }
@@ -0,0 +1,109 @@
+use crate::config::KBConfig;
+use crossbeam::channel::Sender;
+use log::{info, warn};
+use std::{
+    fs, io,
+    path::PathBuf,
+    sync::Arc,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+pub type ReloadResult = Result<Arc<KBConfig>, String>;
+
+// modification times of the watched files, None for ones that can't be read right now
+#[derive(Clone, PartialEq, Debug)]
+struct FileStamps(Vec<Option<SystemTime>>);
+
+impl FileStamps {
+    fn read(paths: &[PathBuf]) -> FileStamps {
+        FileStamps(
+            paths
+                .iter()
+                .map(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+                .collect(),
+        )
+    }
+}
+
+// polls the files `paths` names for the loaded config and sends every reload, good or bad, to
+// the ui. a change is only reloaded once the files have stayed the same for a whole poll, so
+// editors that save in several steps aren't read half written.
+pub fn spawn(
+    kb_config: &KBConfig,
+    load: impl Fn() -> Result<KBConfig, io::Error> + Send + 'static,
+    paths: impl Fn(&KBConfig) -> Vec<PathBuf> + Send + 'static,
+    tx: Sender<ReloadResult>,
+) {
+    let mut watched = paths(kb_config);
+    let mut loaded = FileStamps::read(&watched);
+
+    thread::spawn(move || {
+        let mut last = FileStamps::read(&watched);
+
+        loop {
+            thread::sleep(POLL_INTERVAL);
+
+            let stamps = FileStamps::read(&watched);
+            let settled = stamps == last;
+            last = stamps;
+            if !settled || last == loaded {
+                continue;
+            }
+
+            let result = match load() {
+                Ok(kb_config) => {
+                    info!("reloaded config");
+                    // the keyboard or keymap may have changed, and with it the files to watch
+                    watched = paths(&kb_config);
+                    Ok(Arc::new(kb_config))
+                }
+                Err(err) => {
+                    warn!(
+                        "could not reload config, keeping the last good one: {}",
+                        err
+                    );
+                    Err(err.to_string())
+                }
+            };
+            last = FileStamps::read(&watched);
+            loaded = last.clone();
+
+            if tx.send(result).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stamps_follow_modification() {
+        let dir = std::env::temp_dir().join(format!("kb_host_reload_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("config.json");
+        fs::write(&file, "{}").unwrap();
+        let paths = vec![file.clone(), dir.join("missing.json")];
+
+        let before = FileStamps::read(&paths);
+        assert!(before.0[0].is_some());
+        assert_eq!(before.0[1], None);
+        assert_eq!(FileStamps::read(&paths), before);
+
+        let later = SystemTime::now() + Duration::from_secs(10);
+        fs::File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert_ne!(FileStamps::read(&paths), before);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
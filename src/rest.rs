@@ -0,0 +1,331 @@
+// a small http api for scripts and hotkey daemons, one request per connection:
+//
+//   GET  /state        the last `Telemetry`, as json
+//   POST /effect       {"name": "wave"}, run on its own with default params
+//   POST /color        {"hue": 120, "saturation": 1, "value": 1}, recolors the effects
+//   POST /brightness   {"value": 128}, 0-255
+//   POST /flash        {"hue": 0, "saturation": 1, "value": 1, "duration_ms": 300}
+//
+// hue is in degrees, saturation and value in 0-1 like the control socket. commands are
+// answered with {"ok": true} and mistakes with {"error": "<reason>"}
+use crate::{
+    config::RestConfig,
+    control, effects,
+    threading::{HIDCommand, Telemetry},
+};
+use crossbeam::channel::{Receiver, Sender};
+use log::{debug, info, warn};
+use palette::Hsva;
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+const READ_TIMEOUT: Duration = Duration::from_secs(2);
+// bodies are a handful of fields, anything bigger isn't for us
+const MAX_BODY: usize = 64 * 1024;
+
+#[derive(Debug, PartialEq)]
+struct Request {
+    method: String,
+    path: String,
+    body: String,
+}
+
+#[derive(Debug)]
+enum Route {
+    State,
+    Command(HIDCommand),
+}
+
+#[derive(Deserialize)]
+struct EffectBody {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct ColorBody {
+    hue: f32,
+    saturation: f32,
+    value: f32,
+}
+
+impl ColorBody {
+    fn color(&self) -> Result<Hsva, (u16, String)> {
+        for (name, channel) in [("saturation", self.saturation), ("value", self.value)] {
+            if !(0.0..=1.0).contains(&channel) {
+                return Err((400, format!("{} must be between 0 and 1", name)));
+            }
+        }
+        Ok(Hsva::new(self.hue, self.saturation, self.value, 1.0))
+    }
+}
+
+#[derive(Deserialize)]
+struct BrightnessBody {
+    value: u8,
+}
+
+#[derive(Deserialize)]
+struct FlashBody {
+    #[serde(flatten)]
+    color: ColorBody,
+    duration_ms: f32,
+}
+
+fn parse_body<'a, T: Deserialize<'a>>(body: &'a str) -> Result<T, (u16, String)> {
+    serde_json::from_str(body).map_err(|err| (400, format!("invalid body: {}", err)))
+}
+
+fn route(request: &Request) -> Result<Route, (u16, String)> {
+    let path = request.path.split('?').next().unwrap_or_default();
+    let body = &request.body;
+
+    let command = match (request.method.as_str(), path) {
+        ("GET", "/state") => return Ok(Route::State),
+        ("POST", "/effect") => {
+            let EffectBody { name } = parse_body(body)?;
+            if !effects::buildable_effects().contains(&name.as_str()) {
+                return Err((400, format!("no effect called {:?}", name)));
+            }
+            HIDCommand::SetEffect(name)
+        }
+        ("POST", "/color") => HIDCommand::SetColor(parse_body::<ColorBody>(body)?.color()?),
+        ("POST", "/brightness") => {
+            HIDCommand::SetBrightness(parse_body::<BrightnessBody>(body)?.value)
+        }
+        ("POST", "/flash") => {
+            let flash: FlashBody = parse_body(body)?;
+            let duration = control::duration_from_secs(flash.duration_ms / 1000.0)
+                .ok_or((400, "invalid duration_ms".to_string()))?;
+            HIDCommand::Flash {
+                color: flash.color.color()?,
+                duration,
+            }
+        }
+        (_, "/state" | "/effect" | "/color" | "/brightness" | "/flash") => {
+            return Err((405, format!("{} doesn't take {}", path, request.method)))
+        }
+        _ => return Err((404, format!("nothing at {}", path))),
+    };
+    Ok(Route::Command(command))
+}
+
+// the request line, headers up to the blank line, then as much body as content-length says
+fn read_request(reader: &mut impl BufRead) -> Result<Request, String> {
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(|err| err.to_string())?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(format!("malformed request line {:?}", line.trim()));
+    };
+    let (method, path) = (method.to_string(), path.to_string());
+
+    let mut length = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).map_err(|err| err.to_string())? == 0 {
+            return Err("the request ended in its headers".to_string());
+        }
+        let header = line.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("invalid content-length {:?}", value.trim()))?;
+            }
+        }
+    }
+    if length > MAX_BODY {
+        return Err(format!("a {} byte body is too big", length));
+    }
+
+    let mut body = vec![0; length];
+    reader
+        .read_exact(&mut body)
+        .map_err(|err| err.to_string())?;
+    let body = String::from_utf8(body).map_err(|_| "the body isn't utf-8".to_string())?;
+    Ok(Request { method, path, body })
+}
+
+fn respond(stream: &mut TcpStream, status: u16, body: &serde_json::Value) {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Service Unavailable",
+    };
+    let body = body.to_string();
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        body
+    );
+    if let Err(err) = stream.write_all(response.as_bytes()) {
+        debug!("could not answer rest client: {}", err);
+    }
+}
+
+fn handle_client(
+    mut stream: TcpStream,
+    tx: &Sender<HIDCommand>,
+    latest: &Mutex<Option<Telemetry>>,
+) {
+    let peer = stream.peer_addr().ok();
+    stream.set_read_timeout(Some(READ_TIMEOUT)).ok();
+
+    let request = match stream
+        .try_clone()
+        .map_err(|err| err.to_string())
+        .and_then(|reader| read_request(&mut BufReader::new(reader)))
+    {
+        Ok(request) => request,
+        Err(err) => {
+            debug!("rest client {:?} sent a bad request: {}", peer, err);
+            respond(&mut stream, 400, &json!({ "error": err }));
+            return;
+        }
+    };
+
+    let (status, body) = match route(&request) {
+        Ok(Route::State) => match &*latest.lock().unwrap() {
+            Some(telemetry) => (200, json!(telemetry)),
+            None => (
+                503,
+                json!({ "error": "the hid thread hasn't reported yet" }),
+            ),
+        },
+        Ok(Route::Command(command)) => match tx.send(command) {
+            Ok(()) => (200, json!({ "ok": true })),
+            Err(_) => (503, json!({ "error": "hid thread stopped" })),
+        },
+        Err((status, err)) => (status, json!({ "error": err })),
+    };
+
+    debug!(
+        "rest {} {} from {:?}: {}",
+        request.method, request.path, peer, status
+    );
+    respond(&mut stream, status, &body);
+}
+
+pub fn spawn(config: &RestConfig, tx: Sender<HIDCommand>, telemetry: Receiver<Telemetry>) {
+    let listener = match TcpListener::bind(&config.addr) {
+        Ok(listener) => listener,
+        Err(err) => {
+            warn!("could not open rest api on {}: {}", config.addr, err);
+            return;
+        }
+    };
+    info!("rest api listening on http://{}", config.addr);
+
+    // kept for GET /state, this stops with the hid thread
+    let latest: Arc<Mutex<Option<Telemetry>>> = Arc::default();
+    let updated = latest.clone();
+    thread::spawn(move || {
+        for telemetry in telemetry {
+            *updated.lock().unwrap() = Some(telemetry);
+        }
+    });
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let tx = tx.clone();
+            let latest = latest.clone();
+            thread::spawn(move || handle_client(stream, &tx, &latest));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, path: &str, body: &str) -> Request {
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            body: body.to_string(),
+        }
+    }
+
+    #[test]
+    fn reads_requests() {
+        let raw = "POST /effect HTTP/1.1\r\nHost: localhost\r\ncontent-length: 16\r\n\r\n{\"name\":\"wave\"}\n";
+        assert_eq!(
+            read_request(&mut raw.as_bytes()).unwrap(),
+            request("POST", "/effect", "{\"name\":\"wave\"}\n")
+        );
+
+        assert!(read_request(&mut "GET /state HTTP/1.1\r\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn routes_to_commands() {
+        assert!(matches!(
+            route(&request("GET", "/state?pretty", "")),
+            Ok(Route::State)
+        ));
+        assert!(matches!(
+            route(&request("POST", "/effect", r#"{"name": "wave"}"#)),
+            Ok(Route::Command(HIDCommand::SetEffect(name))) if name == "wave"
+        ));
+        assert!(matches!(
+            route(&request("POST", "/brightness", r#"{"value": 40}"#)),
+            Ok(Route::Command(HIDCommand::SetBrightness(40)))
+        ));
+        assert!(matches!(
+            route(&request(
+                "POST",
+                "/flash",
+                r#"{"hue": 0, "saturation": 1, "value": 1, "duration_ms": 500}"#
+            )),
+            Ok(Route::Command(HIDCommand::Flash { duration, .. }))
+                if duration == Duration::from_millis(500)
+        ));
+
+        let status = |request| route(&request).unwrap_err().0;
+        assert_eq!(
+            status(request("POST", "/effect", r#"{"name": "nope"}"#)),
+            400
+        );
+        assert_eq!(
+            status(request("POST", "/brightness", r#"{"value": 300}"#)),
+            400
+        );
+        assert_eq!(
+            status(request(
+                "POST",
+                "/color",
+                r#"{"hue": 0, "saturation": 2, "value": 1}"#
+            )),
+            400
+        );
+        for duration_ms in ["0", "-5", "1e38", "3.4e38"] {
+            let body = format!(
+                r#"{{"hue": 0, "saturation": 1, "value": 1, "duration_ms": {}}}"#,
+                duration_ms
+            );
+            assert_eq!(
+                status(request("POST", "/flash", &body)),
+                400,
+                "{}",
+                duration_ms
+            );
+        }
+        assert_eq!(status(request("GET", "/effect", "")), 405);
+        assert_eq!(status(request("GET", "/", "")), 404);
+    }
+}
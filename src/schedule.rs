@@ -0,0 +1,239 @@
+use crate::{
+    config::{Config, Location, ScheduleEntry, ScheduleTime},
+    effects::{local_offset, utc_offset},
+    threading::HIDCommand,
+};
+use log::{info, warn};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// julian day of the unix epoch and of the j2000 epoch the sun's orbit is given from
+const UNIX_JULIAN: f64 = 2440587.5;
+const J2000: f64 = 2451545.0;
+
+// unix seconds of sunrise and sunset on the day of the solar noon nearest `unix_secs`, by the
+// usual sunrise equation (good to a minute or two away from the poles). None on days the sun
+// doesn't rise or doesn't set there
+pub fn sun_times(unix_secs: i64, location: Location) -> Option<(i64, i64)> {
+    let julian = unix_secs as f64 / SECS_PER_DAY as f64 + UNIX_JULIAN;
+    let day = (julian - J2000 + location.longitude / 360.0).round();
+    let mean_noon = day - location.longitude / 360.0;
+
+    let anomaly = (357.5291 + 0.98560028 * mean_noon)
+        .rem_euclid(360.0)
+        .to_radians();
+    let center =
+        1.9148 * anomaly.sin() + 0.02 * (2.0 * anomaly).sin() + 0.0003 * (3.0 * anomaly).sin();
+    let ecliptic = (anomaly.to_degrees() + center + 180.0 + 102.9372)
+        .rem_euclid(360.0)
+        .to_radians();
+    let transit = J2000 + mean_noon + 0.0053 * anomaly.sin() - 0.0069 * (2.0 * ecliptic).sin();
+
+    let declination = (ecliptic.sin() * 23.4397f64.to_radians().sin()).asin();
+    let latitude = location.latitude.to_radians();
+    // the sun's center 0.833 degrees under the horizon, for refraction and the sun's size
+    let hour_angle = ((-0.833f64).to_radians().sin() - latitude.sin() * declination.sin())
+        / (latitude.cos() * declination.cos());
+    if !(-1.0..=1.0).contains(&hour_angle) {
+        return None;
+    }
+
+    let half_day = hour_angle.acos().to_degrees() / 360.0;
+    let unix = |julian: f64| ((julian - UNIX_JULIAN) * SECS_PER_DAY as f64).round() as i64;
+    Some((unix(transit - half_day), unix(transit + half_day)))
+}
+
+// the config's schedule, switching to each entry as its time comes around
+pub struct Schedule {
+    entries: Vec<ScheduleEntry>,
+    location: Option<Location>,
+    // the entry running as of the last check
+    current: Option<usize>,
+    last_check: Option<Instant>,
+}
+
+impl Schedule {
+    pub fn new(config: &Config) -> Schedule {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+        if !config.schedule.is_empty() && local_offset(now).is_none() {
+            warn!("local time zone unknown on this system, schedule times are taken as utc");
+        }
+
+        Schedule {
+            entries: config.schedule.clone(),
+            location: config.location,
+            current: None,
+            last_check: None,
+        }
+    }
+
+    // in local seconds like `day_start`, on the day starting then. None for sunrise or sunset
+    // without a location, or on days without one
+    fn start(&self, entry: &ScheduleEntry, day_start: i64, offset: i64) -> Option<i64> {
+        let at = match entry.at {
+            ScheduleTime::Clock(minutes) => day_start + minutes as i64 * 60,
+            ScheduleTime::Sunrise | ScheduleTime::Sunset => {
+                let noon = day_start - offset + SECS_PER_DAY / 2;
+                let (sunrise, sunset) = sun_times(noon, self.location?)?;
+                let at = match entry.at {
+                    ScheduleTime::Sunrise => sunrise,
+                    _ => sunset,
+                };
+                at + offset
+            }
+        };
+        Some(at + entry.offset_minutes as i64 * 60)
+    }
+
+    // the last entry to have started today, or else the one that started last yesterday. of
+    // entries starting together the later one in the list wins
+    fn active(&self, unix_secs: i64, offset: i64) -> Option<usize> {
+        let local = unix_secs + offset;
+        let today = local - local.rem_euclid(SECS_PER_DAY);
+
+        let starts = |day_start: i64| {
+            self.entries
+                .iter()
+                .enumerate()
+                .filter_map(move |(idx, entry)| Some((idx, self.start(entry, day_start, offset)?)))
+        };
+        starts(today)
+            .filter(|(_, start)| *start <= local)
+            .max_by_key(|(_, start)| *start)
+            .or_else(|| starts(today - SECS_PER_DAY).max_by_key(|(_, start)| *start))
+            .map(|(idx, _)| idx)
+    }
+
+    // what to do for the entry that started since the last call, if one did. the first call
+    // starts whichever should be running already
+    fn due(&mut self, unix_secs: i64, offset: i64) -> Vec<HIDCommand> {
+        let active = self.active(unix_secs, offset);
+        if active == self.current {
+            return Vec::new();
+        }
+        self.current = active;
+        let Some(entry) = active.map(|idx| &self.entries[idx]) else {
+            return Vec::new();
+        };
+
+        info!("schedule: switching to the entry at {:?}", entry.at);
+        let mut commands = Vec::new();
+        if let Some(effects) = &entry.effects {
+            commands.push(HIDCommand::SetEffects(effects.clone()));
+        }
+        if let Some(color) = entry.color {
+            commands.push(HIDCommand::SetColor(color.to_hsva()));
+        }
+        if let Some(brightness) = entry.brightness {
+            commands.push(HIDCommand::SetBrightness(brightness));
+        }
+        commands
+    }
+
+    // checked about once a second, against the local time
+    pub fn poll(&mut self) -> Vec<HIDCommand> {
+        if self.entries.is_empty()
+            || self
+                .last_check
+                .is_some_and(|last| last.elapsed() < CHECK_INTERVAL)
+        {
+            return Vec::new();
+        }
+        self.last_check = Some(Instant::now());
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+        self.due(now, utc_offset(now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-06-21 00:00 utc
+    const SOLSTICE: i64 = 1718928000;
+
+    fn schedule(json: serde_json::Value) -> Schedule {
+        let mut config: serde_json::Value = serde_json::json!({
+            "kb": "test", "keymap": "default", "layout": "LAYOUT", "usage_page": "0xFF60",
+            "usage": "0x61",
+        });
+        config
+            .as_object_mut()
+            .unwrap()
+            .extend(json.as_object().unwrap().clone());
+        Schedule::new(&serde_json::from_value(config).unwrap())
+    }
+
+    #[test]
+    fn sunrise_and_sunset() {
+        let london = Location {
+            latitude: 51.5074,
+            longitude: -0.1278,
+        };
+        // 03:43 and 20:21 utc
+        let (sunrise, sunset) = sun_times(SOLSTICE + 12 * 3600, london).unwrap();
+        assert!((sunrise - (SOLSTICE + 3 * 3600 + 43 * 60)).abs() < 120);
+        assert!((sunset - (SOLSTICE + 20 * 3600 + 21 * 60)).abs() < 120);
+
+        // the midnight sun
+        let tromso = Location {
+            latitude: 69.65,
+            longitude: 18.96,
+        };
+        assert!(sun_times(SOLSTICE + 12 * 3600, tromso).is_none());
+    }
+
+    #[test]
+    fn switches_at_each_entry() {
+        let mut schedule = schedule(serde_json::json!({
+            "schedule": [
+                {"at": "08:00", "effects": [{"name": "static"}], "brightness": 255},
+                {"at": "sunset", "offset_minutes": -30, "color": [255, 120, 0]},
+                {"at": "23:00", "brightness": 40},
+            ],
+            "location": {"latitude": 51.5074, "longitude": -0.1278},
+        }));
+        let at = |hour: i64, minute: i64| SOLSTICE + hour * 3600 + minute * 60;
+
+        // before the first entry of the day, yesterday's last is still running
+        let commands = schedule.due(at(7, 0), 0);
+        assert!(matches!(commands[..], [HIDCommand::SetBrightness(40)]));
+        assert!(schedule.due(at(7, 30), 0).is_empty());
+
+        let commands = schedule.due(at(8, 0), 0);
+        assert!(matches!(
+            commands[..],
+            [HIDCommand::SetEffects(_), HIDCommand::SetBrightness(255)]
+        ));
+
+        // half an hour before 20:21
+        assert!(schedule.due(at(19, 50), 0).is_empty());
+        assert!(matches!(
+            schedule.due(at(19, 52), 0)[..],
+            [HIDCommand::SetColor(_)]
+        ));
+
+        // sunset comes at the same moment however the clocks are set, 23:00 doesn't
+        schedule.current = Some(0);
+        assert!(matches!(
+            schedule.due(at(19, 52), 3600)[..],
+            [HIDCommand::SetColor(_)]
+        ));
+        assert!(matches!(
+            schedule.due(at(22, 0), 3600)[..],
+            [HIDCommand::SetBrightness(40)]
+        ));
+
+        assert!(serde_json::from_value::<ScheduleTime>(serde_json::json!("24:00")).is_err());
+        assert!(serde_json::from_value::<ScheduleTime>(serde_json::json!("noon")).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+use log::{info, warn};
+use palette::{Hsva, IntoColor, Srgba};
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+// only every nth pixel (and row) of the screen is looked at, plenty for an average color
+const STRIDE: usize = 8;
+
+// average screen color per region, regions go left to right then top to bottom
+pub type ScreenColors = Arc<Mutex<Vec<Hsva>>>;
+
+// samples the screen on its own thread, so a slow capture never holds up a frame. if capture
+// isn't possible (no X server, wayland without xwayland, ...) this logs once and stops, and the
+// colors stay black.
+pub fn spawn_sampler(columns: usize, rows: usize, rate: f32) -> ScreenColors {
+    let colors: ScreenColors = Arc::new(Mutex::new(vec![
+        Hsva::new(0.0, 0.0, 0.0, 1.0);
+        columns * rows
+    ]));
+    let shared = colors.clone();
+
+    thread::spawn(move || {
+        let mut capture = match Capture::new() {
+            Ok(capture) => capture,
+            Err(err) => {
+                warn!("screen capture unavailable: {}", err);
+                return;
+            }
+        };
+
+        info!("sampling the screen in {}x{} regions", columns, rows);
+
+        // stops once the effect using the colors is gone
+        while Arc::strong_count(&shared) > 1 {
+            match capture.sample(columns, rows) {
+                Ok(sampled) => *shared.lock().unwrap() = sampled,
+                Err(err) => {
+                    warn!("screen capture failed: {}", err);
+                    return;
+                }
+            }
+
+            thread::sleep(Duration::from_secs_f32(1.0 / rate));
+        }
+    });
+
+    colors
+}
+
+// sums of srgb channels per region
+struct Regions {
+    columns: usize,
+    rows: usize,
+    sums: Vec<([f32; 3], u32)>,
+}
+
+impl Regions {
+    fn new(columns: usize, rows: usize) -> Regions {
+        Regions {
+            columns,
+            rows,
+            sums: vec![([0.0; 3], 0); columns * rows],
+        }
+    }
+
+    fn add(&mut self, x: f32, y: f32, rgb: [u8; 3]) {
+        let col = ((x * self.columns as f32) as usize).min(self.columns - 1);
+        let row = ((y * self.rows as f32) as usize).min(self.rows - 1);
+        let (sum, count) = &mut self.sums[row * self.columns + col];
+
+        for (sum, channel) in sum.iter_mut().zip(rgb) {
+            *sum += channel as f32 / 255.0;
+        }
+        *count += 1;
+    }
+
+    fn average(self) -> Vec<Hsva> {
+        self.sums
+            .into_iter()
+            .map(|(sum, count)| {
+                let count = count.max(1) as f32;
+                Srgba::new(sum[0] / count, sum[1] / count, sum[2] / count, 1.0).into_color()
+            })
+            .collect()
+    }
+}
+
+#[cfg(target_os = "linux")]
+struct Capture {
+    conn: x11rb::rust_connection::RustConnection,
+    screen_num: usize,
+}
+
+#[cfg(target_os = "linux")]
+impl Capture {
+    fn new() -> Result<Capture, String> {
+        let (conn, screen_num) = x11rb::connect(None).map_err(|err| err.to_string())?;
+        Ok(Capture { conn, screen_num })
+    }
+
+    fn sample(&mut self, columns: usize, rows: usize) -> Result<Vec<Hsva>, String> {
+        use x11rb::{
+            connection::Connection,
+            protocol::xproto::{ConnectionExt, ImageFormat, ImageOrder},
+        };
+
+        let setup = self.conn.setup();
+        let screen = &setup.roots[self.screen_num];
+        let (width, height) = (screen.width_in_pixels, screen.height_in_pixels);
+
+        let bits_per_pixel = setup
+            .pixmap_formats
+            .iter()
+            .find(|format| format.depth == screen.root_depth)
+            .map(|format| format.bits_per_pixel);
+        if bits_per_pixel != Some(32) {
+            return Err(format!("unsupported screen depth {}", screen.root_depth));
+        }
+        let msb_first = setup.image_byte_order == ImageOrder::MSB_FIRST;
+
+        // one row at a time keeps every reply small, all requests go out before waiting
+        let cookies = (0..height as usize)
+            .step_by(STRIDE)
+            .map(|y| {
+                let cookie = self
+                    .conn
+                    .get_image(
+                        ImageFormat::Z_PIXMAP,
+                        screen.root,
+                        0,
+                        y as i16,
+                        width,
+                        1,
+                        !0,
+                    )
+                    .map_err(|err| err.to_string())?;
+                Ok((y, cookie))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let mut regions = Regions::new(columns, rows);
+
+        for (y, cookie) in cookies {
+            let reply = cookie.reply().map_err(|err| err.to_string())?;
+
+            for (x, pixel) in reply.data.chunks_exact(4).enumerate().step_by(STRIDE) {
+                let rgb = if msb_first {
+                    [pixel[1], pixel[2], pixel[3]]
+                } else {
+                    [pixel[2], pixel[1], pixel[0]]
+                };
+
+                regions.add(x as f32 / width as f32, y as f32 / height as f32, rgb);
+            }
+        }
+
+        Ok(regions.average())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+struct Capture;
+
+#[cfg(not(target_os = "linux"))]
+impl Capture {
+    fn new() -> Result<Capture, String> {
+        Err("screen capture is only supported on linux (X11)".to_string())
+    }
+
+    fn sample(&mut self, _columns: usize, _rows: usize) -> Result<Vec<Hsva>, String> {
+        unreachable!()
+    }
+}
@@ -0,0 +1,239 @@
+use crate::config::KeyUsage;
+use egui::Color32;
+use palette::{Hsva, IntoColor, Srgba};
+use serde::{Deserialize, Deserializer};
+use std::{fs, io, path::Path};
+
+// [r, g, b] or [r, g, b, a]
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(try_from = "Vec<u8>")]
+pub struct ThemeColor(pub Color32);
+
+impl TryFrom<Vec<u8>> for ThemeColor {
+    type Error = String;
+
+    fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+        match value[..] {
+            [r, g, b] => Ok(ThemeColor(Color32::from_rgb(r, g, b))),
+            [r, g, b, a] => Ok(ThemeColor(Color32::from_rgba_unmultiplied(r, g, b, a))),
+            _ => Err(format!(
+                "expected 3 or 4 color channels, got {}",
+                value.len()
+            )),
+        }
+    }
+}
+
+impl ThemeColor {
+    pub fn to_hsva(self) -> Hsva {
+        let [r, g, b, a] = self.0.to_srgba_unmultiplied();
+        let rgba = Srgba::new(r, g, b, a).into_format::<f32, f32>();
+
+        rgba.into_color()
+    }
+}
+
+// the other way around, for writing a color back into an effect's params
+pub fn hsva_value(color: Hsva) -> serde_json::Value {
+    let rgba: Srgba = color.into_color();
+    let (r, g, b, a) = rgba.into_format::<u8, u8>().into_components();
+
+    if a == u8::MAX {
+        serde_json::json!([r, g, b])
+    } else {
+        serde_json::json!([r, g, b, a])
+    }
+}
+
+// for config fields that are used as led colors
+pub fn deserialize_hsva<'de, D>(deserializer: D) -> Result<Hsva, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    ThemeColor::deserialize(deserializer).map(ThemeColor::to_hsva)
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct KeyColors {
+    pub background: ThemeColor,
+    pub pressed: ThemeColor,
+    pub foreground: ThemeColor,
+}
+
+impl KeyColors {
+    const fn new(background: Color32, pressed: Color32, foreground: Color32) -> KeyColors {
+        KeyColors {
+            background: ThemeColor(background),
+            pressed: ThemeColor(pressed),
+            foreground: ThemeColor(foreground),
+        }
+    }
+}
+
+const REMOVED_COLOR: Color32 = Color32::from_rgb(20, 20, 20);
+
+// visualizer colors per key usage, any usage left out of the config keeps its default
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct KeyColorTheme {
+    pub removed: KeyColors,
+    pub unused: KeyColors,
+    pub passthrough: KeyColors,
+    pub default: KeyColors,
+    pub modtap: KeyColors,
+    pub modifier: KeyColors,
+    pub layertap: KeyColors,
+    pub layer: KeyColors,
+    pub function: KeyColors,
+    pub mouse: KeyColors,
+}
+
+impl Default for KeyColorTheme {
+    fn default() -> Self {
+        let default = KeyColors::new(
+            Color32::from_rgb(90, 90, 90),
+            Color32::from_rgb(50, 50, 50),
+            Color32::WHITE,
+        );
+        let modifier = KeyColors::new(
+            Color32::from_rgb(68, 51, 127),
+            Color32::from_rgb(27, 20, 51),
+            Color32::WHITE,
+        );
+        let layer = KeyColors::new(
+            Color32::from_rgb(127, 51, 51),
+            Color32::from_rgb(51, 20, 20),
+            Color32::WHITE,
+        );
+
+        KeyColorTheme {
+            removed: KeyColors::new(REMOVED_COLOR, REMOVED_COLOR, Color32::TRANSPARENT),
+            unused: default,
+            // normally resolved to a lower layer before rendering, so just outline it
+            passthrough: KeyColors::new(
+                Color32::TRANSPARENT,
+                Color32::from_rgb(50, 50, 50),
+                Color32::WHITE,
+            ),
+            default,
+            modtap: modifier,
+            modifier,
+            layertap: layer,
+            layer,
+            function: KeyColors::new(
+                Color32::from_rgb(51, 57, 127),
+                Color32::from_rgb(20, 22, 51),
+                Color32::WHITE,
+            ),
+            mouse: KeyColors::new(
+                Color32::from_rgb(51, 127, 100),
+                Color32::from_rgb(20, 51, 40),
+                Color32::WHITE,
+            ),
+        }
+    }
+}
+
+// pressed keys are drawn at 40% of their background
+fn darken(color: Color32) -> Color32 {
+    let [r, g, b, _] = color.to_array();
+    Color32::from_rgb(r / 5 * 2, g / 5 * 2, b / 5 * 2)
+}
+
+fn colors(background: Color32, foreground: Color32) -> KeyColors {
+    KeyColors::new(background, darken(background), foreground)
+}
+
+// dark legends on a pale key, which is pressed at 75% since 40% would hide them
+fn pale(background: Color32) -> KeyColors {
+    let [r, g, b, _] = background.to_array();
+    let scale = |c: u8| (c as u16 * 3 / 4) as u8;
+    let pressed = Color32::from_rgb(scale(r), scale(g), scale(b));
+
+    KeyColors::new(background, pressed, Color32::BLACK)
+}
+
+impl KeyColorTheme {
+    // the default colors, for a dark ui
+    pub fn dark() -> KeyColorTheme {
+        KeyColorTheme::default()
+    }
+
+    // pale keys with dark legends, for a light ui
+    pub fn light() -> KeyColorTheme {
+        let default = pale(Color32::from_rgb(225, 225, 225));
+        let modifier = pale(Color32::from_rgb(200, 190, 240));
+        let layer = pale(Color32::from_rgb(240, 190, 190));
+        let removed = Color32::from_rgb(240, 240, 240);
+
+        KeyColorTheme {
+            removed: KeyColors::new(removed, removed, Color32::TRANSPARENT),
+            unused: default,
+            passthrough: KeyColors::new(
+                Color32::TRANSPARENT,
+                Color32::from_rgb(180, 180, 180),
+                Color32::BLACK,
+            ),
+            default,
+            modtap: modifier,
+            modifier,
+            layertap: layer,
+            layer,
+            function: pale(Color32::from_rgb(190, 195, 240)),
+            mouse: pale(Color32::from_rgb(185, 230, 210)),
+        }
+    }
+
+    // a json file written like the config's key_colors
+    pub fn load(path: &Path) -> Result<KeyColorTheme, io::Error> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    // categories differ in brightness as well as hue, taken from the okabe-ito color blind
+    // safe set. meant to be used together with the usage letters.
+    pub fn color_blind() -> KeyColorTheme {
+        let default = colors(Color32::from_rgb(100, 100, 100), Color32::WHITE);
+        let modifier = colors(Color32::from_rgb(230, 159, 0), Color32::BLACK);
+        let layer = colors(Color32::from_rgb(0, 114, 178), Color32::WHITE);
+
+        KeyColorTheme {
+            unused: default,
+            default,
+            modtap: modifier,
+            modifier,
+            layertap: layer,
+            layer,
+            function: colors(Color32::from_rgb(240, 228, 66), Color32::BLACK),
+            mouse: colors(Color32::from_rgb(86, 180, 233), Color32::BLACK),
+            ..KeyColorTheme::default()
+        }
+    }
+
+    // short marker for the usage, so categories can be told apart without color
+    pub fn usage_letter(usage: &KeyUsage) -> Option<&'static str> {
+        match usage {
+            KeyUsage::Modtap => Some("MT"),
+            KeyUsage::Modifier => Some("M"),
+            KeyUsage::Layertap => Some("LT"),
+            KeyUsage::Layer => Some("L"),
+            KeyUsage::Function => Some("F"),
+            KeyUsage::Mouse => Some("MS"),
+            _ => None,
+        }
+    }
+
+    pub fn get(&self, usage: &KeyUsage) -> &KeyColors {
+        match usage {
+            KeyUsage::Removed => &self.removed,
+            KeyUsage::Unused => &self.unused,
+            KeyUsage::Passthrough => &self.passthrough,
+            KeyUsage::Default => &self.default,
+            KeyUsage::Modtap => &self.modtap,
+            KeyUsage::Modifier => &self.modifier,
+            KeyUsage::Layertap => &self.layertap,
+            KeyUsage::Layer => &self.layer,
+            KeyUsage::Function => &self.function,
+            KeyUsage::Mouse => &self.mouse,
+        }
+    }
+}
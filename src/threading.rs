@@ -1,13 +1,29 @@
 use crate::{
-    config::KBConfig,
-    effects::{LedEffect, LedState},
-    protocol::{ProtocolMessage, RgbSetFullMessage, RgbSetMessage, RAW_EPSIZE},
+    config::{self, Config, EffectConfig, KBConfig},
+    device::{DeviceEvent, DeviceSource},
+    effects::{
+        alpha_over, blend, build_effect, effect_names, words_per_minute, EffectContext,
+        EffectParam, FlashEffect, LedEffect, LedState, ProgressEffect,
+    },
+    keycode,
+    presets::{self, KeyColors},
+    priority,
+    profile::{self, Profile, ProfileStore},
+    protocol::{
+        self, color_value, ColorFormat, FirmwareVersion, Framing, LockState, Mods, ProtocolMessage,
+        ProtocolReader, RgbSetFullMessage, RgbSetMessage, RgbStateMessage, RgbStreamMessage,
+        ACK_VERSION,
+    },
+    schedule::Schedule,
+    transport::{HidTransport, SplitDevice},
 };
 use crossbeam::channel::{unbounded, Receiver, Sender};
-use hidapi::HidDevice;
-use palette::{Hsv, Hsva};
+use log::{debug, error, info, trace, warn};
+use palette::{rgb::Rgb, Hsv, Hsva, IntoColor};
+use serde::Serialize;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
+    ops::{Deref, DerefMut},
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
@@ -16,27 +32,779 @@ use std::{
     time::{Duration, Instant},
 };
 
+const LAYER_QUERY_TIMEOUT: Duration = Duration::from_millis(500);
+const VERSION_QUERY_TIMEOUT: Duration = Duration::from_millis(500);
+// for control messages sent with send_acked, see ACK_VERSION
+const ACK_TIMEOUT: Duration = Duration::from_millis(50);
+const ACK_ATTEMPTS: u32 = 3;
+
+// longest pause between send attempts while writes keep failing
+const MAX_BACKOFF: Duration = Duration::from_secs(2);
+
+// frames in a row with failed writes, or reads in a row that failed, before the device is
+// reopened
+const RECONNECT_FAILURES: u32 = 8;
+
 #[derive(Default, Clone)]
 pub struct KeyState {
     // last time the down event was sent for this key
     pub last_down: Option<Instant>,
     // last time this key was not up
     pub last_pressed: Option<Instant>,
+    // last time the up event was sent for this key
+    pub last_up: Option<Instant>,
     pub is_pressed: bool,
+    // what the key did when it was last pressed, as the firmware resolved it
+    pub keycode: Option<u16>,
+}
+
+#[derive(Default, Debug, Clone, Copy)]
+pub struct EncoderState {
+    // clicks clockwise minus clicks counterclockwise since the thread started
+    pub position: i32,
+    // which way the last click went
+    pub clockwise: bool,
 }
 
 #[derive(Default, Clone)]
 pub struct HIDThreadState {
     pub delta_update: f32,
     pub delta_frame: f32,
+    pub update_timing: TimingSummary,
+    pub frame_timing: TimingSummary,
+    // every delta since the state before, oldest first, for graphing
+    pub update_deltas: Vec<f32>,
+    pub frame_deltas: Vec<f32>,
+    pub typing: TypingSummary,
     pub matrix: Vec<Vec<KeyState>>,
     pub led_state: Vec<Hsva>,
-    pub layer_state: u8,
+    // bitmask of active layers, bit 0 is the base layer
+    pub layer_state: u32,
+    pub locks: LockState,
+    // modifiers active as of the last press or release
+    pub mods: Mods,
+    // by encoder index, for every encoder up to the highest one that turned
+    pub encoders: Vec<EncoderState>,
+    // keycode of the last key pressed, as the firmware resolved it
+    pub last_keycode: Option<u16>,
+    pub connected: bool,
+    pub status: ConnectionStatus,
+    // reported by the firmware after connecting, or assumed legacy if it never answered
+    pub firmware_version: Option<FirmwareVersion>,
+    // percent and whether it's charging, for wireless boards that have reported it since
+    // connecting
+    pub battery: Option<(u8, bool)>,
+    // what the keyboard reported it's showing, only in the state sent right after a read back
+    // completes. keep it for as long as it's needed
+    pub device_colors: Option<Vec<Hsva>>,
+    // failed writes since the thread started
+    pub write_errors: u64,
+    // the last read or write to the keyboard that failed, and why
+    pub last_error: Option<String>,
+    // names of the effects in the stack, in the order they're applied
+    pub effects: Vec<String>,
+    // the tunable params of every effect built from the stack, by its index among them
+    pub effect_params: Vec<(&'static str, Vec<EffectParam>)>,
+    // how long each effect in the stack took to update, bottom to top, to see which is slow
+    pub effect_timing: Vec<(&'static str, TimingSummary)>,
+}
+
+// whether the thread is talking to the keyboard right now
+#[derive(Default, Debug, Clone, PartialEq)]
+pub enum ConnectionStatus {
+    Connected,
+    // the keyboard hasn't been plugged in since startup, also before the thread first reports
+    #[default]
+    Waiting,
+    // waiting for the keyboard to show up again
+    Reconnecting,
+    // connected, but the last reads or writes failed
+    Error(String),
+}
+
+impl ConnectionStatus {
+    fn new(connected: bool, seen: bool, failing: bool, last_error: Option<&String>) -> Self {
+        match (connected, last_error) {
+            (false, _) if !seen => ConnectionStatus::Waiting,
+            (false, _) => ConnectionStatus::Reconnecting,
+            (true, Some(err)) if failing => ConnectionStatus::Error(err.clone()),
+            (true, _) => ConnectionStatus::Connected,
+        }
+    }
+}
+
+// what integrations outside the ui follow, sent whenever any of it changes
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Telemetry {
+    pub connected: bool,
+    // the highest layer on
+    pub layer: usize,
+    pub layer_state: u32,
+    pub wpm: u32,
+    // names of the effects in the stack, in the order they're applied
+    pub effects: Vec<String>,
+    // the last brightness asked for, if any was since startup
+    pub brightness: Option<u8>,
+    pub paused: bool,
+}
+
+// the board as external visualizers see it, see websocket.rs
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct LiveFrame {
+    // every led's color with the brightness applied but before gamma, [r, g, b] in 0-255
+    pub leds: Vec<[u8; 3]>,
+    // matrix positions held down
+    pub pressed: Vec<(u8, u8)>,
+    pub layer: usize,
+    pub layer_state: u32,
+    pub connected: bool,
+}
+
+// sent from the ui to the hid thread
+#[derive(Debug)]
+pub enum HIDCommand {
+    // resend every led on the next frame, not just the ones that changed
+    ForceRefresh,
+    // start over with another layout or keymap of the same keyboard, keeping the effects
+    SwitchConfig(Arc<KBConfig>),
+    // start over with a config reread from disk, its effects included
+    Reload(Arc<KBConfig>),
+    // pin keys (by matrix position) to a color on top of the effects
+    SetKeyColors(Vec<((u8, u8), Hsva)>),
+    ClearKeyColors(Vec<(u8, u8)>),
+    // show only these pinned colors, with every other led off
+    LoadPreset(HashMap<(u8, u8), Hsva>),
+    // go back to the effects, dropping any pinned colors
+    ResetEffects,
+    // replace the effect stack, applied in order
+    SetEffects(Vec<EffectConfig>),
+    // switch to just this effect, with default params
+    SetEffect(String),
+    // recolor the effects drawn in one color, see `LedEffect::set_color`
+    SetColor(Hsva),
+    // tune a param of one of the running effects, see `LedEffect::params`
+    SetParam {
+        effect: usize,
+        name: String,
+        value: f32,
+    },
+    // start every effect over without rebuilding them
+    RestartEffects,
+    // hold the current frame without updating effects or sending anything, and go on from there
+    Pause,
+    Resume,
+    // one tap of tap tempo, for the effects that keep time
+    TapTempo,
+    // the message of the effects that show text, see `LedEffect::set_text`
+    SetText(String),
+    // flash the whole board over the effects once, any number can overlap
+    Flash {
+        color: Hsva,
+        duration: Duration,
+    },
+    // pulse a color over the effects, on the given keys or the whole board
+    Notify {
+        color: Hsva,
+        duration: Duration,
+        keys: Option<Vec<(u8, u8)>>,
+    },
+    // show a progress bar (0-1) over the effects, or hide it
+    SetProgress(Option<f32>),
+    // the firmware's global brightness scale, 255 is full. scales the colors sent instead with
+    // host_brightness
+    SetBrightness(u8),
+    // ask the keyboard which colors it's showing
+    QueryRgb,
+    // forget the press counts and typing speeds so far
+    ResetTyping,
+}
+
+// led colors read back from the keyboard, collected over as many reports as it takes
+struct RgbReadback {
+    colors: Vec<Option<Hsva>>,
+}
+
+impl RgbReadback {
+    fn new(led_count: usize) -> Self {
+        RgbReadback {
+            colors: vec![None; led_count],
+        }
+    }
+
+    fn add(&mut self, msg: &RgbStateMessage, gamma: f32, format: ColorFormat) {
+        let start = msg.start as usize;
+
+        for (led, bytes) in self.colors.iter_mut().skip(start).zip(&msg.colors) {
+            *led = Some(color_value(*bytes, gamma, format).into_color());
+        }
+
+        if start + msg.colors.len() > self.colors.len() {
+            debug!("rgb state past the last led, from {}", start);
+        }
+    }
+
+    // every led, once all of them have been read
+    fn complete(&self) -> Option<Vec<Hsva>> {
+        self.colors.iter().copied().collect()
+    }
+}
+
+// a transient pulse drawn over the finished frame, so effects never see it
+struct Notification {
+    color: Hsva,
+    duration: Duration,
+    start: Instant,
+    keys: Option<Vec<(u8, u8)>>,
+}
+
+impl Notification {
+    fn finished(&self) -> bool {
+        self.start.elapsed() >= self.duration
+    }
+
+    fn apply(&self, frame: &mut [LedState], kb_config: &KBConfig) {
+        let t = self.start.elapsed().as_secs_f32() / self.duration.as_secs_f32();
+        let intensity = (t.clamp(0.0, 1.0) * std::f32::consts::PI).sin();
+
+        match &self.keys {
+            Some(keys) => {
+                for led_idx in keys.iter().filter_map(|key| kb_config.led_at(*key)) {
+                    let led = &mut frame[led_idx];
+                    led.color = blend(led.color, self.color, intensity);
+                }
+            }
+            None => {
+                for led in frame {
+                    led.color = blend(led.color, self.color, intensity);
+                }
+            }
+        }
+    }
+}
+
+// slows down sending while writes fail, doubling the pause with every failed frame so a
+// congested bus gets a chance to recover
+#[derive(Default)]
+struct SendBackoff {
+    failures: u32,
+    until: Option<Instant>,
+}
+
+impl SendBackoff {
+    fn ready(&self) -> bool {
+        self.until.is_none_or(|until| Instant::now() >= until)
+    }
+
+    // true once writes have failed for long enough that the device should be reopened
+    fn record(&mut self, stats: &SendStats, wait_frame: f32) -> bool {
+        if stats.failures == 0 {
+            if self.failures > 0 {
+                info!("writes recovered after {} failed frames", self.failures);
+            }
+            *self = SendBackoff::default();
+            return false;
+        }
+
+        self.failures += 1;
+        let pause = Duration::from_secs_f32(wait_frame)
+            .saturating_mul(1 << self.failures.min(16))
+            .min(MAX_BACKOFF);
+        self.until = Some(Instant::now() + pause);
+
+        debug!(
+            "{} failed writes, backing off for {:?}",
+            stats.failures, pause
+        );
+
+        self.failures >= RECONNECT_FAILURES
+    }
+}
+
+// seconds of deltas the timing summaries cover
+const TIMING_WINDOW: Duration = Duration::from_secs(5);
+
+// how long updates or frames took over the last TIMING_WINDOW, in seconds
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct TimingSummary {
+    pub min: f32,
+    pub mean: f32,
+    pub p99: f32,
+    pub max: f32,
+}
+
+// a rolling window of deltas, to catch the odd long frame an instantaneous readout hides
+#[derive(Default)]
+struct TimingStats {
+    samples: VecDeque<(Instant, f32)>,
+}
+
+impl TimingStats {
+    fn record(&mut self, delta: f32) {
+        let now = Instant::now();
+        self.samples.push_back((now, delta));
+
+        while self
+            .samples
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) > TIMING_WINDOW)
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    fn summary(&self) -> TimingSummary {
+        if self.samples.is_empty() {
+            return TimingSummary::default();
+        }
+
+        let mut deltas: Vec<f32> = self.samples.iter().map(|(_, delta)| *delta).collect();
+        deltas.sort_unstable_by(f32::total_cmp);
+
+        // nearest rank, so a single stall among a hundred samples shows up
+        let rank = (deltas.len() as f32 * 0.99).ceil() as usize;
+        TimingSummary {
+            min: deltas[0],
+            mean: deltas.iter().sum::<f32>() / deltas.len() as f32,
+            p99: deltas[rank.clamp(1, deltas.len()) - 1],
+            max: deltas[deltas.len() - 1],
+        }
+    }
+}
+
+// steps a Ticker makes up for at once after falling behind, time past that is dropped
+const MAX_CATCH_UP: u32 = 4;
+
+// a clock ticking every `step`, each tick aimed at when it's due rather than a whole step after
+// the last one was handled, so time spent working doesn't add up to drift
+struct Ticker {
+    step: Duration,
+    next: Instant,
+}
+
+impl Ticker {
+    fn new(step: Duration) -> Ticker {
+        Ticker {
+            step,
+            next: Instant::now() + step,
+        }
+    }
+
+    // the steps that came due by `now`, moving past them. from more than MAX_CATCH_UP behind
+    // only that many are made up, and the ticks start over from `now`
+    fn due(&mut self, now: Instant) -> u32 {
+        if now < self.next {
+            return 0;
+        }
+
+        let behind = (now - self.next).as_secs_f64() / self.step.as_secs_f64();
+        let steps = (behind as u32).saturating_add(1);
+        if steps > MAX_CATCH_UP {
+            self.restart(now);
+            return MAX_CATCH_UP;
+        }
+        self.next += self.step * steps;
+        steps
+    }
+
+    fn remaining(&self, now: Instant) -> Duration {
+        self.next.saturating_duration_since(now)
+    }
+
+    fn restart(&mut self, now: Instant) {
+        self.next = now + self.step;
+    }
+}
+
+// presses this recent count towards the current typing speed
+const WPM_WINDOW: Duration = Duration::from_secs(10);
+
+// most pressed keys in a TypingSummary
+const TOP_KEYS: usize = 10;
+
+// typing since the thread started or was last reset
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct TypingSummary {
+    pub presses: u64,
+    pub wpm: f32,
+    pub peak_wpm: f32,
+    // matrix positions and their press counts, most pressed first
+    pub top_keys: Vec<((u8, u8), u64)>,
+    // every key pressed at least once
+    pub counts: HashMap<(u8, u8), u64>,
+}
+
+#[derive(Default)]
+struct TypingStats {
+    counts: HashMap<(u8, u8), u64>,
+    presses: u64,
+    recent: VecDeque<Instant>,
+    peak_wpm: f32,
+}
+
+impl TypingStats {
+    fn press(&mut self, pos: (u8, u8), now: Instant) {
+        *self.counts.entry(pos).or_default() += 1;
+        self.presses += 1;
+        self.recent.push_back(now);
+    }
+
+    // the peak is the fastest any summary has seen
+    fn summary(&mut self, now: Instant) -> TypingSummary {
+        while self
+            .recent
+            .front()
+            .is_some_and(|at| now.duration_since(*at) > WPM_WINDOW)
+        {
+            self.recent.pop_front();
+        }
+
+        let wpm = words_per_minute(self.recent.len(), WPM_WINDOW.as_secs_f32());
+        self.peak_wpm = self.peak_wpm.max(wpm);
+
+        let mut top_keys: Vec<_> = self.counts.iter().map(|(pos, n)| (*pos, *n)).collect();
+        // ties in matrix order, so the list doesn't shuffle between frames
+        top_keys.sort_unstable_by_key(|(pos, n)| (std::cmp::Reverse(*n), *pos));
+        top_keys.truncate(TOP_KEYS);
+
+        TypingSummary {
+            presses: self.presses,
+            wpm,
+            peak_wpm: self.peak_wpm,
+            top_keys,
+            counts: self.counts.clone(),
+        }
+    }
+}
+
+// decides which leds go out each frame. only changes are sent normally, but a dropped report
+// would leave the keyboard out of sync until that led changes again, so every led is resent
+// periodically or on request. resyncs cost a full board of reports, so the interval should
+// stay in the seconds range. frames where most leds changed anyway are sent in full too when
+// streaming, since a full board streams in fewer reports than the changes alone.
+pub struct FrameSync {
+    interval: Option<Duration>,
+    stream_threshold: Option<f32>,
+    last_full: Instant,
+    force: bool,
+}
+
+impl FrameSync {
+    pub fn new(interval: f32, stream_threshold: Option<f32>) -> FrameSync {
+        FrameSync {
+            interval: (interval > 0.0).then(|| Duration::from_secs_f32(interval)),
+            stream_threshold,
+            last_full: Instant::now(),
+            force: false,
+        }
+    }
+
+    fn force_refresh(&mut self) {
+        self.force = true;
+    }
+
+    // whether every led is due to be resent, which then counts as done
+    fn resync_due(&mut self) -> bool {
+        let due = self.force
+            || self
+                .interval
+                .is_some_and(|interval| self.last_full.elapsed() >= interval);
+
+        if due {
+            trace!("full led resync");
+            self.force = false;
+            self.last_full = Instant::now();
+        }
+
+        due
+    }
+
+    pub fn frame(&mut self, led_state: &[LedState], pre_state: &[LedState]) -> HashMap<u16, Hsv> {
+        let changed = |idx: usize, led: &LedState| led.color != pre_state[idx].color;
+        let busy = self.stream_threshold.is_some_and(|threshold| {
+            let count = led_state
+                .iter()
+                .enumerate()
+                .filter(|(idx, led)| changed(*idx, led))
+                .count();
+            count > 0 && count as f32 >= threshold * led_state.len() as f32
+        });
+        let full = self.resync_due() || busy;
+
+        led_state
+            .iter()
+            .enumerate()
+            .filter(|(idx, led)| full || changed(*idx, led))
+            .map(|(idx, led)| (idx as u16, sent_color(led)))
+            .collect()
+    }
+
+    // the color the whole board shows, if it's one color. it only needs sending if it's not
+    // what the board was last set to, or a resync is due
+    pub fn solid(
+        &mut self,
+        led_state: &[LedState],
+        last_solid: Option<Hsv>,
+    ) -> Option<Option<Hsv>> {
+        let color = sent_color(led_state.first()?);
+        if led_state.iter().any(|led| sent_color(led) != color) {
+            return None;
+        }
+
+        let due = self.resync_due();
+        Some((due || last_solid != Some(color)).then_some(color))
+    }
+}
+
+// the opaque color an led is sent as, its alpha darkens it. effects can overshoot either, the
+// result is kept in 0-1
+fn sent_color(led: &LedState) -> Hsv {
+    Hsv::new(
+        led.color.hue,
+        protocol::unit(led.color.saturation),
+        protocol::unit(protocol::unit(led.color.value) * protocol::unit(led.color.alpha)),
+    )
+}
+
+// every led with the key it sits under
+pub fn led_state(kb_config: &KBConfig) -> Vec<LedState<'_>> {
+    let mut led_state = vec![LedState::default(); kb_config.led_count().into()];
+
+    for key in &kb_config.layout().layout {
+        let led_idx = kb_config.matrix[key.matrix.0 as usize][key.matrix.1 as usize];
+        if led_idx < 0 {
+            continue;
+        }
+
+        trace!("led {} -> key {} {:?}", led_idx, key.label, key.matrix);
+        led_state[led_idx as usize].key = Some(key);
+        led_state[led_idx as usize].phase_offset = kb_config.phase_offset(key);
+    }
+
+    led_state
+}
+
+#[derive(Default, Debug)]
+pub struct SendStats {
+    pub writes: usize,
+    pub failures: usize,
+    pub write_time: Duration,
+    // why the last failed write failed
+    pub last_error: Option<String>,
+}
+
+// consecutive leds and their colors
+type LedRun = Vec<(u16, Hsv)>;
+
+// leds sorted by index, split wherever there's a gap
+fn runs(colors: &HashMap<u16, Hsv>) -> Vec<LedRun> {
+    let mut leds: Vec<_> = colors.iter().map(|(idx, color)| (*idx, *color)).collect();
+    leds.sort_unstable_by_key(|(idx, _)| *idx);
+
+    let mut runs: Vec<LedRun> = Vec::new();
+    for led in leds {
+        let follows = |run: &LedRun| run.last().unwrap().0 as usize + 1 == led.0 as usize;
+        match runs.last_mut() {
+            Some(run) if follows(run) => run.push(led),
+            _ => runs.push(vec![led]),
+        }
+    }
+    runs
+}
+
+// the frame split into streamed runs and leds left to set one by one. a run is streamed a
+// report at a time, as long as the piece going in a report is at least as many leds as one
+// report of sets takes, so splitting never takes more reports than setting every led would
+fn plan_frame(colors: &HashMap<u16, Hsv>, framing: Framing) -> (Vec<LedRun>, LedRun) {
+    let wide = colors.keys().any(|idx| *idx > u8::MAX.into());
+    let set_capacity = RgbSetMessage::capacity(wide, framing);
+
+    let (mut streamed, mut set) = (Vec::new(), Vec::new());
+    for run in runs(colors) {
+        for piece in run.chunks(RgbStreamMessage::capacity(framing)) {
+            if piece.len() >= set_capacity {
+                streamed.push(piece.to_vec());
+            } else {
+                set.extend_from_slice(piece);
+            }
+        }
+    }
+    (streamed, set)
+}
+
+// writes one frame of colors in as many reports as it takes. with streaming on, runs of
+// consecutive leds are streamed where that takes fewer reports (full frames, see FrameSync,
+// are one run). the rest go out one by one with compact 8 bit led indices, unless some of them
+// are past 255
+pub fn send_colors(
+    device: &dyn HidTransport,
+    colors: HashMap<u16, Hsv>,
+    config: &Config,
+) -> SendStats {
+    let mut stats = SendStats::default();
+    let mut write = |message: ProtocolMessage| {
+        let start = Instant::now();
+        let result = message.send(device, config.framing());
+        stats.write_time += start.elapsed();
+        stats.writes += 1;
+
+        if let Err(err) = result {
+            debug!("dropped rgb frame chunk: {}", err);
+            stats.failures += 1;
+            stats.last_error = Some(err.to_string());
+        }
+    };
+
+    let (streamed, set) = match config.stream_threshold {
+        Some(_) => plan_frame(&colors, config.framing()),
+        None => (Vec::new(), colors.into_iter().collect()),
+    };
+
+    for run in streamed {
+        write(ProtocolMessage::RgbStream(RgbStreamMessage {
+            start: run[0].0,
+            colors: run.iter().map(|(_, color)| *color).collect(),
+            gamma: config.gamma,
+            format: config.color_format,
+        }));
+    }
+
+    let wide = set.iter().any(|(idx, _)| *idx > u8::MAX.into());
+    for chunk in set.chunks(RgbSetMessage::capacity(wide, config.framing())) {
+        write(ProtocolMessage::RgbSet(RgbSetMessage {
+            colors: chunk.iter().copied().collect(),
+            gamma: config.gamma,
+            format: config.color_format,
+            wide,
+        }));
+    }
+
+    stats
+}
+
+// sets the whole board to one color in a single report
+fn send_solid(device: &dyn HidTransport, color: Hsv, config: &Config) -> SendStats {
+    let start = Instant::now();
+    let result = ProtocolMessage::RgbSetFull(RgbSetFullMessage {
+        color,
+        gamma: config.gamma,
+        format: config.color_format,
+    })
+    .send(device, config.framing());
+
+    if let Err(err) = &result {
+        debug!("dropped solid frame: {}", err);
+    }
+
+    SendStats {
+        writes: 1,
+        failures: result.is_err() as usize,
+        write_time: start.elapsed(),
+        last_error: result.err().map(|err| err.to_string()),
+    }
+}
+
+pub fn clear_leds(device: &dyn HidTransport, framing: Framing) {
+    let clear = ProtocolMessage::RgbSetFull(RgbSetFullMessage {
+        color: Hsv::new(0.0, 0.0, 0.0),
+        gamma: 1.0,
+        // black either way
+        format: ColorFormat::Rgb,
+    });
+
+    if let Err(err) = clear.send(device, framing) {
+        warn!("failed to clear keyboard leds: {}", err);
+    }
+}
+
+// the open keyboard, cleared whenever the thread lets go of it: when it stops, when it starts
+// over with another config, and while unwinding from a panic. boards without leds are left
+// alone
+struct ClearOnDrop {
+    device: Option<SplitDevice>,
+    framing: Framing,
+    has_leds: bool,
+}
+
+impl Deref for ClearOnDrop {
+    type Target = Option<SplitDevice>;
+
+    fn deref(&self) -> &Option<SplitDevice> {
+        &self.device
+    }
+}
+
+impl DerefMut for ClearOnDrop {
+    fn deref_mut(&mut self) -> &mut Option<SplitDevice> {
+        &mut self.device
+    }
+}
+
+impl Drop for ClearOnDrop {
+    fn drop(&mut self) {
+        if let Some(device) = self.device.as_ref().filter(|_| self.has_leds) {
+            clear_leds(device, self.framing);
+        }
+    }
+}
+
+// what the thread keeps when the layout changes, everything else starts over with the new one
+struct Kept {
+    watcher: Box<dyn DeviceSource>,
+    effect_configs: Vec<EffectConfig>,
+    // pinned keys to start with, maybe from the saved settings, taken by the first layout
+    key_colors: Option<KeyColors>,
+    // last brightness asked for, sent again whenever the keyboard reconnects
+    brightness: Option<u8>,
+    paused: bool,
+    typing: TypingStats,
+    schedule: Schedule,
+    profile: Option<ProfileStore>,
+}
+
+// the thread's ends of the channels to and from the ui
+struct ThreadChannels {
+    state_tx: Sender<HIDThreadState>,
+    console_tx: Sender<String>,
+    cmd_rx: Receiver<HIDCommand>,
+    telemetry_txs: Vec<Sender<Telemetry>>,
+    live_tx: Option<Sender<LiveFrame>>,
+}
+
+// an effect and the leds it draws into, composited with the other layers every frame
+struct EffectLayer<'a> {
+    effect: Box<dyn LedEffect>,
+    canvas: Vec<LedState<'a>>,
+    // how long the effect's updates take, every frame's steps together
+    timing: TimingStats,
+}
+
+impl<'a> EffectLayer<'a> {
+    // canvases start out transparent, so a new layer shows nothing until its first update
+    fn new(effect: Box<dyn LedEffect>, base: &[LedState<'a>]) -> Self {
+        let mut canvas = base.to_vec();
+        for led in &mut canvas {
+            led.color.alpha = 0.0;
+        }
+
+        EffectLayer {
+            effect,
+            canvas,
+            timing: TimingStats::default(),
+        }
+    }
 }
 
 pub struct HIDThread {
     tx: Sender<HIDThreadState>,
     rx: Receiver<HIDThreadState>,
+    console_tx: Sender<String>,
+    console_rx: Receiver<String>,
+    cmd_tx: Sender<HIDCommand>,
+    cmd_rx: Receiver<HIDCommand>,
+    telemetry_txs: Vec<Sender<Telemetry>>,
+    live_tx: Option<Sender<LiveFrame>>,
+    // where runtime settings are saved, see profile.rs
+    profile: Option<ProfileStore>,
     cancel: Arc<AtomicBool>,
     thread: Option<thread::JoinHandle<()>>,
     kb_config: Arc<KBConfig>,
@@ -45,162 +813,987 @@ pub struct HIDThread {
 impl HIDThread {
     pub fn new(kb_config: Arc<KBConfig>) -> HIDThread {
         let (tx, rx) = unbounded::<HIDThreadState>();
+        let (cmd_tx, cmd_rx) = unbounded::<HIDCommand>();
+        let (console_tx, console_rx) = unbounded::<String>();
 
         HIDThread {
             tx,
             rx,
+            console_tx,
+            console_rx,
+            cmd_tx,
+            cmd_rx,
+            telemetry_txs: Vec::new(),
+            live_tx: None,
+            profile: None,
             cancel: Arc::new(AtomicBool::new(false)),
             thread: None,
             kb_config,
         }
     }
 
-    pub fn start(&mut self, update_rate: f32, frame_rate: f32, device: HidDevice) {
+    pub fn start(&mut self, update_rate: f32, frame_rate: f32, watcher: Box<dyn DeviceSource>) {
         let delta_update = 1.0 / update_rate;
         let delta_frame = 1.0 / frame_rate;
         let kb_config = self.kb_config.clone();
-        let tx = self.tx.clone();
+        let channels = ThreadChannels {
+            state_tx: self.tx.clone(),
+            console_tx: self.console_tx.clone(),
+            cmd_rx: self.cmd_rx.clone(),
+            telemetry_txs: self.telemetry_txs.clone(),
+            live_tx: self.live_tx.clone(),
+        };
         let cancel_arc = self.cancel.clone();
+        let profile = self.profile.take();
 
         self.thread = Some(thread::spawn(move || {
-            Self::run(delta_update, delta_frame, device, kb_config, tx, cancel_arc)
+            if kb_config.host_config.realtime_priority {
+                priority::raise_current_thread();
+            }
+            Self::run(
+                delta_update,
+                delta_frame,
+                watcher,
+                kb_config,
+                channels,
+                cancel_arc,
+                profile,
+            )
         }));
     }
 
     pub fn stop(&mut self) {
         self.cancel.store(true, Ordering::Relaxed);
-        self.thread
-            .take()
-            .expect("thread has not started!")
-            .join()
-            .unwrap();
+
+        // the panic itself was already printed, and the leds cleared on the way out
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("hid thread panicked");
+            }
+        }
     }
 
     pub fn rx(&self) -> Receiver<HIDThreadState> {
         self.rx.clone()
     }
 
+    pub fn tx(&self) -> Sender<HIDCommand> {
+        self.cmd_tx.clone()
+    }
+
+    // complete lines of console text from the firmware
+    pub fn console(&self) -> Receiver<String> {
+        self.console_rx.clone()
+    }
+
+    // nothing is sent unless this was asked for before `start`, every receiver gets every change
+    pub fn telemetry(&mut self) -> Receiver<Telemetry> {
+        let (tx, rx) = unbounded();
+        self.telemetry_txs.push(tx);
+        rx
+    }
+
+    // at most one a frame, and nothing unless this was asked for before `start`
+    pub fn live_frames(&mut self) -> Receiver<LiveFrame> {
+        let (tx, rx) = unbounded();
+        self.live_tx = Some(tx);
+        rx
+    }
+
+    // starts from the settings `profile` saved last and keeps saving them as they change,
+    // nothing is saved unless this was asked for before `start`
+    pub fn remember(&mut self, profile: ProfileStore) {
+        self.profile = Some(profile);
+    }
+
+    // every layer starts out transparent. configs that don't build are dropped, so the layers
+    // and the configs line up and a change to a layer can go straight into its config
+    fn build_effects<'a>(
+        effect_configs: &mut Vec<EffectConfig>,
+        base: &[LedState<'a>],
+    ) -> Vec<EffectLayer<'a>> {
+        let mut effects = Vec::with_capacity(effect_configs.len());
+        effect_configs.retain(|effect_config| {
+            match build_effect(&effect_config.name, &effect_config.params) {
+                Some(effect) => {
+                    effects.push(EffectLayer::new(effect, base));
+                    true
+                }
+                None => {
+                    warn!(
+                        "could not build effect {} (known effects: {})",
+                        effect_config.name,
+                        effect_names().collect::<Vec<_>>().join(", ")
+                    );
+                    false
+                }
+            }
+        });
+        effects
+    }
+
     fn run(
         wait_update: f32,
         wait_frame: f32,
-        device: HidDevice,
+        watcher: Box<dyn DeviceSource>,
         kb_config: Arc<KBConfig>,
-        state_tx: Sender<HIDThreadState>,
+        channels: ThreadChannels,
         cancel: Arc<AtomicBool>,
+        profile: Option<ProfileStore>,
     ) {
+        let saved = profile.as_ref().and_then(|profile| profile.saved());
+        let (effect_configs, pinned_colors) = profile::restored(saved, &kb_config.host_config);
+        let mut kept = Kept {
+            watcher,
+            effect_configs: effect_configs.to_vec(),
+            key_colors: Some(presets::from_preset(
+                pinned_colors,
+                &kb_config,
+                "pinned_colors",
+            )),
+            brightness: saved.and_then(|saved| saved.brightness),
+            paused: false,
+            typing: TypingStats::default(),
+            schedule: Schedule::new(&kb_config.host_config),
+            profile,
+        };
+        let mut kb_config = kb_config;
+
+        while let Some(new_config) = Self::run_layout(
+            wait_update,
+            wait_frame,
+            &kb_config,
+            &channels,
+            &cancel,
+            &mut kept,
+        ) {
+            info!("restarting with layout {}", new_config.host_config.layout);
+            kb_config = new_config;
+        }
+    }
+
+    // runs until the thread is stopped, or returns the config to go on with when the layout
+    // changes or the config is reloaded
+    fn run_layout(
+        wait_update: f32,
+        wait_frame: f32,
+        kb_config: &KBConfig,
+        channels: &ThreadChannels,
+        cancel: &AtomicBool,
+        kept: &mut Kept,
+    ) -> Option<Arc<KBConfig>> {
+        let Kept {
+            watcher,
+            effect_configs,
+            key_colors: restored_key_colors,
+            brightness,
+            paused,
+            typing,
+            schedule,
+            profile,
+        } = kept;
+        let ThreadChannels {
+            state_tx,
+            console_tx,
+            cmd_rx,
+            telemetry_txs,
+            live_tx,
+        } = channels;
+
         let mut last_update = Instant::now();
         let mut last_frame = Instant::now();
+        let mut last_publish = Instant::now();
+        // effects always move on by whole frames, the measured deltas are only for the stats
+        let frame_step = Duration::from_secs_f32(wait_frame);
+        let mut frame_clock = Ticker::new(frame_step);
+        let mut update_clock = Ticker::new(Duration::from_secs_f32(wait_update));
 
         let mut delta_frame = wait_frame;
+        let mut update_timing = TimingStats::default();
+        let mut frame_timing = TimingStats::default();
+        let mut update_deltas = Vec::new();
+        let mut frame_deltas = Vec::new();
 
-        let mut recv_buffer = [0u8; RAW_EPSIZE];
-
-        let layout = kb_config.layout();
-
-        let mut effects: Vec<Box<dyn LedEffect>> =
-            vec![Box::new(crate::effects::Rainbow1Effect::default())];
+        let mut recv_buffer = vec![0u8; kb_config.host_config.raw_epsize];
+        let mut reader = ProtocolReader::new(kb_config.host_config.framing());
+        // reports that arrived while waiting for an ack, handled before reading any more
+        let mut received: VecDeque<ProtocolMessage> = VecDeque::new();
+        let mut console_text = String::new();
 
         let mut matrix = vec![
             vec![KeyState::default(); kb_config.columns() as usize];
             kb_config.rows() as usize
         ];
 
-        let mut led_state = vec![LedState::default(); kb_config.led_count().into()];
+        // the opaque black every frame is composited onto
+        let led_state = led_state(kb_config);
 
-        for key in &layout.layout {
-            let led_idx = kb_config.matrix[key.matrix.0 as usize][key.matrix.1 as usize];
-            if led_idx < 0 {
-                continue;
-            }
+        #[cfg(feature = "openrgb")]
+        let openrgb = kb_config
+            .host_config
+            .openrgb
+            .as_ref()
+            .map(crate::openrgb::OpenRgbSink::spawn);
+        #[cfg(not(feature = "openrgb"))]
+        if kb_config.host_config.openrgb.is_some() {
+            warn!("built without the openrgb feature, not mirroring leds to openrgb");
+        }
 
-            led_state[led_idx as usize].key = Some(key);
+        let mut effects = Self::build_effects(effect_configs, &led_state);
+
+        info!("hid thread started with {} leds", led_state.len());
+
+        let mut layer_state: u32 = 0;
+        let (mut previous_layer_state, mut layer_changed) = (0, false);
+        let mut locks = LockState::default();
+        let mut mods = Mods::default();
+        let mut last_keycode: Option<u16> = None;
+        let mut encoders: Vec<EncoderState> = Vec::new();
+
+        let mut device = ClearOnDrop {
+            device: watcher
+                .open(kb_config)
+                .map(|device| SplitDevice::new(device, kb_config.host_config.raw_epsize)),
+            framing: kb_config.host_config.framing(),
+            has_leds: kb_config.led_count() > 0,
+        };
+        match (&*device, *brightness) {
+            (None, _) => info!("waiting for device"),
+            (Some(device), Some(value)) => {
+                Self::send_brightness(device, kb_config, value, &mut reader, &mut received)
+            }
+            _ => {}
         }
 
-        let mut layer_state: u8 = 0;
+        // until the keyboard answers, layer_state is only a guess
+        let mut layer_query = device
+            .as_ref()
+            .and_then(|device| Self::query(device, ProtocolMessage::GetLayer, kb_config));
+        let mut version_query = device
+            .as_ref()
+            .and_then(|device| Self::query(device, ProtocolMessage::VersionQuery, kb_config));
+        let mut firmware_version: Option<FirmwareVersion> = None;
+
+        let mut frame_sync = FrameSync::new(
+            kb_config.host_config.resync_interval,
+            kb_config.host_config.stream_threshold,
+        );
+        let mut backoff = SendBackoff::default();
+        let mut write_errors: u64 = 0;
+        // reads that failed in a row, the device is reopened after RECONNECT_FAILURES
+        let mut read_failures: u32 = 0;
+        let mut last_error: Option<String> = None;
+        // whether the keyboard has been connected at all, to tell waiting from reconnecting
+        let mut seen_device = false;
+        let mut last_telemetry: Option<Telemetry> = None;
+        let mut last_live = Instant::now();
+
+        let mut key_colors = restored_key_colors
+            .take()
+            .unwrap_or_else(|| presets::pinned_colors(kb_config));
+        let mut notifications: Vec<Notification> = Vec::new();
+        let mut progress: Option<ProgressEffect> = None;
+        let mut transition: Option<EffectLayer> = None;
+        let mut flash: Option<EffectLayer> = None;
+        let mut readback = device
+            .as_ref()
+            .and_then(|device| Self::query_rgb(device, kb_config));
+        // a read back that hasn't gone out with a state yet
+        let mut device_colors: Option<Vec<Hsva>> = None;
+        let mut battery: Option<(u8, bool)> = None;
+
+        // what was last sent, i.e. the composited effects plus everything drawn over them
+        let mut frame = led_state.clone();
+        // the color the whole board was last set to with a single report, while it still shows it
+        let mut last_solid: Option<Hsv> = None;
 
         while !cancel.load(Ordering::Relaxed) {
             // prep
             let delta_update = last_update.elapsed().as_secs_f32();
+            update_timing.record(delta_update);
+            update_deltas.push(delta_update);
+
+            // hotplug
+            match watcher.poll(kb_config, device.is_some()) {
+                Some(DeviceEvent::Connected(new_device)) => {
+                    info!("device connected");
+                    let new_device = SplitDevice::new(new_device, kb_config.host_config.raw_epsize);
+                    // nothing half read from before counts anymore
+                    reader = ProtocolReader::new(kb_config.host_config.framing());
+                    backoff = SendBackoff::default();
+                    read_failures = 0;
+                    layer_query = Self::query(&new_device, ProtocolMessage::GetLayer, kb_config);
+                    version_query =
+                        Self::query(&new_device, ProtocolMessage::VersionQuery, kb_config);
+                    firmware_version = None;
+                    if let Some(value) = *brightness {
+                        Self::send_brightness(
+                            &new_device,
+                            kb_config,
+                            value,
+                            &mut reader,
+                            &mut received,
+                        );
+                    }
+                    readback = Self::query_rgb(&new_device, kb_config);
+                    // only what this connection reports counts
+                    battery = None;
+                    frame_sync.force_refresh();
+                    *device = Some(new_device);
+                }
+                Some(DeviceEvent::Disconnected) => {
+                    info!("device disconnected, waiting for it to come back");
+                    *device = None;
+                    battery = None;
+                }
+                None => {}
+            }
+
+            // commands, and the schedule's when an entry starts. a dragged color picker sends a
+            // color every ui frame, only the latest is applied
+            let mut new_color = None;
+            for command in cmd_rx.try_iter().chain(schedule.poll()) {
+                debug!("command {:?}", command);
+
+                match command {
+                    HIDCommand::ForceRefresh => frame_sync.force_refresh(),
+                    HIDCommand::SetKeyColors(colors) => key_colors.extend(colors),
+                    HIDCommand::ClearKeyColors(positions) => {
+                        for pos in positions {
+                            key_colors.remove(&pos);
+                        }
+                    }
+                    HIDCommand::LoadPreset(colors) => {
+                        // so the state, the profile and the next rebuild agree nothing's running
+                        effect_configs.clear();
+                        effects.clear();
+                        key_colors = colors;
+                    }
+                    // dropping the device clears it, so leds the new layout doesn't map don't
+                    // stay lit
+                    HIDCommand::SwitchConfig(new_config) => return Some(new_config),
+                    HIDCommand::Reload(new_config) => {
+                        *effect_configs = new_config.host_config.effects.clone();
+                        *schedule = Schedule::new(&new_config.host_config);
+                        return Some(new_config);
+                    }
+                    HIDCommand::ResetEffects => {
+                        effects = Self::build_effects(effect_configs, &led_state);
+                        key_colors.clear();
+                    }
+                    HIDCommand::ResetTyping => *typing = TypingStats::default(),
+                    HIDCommand::Pause => *paused = true,
+                    HIDCommand::Resume => *paused = false,
+                    HIDCommand::RestartEffects => {
+                        for layer in &mut effects {
+                            debug!("restarting {}", layer.effect.name());
+                            layer.effect.reset();
+                            for led in &mut layer.canvas {
+                                led.color.alpha = 0.0;
+                            }
+                        }
+                    }
+                    HIDCommand::TapTempo => {
+                        let now = Instant::now();
+                        for (idx, layer) in effects.iter_mut().enumerate() {
+                            if !layer.effect.tap(now) {
+                                continue;
+                            }
+                            // kept like a slider's change, so saving writes the tapped tempo
+                            let bpm = layer.effect.params().into_iter().find(|p| p.name == "bpm");
+                            if let Some(bpm) = bpm {
+                                effect_configs[idx].set_param("bpm", bpm.value);
+                            }
+                        }
+                    }
+                    // over the stack rather than in it, a new one replacing one still fading
+                    HIDCommand::Flash { color, duration } => {
+                        flash = Some(EffectLayer::new(
+                            Box::new(FlashEffect::new(color, duration)),
+                            &led_state,
+                        ));
+                    }
+                    HIDCommand::SetEffect(name) => {
+                        *effect_configs = vec![EffectConfig {
+                            name,
+                            params: serde_json::Value::Null,
+                        }];
+                        effects = Self::build_effects(effect_configs, &led_state);
+                    }
+                    HIDCommand::SetEffects(configs) => {
+                        *effect_configs = configs;
+                        effects = Self::build_effects(effect_configs, &led_state);
+                    }
+                    HIDCommand::SetColor(color) => new_color = Some(color),
+                    HIDCommand::SetText(text) => {
+                        for layer in &mut effects {
+                            layer.effect.set_text(&text);
+                        }
+                    }
+                    HIDCommand::SetParam {
+                        effect,
+                        name,
+                        value,
+                    } => {
+                        let layer = effects.get_mut(effect);
+                        // the stack is rebuilt from the configs on a layout switch
+                        if layer.is_some_and(|layer| layer.effect.set_param(&name, value)) {
+                            effect_configs[effect].set_param(&name, value);
+                        }
+                    }
+                    HIDCommand::Notify {
+                        color,
+                        duration,
+                        keys,
+                    } => notifications.push(Notification {
+                        color,
+                        duration,
+                        start: Instant::now(),
+                        keys,
+                    }),
+                    HIDCommand::SetProgress(value) => {
+                        progress = value.map(|value| ProgressEffect {
+                            progress: value,
+                            ..kb_config.host_config.progress.clone()
+                        })
+                    }
+                    HIDCommand::QueryRgb => {
+                        if let Some(device) = &*device {
+                            readback = Self::query_rgb(device, kb_config);
+                        }
+                    }
+                    HIDCommand::SetBrightness(value) => {
+                        *brightness = Some(value);
+                        if let Some(device) = &*device {
+                            Self::send_brightness(
+                                device,
+                                kb_config,
+                                value,
+                                &mut reader,
+                                &mut received,
+                            );
+                        }
+                    }
+                }
+            }
+
+            // kept in the configs like a slider's change, so rebuilding the stack or saving the
+            // profile keeps the picked color
+            if let Some(color) = new_color {
+                for (idx, layer) in effects.iter_mut().enumerate() {
+                    for (name, value) in layer.effect.set_color(color) {
+                        effect_configs[idx].set_value(name, value);
+                    }
+                }
+            }
+
+            if let Some(profile) = profile {
+                profile.poll(|| {
+                    Profile::new(
+                        &kb_config.host_config,
+                        effect_configs,
+                        &key_colors,
+                        *brightness,
+                    )
+                });
+            }
 
             // work
-            if let Ok(size) = device.read_timeout(&mut recv_buffer, 0) {
-                match ProtocolMessage::read_buffer(&recv_buffer, size) {
-                    Some(ProtocolMessage::Press(press)) => {
-                        let key_state = &mut matrix[press.row as usize][press.col as usize];
-
-                        if press.pressed {
-                            if !key_state.is_pressed {
-                                key_state.last_down = Some(Instant::now());
+            let message = match received.pop_front() {
+                Some(message) => Some(message),
+                None => match device
+                    .as_ref()
+                    .map(|device| device.read_timeout(&mut recv_buffer, 0))
+                {
+                    Some(Ok(size)) => {
+                        read_failures = 0;
+                        reader.read(&recv_buffer, size)
+                    }
+                    Some(Err(err)) => {
+                        debug!("read failed: {}", err);
+                        last_error = Some(format!("read failed: {}", err));
+                        read_failures += 1;
+                        // an unplugged keyboard can take a while to drop out of the device list
+                        if read_failures >= RECONNECT_FAILURES {
+                            warn!("reads keep failing, reopening the device");
+                            *device = None;
+                            read_failures = 0;
+                        }
+                        None
+                    }
+                    None => None,
+                },
+            };
+
+            let got_message = message.is_some();
+            match message {
+                Some(ProtocolMessage::Press(press)) => {
+                    trace!(
+                        "key ({}, {}) pressed={} keycode={}",
+                        press.row,
+                        press.col,
+                        press.pressed,
+                        keycode::describe(press.keycode)
+                    );
+
+                    // the matrix is sized from the layout, which may not have every position
+                    let key_state = matrix
+                        .get_mut(press.row as usize)
+                        .and_then(|row| row.get_mut(press.col as usize));
+
+                    match key_state {
+                        None => debug!(
+                            "key ({}, {}) isn't in layout {}, ignoring it",
+                            press.row, press.col, kb_config.host_config.layout
+                        ),
+                        Some(key_state) => {
+                            if press.pressed {
+                                if !key_state.is_pressed {
+                                    typing.press((press.row, press.col), Instant::now());
+                                    key_state.last_down = Some(Instant::now());
+                                    last_keycode = Some(press.keycode);
+                                    key_state.keycode = Some(press.keycode);
+                                }
+
+                                key_state.last_pressed = Some(Instant::now());
+                            } else if key_state.is_pressed {
+                                key_state.last_up = Some(Instant::now());
                             }
 
-                            key_state.last_pressed = Some(Instant::now());
+                            key_state.is_pressed = press.pressed;
                         }
+                    }
+                    mods = press.mods;
+                }
+                Some(ProtocolMessage::Layer(layer)) => {
+                    debug!(
+                        "layer state {:#010x} ({})",
+                        layer.layer_state,
+                        kb_config.legends.layer_name(layer.layer_state)
+                    );
+                    // the answer to a layer query isn't a change the user made
+                    let changed = layer.layer_state != layer_state && layer_query.is_none();
+                    let config = kb_config.host_config.layer_transition.as_ref();
 
-                        key_state.is_pressed = press.pressed;
+                    // replaces a sweep that's still going instead of stacking
+                    if let Some(config) = config.filter(|_| changed) {
+                        transition = Some(EffectLayer::new(Box::new(config.clone()), &led_state));
+                    }
+                    if changed {
+                        previous_layer_state = layer_state;
+                        layer_changed = true;
                     }
-                    Some(ProtocolMessage::Layer(layer)) => {
-                        layer_state = layer.layer_state;
+
+                    layer_state = layer.layer_state;
+                    layer_query = None;
+                }
+                Some(ProtocolMessage::RgbState(state)) => match &mut readback {
+                    Some(pending) => {
+                        pending.add(
+                            &state,
+                            kb_config.host_config.gamma,
+                            kb_config.host_config.color_format,
+                        );
+
+                        if let Some(colors) = pending.complete() {
+                            debug!("read back {} led colors", colors.len());
+                            device_colors = Some(colors);
+                            readback = None;
+                        }
+                    }
+                    None => debug!("ignoring rgb state nobody asked for"),
+                },
+                Some(ProtocolMessage::Version(version)) => {
+                    info!("firmware version {}", version);
+                    Self::check_version(version, kb_config);
+                    firmware_version = Some(version);
+                    version_query = None;
+                }
+                Some(ProtocolMessage::Battery { percent, charging }) => {
+                    trace!("battery {}% charging={}", percent, charging);
+                    battery = Some((percent, charging));
+                }
+                Some(ProtocolMessage::Encoder { index, clockwise }) => {
+                    trace!("encoder {} clockwise={}", index, clockwise);
+
+                    if encoders.len() <= index as usize {
+                        encoders.resize(index as usize + 1, EncoderState::default());
                     }
-                    _ => {}
+                    let encoder = &mut encoders[index as usize];
+                    encoder.position += if clockwise { 1 } else { -1 };
+                    encoder.clockwise = clockwise;
                 }
+                Some(ProtocolMessage::Locks(new_locks)) => {
+                    debug!("lock state {:?}", new_locks);
+                    locks = new_locks;
+                }
+                Some(ProtocolMessage::Console(console)) => {
+                    console_text.push_str(&console.text);
+
+                    if !console.more {
+                        let text = std::mem::take(&mut console_text);
+                        debug!("console: {}", text);
+                        console_tx.send(text).ok();
+                    }
+                }
+                _ => {}
+            }
+
+            if layer_query.is_some_and(|sent| sent.elapsed() >= LAYER_QUERY_TIMEOUT) {
+                info!("no response to layer query, assuming layer 0 until the next change");
+                layer_query = None;
+            }
+
+            if version_query.is_some_and(|sent| sent.elapsed() >= VERSION_QUERY_TIMEOUT) {
+                warn!(
+                    "no response to version query, assuming legacy firmware {}",
+                    FirmwareVersion::LEGACY
+                );
+                Self::check_version(FirmwareVersion::LEGACY, kb_config);
+                firmware_version = Some(FirmwareVersion::LEGACY);
+                version_query = None;
             }
 
-            if last_frame.elapsed() >= Duration::from_secs_f32(wait_frame) {
+            let frame_steps = if *paused {
+                // so effects pick up where they stopped instead of jumping ahead on resume
+                last_frame = Instant::now();
+                frame_clock.restart(last_frame);
+                0
+            } else {
+                frame_clock.due(Instant::now())
+            };
+
+            if frame_steps > 0 {
+                // start to start, the work in between included
                 delta_frame = last_frame.elapsed().as_secs_f32();
+                last_frame = Instant::now();
+                frame_timing.record(delta_frame);
+                frame_deltas.push(delta_frame);
+
+                let ctx = EffectContext {
+                    key_state: &matrix,
+                    layer_state,
+                    previous_layer_state,
+                    layer_changed: std::mem::take(&mut layer_changed),
+                    locks,
+                    mods,
+                    encoders: &encoders,
+                    kb_config,
+                };
+
+                let pre_frame = std::mem::replace(&mut frame, led_state.clone());
+
+                // bottom to top, each layer over the ones before it
+                for layer in &mut effects {
+                    let started = Instant::now();
+                    for _ in 0..frame_steps {
+                        layer
+                            .effect
+                            .update(frame_step.as_secs_f32(), &mut layer.canvas, &ctx);
+                    }
+                    layer.timing.record(started.elapsed().as_secs_f32());
 
-                let pre_state = led_state.clone();
+                    for (led, over) in frame.iter_mut().zip(&layer.canvas) {
+                        led.color = alpha_over(led.color, over.color);
+                    }
+                }
 
-                for effect in &mut effects {
-                    effect.update(delta_frame, &mut led_state, &matrix);
+                // a one-shot effect from the config goes with its config, so they still line up
+                for idx in (0..effects.len()).rev() {
+                    if effects[idx].effect.finished() {
+                        debug!("{} finished", effects[idx].effect.name());
+                        effects.remove(idx);
+                        effect_configs.remove(idx);
+                    }
                 }
 
-                let mut colors: HashMap<u8, Hsv> = HashMap::new();
+                // the layer sweep, then a flash over everything
+                for one_shot in [&mut transition, &mut flash] {
+                    if let Some(layer) = one_shot {
+                        for _ in 0..frame_steps {
+                            layer
+                                .effect
+                                .update(frame_step.as_secs_f32(), &mut layer.canvas, &ctx);
+                        }
 
-                for (idx, led) in led_state.iter().enumerate() {
-                    if led.color != pre_state[idx].color {
-                        colors.insert(
-                            idx as u8,
-                            Hsv::new(
-                                led.color.hue,
-                                led.color.saturation,
-                                led.color.value * led.color.alpha,
-                            ),
-                        );
+                        for (led, over) in frame.iter_mut().zip(&layer.canvas) {
+                            led.color = alpha_over(led.color, over.color);
+                        }
+                    }
+                    if one_shot
+                        .as_ref()
+                        .is_some_and(|layer| layer.effect.finished())
+                    {
+                        *one_shot = None;
                     }
                 }
 
-                for chunk in colors.into_iter().collect::<Vec<_>>().chunks(7) {
-                    let colors: HashMap<u8, Hsv> = chunk.iter().copied().collect();
+                if let Some(progress) = &mut progress {
+                    for _ in 0..frame_steps {
+                        progress.update(frame_step.as_secs_f32(), &mut frame, &ctx);
+                    }
+                }
 
-                    ProtocolMessage::RgbSet(RgbSetMessage { colors })
-                        .send(&device)
-                        .ok();
+                // pinned keys win over every effect
+                for (key, color) in &key_colors {
+                    if let Some(led_idx) = kb_config.led_at(*key) {
+                        frame[led_idx].color = *color;
+                    }
                 }
 
-                last_frame = Instant::now();
+                notifications.retain(|notification| !notification.finished());
+                for notification in &notifications {
+                    notification.apply(&mut frame, kb_config);
+                }
+
+                // the firmware's brightness at 0 is sent black as well, so nothing stale stays lit
+                // if it doesn't know the message
+                let host_scale = brightness
+                    .filter(|value| kb_config.host_config.host_brightness || *value == 0)
+                    .map(|value| value as f32 / 255.0);
+                if let Some(scale) = host_scale {
+                    for led in &mut frame {
+                        led.color.value *= scale;
+                    }
+                }
+
+                #[cfg(feature = "openrgb")]
+                if let Some(sink) = &openrgb {
+                    sink.send(frame.iter().map(|led| led.color.color).collect());
+                }
+
+                match &*device {
+                    Some(connected) if backoff.ready() => {
+                        let stats = match frame_sync.solid(&frame, last_solid) {
+                            // already showing it
+                            Some(None) => SendStats::default(),
+                            Some(Some(color)) => {
+                                last_solid = Some(color);
+                                send_solid(connected, color, &kb_config.host_config)
+                            }
+                            None => {
+                                last_solid = None;
+                                let colors = frame_sync.frame(&frame, &pre_frame);
+                                send_colors(connected, colors, &kb_config.host_config)
+                            }
+                        };
+                        write_errors += stats.failures as u64;
+                        if let Some(err) = &stats.last_error {
+                            last_error = Some(format!("write failed: {}", err));
+                        }
+
+                        if stats.failures > 0 {
+                            // some of this frame is missing on the keyboard now
+                            frame_sync.force_refresh();
+                            last_solid = None;
+                        }
+
+                        if backoff.record(&stats, wait_frame) {
+                            warn!("writes keep failing, reopening the device");
+                            *device = None;
+                        }
+                    }
+                    // frames skipped here aren't on the keyboard either
+                    _ => frame_sync.force_refresh(),
+                }
             }
 
-            // tx
-            state_tx
-                .try_send(HIDThreadState {
+            // tx, only as often as frames unless a report came in, since the ui repaints at
+            // most that fast and every state copies the whole matrix
+            let publish_due = last_publish.elapsed() >= Duration::from_secs_f32(wait_frame);
+            if got_message || publish_due {
+                last_publish = Instant::now();
+                seen_device |= device.is_some();
+                let typing_summary = typing.summary(Instant::now());
+
+                if !telemetry_txs.is_empty() {
+                    let telemetry = Telemetry {
+                        connected: device.is_some(),
+                        layer: config::active_layers(layer_state).last().unwrap_or(0),
+                        layer_state,
+                        wpm: typing_summary.wpm.round() as u32,
+                        effects: effect_configs
+                            .iter()
+                            .map(|config| config.name.clone())
+                            .collect(),
+                        brightness: *brightness,
+                        paused: *paused,
+                    };
+                    if last_telemetry.as_ref() != Some(&telemetry) {
+                        for telemetry_tx in telemetry_txs {
+                            telemetry_tx.send(telemetry.clone()).ok();
+                        }
+                        last_telemetry = Some(telemetry);
+                    }
+                }
+
+                let live_due = last_live.elapsed() >= Duration::from_secs_f32(wait_frame);
+                if let Some(live_tx) = live_tx.as_ref().filter(|_| live_due) {
+                    last_live = Instant::now();
+                    live_tx
+                        .send(Self::live_frame(
+                            &frame,
+                            &matrix,
+                            layer_state,
+                            device.is_some(),
+                        ))
+                        .ok();
+                }
+
+                if let Err(err) = state_tx.try_send(HIDThreadState {
                     delta_update,
                     delta_frame,
+                    update_timing: update_timing.summary(),
+                    frame_timing: frame_timing.summary(),
+                    update_deltas: std::mem::take(&mut update_deltas),
+                    frame_deltas: std::mem::take(&mut frame_deltas),
+                    typing: typing_summary,
                     matrix: matrix.clone(),
-                    led_state: led_state.iter().map(|state| state.color).collect(),
+                    led_state: frame.iter().map(|state| state.color).collect(),
                     layer_state,
-                })
-                .ok();
+                    locks,
+                    mods,
+                    encoders: encoders.clone(),
+                    last_keycode,
+                    connected: device.is_some(),
+                    status: ConnectionStatus::new(
+                        device.is_some(),
+                        seen_device,
+                        read_failures > 0 || backoff.failures > 0,
+                        last_error.as_ref(),
+                    ),
+                    firmware_version,
+                    battery,
+                    device_colors: device_colors.take(),
+                    write_errors,
+                    last_error: last_error.clone(),
+                    effects: effect_configs
+                        .iter()
+                        .map(|config| config.name.clone())
+                        .collect(),
+                    effect_params: effects
+                        .iter()
+                        .map(|layer| (layer.effect.name(), layer.effect.params()))
+                        .collect(),
+                    effect_timing: effects
+                        .iter()
+                        .map(|layer| (layer.effect.name(), layer.timing.summary()))
+                        .collect(),
+                }) {
+                    trace!("dropped thread state: {}", err);
+                    // the read back goes out with the next one instead
+                    device_colors = err.into_inner().device_colors;
+                }
+            }
 
-            // sleep
+            // sleep until the next update or frame is due, unless input comes in before that
             last_update = Instant::now();
-            thread::sleep(Duration::from_secs_f32(wait_update));
+            update_clock.due(last_update);
+            let wait = update_clock
+                .remaining(last_update)
+                .min(frame_clock.remaining(last_update));
+            match &*device {
+                Some(device) if !got_message => device.wait_for_input(wait),
+                Some(_) => {}
+                None => thread::sleep(wait),
+            }
         }
 
-        ProtocolMessage::RgbSetFull(RgbSetFullMessage {
-            color: Hsv::new(0.0, 0.0, 0.0),
-        })
-        .send(&device)
-        .expect("failed to clear keyboard leds");
+        info!("hid thread stopping");
+        None
+    }
+
+    fn live_frame(
+        frame: &[LedState],
+        matrix: &[Vec<KeyState>],
+        layer_state: u32,
+        connected: bool,
+    ) -> LiveFrame {
+        let pressed = matrix.iter().enumerate().flat_map(|(row, keys)| {
+            keys.iter()
+                .enumerate()
+                .filter(|(_, key)| key.is_pressed)
+                .map(move |(col, _)| (row as u8, col as u8))
+        });
+
+        LiveFrame {
+            leds: frame
+                .iter()
+                .map(|led| {
+                    let rgb: Rgb = led.color.color.into_color();
+                    [rgb.red, rgb.green, rgb.blue].map(|value| protocol::channel(value, 1.0))
+                })
+                .collect(),
+            pressed: pressed.collect(),
+            layer: config::active_layers(layer_state).last().unwrap_or(0),
+            layer_state,
+            connected,
+        }
+    }
+
+    // scaled on the host instead with host_brightness, see the frame loop
+    fn send_brightness(
+        device: &dyn HidTransport,
+        kb_config: &KBConfig,
+        value: u8,
+        reader: &mut ProtocolReader,
+        received: &mut VecDeque<ProtocolMessage>,
+    ) {
+        if kb_config.host_config.host_brightness {
+            return;
+        }
+
+        let message = ProtocolMessage::SetBrightness(value);
+        let framing = kb_config.host_config.framing();
+
+        let result = if kb_config.host_config.protocol_version >= ACK_VERSION {
+            message.send_acked(device, reader, ACK_TIMEOUT, ACK_ATTEMPTS, received)
+        } else {
+            message.send(device, framing).map(|_| ())
+        };
+
+        if let Err(err) = result {
+            warn!("failed to set brightness: {}", err);
+        }
+    }
+
+    fn query_rgb(device: &dyn HidTransport, kb_config: &KBConfig) -> Option<RgbReadback> {
+        if kb_config.led_count() == 0 {
+            return None;
+        }
+        match ProtocolMessage::RgbQuery.send(device, kb_config.host_config.framing()) {
+            Ok(_) => Some(RgbReadback::new(kb_config.led_count().into())),
+            Err(err) => {
+                warn!("failed to query led colors: {}", err);
+                None
+            }
+        }
+    }
+
+    // sends a message the firmware answers, returns when it was sent
+    fn query(
+        device: &dyn HidTransport,
+        message: ProtocolMessage,
+        kb_config: &KBConfig,
+    ) -> Option<Instant> {
+        match message.send(device, kb_config.host_config.framing()) {
+            Ok(_) => Some(Instant::now()),
+            Err(err) => {
+                warn!("failed to send {:?}: {}", message, err);
+                None
+            }
+        }
+    }
+
+    // the major version is the protocol version, anything else only matters to the firmware
+    fn check_version(version: FirmwareVersion, kb_config: &KBConfig) {
+        let configured = kb_config.host_config.protocol_version;
+        if version.major != configured {
+            warn!(
+                "firmware speaks protocol version {} but protocol_version is set to {}",
+                version.major, configured
+            );
+        }
     }
 }
 
@@ -209,3 +1802,451 @@ impl Drop for HIDThread {
         self.stop();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{MockDevice, MockSource};
+
+    fn leds(count: usize, value: f32) -> Vec<LedState<'static>> {
+        vec![
+            LedState {
+                color: Hsva::new(0.0, 0.0, value, 1.0),
+                key: None,
+                phase_offset: 0.0,
+            };
+            count
+        ]
+    }
+
+    #[test]
+    fn only_changes_are_sent() {
+        let mut frame_sync = FrameSync::new(0.0, None);
+        let mut led_state = leds(4, 0.0);
+        let pre_state = led_state.clone();
+
+        assert!(frame_sync.frame(&led_state, &pre_state).is_empty());
+
+        led_state[2].color.value = 1.0;
+        let colors = frame_sync.frame(&led_state, &pre_state);
+        assert_eq!(colors.keys().copied().collect::<Vec<_>>(), vec![2]);
+    }
+
+    #[test]
+    fn busy_frames_stream_in_full() {
+        let mut frame_sync = FrameSync::new(0.0, Some(0.5));
+        let pre_state = leds(10, 0.0);
+        let mut led_state = pre_state.clone();
+
+        // a few changes stay sparse
+        led_state[3].color.value = 1.0;
+        let colors = frame_sync.frame(&led_state, &pre_state);
+        assert_eq!(colors.len(), 1);
+        assert_eq!(runs(&colors).len(), 1);
+
+        led_state[0].color.value = 1.0;
+        let colors = frame_sync.frame(&led_state, &pre_state);
+        assert_eq!(runs(&colors).len(), 2);
+
+        for led in &mut led_state[5..] {
+            led.color.value = 1.0;
+        }
+        let colors = frame_sync.frame(&led_state, &pre_state);
+        assert_eq!(colors.len(), 10);
+        let runs = runs(&colors);
+        assert_eq!(runs.len(), 1);
+        let run = &runs[0];
+        assert_eq!(run.first().unwrap().0, 0);
+        assert_eq!(run.last().unwrap().0, 9);
+
+        // nothing changed, nothing to stream
+        assert!(frame_sync.frame(&pre_state, &pre_state).is_empty());
+    }
+
+    #[test]
+    fn runs_are_streamed_when_cheaper() {
+        let kb_config = crate::config::tests::kb_config_2x2();
+        let matrix = vec![vec![KeyState::default(); 2]; 2];
+        let ctx = EffectContext::at_rest(&matrix, &kb_config);
+
+        // a rainbow over 64 leds, a few of them held still like lock indicators
+        let mut led_state = leds(64, 1.0);
+        for (idx, led) in led_state.iter_mut().enumerate() {
+            led.phase_offset = idx as f32 / 64.0;
+        }
+        let mut rainbow = crate::effects::Rainbow1Effect::default();
+        rainbow.update(0.1, &mut led_state, &ctx);
+        let pre_state = led_state.clone();
+        rainbow.update(0.1, &mut led_state, &ctx);
+        for idx in [10, 30, 31, 50] {
+            led_state[idx].color = pre_state[idx].color;
+        }
+        let colors = FrameSync::new(0.0, Some(1.0)).frame(&led_state, &pre_state);
+        assert_eq!(colors.len(), 60);
+
+        // 8 leds to a streamed report and 7 to one of sets, so runs of 10, 19, 18 and 13 stream
+        // 6 reports and leave 12 leds to set
+        let framing = kb_config.host_config.framing();
+        let (streamed, set) = plan_frame(&colors, framing);
+        assert_eq!(streamed.len(), 6);
+        assert!(streamed.iter().all(|run| run.len() == 8));
+        assert_eq!(set.len(), 12);
+
+        let send = |stream_threshold| {
+            let device = MockDevice::new(framing, 2);
+            let config = Config {
+                stream_threshold,
+                ..kb_config.host_config.clone()
+            };
+            send_colors(&device, colors.clone(), &config).writes
+        };
+        assert_eq!(send(None), 9);
+        assert_eq!(send(Some(1.0)), 8);
+
+        // scattered changes are never worse off
+        let scattered: HashMap<u16, Hsv> = (0..30).map(|idx| (idx * 2, Hsv::default())).collect();
+        let (streamed, set) = plan_frame(&scattered, framing);
+        assert!(streamed.is_empty());
+        assert_eq!(set.len(), 30);
+    }
+
+    #[test]
+    fn ticks_keep_to_the_step() {
+        let step = Duration::from_millis(10);
+        let mut ticker = Ticker::new(step);
+        let start = ticker.next - step;
+
+        assert_eq!(ticker.due(start + Duration::from_millis(9)), 0);
+        assert_eq!(ticker.remaining(start), step);
+        // a tick handled late doesn't push the next one back
+        assert_eq!(ticker.due(start + Duration::from_millis(14)), 1);
+        assert_eq!(
+            ticker.remaining(start + Duration::from_millis(14)),
+            Duration::from_millis(6)
+        );
+        assert_eq!(ticker.due(start + Duration::from_millis(20)), 1);
+
+        // a few missed steps are made up, a long stall isn't
+        assert_eq!(ticker.due(start + Duration::from_millis(51)), 3);
+        assert_eq!(ticker.due(start + Duration::from_millis(500)), MAX_CATCH_UP);
+        assert_eq!(ticker.remaining(start + Duration::from_millis(500)), step);
+    }
+
+    #[test]
+    fn sent_colors_stay_in_range() {
+        let mut led_state = leds(1, 3.0);
+        assert_eq!(sent_color(&led_state[0]).value, 1.0);
+        // an alpha past 1 doesn't brighten
+        led_state[0].color = Hsva::new(0.0, 1.0, 0.8, 2.0);
+        assert_eq!(sent_color(&led_state[0]).value, 0.8);
+
+        led_state[0].color.value = -1.0;
+        assert_eq!(sent_color(&led_state[0]).value, 0.0);
+        led_state[0].color = Hsva::new(0.0, 1.2, 1.0, f32::NAN);
+        assert_eq!(sent_color(&led_state[0]), Hsv::new(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn solid_boards_are_sent_once() {
+        let mut frame_sync = FrameSync::new(0.0, None);
+        let mut led_state = leds(4, 0.5);
+        let color = sent_color(&led_state[0]);
+
+        assert_eq!(frame_sync.solid(&led_state, None), Some(Some(color)));
+        assert_eq!(frame_sync.solid(&led_state, Some(color)), Some(None));
+
+        frame_sync.force_refresh();
+        assert_eq!(frame_sync.solid(&led_state, Some(color)), Some(Some(color)));
+
+        led_state[1].color.value = 1.0;
+        assert_eq!(frame_sync.solid(&led_state, Some(color)), None);
+    }
+
+    #[test]
+    fn force_refresh_sends_every_led() {
+        let mut frame_sync = FrameSync::new(0.0, None);
+        let led_state = leds(30, 0.5);
+
+        frame_sync.force_refresh();
+        let colors = frame_sync.frame(&led_state, &led_state);
+        assert_eq!(colors.len(), 30);
+        assert!((0..30).all(|idx| colors.contains_key(&idx)));
+
+        // only once
+        assert!(frame_sync.frame(&led_state, &led_state).is_empty());
+    }
+
+    #[test]
+    fn periodic_resync() {
+        let mut frame_sync = FrameSync::new(0.01, None);
+        let led_state = leds(3, 0.5);
+
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(frame_sync.frame(&led_state, &led_state).len(), 3);
+        assert!(frame_sync.frame(&led_state, &led_state).is_empty());
+    }
+
+    #[test]
+    fn timing_summary_catches_stalls() {
+        let mut stats = TimingStats::default();
+        assert_eq!(stats.summary(), TimingSummary::default());
+
+        for _ in 0..197 {
+            stats.record(0.05);
+        }
+        for _ in 0..3 {
+            stats.record(0.5);
+        }
+
+        let summary = stats.summary();
+        assert_eq!(summary.min, 0.05);
+        assert_eq!(summary.max, 0.5);
+        // more than 1% of frames stalled, so the 99th percentile is a stall
+        assert_eq!(summary.p99, 0.5);
+        assert!((summary.mean - (197.0 * 0.05 + 1.5) / 200.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn typing_summary_counts_presses() {
+        let mut stats = TypingStats::default();
+        let start = Instant::now();
+
+        // 50 presses in 10 seconds is 60 wpm
+        for idx in 0..50 {
+            let pos = if idx < 30 { (0, 1) } else { (1, 0) };
+            stats.press(pos, start + Duration::from_millis(idx * 100));
+        }
+        stats.press((0, 0), start + Duration::from_secs(9));
+
+        let summary = stats.summary(start + Duration::from_secs(9));
+        assert_eq!(summary.presses, 51);
+        assert!((summary.wpm - 61.2).abs() < 1e-3);
+        assert_eq!(
+            summary.top_keys,
+            vec![((0, 1), 30), ((1, 0), 20), ((0, 0), 1)]
+        );
+
+        // the burst falls out of the window, the peak stays
+        let later = stats.summary(start + Duration::from_secs(15));
+        assert!((later.wpm - 1.2).abs() < 1e-3);
+        assert_eq!(later.peak_wpm, summary.wpm);
+        assert_eq!(later.presses, 51);
+        assert_eq!(later.counts.values().sum::<u64>(), 51);
+    }
+
+    #[test]
+    fn connection_status_from_failures() {
+        let err = "read failed: gone".to_string();
+        let status = |connected, failing, last_error| {
+            ConnectionStatus::new(connected, true, failing, last_error)
+        };
+
+        assert_eq!(
+            ConnectionStatus::new(false, false, false, None),
+            ConnectionStatus::Waiting
+        );
+        assert_eq!(
+            status(false, true, Some(&err)),
+            ConnectionStatus::Reconnecting
+        );
+        assert_eq!(status(true, false, None), ConnectionStatus::Connected);
+        // an error from before the reads recovered is only history
+        assert_eq!(status(true, false, Some(&err)), ConnectionStatus::Connected);
+        assert_eq!(
+            status(true, true, Some(&err)),
+            ConnectionStatus::Error(err.clone())
+        );
+    }
+
+    #[test]
+    fn backoff_reconnects_and_resets() {
+        let failed = SendStats {
+            writes: 1,
+            failures: 1,
+            ..Default::default()
+        };
+        let ok = SendStats {
+            writes: 1,
+            ..Default::default()
+        };
+
+        let mut backoff = SendBackoff::default();
+        assert!(backoff.ready());
+
+        for _ in 1..RECONNECT_FAILURES {
+            assert!(!backoff.record(&failed, 0.05));
+        }
+        assert!(!backoff.ready());
+        assert!(backoff.record(&failed, 0.05));
+
+        assert!(!backoff.record(&ok, 0.05));
+        assert!(backoff.ready());
+        assert!(!backoff.record(&failed, 0.05));
+    }
+
+    #[test]
+    fn readback_completes_out_of_order() {
+        let mut readback = RgbReadback::new(10);
+        let chunk = |start, count| RgbStateMessage {
+            start,
+            colors: vec![[255, 255, 255]; count],
+        };
+
+        readback.add(&chunk(8, 2), 1.0, ColorFormat::Rgb);
+        assert!(readback.complete().is_none());
+        readback.add(&chunk(0, 8), 1.0, ColorFormat::Rgb);
+
+        let colors = readback.complete().unwrap();
+        assert_eq!(colors.len(), 10);
+        assert!(colors.iter().all(|color| (color.value - 1.0).abs() < 1e-5));
+
+        // a chunk running past the end is cut off rather than growing the board
+        readback.add(&chunk(9, 5), 1.0, ColorFormat::Rgb);
+        assert_eq!(readback.complete().unwrap().len(), 10);
+    }
+
+    #[test]
+    fn drives_a_mock_keyboard() {
+        let kb_config = Arc::new(crate::config::tests::kb_config_2x2());
+        let config = &kb_config.host_config;
+        let device = MockDevice::new(config.framing(), config.protocol_version);
+        let mut thread = HIDThread::new(kb_config.clone());
+        let rx = thread.rx();
+        thread.start(240.0, 60.0, Box::new(MockSource::new(device.clone())));
+
+        let wait_for = |check: &dyn Fn(&HIDThreadState) -> bool| {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while Instant::now() < deadline {
+                if rx
+                    .recv_timeout(Duration::from_millis(100))
+                    .is_ok_and(|state| check(&state))
+                {
+                    return true;
+                }
+            }
+            false
+        };
+        // until every opcode has been written since the last call
+        let wait_sent = |opcodes: &[u8]| {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            let mut sent = Vec::new();
+            while Instant::now() < deadline {
+                sent.extend(device.written().iter().map(|report| report[4] >> 4));
+                if opcodes.iter().all(|op| sent.contains(op)) {
+                    return true;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            false
+        };
+        let queried_and_lit = [protocol::KSK_GET_LAYER, protocol::KSK_RGB_SET];
+
+        // a position the layout doesn't have is ignored rather than taking the thread down
+        device.press(7, 7, 0x04, true);
+        device.press(0, 1, 0x05, true);
+        assert!(wait_for(
+            &|state| state.connected && state.matrix[0][1].is_pressed
+        ));
+        assert!(wait_sent(&queried_and_lit));
+
+        device.set_plugged(false);
+        assert!(wait_for(&|state| !state.connected));
+
+        // asked again on reconnecting, and the leds sent again
+        device.set_plugged(true);
+        assert!(wait_for(&|state| state.connected));
+        assert!(wait_sent(&queried_and_lit));
+
+        thread.stop();
+    }
+
+    #[test]
+    fn params_set_during_a_flash_reach_the_configs() {
+        let kb_config = Arc::new(crate::config::tests::kb_config_2x2());
+        let config = &kb_config.host_config;
+        let device = MockDevice::new(config.framing(), config.protocol_version);
+        let mut thread = HIDThread::new(kb_config.clone());
+        let rx = thread.rx();
+        let tx = thread.tx();
+        thread.start(240.0, 60.0, Box::new(MockSource::new(device.clone())));
+
+        tx.send(HIDCommand::Flash {
+            color: Hsva::new(0.0, 0.0, 1.0, 1.0),
+            duration: Duration::from_secs(10),
+        })
+        .unwrap();
+        tx.send(HIDCommand::SetParam {
+            effect: 0,
+            name: "speed".to_string(),
+            value: 5.0,
+        })
+        .unwrap();
+        // rebuilt from the configs, so the speed is only still there if it was written back
+        tx.send(HIDCommand::ResetEffects).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut kept = false;
+        while !kept && Instant::now() < deadline {
+            kept = rx
+                .recv_timeout(Duration::from_millis(100))
+                .is_ok_and(|state| match &state.effect_params[..] {
+                    [("rainbow1", params)] => {
+                        params.iter().any(|p| p.name == "speed" && p.value == 5.0)
+                    }
+                    _ => false,
+                });
+        }
+        thread.stop();
+        assert!(kept);
+    }
+
+    #[test]
+    fn unbuildable_effects_drop_their_configs() {
+        let kb_config = crate::config::tests::kb_config_2x2();
+        let led_state = led_state(&kb_config);
+        let mut configs: Vec<EffectConfig> =
+            serde_json::from_str(r#"[{"name": "rainbow1"}, {"name": "nope"}, {"name": "wave"}]"#)
+                .unwrap();
+
+        let effects = HIDThread::build_effects(&mut configs, &led_state);
+        let names: Vec<_> = effects.iter().map(|layer| layer.effect.name()).collect();
+        assert_eq!(names, ["rainbow1", "wave"]);
+        assert_eq!(configs.len(), 2);
+        assert_eq!(configs[1].name, "wave");
+    }
+
+    #[test]
+    fn boards_without_leds_get_no_colors() {
+        let kb_config = Arc::new(crate::config::tests::kb_config_without_leds());
+        let config = &kb_config.host_config;
+        let device = MockDevice::new(config.framing(), config.protocol_version);
+        let mut thread = HIDThread::new(kb_config.clone());
+        let rx = thread.rx();
+        thread.start(240.0, 60.0, Box::new(MockSource::new(device.clone())));
+
+        device.press(0, 1, 0x05, true);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut pressed = false;
+        while !pressed && Instant::now() < deadline {
+            pressed = rx
+                .recv_timeout(Duration::from_millis(100))
+                .is_ok_and(|state| state.connected && state.matrix[0][1].is_pressed);
+        }
+        assert!(pressed);
+        // a few more frames' worth
+        thread::sleep(Duration::from_millis(100));
+        thread.stop();
+
+        let rgb = [
+            protocol::KSK_RGB_SET,
+            protocol::KSK_RGB_SET_WIDE,
+            protocol::KSK_RGB_STREAM,
+            protocol::KSK_RGB_STATE,
+        ];
+        let written = device.written();
+        assert!(written
+            .iter()
+            .any(|report| report[4] >> 4 == protocol::KSK_GET_LAYER));
+        assert!(!written.iter().any(|report| rgb.contains(&(report[4] >> 4))));
+    }
+}
@@ -0,0 +1,451 @@
+use crate::{
+    config::KBConfig,
+    device::{DeviceEvent, DeviceSource},
+    protocol::{
+        crc8, Framing, ACK_VERSION, K, KSK_ACK, KSK_BRIGHTNESS, KSK_GET_LAYER, KSK_LAYER,
+        KSK_PRESS, KSK_VERSION, S,
+    },
+};
+use crossbeam::channel::{bounded, Receiver, RecvTimeoutError, Select, TryRecvError};
+use hidapi::{HidDevice, HidError, HidResult};
+use log::{debug, info, trace, warn};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+// the two calls the thread makes on an open keyboard, so it can drive a mock one just the same
+pub trait HidTransport {
+    fn write(&self, data: &[u8]) -> HidResult<usize>;
+    fn read_timeout(&self, buf: &mut [u8], timeout: i32) -> HidResult<usize>;
+}
+
+impl HidTransport for HidDevice {
+    fn write(&self, data: &[u8]) -> HidResult<usize> {
+        HidDevice::write(self, data)
+    }
+
+    fn read_timeout(&self, buf: &mut [u8], timeout: i32) -> HidResult<usize> {
+        HidDevice::read_timeout(self, buf, timeout)
+    }
+}
+
+// so the thread's boxed device goes wherever a transport does
+impl<T: HidTransport + ?Sized> HidTransport for Box<T> {
+    fn write(&self, data: &[u8]) -> HidResult<usize> {
+        (**self).write(data)
+    }
+
+    fn read_timeout(&self, buf: &mut [u8], timeout: i32) -> HidResult<usize> {
+        (**self).read_timeout(buf, timeout)
+    }
+}
+
+// how often the reader thread looks for reports, and how long it waits after a failed read
+const READ_POLL: Duration = Duration::from_millis(1);
+const READ_ERROR_PAUSE: Duration = Duration::from_millis(10);
+// reports read but not handled yet, past this many the newest are dropped
+const READ_BACKLOG: usize = 1024;
+
+// an open keyboard read from a thread of its own, so a slow write never holds up input and
+// input never holds up a frame. the reader only takes the device for reads that don't wait,
+// and what it reads is handed over in order through `read_timeout`
+pub struct SplitDevice {
+    device: Arc<Mutex<Box<dyn HidTransport + Send>>>,
+    reports: Receiver<HidResult<Vec<u8>>>,
+    stop: Arc<AtomicBool>,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl SplitDevice {
+    pub fn new(device: Box<dyn HidTransport + Send>, epsize: usize) -> SplitDevice {
+        let device = Arc::new(Mutex::new(device));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (tx, reports) = bounded(READ_BACKLOG);
+
+        let (reader_device, reader_stop) = (device.clone(), stop.clone());
+        let reader = thread::spawn(move || {
+            let mut buf = vec![0u8; epsize];
+            // only the first failure of a run is logged, they come every READ_ERROR_PAUSE
+            let mut failing = false;
+            while !reader_stop.load(Ordering::Relaxed) {
+                let result = reader_device.lock().unwrap().read_timeout(&mut buf, 0);
+                let pause = match &result {
+                    Ok(0) => READ_POLL,
+                    Ok(size) => {
+                        trace!("read {} byte report", size);
+                        Duration::ZERO
+                    }
+                    Err(err) => {
+                        if !failing {
+                            warn!("reads from the keyboard started failing: {}", err);
+                        }
+                        READ_ERROR_PAUSE
+                    }
+                };
+                if failing && result.is_ok() {
+                    info!("reads from the keyboard work again");
+                }
+                failing = result.is_err();
+
+                if !matches!(result, Ok(0))
+                    && tx
+                        .try_send(result.map(|size| buf[..size].to_vec()))
+                        .is_err()
+                {
+                    debug!("input backed up, dropping a report");
+                }
+                thread::sleep(pause);
+            }
+        });
+
+        SplitDevice {
+            device,
+            reports,
+            stop,
+            reader: Some(reader),
+        }
+    }
+
+    // returns as soon as a report (or a failed read) is waiting, or after `timeout`
+    pub fn wait_for_input(&self, timeout: Duration) {
+        let mut select = Select::new();
+        select.recv(&self.reports);
+        select.ready_timeout(timeout).ok();
+    }
+}
+
+impl HidTransport for SplitDevice {
+    fn write(&self, data: &[u8]) -> HidResult<usize> {
+        let result = self.device.lock().unwrap().write(data);
+        if let Err(err) = &result {
+            debug!("{} byte write failed: {}", data.len(), err);
+        }
+        result
+    }
+
+    // what the reader thread read, waiting like hidapi: not at all for 0, forever when negative
+    fn read_timeout(&self, buf: &mut [u8], timeout: i32) -> HidResult<usize> {
+        let report = match timeout {
+            0 => self
+                .reports
+                .try_recv()
+                .map_err(|err| err == TryRecvError::Disconnected),
+            timeout if timeout < 0 => self.reports.recv().map_err(|_| true),
+            timeout => self
+                .reports
+                .recv_timeout(Duration::from_millis(timeout as u64))
+                .map_err(|err| err == RecvTimeoutError::Disconnected),
+        };
+
+        match report {
+            Ok(report) => {
+                let report = report?;
+                let size = report.len().min(buf.len());
+                buf[..size].copy_from_slice(&report[..size]);
+                Ok(size)
+            }
+            Err(false) => Ok(0),
+            Err(true) => Err(HidError::HidApiError {
+                message: "reader thread stopped".to_string(),
+            }),
+        }
+    }
+}
+
+impl Drop for SplitDevice {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(reader) = self.reader.take() {
+            reader.join().ok();
+        }
+    }
+}
+
+// writes kept before the oldest are dropped, so a long running mock doesn't grow forever
+const MAX_WRITTEN: usize = 4096;
+
+struct MockState {
+    // reports waiting to be read, oldest first
+    incoming: VecDeque<Vec<u8>>,
+    // every write as hidapi got it, report id first
+    written: VecDeque<Vec<u8>>,
+    layer_state: u32,
+    plugged: bool,
+}
+
+// a keyboard that isn't there: reads come from reports pushed onto it, writes are recorded. it
+// answers layer and version queries and acks like firmware speaking `version` would. clones
+// share the same device, so a test can keep one while the thread drives another
+#[derive(Clone)]
+pub struct MockDevice {
+    framing: Framing,
+    version: u8,
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockDevice {
+    pub fn new(framing: Framing, version: u8) -> MockDevice {
+        MockDevice {
+            framing,
+            version,
+            state: Arc::new(Mutex::new(MockState {
+                incoming: VecDeque::new(),
+                written: VecDeque::new(),
+                layer_state: 1,
+                plugged: true,
+            })),
+        }
+    }
+
+    // a report as the firmware frames it, without the report id reads don't have
+    fn report(&self, header: u8, payload: &[u8]) -> Vec<u8> {
+        let mut report = vec![K, S, K, header];
+        report.extend_from_slice(payload);
+        report.resize(self.framing.epsize, 0);
+        if self.framing.checksum {
+            let end = self.framing.report_size();
+            report[end] = crc8(&report[..end]);
+        }
+        report
+    }
+
+    pub fn push_report(&self, report: Vec<u8>) {
+        self.state.lock().unwrap().incoming.push_back(report);
+    }
+
+    pub fn press(&self, row: u8, col: u8, keycode: u16, pressed: bool) {
+        let [low, high] = keycode.to_le_bytes();
+        self.push_report(self.report(KSK_PRESS << 4 | pressed as u8, &[low, high, col, row, 0]));
+    }
+
+    // the new layer state is also what later layer queries are answered with
+    pub fn layer(&self, layer_state: u32) {
+        self.state.lock().unwrap().layer_state = layer_state;
+        self.push_report(self.report(KSK_LAYER << 4, &layer_state.to_le_bytes()));
+    }
+
+    // everything written since the last call, oldest first
+    pub fn written(&self) -> Vec<Vec<u8>> {
+        self.state.lock().unwrap().written.drain(..).collect()
+    }
+
+    // unplugged, reads and writes fail and the source reports it gone
+    pub fn set_plugged(&self, plugged: bool) {
+        self.state.lock().unwrap().plugged = plugged;
+    }
+
+    pub fn is_plugged(&self) -> bool {
+        self.state.lock().unwrap().plugged
+    }
+
+    fn unplugged() -> HidError {
+        HidError::HidApiError {
+            message: "mock device unplugged".to_string(),
+        }
+    }
+}
+
+impl HidTransport for MockDevice {
+    fn write(&self, data: &[u8]) -> HidResult<usize> {
+        let mut state = self.state.lock().unwrap();
+        if !state.plugged {
+            return Err(Self::unplugged());
+        }
+
+        state.written.push_back(data.to_vec());
+        if state.written.len() > MAX_WRITTEN {
+            state.written.pop_front();
+        }
+
+        // after the report id and the magic
+        let op = data.get(4).map_or(u8::MAX, |header| header >> 4);
+        let answer = match op {
+            KSK_GET_LAYER => Some(self.report(KSK_LAYER << 4, &state.layer_state.to_le_bytes())),
+            KSK_VERSION => Some(self.report(KSK_VERSION << 4, &[self.version, 0, 0])),
+            KSK_BRIGHTNESS if self.version >= ACK_VERSION => {
+                Some(self.report(KSK_ACK << 4 | KSK_BRIGHTNESS, &[]))
+            }
+            _ => None,
+        };
+        state.incoming.extend(answer);
+
+        Ok(data.len())
+    }
+
+    // never waits, there's nothing to wait for
+    fn read_timeout(&self, buf: &mut [u8], _timeout: i32) -> HidResult<usize> {
+        let mut state = self.state.lock().unwrap();
+        if !state.plugged {
+            return Err(Self::unplugged());
+        }
+
+        match state.incoming.pop_front() {
+            Some(report) => {
+                let size = report.len().min(buf.len());
+                buf[..size].copy_from_slice(&report[..size]);
+                Ok(size)
+            }
+            None => Ok(0),
+        }
+    }
+}
+
+// hands the thread a mock device, connected whenever it's plugged in
+pub struct MockSource {
+    device: MockDevice,
+}
+
+impl MockSource {
+    pub fn new(device: MockDevice) -> MockSource {
+        MockSource { device }
+    }
+}
+
+impl DeviceSource for MockSource {
+    fn open(&self, _kb_config: &KBConfig) -> Option<Box<dyn HidTransport + Send>> {
+        self.device
+            .is_plugged()
+            .then(|| Box::new(self.device.clone()) as Box<dyn HidTransport + Send>)
+    }
+
+    fn poll(&mut self, kb_config: &KBConfig, connected: bool) -> Option<DeviceEvent> {
+        match (self.device.is_plugged(), connected) {
+            (true, false) => self.open(kb_config).map(DeviceEvent::Connected),
+            (false, true) => Some(DeviceEvent::Disconnected),
+            _ => None,
+        }
+    }
+}
+
+// a mock keyboard for --mock that types on its own: random keys tapped a few times a second,
+// the second layer held every few seconds, and unplugged for a moment every minute or so to show
+// the reconnecting
+pub fn spawn_demo(kb_config: &KBConfig) -> MockSource {
+    let framing = kb_config.host_config.framing();
+    let device = MockDevice::new(framing, kb_config.host_config.protocol_version);
+    let keys: Vec<(u8, u8)> = kb_config
+        .layout()
+        .layout
+        .iter()
+        .map(|key| key.matrix)
+        .collect();
+    info!("mock keyboard with {} keys", keys.len());
+
+    let demo = device.clone();
+    thread::spawn(move || {
+        let mut rng = SmallRng::seed_from_u64(0);
+        for tick in 0u32.. {
+            if tick % 40 == 0 {
+                demo.layer(if tick % 80 == 0 { 0b11 } else { 0b1 });
+                debug!("mock keyboard got {} reports", demo.written().len());
+            }
+            if tick % 400 == 399 {
+                info!("unplugging the mock keyboard");
+                demo.set_plugged(false);
+                thread::sleep(Duration::from_secs(2));
+                demo.set_plugged(true);
+            }
+
+            if let Some(&(row, col)) = keys.get(rng.gen_range(0..keys.len().max(1))) {
+                demo.press(row, col, 0, true);
+                thread::sleep(Duration::from_millis(60));
+                demo.press(row, col, 0, false);
+            }
+            thread::sleep(Duration::from_millis(rng.gen_range(60..240)));
+        }
+    });
+
+    MockSource::new(device)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{ProtocolMessage, ProtocolReader};
+
+    fn read(device: &MockDevice, framing: Framing) -> Option<ProtocolMessage> {
+        let mut buf = vec![0; framing.epsize];
+        let size = device.read_timeout(&mut buf, 0).unwrap();
+        ProtocolReader::new(framing).read(&buf, size)
+    }
+
+    #[test]
+    fn reads_scripted_reports() {
+        for checksum in [false, true] {
+            let framing = Framing {
+                checksum,
+                ..Default::default()
+            };
+            let device = MockDevice::new(framing, 2);
+
+            device.press(1, 0, 0x04, true);
+            device.layer(0b101);
+            match read(&device, framing) {
+                Some(ProtocolMessage::Press(press)) => {
+                    assert_eq!((press.row, press.col, press.keycode), (1, 0, 0x04));
+                    assert!(press.pressed);
+                }
+                other => panic!("expected a press, got {:?}", other),
+            }
+            match read(&device, framing) {
+                Some(ProtocolMessage::Layer(layer)) => assert_eq!(layer.layer_state, 0b101),
+                other => panic!("expected a layer, got {:?}", other),
+            }
+            assert!(read(&device, framing).is_none());
+
+            // answers queries with what it was told last
+            ProtocolMessage::GetLayer.send(&device, framing).unwrap();
+            assert!(matches!(
+                read(&device, framing),
+                Some(ProtocolMessage::Layer(layer)) if layer.layer_state == 0b101
+            ));
+            assert_eq!(device.written().len(), 1);
+
+            device.set_plugged(false);
+            assert!(ProtocolMessage::GetLayer.send(&device, framing).is_err());
+            assert!(device.read_timeout(&mut [0; 32], 0).is_err());
+        }
+    }
+
+    #[test]
+    fn reads_on_its_own_thread() {
+        let framing = Framing::default();
+        let mock = MockDevice::new(framing, 2);
+        let device = SplitDevice::new(Box::new(mock.clone()), framing.epsize);
+        let mut buf = vec![0; framing.epsize];
+        assert_eq!(device.read_timeout(&mut buf, 0).unwrap(), 0);
+
+        // input wakes a wait well before it runs out
+        let start = std::time::Instant::now();
+        mock.press(0, 1, 0x05, true);
+        device.wait_for_input(Duration::from_secs(5));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        let size = device.read_timeout(&mut buf, 0).unwrap();
+        assert!(matches!(
+            ProtocolReader::new(framing).read(&buf, size),
+            Some(ProtocolMessage::Press(press)) if press.col == 1
+        ));
+
+        // answers come back through the reader too, in order
+        mock.layer(0b11);
+        ProtocolMessage::GetLayer.send(&device, framing).unwrap();
+        assert_eq!(mock.written().len(), 1);
+        for _ in 0..2 {
+            let size = device.read_timeout(&mut buf, 1000).unwrap();
+            assert!(matches!(
+                ProtocolReader::new(framing).read(&buf, size),
+                Some(ProtocolMessage::Layer(layer)) if layer.layer_state == 0b11
+            ));
+        }
+
+        mock.set_plugged(false);
+        assert!(device.read_timeout(&mut buf, 1000).is_err());
+    }
+}
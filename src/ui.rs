@@ -1,142 +1,1747 @@
 use crate::{
-    config::{KBConfig, KeyUsage},
-    threading::HIDThreadState,
+    config::{self, EffectConfig, HostSettings, KBConfig, KeyDef, KeyUsage},
+    effects,
+    export::{self, KeyShape},
+    keycode,
+    paths::ConfigSource,
+    presets::{self, KeyColors},
+    profile::{self, Profile},
+    reload::ReloadResult,
+    theme::KeyColorTheme,
+    threading::{ConnectionStatus, HIDCommand, HIDThreadState, TimingSummary},
 };
-use crossbeam::channel::Receiver;
-use eframe::epaint::{RectShape, TextShape};
+use crossbeam::channel::{Receiver, Sender};
+use eframe::epaint::{RectShape, Shape, TextShape};
 use egui::{
-    color::Hsva, text::LayoutJob, Color32, FontFamily, FontId, Painter, Pos2, Rect, Rounding,
-    Stroke, Ui, Vec2,
+    color::Hsva,
+    plot::{Legend, Line, Plot, PlotPoints},
+    text::LayoutJob,
+    Align2, Color32, FontFamily, FontId, Painter, Pos2, Rect, Rounding, Sense, Stroke, Ui, Vec2,
+};
+use log::{info, warn};
+use palette::IntoColor;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashSet, VecDeque},
+    fs,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
 };
-use std::sync::Arc;
 
-pub struct App {
+// console lines kept before the oldest are dropped
+const CONSOLE_LINES: usize = 1000;
+
+// seconds of deltas in the information window's timing graph
+const TIMING_GRAPH_SECS: f64 = 10.0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum WindowKind {
+    Information,
+    Keyboard,
+    Paint,
+    Appearance,
+    Console,
+    Effects,
+    Statistics,
+    Settings,
+    Matrix,
+}
+
+impl WindowKind {
+    fn title(self) -> &'static str {
+        match self {
+            WindowKind::Information => "Information",
+            WindowKind::Keyboard => "Keyboard",
+            WindowKind::Paint => "Paint",
+            WindowKind::Appearance => "Appearance",
+            WindowKind::Console => "Console",
+            WindowKind::Effects => "Effects",
+            WindowKind::Statistics => "Statistics",
+            WindowKind::Settings => "Settings",
+            WindowKind::Matrix => "Matrix",
+        }
+    }
+
+    // shown once, the rest are shown for every keyboard
+    fn shared(self) -> bool {
+        self == WindowKind::Appearance
+    }
+}
+
+// every window and its default position, in view menu order
+const WINDOWS: &[(WindowKind, [f32; 2])] = &[
+    (WindowKind::Information, [10.0, 40.0]),
+    (WindowKind::Keyboard, [10.0, 330.0]),
+    (WindowKind::Paint, [420.0, 40.0]),
+    (WindowKind::Appearance, [420.0, 330.0]),
+    (WindowKind::Console, [820.0, 40.0]),
+    (WindowKind::Effects, [820.0, 330.0]),
+    (WindowKind::Statistics, [1220.0, 40.0]),
+    (WindowKind::Settings, [1220.0, 330.0]),
+    (WindowKind::Matrix, [1220.0, 620.0]),
+];
+
+// how far every keyboard's windows start out from the keyboard before's
+const BOARD_OFFSET: [f32; 2] = [30.0, 30.0];
+
+// points per key unit the keyboard can be drawn at
+const SCALE_RANGE: RangeInclusive<f32> = 15.0..=150.0;
+
+// the keyboard window's layer choice for showing the active layers
+const AUTO_LAYER: &str = "auto (follow keyboard)";
+
+// longest parameter summary shown next to an effect
+const PARAMS_SUMMARY_LEN: usize = 40;
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+struct WindowState {
+    open: bool,
+    pos: Option<[f32; 2]>,
+}
+
+impl Default for WindowState {
+    fn default() -> Self {
+        WindowState {
+            open: true,
+            pos: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum PaletteMode {
+    // key_colors from the config
+    Config,
+    // the built-in colors of the ui theme
+    Theme,
+    ColorBlind,
+    // a KeyColorTheme json file, see UiState::theme_file
+    File,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+enum UiTheme {
+    #[default]
+    Dark,
+    Light,
+}
+
+impl UiTheme {
+    fn visuals(self) -> egui::Visuals {
+        match self {
+            UiTheme::Dark => egui::Visuals::dark(),
+            UiTheme::Light => egui::Visuals::light(),
+        }
+    }
+}
+
+// the key colors shared by every keyboard
+struct Palettes {
+    dark: KeyColorTheme,
+    light: KeyColorTheme,
+    color_blind: KeyColorTheme,
+    // the last theme file loaded, or why it couldn't be
+    file: Option<Result<KeyColorTheme, String>>,
+}
+
+impl Palettes {
+    fn new(ui_state: &UiState) -> Palettes {
+        let mut palettes = Palettes {
+            dark: KeyColorTheme::dark(),
+            light: KeyColorTheme::light(),
+            color_blind: KeyColorTheme::color_blind(),
+            file: None,
+        };
+        if ui_state.style.palette == PaletteMode::File {
+            palettes.load_file(&ui_state.theme_file);
+        }
+        palettes
+    }
+
+    fn load_file(&mut self, path: &str) {
+        let result = KeyColorTheme::load(Path::new(path));
+        if let Err(err) = &result {
+            warn!("could not load key colors from {}: {}", path, err);
+        }
+        self.file = Some(result.map_err(|err| err.to_string()));
+    }
+
+    // a theme file that didn't load falls back to the ui theme's colors
+    fn key_colors<'a>(
+        &'a self,
+        style: &RenderStyle,
+        config: &'a KeyColorTheme,
+    ) -> &'a KeyColorTheme {
+        let theme = match style.theme {
+            UiTheme::Dark => &self.dark,
+            UiTheme::Light => &self.light,
+        };
+
+        match style.palette {
+            PaletteMode::Config => config,
+            PaletteMode::Theme => theme,
+            PaletteMode::ColorBlind => &self.color_blind,
+            PaletteMode::File => match &self.file {
+                Some(Ok(file)) => file,
+                _ => theme,
+            },
+        }
+    }
+}
+
+// key geometry, mostly relative to the size of a 1u key
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(default)]
+struct RenderStyle {
+    rounding: f32,
+    border: f32,
+    text_margin: f32,
+    // legend size per key unit, unless overridden with a fixed point size
+    font_scale: f32,
+    font_size: Option<f32>,
+    theme: UiTheme,
+    palette: PaletteMode,
+    // mark every key with its usage category in the corner
+    usage_letters: bool,
+    // points per key unit, unless the board is fit to its window
+    scale: f32,
+    fit: bool,
+    // tint keys by how often they've been pressed, whatever the leds show
+    heatmap: bool,
+}
+
+impl Default for RenderStyle {
+    fn default() -> Self {
+        RenderStyle {
+            rounding: 0.1,
+            border: 0.04,
+            text_margin: 0.1,
+            font_scale: 14.0 / 45.0,
+            font_size: None,
+            theme: UiTheme::Dark,
+            palette: PaletteMode::Config,
+            usage_letters: false,
+            scale: 45.0,
+            fit: true,
+            heatmap: false,
+        }
+    }
+}
+
+impl RenderStyle {
+    fn font_size(&self, scale: f32) -> f32 {
+        self.font_size.unwrap_or(self.font_scale * scale)
+    }
+}
+
+// remembered between runs in eframe's storage
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+struct UiState {
+    windows: BTreeMap<String, WindowState>,
+    style: RenderStyle,
+    // read for the File palette
+    theme_file: String,
+}
+
+const SELECTION_COLOR: Color32 = Color32::from_rgb(255, 210, 0);
+
+// how much of the heat color covers the most pressed key
+const HEAT_OPACITY: f32 = 0.7;
+
+// the key test's colors for keys pressed since it started and ones still waiting
+const TESTED_COLOR: Color32 = Color32::from_rgb(40, 150, 60);
+const UNTESTED_COLOR: Color32 = Color32::from_rgb(170, 40, 40);
+
+// which keys went down since the test started, whatever they're mapped to, for checking every
+// switch of a new board
+struct KeyTest {
+    enabled: bool,
+    since: Instant,
+    seen: HashSet<(u8, u8)>,
+}
+
+impl KeyTest {
+    fn new() -> KeyTest {
+        KeyTest {
+            enabled: false,
+            since: Instant::now(),
+            seen: HashSet::new(),
+        }
+    }
+
+    // every state, a tap can start and end between two the ui shows
+    fn record(&mut self, state: &HIDThreadState) {
+        for (row, cols) in state.matrix.iter().enumerate() {
+            for (col, key) in cols.iter().enumerate() {
+                if key.last_down.is_some_and(|down| down >= self.since) {
+                    self.seen.insert((row as u8, col as u8));
+                }
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.since = Instant::now();
+        self.seen.clear();
+    }
+}
+
+// blue for keys never pressed to red for the most pressed one, as the heatmap effect
+fn heat_color(fill: Color32, heat: f32) -> Color32 {
+    let color: Color32 = Hsva::new(2.0 / 3.0 * (1.0 - heat), 1.0, 1.0, 1.0).into();
+    let amount = HEAT_OPACITY * heat.max(0.1);
+    let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * amount) as u8;
+    let ([r0, g0, b0, _], [r1, g1, b1, _]) = (fill.to_array(), color.to_array());
+    Color32::from_rgb(mix(r0, r1), mix(g0, g1), mix(b0, b1))
+}
+
+// grayed out and faded, for keys drawn while the keyboard is disconnected
+fn muted(color: Color32) -> Color32 {
+    let [r, g, b, a] = color.to_array();
+    let gray = (0.3 * r as f32 + 0.59 * g as f32 + 0.11 * b as f32) as u8;
+    Color32::from_rgba_premultiplied(gray, gray, gray, a).linear_multiply(0.5)
+}
+
+// deltas laid out on their own clock, the sum of the deltas before them, so states the ui
+// handles late or together still graph at the right spacing
+#[derive(Default)]
+struct TimingHistory {
+    updates: VecDeque<(f64, f32)>,
+    frames: VecDeque<(f64, f32)>,
+    update_clock: f64,
+    frame_clock: f64,
+}
+
+impl TimingHistory {
+    fn record(&mut self, state: &HIDThreadState) {
+        let add = |samples: &mut VecDeque<(f64, f32)>, clock: &mut f64, deltas: &[f32]| {
+            for delta in deltas {
+                *clock += *delta as f64;
+                samples.push_back((*clock, *delta));
+            }
+            while samples
+                .front()
+                .is_some_and(|(at, _)| *clock - at > TIMING_GRAPH_SECS)
+            {
+                samples.pop_front();
+            }
+        };
+
+        add(
+            &mut self.updates,
+            &mut self.update_clock,
+            &state.update_deltas,
+        );
+        add(&mut self.frames, &mut self.frame_clock, &state.frame_deltas);
+    }
+
+    // milliseconds against seconds ago, the newest at 0
+    fn line(samples: &VecDeque<(f64, f32)>, clock: f64) -> PlotPoints {
+        samples
+            .iter()
+            .map(|(at, delta)| [at - clock, *delta as f64 * 1000.0])
+            .collect()
+    }
+
+    fn show(&self, ui: &mut Ui) {
+        Plot::new("timing_graph")
+            .height(120.0)
+            .legend(Legend::default())
+            .allow_drag(false)
+            .allow_zoom(false)
+            .allow_scroll(false)
+            .include_x(-TIMING_GRAPH_SECS)
+            .include_y(0.0)
+            .show(ui, |plot| {
+                plot.line(Line::new(Self::line(&self.frames, self.frame_clock)).name("frame ms"));
+                plot.line(
+                    Line::new(Self::line(&self.updates, self.update_clock)).name("update ms"),
+                );
+            });
+    }
+}
+
+#[derive(Default)]
+struct PaintState {
+    enabled: bool,
+    color: Hsva,
+    selection: HashSet<(u8, u8)>,
+    // last clicked key, shift-click selects everything between it and the next
+    anchor: Option<(u8, u8)>,
+    // what has been sent to the thread, so it can be saved as a preset
+    key_colors: KeyColors,
+    // clicking a key opens a picker for its color instead of selecting it
+    pin_on_click: bool,
+    // the key whose picker is open, and where it was clicked
+    pinning: Option<((u8, u8), Pos2)>,
+    preset_name: String,
+    presets: Vec<PathBuf>,
+    // a preset that's already there, until saving over it is confirmed or cancelled
+    overwriting: Option<PathBuf>,
+}
+
+struct EffectLayer {
+    config: EffectConfig,
+    enabled: bool,
+}
+
+impl EffectLayer {
+    // keeps a picked color in the params like the thread does, so sending the list again
+    // doesn't undo it. only colored effects are built, others may start capturing on build
+    fn recolor(&mut self, color: palette::Hsva) {
+        let inner = self
+            .config
+            .params
+            .pointer("/effect/name")
+            .and_then(serde_json::Value::as_str);
+        let colored = |name: &str| effects::COLOR_EFFECTS.contains(&name);
+        if !colored(&self.config.name) && !inner.is_some_and(colored) {
+            return;
+        }
+
+        if let Some(mut effect) = effects::build_effect(&self.config.name, &self.config.params) {
+            for (name, value) in effect.set_color(color) {
+                self.config.set_value(name, value);
+            }
+        }
+    }
+}
+
+// one keyboard's thread and everything shown for it
+pub struct Board {
     rx: Receiver<HIDThreadState>,
+    tx: Sender<HIDCommand>,
+    console_rx: Receiver<String>,
+    reload_rx: Receiver<ReloadResult>,
+    source: ConfigSource,
     kb_config: Arc<KBConfig>,
+    // why the config on disk was last rejected, until a reload goes through
+    reload_error: Option<String>,
     curr_state: HIDThreadState,
+    // the last colors read back from the keyboard, and whether the board is drawn with them
+    // instead of the sent ones
+    device_colors: Option<Vec<palette::Hsva>>,
+    show_device_colors: bool,
+    console: VecDeque<String>,
+    paint: PaintState,
+    effects: Vec<EffectLayer>,
+    // index of the effect whose handle is being dragged
+    dragged_effect: Option<usize>,
+    // effects are frozen on the frame they were showing
+    paused: bool,
+    // legends shown for this layer over the base layer, instead of the keyboard's active ones
+    preview_layer: Option<usize>,
+    // the settings window's edits, until they're saved or reverted
+    settings: HostSettings,
+    save_effects: bool,
+    save_pins: bool,
+    // where Export SVG writes, and whether it includes the live leds and presses
+    export_path: String,
+    export_live: bool,
+    export_result: Option<Result<(), String>>,
+    // how the last save went, until the next one
+    save_result: Option<Result<(), String>>,
+    // for the effects drawn in one color, only sent once picked
+    effect_color: Hsva,
+    flash_color: Hsva,
+    flash_ms: f32,
+    timing: TimingHistory,
+    key_test: KeyTest,
+    // percent of full brightness, only sent once the slider is moved
+    brightness: u8,
 }
 
-const REMOVED_COLOR: Color32 = Color32::from_rgb(20, 20, 20);
-
-// unpressed, pressed, foreground
-fn get_key_colors(usage: &KeyUsage) -> (Color32, Color32, Color32) {
-    match usage {
-        KeyUsage::Removed => (REMOVED_COLOR, REMOVED_COLOR, Color32::TRANSPARENT),
-        KeyUsage::Modtap | KeyUsage::Modifier => (
-            Color32::from_rgb(68, 51, 127),
-            Color32::from_rgb(27, 20, 51),
-            Color32::WHITE,
-        ),
-        KeyUsage::Layertap | KeyUsage::Layer => (
-            Color32::from_rgb(127, 51, 51),
-            Color32::from_rgb(51, 20, 20),
-            Color32::WHITE,
-        ),
-        KeyUsage::Function => (
-            Color32::from_rgb(51, 57, 127),
-            Color32::from_rgb(20, 22, 51),
-            Color32::WHITE,
-        ),
-        KeyUsage::Mouse => (
-            Color32::from_rgb(51, 127, 100),
-            Color32::from_rgb(20, 51, 40),
-            Color32::WHITE,
-        ),
-        KeyUsage::Passthrough => unreachable!(),
-        _ => (
-            Color32::from_rgb(90, 90, 90),
-            Color32::from_rgb(50, 50, 50),
-            Color32::WHITE,
-        ),
-    }
+pub struct App {
+    boards: Vec<Board>,
+    // repaints per second, the same as the frames sent to the keyboards
+    fps: f32,
+    ui_state: UiState,
+    palettes: Palettes,
+    // the theme egui was last set to
+    visuals: Option<UiTheme>,
+    // put every window back at its default position on the next frame
+    reset_layout: bool,
 }
 
-impl App {
-    pub fn new(rx: Receiver<HIDThreadState>, kb_config: Arc<KBConfig>) -> App {
-        App {
+// a window to show, `board` is unused for the shared ones
+struct WindowSpec {
+    title: String,
+    kind: WindowKind,
+    board: usize,
+    default_pos: [f32; 2],
+}
+
+fn to_led_color(color: Hsva) -> palette::Hsva {
+    palette::Hsva::new(color.h * 360.0, color.s, color.v, color.a)
+}
+
+fn from_led_color(color: palette::Hsva) -> Hsva {
+    Hsva::new(
+        color.hue.to_positive_degrees() / 360.0,
+        color.saturation,
+        color.value,
+        color.alpha,
+    )
+}
+
+impl Board {
+    pub fn new(
+        rx: Receiver<HIDThreadState>,
+        tx: Sender<HIDCommand>,
+        console_rx: Receiver<String>,
+        reload_rx: Receiver<ReloadResult>,
+        source: ConfigSource,
+        kb_config: Arc<KBConfig>,
+        saved: Option<&Profile>,
+    ) -> Board {
+        let (effects, pinned_colors) = profile::restored(saved, &kb_config.host_config);
+        Board {
+            effects: Self::layers(effects),
+            settings: HostSettings::new(&kb_config.host_config),
+            export_path: format!("{}.svg", kb_config.host_config.kb.replace('/', "_")),
+            paint: PaintState {
+                color: Hsva::new(0.0, 1.0, 1.0, 1.0),
+                key_colors: presets::from_preset(pinned_colors, &kb_config, "pinned_colors"),
+                presets: presets::list_presets(),
+                ..Default::default()
+            },
             rx,
+            tx,
+            console_rx,
+            reload_rx,
+            source,
             kb_config,
+            reload_error: None,
             curr_state: Default::default(),
+            device_colors: None,
+            show_device_colors: false,
+            console: VecDeque::new(),
+            dragged_effect: None,
+            paused: false,
+            preview_layer: None,
+            save_effects: false,
+            save_pins: false,
+            export_live: true,
+            export_result: None,
+            save_result: None,
+            effect_color: Hsva::new(0.0, 0.0, 1.0, 1.0),
+            flash_color: Hsva::new(0.0, 0.0, 1.0, 1.0),
+            flash_ms: 300.0,
+            timing: TimingHistory::default(),
+            key_test: KeyTest::new(),
+            brightness: saved
+                .and_then(|saved| saved.brightness)
+                .map_or(100, |value| (value as f32 / 2.55).round() as u8),
         }
     }
 
-    fn info_window(&self, ctx: &egui::Context) {
-        egui::Window::new("Information").show(ctx, |ui| {
-            egui::Grid::new("info_grid")
-                .num_columns(2)
-                .spacing([40.0, 4.0])
-                .striped(true)
-                .show(ui, |ui| {
-                    let qmk_info = &self.kb_config.qmk_info;
+    // takes in whatever the thread and the reload watcher sent since the last frame
+    fn poll(&mut self) {
+        // every state, since each has only the deltas since the one before
+        for mut state in self.rx.try_iter() {
+            self.timing.record(&state);
+            self.key_test.record(&state);
+            if let Some(colors) = state.device_colors.take() {
+                self.device_colors = Some(colors);
+            }
+            self.curr_state = state;
+        }
 
-                    ui.label("Keyboard");
-                    ui.label(&qmk_info.keyboard_name);
-                    ui.end_row();
+        if let Some(result) = self.reload_rx.try_iter().last() {
+            self.reload(result);
+        }
 
-                    ui.label("Vendor");
-                    ui.label(&qmk_info.manufacturer);
-                    ui.end_row();
+        self.console.extend(self.console_rx.try_iter());
+        if self.console.len() > CONSOLE_LINES {
+            self.console.drain(..self.console.len() - CONSOLE_LINES);
+        }
+    }
 
-                    ui.label("Vendor ID");
-                    ui.label(format!("{:#06X}", qmk_info.usb.vid));
-                    ui.end_row();
+    fn paint_window(&mut self, ui: &mut Ui) {
+        ui.checkbox(&mut self.paint.enabled, "Select keys")
+            .on_hover_text("click to select, shift-click for a range, drag for a rectangle");
 
-                    ui.label("Product ID");
-                    ui.label(format!("{:#06X}", qmk_info.usb.pid));
-                    ui.end_row();
+        ui.horizontal(|ui| {
+            ui.label("Color");
+            ui.color_edit_button_hsva(&mut self.paint.color);
+        });
 
-                    ui.label("LED count");
-                    ui.label(format!("{}", self.kb_config.led_count()));
-                    ui.end_row();
+        ui.checkbox(&mut self.paint.pin_on_click, "Pin a color on click")
+            .on_hover_text("click a key to pick the color it keeps over the effects");
+
+        ui.label(format!("{} keys selected", self.paint.selection.len()));
+
+        ui.horizontal(|ui| {
+            if ui.button("Apply").clicked() {
+                let color = to_led_color(self.paint.color);
+                let colors: Vec<_> = self
+                    .paint
+                    .selection
+                    .iter()
+                    .map(|pos| (*pos, color))
+                    .collect();
+
+                self.paint.key_colors.extend(colors.iter().copied());
+                self.tx.send(HIDCommand::SetKeyColors(colors)).ok();
+            }
+
+            if ui.button("Clear colors").clicked() {
+                let positions: Vec<_> = self.paint.selection.iter().copied().collect();
+
+                for pos in &positions {
+                    self.paint.key_colors.remove(pos);
+                }
+                self.tx.send(HIDCommand::ClearKeyColors(positions)).ok();
+            }
+
+            if ui.button("Deselect").clicked() {
+                self.paint.selection.clear();
+                self.paint.anchor = None;
+            }
+        });
+
+        ui.separator();
+        self.presets_ui(ui);
+    }
+
+    fn presets_ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.paint.preset_name);
+
+            let name = self.paint.preset_name.trim();
+            let save = ui.add_enabled(
+                !name.is_empty() && self.paint.overwriting.is_none(),
+                egui::Button::new("Save preset"),
+            );
+            if save.clicked() {
+                match presets::preset_path(name) {
+                    Ok(path) if path.exists() => self.paint.overwriting = Some(path),
+                    Ok(path) => self.save_preset(path),
+                    Err(err) => warn!("could not save preset: {}", err),
+                }
+            }
+        });
+
+        if let Some(path) = self.paint.overwriting.clone() {
+            ui.horizontal(|ui| {
+                let name = path.file_stem().unwrap_or_default().to_string_lossy();
+                ui.label(format!("{} already exists, replace it?", name));
+
+                if ui.button("Replace").clicked() {
+                    self.paint.overwriting = None;
+                    self.save_preset(path.clone());
+                }
+                if ui.button("Cancel").clicked() {
+                    self.paint.overwriting = None;
+                }
+            });
+        }
+
+        for path in &self.paint.presets {
+            let name = path.file_stem().unwrap_or_default().to_string_lossy();
+
+            if ui.button(format!("Load {}", name)).clicked() {
+                match presets::load_preset(path, &self.kb_config) {
+                    Ok(colors) => {
+                        self.paint.key_colors = colors.clone();
+                        self.tx.send(HIDCommand::LoadPreset(colors)).ok();
+                    }
+                    Err(err) => warn!("could not load preset {:?}: {}", path, err),
+                }
+            }
+        }
+
+        ui.horizontal(|ui| {
+            if ui.button("Refresh").clicked() {
+                self.paint.presets = presets::list_presets();
+            }
+
+            if ui.button("Back to effects").clicked() {
+                self.paint.key_colors.clear();
+                self.tx.send(HIDCommand::ResetEffects).ok();
+            }
+        });
+    }
+
+    fn save_preset(&mut self, path: PathBuf) {
+        match presets::save_preset(&path, &self.paint.key_colors) {
+            Ok(()) => info!("saved preset {:?}", path),
+            Err(err) => warn!("could not save preset {:?}: {}", path, err),
+        }
+
+        self.paint.presets = presets::list_presets();
+    }
+
+    // keys whose rects intersect `area`
+    fn keys_in(key_rects: &[((u8, u8), Rect)], area: Rect) -> impl Iterator<Item = (u8, u8)> + '_ {
+        key_rects
+            .iter()
+            .filter(move |(_, rect)| rect.intersects(area))
+            .map(|(pos, _)| *pos)
+    }
+
+    fn handle_selection(
+        &mut self,
+        ui: &Ui,
+        response: &egui::Response,
+        key_rects: &[((u8, u8), Rect)],
+    ) {
+        let shift = ui.input().modifiers.shift;
+
+        if response.clicked() {
+            let hit = response.interact_pointer_pos().and_then(|pos| {
+                key_rects
+                    .iter()
+                    .find(|(_, rect)| rect.contains(pos))
+                    .map(|(pos, rect)| (*pos, *rect))
+            });
+
+            match (hit, self.paint.anchor) {
+                (Some((pos, rect)), Some(anchor)) if shift => {
+                    let anchor_rect = key_rects
+                        .iter()
+                        .find(|(p, _)| *p == anchor)
+                        .map(|(_, r)| *r);
+                    let area = anchor_rect.map_or(rect, |anchor_rect| anchor_rect.union(rect));
+                    self.paint.selection.extend(Self::keys_in(key_rects, area));
+                    self.paint.anchor = Some(pos);
+                }
+                (Some((pos, _)), _) => {
+                    if !shift {
+                        self.paint.selection.clear();
+                    }
+                    self.paint.selection.insert(pos);
+                    self.paint.anchor = Some(pos);
+                }
+                (None, _) => {
+                    if !shift {
+                        self.paint.selection.clear();
+                    }
+                    self.paint.anchor = None;
+                }
+            }
+        }
+
+        let origin = ui.input().pointer.press_origin();
+        if let (Some(origin), Some(pos)) = (origin, response.interact_pointer_pos()) {
+            let area = Rect::from_two_pos(origin, pos);
+
+            if response.dragged() {
+                ui.painter().rect(
+                    area,
+                    Rounding::none(),
+                    SELECTION_COLOR.linear_multiply(0.1),
+                    Stroke::new(1.0, SELECTION_COLOR),
+                );
+            }
+
+            if response.drag_released() {
+                if !shift {
+                    self.paint.selection.clear();
+                }
+                self.paint.selection.extend(Self::keys_in(key_rects, area));
+            }
+        }
+    }
+
+    fn config_effects(kb_config: &KBConfig) -> Vec<EffectLayer> {
+        Self::layers(&kb_config.host_config.effects)
+    }
+
+    fn layers(effects: &[EffectConfig]) -> Vec<EffectLayer> {
+        effects
+            .iter()
+            .map(|config| EffectLayer {
+                config: config.clone(),
+                enabled: true,
+            })
+            .collect()
+    }
+
+    fn params_summary(params: &serde_json::Value) -> String {
+        let summary = match params {
+            serde_json::Value::Null => "defaults".to_string(),
+            serde_json::Value::Object(map) => map
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect::<Vec<_>>()
+                .join(", "),
+            other => other.to_string(),
+        };
+
+        if summary.chars().count() > PARAMS_SUMMARY_LEN {
+            let cut: String = summary.chars().take(PARAMS_SUMMARY_LEN - 1).collect();
+            format!("{}…", cut)
+        } else {
+            summary
+        }
+    }
+
+    fn enabled_effects(&self) -> Vec<EffectConfig> {
+        self.effects
+            .iter()
+            .filter(|layer| layer.enabled)
+            .map(|layer| layer.config.clone())
+            .collect()
+    }
+
+    fn send_effects(&self) {
+        self.tx
+            .send(HIDCommand::SetEffects(self.enabled_effects()))
+            .ok();
+    }
+
+    // effects are applied top to bottom, drag the handle to reorder
+    fn effects_window(&mut self, ui: &mut Ui) {
+        let mut changed = false;
+        let mut remove = None;
+        // swaps the effect at this index with the one after it
+        let mut swap = None;
+        let mut row_rects = Vec::with_capacity(self.effects.len());
+        let count = self.effects.len();
+
+        for (idx, layer) in self.effects.iter_mut().enumerate() {
+            let row = ui.horizontal(|ui| {
+                let handle = ui.add(egui::Label::new("☰").sense(Sense::drag()));
+                if handle.drag_started() {
+                    self.dragged_effect = Some(idx);
+                }
+
+                changed |= ui
+                    .checkbox(&mut layer.enabled, &layer.config.name)
+                    .changed();
+                ui.weak(Self::params_summary(&layer.config.params));
+
+                let up = ui.add_enabled(idx > 0, egui::Button::new("⏶").small());
+                if up.on_hover_text("Move up").clicked() {
+                    swap = Some(idx - 1);
+                }
+                let down = ui.add_enabled(idx + 1 < count, egui::Button::new("⏷").small());
+                if down.on_hover_text("Move down").clicked() {
+                    swap = Some(idx);
+                }
+
+                if ui.small_button("🗑").on_hover_text("Remove").clicked() {
+                    remove = Some(idx);
+                }
+            });
+            row_rects.push(row.response.rect);
+        }
+
+        if self.effects.is_empty() {
+            ui.weak("no effects");
+        }
+
+        // on top of the others, with default params. wrappers need params to have anything to
+        // wrap, so they're only added from the config
+        egui::ComboBox::from_id_source("add_effect")
+            .selected_text("Add effect")
+            .show_ui(ui, |ui| {
+                let buildable = effects::effect_names()
+                    .filter(|name| effects::build_effect(name, &serde_json::Value::Null).is_some());
+                for name in buildable {
+                    if ui.selectable_label(false, name).clicked() {
+                        self.effects.push(EffectLayer {
+                            config: EffectConfig {
+                                name: name.to_string(),
+                                params: serde_json::Value::Null,
+                            },
+                            enabled: true,
+                        });
+                        changed = true;
+                    }
+                }
+            });
+
+        if let Some(from) = self.dragged_effect {
+            let pointer = ui.input().pointer.interact_pos();
+            let target = pointer.and_then(|pos| {
+                row_rects
+                    .iter()
+                    .enumerate()
+                    .min_by(|(_, a), (_, b)| {
+                        let a = (a.center().y - pos.y).abs();
+                        let b = (b.center().y - pos.y).abs();
+                        a.total_cmp(&b)
+                    })
+                    .map(|(idx, _)| idx)
+            });
+
+            if let Some(target) = target {
+                let rect = row_rects[target];
+                ui.painter()
+                    .rect_stroke(rect, 2.0, Stroke::new(1.0, SELECTION_COLOR));
+            }
+
+            if !ui.input().pointer.any_down() {
+                self.dragged_effect = None;
+
+                if let Some(to) = target.filter(|to| *to != from) {
+                    let layer = self.effects.remove(from);
+                    self.effects.insert(to, layer);
+                    changed = true;
+                }
+            }
+        }
+
+        if let Some(idx) = swap {
+            self.effects.swap(idx, idx + 1);
+            changed = true;
+        }
+
+        if let Some(idx) = remove {
+            self.effects.remove(idx);
+            changed = true;
+        }
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            // what the thread is running, which a reload or the control socket may have changed
+            let active = &self.curr_state.effects;
+            let selected = match active.as_slice() {
+                [] => "no effect".to_string(),
+                [name] => name.clone(),
+                stack => format!("{} effects", stack.len()),
+            };
+
+            egui::ComboBox::from_id_source("switch_effect")
+                .selected_text(selected)
+                .show_ui(ui, |ui| {
+                    for name in effects::effect_names() {
+                        let current = active.len() == 1 && active[0] == name;
+                        if ui.selectable_label(current, name).clicked() {
+                            self.effects = vec![EffectLayer {
+                                config: EffectConfig {
+                                    name: name.to_string(),
+                                    params: serde_json::Value::Null,
+                                },
+                                enabled: true,
+                            }];
+                            self.tx.send(HIDCommand::SetEffect(name.to_string())).ok();
+                        }
+                    }
+                });
+
+            if ui.button("Reset to config").clicked() {
+                self.effects = Self::config_effects(&self.kb_config);
+                changed = true;
+            }
+
+            if ui.button("Restart").clicked() {
+                self.tx.send(HIDCommand::RestartEffects).ok();
+            }
+
+            let label = if self.paused { "Resume" } else { "Pause" };
+            if ui.button(label).clicked() {
+                self.paused = !self.paused;
+                let command = if self.paused {
+                    HIDCommand::Pause
+                } else {
+                    HIDCommand::Resume
+                };
+                self.tx.send(command).ok();
+            }
+        });
+
+        let colored = self
+            .curr_state
+            .effects
+            .iter()
+            .any(|name| effects::COLOR_EFFECTS.contains(&name.as_str()));
+        if colored {
+            ui.horizontal(|ui| {
+                ui.label("Color");
+                // changes at most once a frame however fast it's dragged
+                if ui.color_edit_button_hsva(&mut self.effect_color).changed() {
+                    let color = to_led_color(self.effect_color);
+                    self.tx.send(HIDCommand::SetColor(color)).ok();
+
+                    for layer in self.effects.iter_mut().filter(|layer| layer.enabled) {
+                        layer.recolor(color);
+                    }
+                }
+            });
+        }
+
+        // tuned live, and kept in the list above so they're saved and applied again with it
+        let effect_params = self.curr_state.effect_params.clone();
+        for (idx, (name, params)) in effect_params.iter().enumerate() {
+            if params.is_empty() {
+                continue;
+            }
+
+            ui.weak(*name);
+            for param in params {
+                let mut value = param.value;
+                let slider = egui::Slider::new(&mut value, param.range.clone()).text(param.name);
+                if ui.add(slider).changed() {
+                    self.tx
+                        .send(HIDCommand::SetParam {
+                            effect: idx,
+                            name: param.name.to_string(),
+                            value,
+                        })
+                        .ok();
+
+                    let mut running = self.effects.iter_mut().filter(|layer| layer.enabled);
+                    if let Some(layer) = running.nth(idx) {
+                        layer.config.set_param(param.name, value);
+                    }
+                }
+            }
+        }
+
+        ui.horizontal(|ui| {
+            ui.color_edit_button_hsva(&mut self.flash_color);
+            ui.add(
+                egui::DragValue::new(&mut self.flash_ms)
+                    .clamp_range(10.0..=5000.0)
+                    .suffix(" ms"),
+            );
+
+            if ui.button("Flash").clicked() {
+                self.tx
+                    .send(HIDCommand::Flash {
+                        color: to_led_color(self.flash_color),
+                        duration: Duration::from_secs_f32(self.flash_ms / 1000.0),
+                    })
+                    .ok();
+            }
+
+            // for effects that keep time, click along with the music
+            if ui.button("Tap tempo").clicked() {
+                self.tx.send(HIDCommand::TapTempo).ok();
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Brightness");
+            if ui
+                .add(egui::Slider::new(&mut self.brightness, 0..=100).suffix("%"))
+                .changed()
+            {
+                let value = (self.brightness as f32 * 2.55).round() as u8;
+                self.tx.send(HIDCommand::SetBrightness(value)).ok();
+            }
+        });
+
+        if changed {
+            self.send_effects();
+        }
+    }
+
+    // a key's base layer legend, or where it is for keys without one
+    fn key_name(&self, (row, col): (u8, u8)) -> String {
+        // counts outlive a reload to a smaller matrix
+        let in_matrix = row < self.kb_config.rows() && col < self.kb_config.columns();
+        in_matrix
+            .then(|| self.kb_config.legends.get_key(0, row, col))
+            .flatten()
+            .and_then(|def| def.label.clone())
+            .unwrap_or_else(|| format!("{},{}", row, col))
+    }
+
+    fn statistics_window(&mut self, ui: &mut Ui) {
+        let typing = &self.curr_state.typing;
+
+        egui::Grid::new("typing_grid")
+            .num_columns(2)
+            .spacing([40.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("Presses");
+                ui.label(typing.presses.to_string());
+                ui.end_row();
+
+                ui.label("WPM");
+                ui.label(format!("{:.0}", typing.wpm));
+                ui.end_row();
+
+                ui.label("Peak WPM");
+                ui.label(format!("{:.0}", typing.peak_wpm));
+                ui.end_row();
+            });
 
-                    ui.label("HID delta update");
-                    ui.label(format!("{:.2}", self.curr_state.delta_update * 1000.0));
+        ui.separator();
+        ui.label("Most pressed");
+
+        if typing.top_keys.is_empty() {
+            ui.weak("nothing pressed yet");
+        }
+
+        egui::Grid::new("top_keys_grid")
+            .num_columns(2)
+            .spacing([40.0, 4.0])
+            .show(ui, |ui| {
+                for (pos, count) in &typing.top_keys {
+                    ui.label(self.key_name(*pos));
+                    ui.label(count.to_string());
                     ui.end_row();
+                }
+            });
+    }
+
+    // the electrical matrix as the firmware reports it, whatever the layout makes of it. cells show
+    // their led index, positions the layout doesn't have a key for are only outlined
+    fn matrix_window(&self, ui: &mut Ui) {
+        const CELL: f32 = 32.0;
+        let (rows, columns) = (self.kb_config.rows(), self.kb_config.columns());
+        let in_layout: HashSet<(u8, u8)> = self
+            .kb_config
+            .layout()
+            .layout
+            .iter()
+            .map(|key| key.matrix)
+            .collect();
+
+        let size = Vec2::new(columns as f32, rows as f32) * CELL;
+        let (rect, response) = ui.allocate_exact_size(size, Sense::hover());
+        let painter = ui.painter_at(rect);
+        let visuals = ui.visuals();
+
+        for row in 0..rows {
+            for col in 0..columns {
+                let min = rect.min + Vec2::new(col as f32, row as f32) * CELL;
+                let cell = Rect::from_min_size(min, Vec2::splat(CELL)).shrink(2.0);
+                let pressed = self
+                    .curr_state
+                    .matrix
+                    .get(row as usize)
+                    .and_then(|cols| cols.get(col as usize))
+                    .is_some_and(|state| state.is_pressed);
+
+                let fill = if pressed {
+                    SELECTION_COLOR
+                } else if in_layout.contains(&(row, col)) {
+                    visuals.widgets.inactive.bg_fill
+                } else {
+                    Color32::TRANSPARENT
+                };
+                let text_color = if pressed {
+                    Color32::BLACK
+                } else {
+                    visuals.text_color()
+                };
+                painter.rect(cell, 3.0, fill, visuals.widgets.noninteractive.bg_stroke);
+
+                let led_index = self.kb_config.matrix[row as usize][col as usize];
+                let label = if led_index >= 0 {
+                    led_index.to_string()
+                } else {
+                    "-".to_string()
+                };
+                painter.text(
+                    cell.center(),
+                    Align2::CENTER_CENTER,
+                    label,
+                    FontId::monospace(11.0),
+                    text_color,
+                );
+            }
+        }
+
+        let hovered = response.hover_pos().map(|pos| {
+            let cell = (pos - rect.min) / CELL;
+            (cell.y as u8, cell.x as u8)
+        });
+        if let Some((row, col)) = hovered.filter(|(row, col)| *row < rows && *col < columns) {
+            response.on_hover_text_at_pointer(format!(
+                "({}, {}) {}",
+                row,
+                col,
+                self.key_name((row, col))
+            ));
+        }
 
-                    ui.label("HID delta frame");
-                    ui.label(format!("{:.2}", self.curr_state.delta_frame * 1000.0));
+        ui.label(format!("{} rows x {} columns", rows, columns));
+    }
+
+    // edits the host config file, which is only written if what it'd read as passes the same
+    // checks as the config at startup
+    fn settings_window(&mut self, ui: &mut Ui) {
+        egui::Grid::new("settings_grid")
+            .num_columns(2)
+            .spacing([40.0, 4.0])
+            .show(ui, |ui| {
+                let settings = &mut self.settings;
+                for (label, value) in [
+                    ("Keyboard", &mut settings.kb),
+                    ("Keymap", &mut settings.keymap),
+                    ("Layout", &mut settings.layout),
+                    ("Usage page", &mut settings.usage_page),
+                    ("Usage", &mut settings.usage),
+                ] {
+                    ui.label(label);
+                    ui.text_edit_singleline(value);
                     ui.end_row();
+                }
+            });
+
+        ui.checkbox(
+            &mut self.save_effects,
+            "Save the effects in the Effects window",
+        )
+        .on_hover_text("otherwise the config's effects are kept");
+        ui.checkbox(
+            &mut self.save_pins,
+            "Save the pinned colors from the Paint window",
+        )
+        .on_hover_text("otherwise the config's pinned_colors are kept");
+
+        ui.horizontal(|ui| {
+            if ui.button("Save").clicked() {
+                let settings = HostSettings {
+                    effects: self.save_effects.then(|| self.enabled_effects()),
+                    pinned_colors: self
+                        .save_pins
+                        .then(|| presets::to_preset(&self.paint.key_colors)),
+                    ..self.settings.clone()
+                };
+                let result = self.source.save(&settings);
+                match &result {
+                    Ok(()) => info!("saved config for {}", settings.kb),
+                    Err(err) => warn!("not saving config: {}", err),
+                }
+                self.save_result = Some(result.map_err(|err| err.to_string()));
+            }
+
+            if ui.button("Revert").clicked() {
+                self.settings = HostSettings::new(&self.kb_config.host_config);
+                self.save_result = None;
+            }
+        });
+
+        match &self.save_result {
+            Some(Ok(())) => {
+                ui.colored_label(Color32::GREEN, "saved");
+            }
+            Some(Err(err)) => {
+                ui.colored_label(Color32::RED, format!("not saved: {}", err));
+            }
+            None => {}
+        }
+    }
+
+    fn console_window(&mut self, ui: &mut Ui) {
+        if ui.button("Clear").clicked() {
+            self.console.clear();
+        }
+        ui.separator();
+
+        egui::ScrollArea::vertical()
+            .stick_to_bottom(true)
+            .auto_shrink([false, false])
+            .max_height(300.0)
+            .show(ui, |ui| {
+                for line in &self.console {
+                    ui.monospace(line);
+                }
+            });
+    }
+
+    // leds whose read back color is visibly off from what was last sent
+    fn differing_leds(device: &[palette::Hsva], sent: &[palette::Hsva]) -> usize {
+        device
+            .iter()
+            .zip(sent)
+            .filter(|(device, sent)| {
+                let device: palette::Srgb = device.color.into_color();
+                let sent: palette::Srgb = sent.color.into_color();
+                (device.red - sent.red).abs() > 0.02
+                    || (device.green - sent.green).abs() > 0.02
+                    || (device.blue - sent.blue).abs() > 0.02
+            })
+            .count()
+    }
+
+    // a reread config replaces everything that came from the old one, effects included
+    fn reload(&mut self, result: ReloadResult) {
+        match result {
+            Ok(kb_config) => {
+                self.effects = Self::config_effects(&kb_config);
+                self.settings = HostSettings::new(&kb_config.host_config);
+                self.paint.key_colors = presets::pinned_colors(&kb_config);
+                self.kb_config = kb_config;
+                self.reload_error = None;
+                self.tx
+                    .send(HIDCommand::Reload(self.kb_config.clone()))
+                    .ok();
+            }
+            Err(err) => self.reload_error = Some(err),
+        }
+    }
+
+    // the thread starts over with the config's pinned colors
+    fn switch_config(&mut self, kb_config: KBConfig) {
+        self.paint.key_colors = presets::pinned_colors(&kb_config);
+        self.kb_config = Arc::new(kb_config);
+        self.tx
+            .send(HIDCommand::SwitchConfig(self.kb_config.clone()))
+            .ok();
+    }
+
+    fn info_window(&mut self, ui: &mut Ui) {
+        let mut switch_layout = None;
+        let mut switch_keymap = None;
+
+        if let Some(err) = &self.reload_error {
+            ui.colored_label(Color32::RED, format!("config not reloaded: {}", err));
+        }
+
+        egui::Grid::new("info_grid")
+            .num_columns(2)
+            .spacing([40.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                let qmk_info = &self.kb_config.qmk_info;
+
+                ui.label("Keyboard");
+                ui.label(&qmk_info.keyboard_name);
+                ui.end_row();
+
+                ui.label("Vendor");
+                ui.label(&qmk_info.manufacturer);
+                ui.end_row();
+
+                ui.label("Vendor ID");
+                ui.label(format!("{:#06X}", qmk_info.usb.vid));
+                ui.end_row();
+
+                ui.label("Product ID");
+                ui.label(format!("{:#06X}", qmk_info.usb.pid));
+                ui.end_row();
+
+                let current = &self.kb_config.host_config.layout;
+                ui.label("Layout");
+                egui::ComboBox::from_id_source("switch_layout")
+                    .selected_text(current)
+                    .show_ui(ui, |ui| {
+                        for name in self.kb_config.layout_names() {
+                            if ui.selectable_label(name == current, name).clicked() {
+                                switch_layout = Some(name.to_string());
+                            }
+                        }
+                    });
+                ui.end_row();
+
+                let current = &self.kb_config.host_config.keymap;
+                ui.label("Keymap");
+                egui::ComboBox::from_id_source("switch_keymap")
+                    .selected_text(current)
+                    .show_ui(ui, |ui| {
+                        let host_config = &self.kb_config.host_config;
+                        for name in self.source.keymap_names(host_config) {
+                            if ui.selectable_label(name == *current, &name).clicked() {
+                                switch_keymap = Some(name);
+                            }
+                        }
+                    });
+                ui.end_row();
+
+                ui.label("Connected");
+                if self.curr_state.connected {
+                    ui.colored_label(Color32::GREEN, "yes");
+                } else {
+                    ui.colored_label(Color32::RED, "no");
+                }
+                ui.end_row();
+
+                ui.label("Last HID error");
+                match &self.curr_state.last_error {
+                    Some(err) => ui.colored_label(Color32::RED, err),
+                    None => ui.label("none"),
+                };
+                ui.end_row();
+
+                ui.label("Firmware");
+                ui.label(match self.curr_state.firmware_version {
+                    Some(version) => version.to_string(),
+                    None => "unknown".to_owned(),
+                });
+                ui.end_row();
+
+                ui.label("Battery");
+                ui.label(match self.curr_state.battery {
+                    Some((percent, true)) => format!("{}% (charging)", percent),
+                    Some((percent, false)) => format!("{}%", percent),
+                    None => "N/A".to_owned(),
+                });
+                ui.end_row();
+
+                ui.label("Write errors");
+                ui.label(format!("{}", self.curr_state.write_errors));
+                ui.end_row();
+
+                ui.label("Last key");
+                ui.label(match self.curr_state.last_keycode {
+                    Some(code) => keycode::describe(code),
+                    None => "none".to_owned(),
+                });
+                ui.end_row();
+
+                ui.label("Device LEDs");
+                ui.horizontal(|ui| {
+                    match &self.device_colors {
+                        Some(colors) => {
+                            let differ = Self::differing_leds(colors, &self.curr_state.led_state);
+                            ui.label(format!("{} of {} differ", differ, colors.len()))
+                        }
+                        None => ui.label("not read back"),
+                    };
 
-                    ui.label("HID FPS");
-                    ui.label(format!("{:.2}", 1.0 / self.curr_state.delta_frame));
+                    if ui.small_button("Read back").clicked() {
+                        self.tx.send(HIDCommand::QueryRgb).ok();
+                    }
+                });
+                ui.end_row();
+
+                let layer_state = self.curr_state.layer_state;
+                ui.label("Layer");
+                ui.label(format!(
+                    "{} ({:#b})",
+                    self.kb_config.legends.layer_name(layer_state),
+                    layer_state
+                ));
+                ui.end_row();
+
+                // the top one is what the keyboard window draws, the others only show through
+                // its transparent keys
+                let top = self.kb_config.legends.top_layer(layer_state);
+                ui.label("Active layers");
+                ui.horizontal(|ui| {
+                    for idx in config::active_layers(layer_state) {
+                        if idx == top {
+                            ui.strong(idx.to_string());
+                        } else {
+                            ui.label(idx.to_string());
+                        }
+                    }
+                });
+                ui.end_row();
+
+                let locks = self.curr_state.locks;
+                let on = [
+                    (locks.caps_lock, "Caps"),
+                    (locks.num_lock, "Num"),
+                    (locks.scroll_lock, "Scroll"),
+                ]
+                .iter()
+                .filter(|(on, _)| *on)
+                .map(|(_, name)| *name)
+                .collect::<Vec<_>>();
+
+                let mods = self.curr_state.mods.names();
+                ui.label("Mods");
+                ui.label(if mods.is_empty() {
+                    "none".to_owned()
+                } else {
+                    mods.join(" + ")
+                });
+                ui.end_row();
+
+                ui.label("Locks");
+                ui.label(if on.is_empty() {
+                    "none".to_owned()
+                } else {
+                    on.join(", ")
+                });
+                ui.end_row();
+
+                ui.label("Encoders");
+                ui.label(if self.curr_state.encoders.is_empty() {
+                    "none turned".to_owned()
+                } else {
+                    self.curr_state
+                        .encoders
+                        .iter()
+                        .enumerate()
+                        .map(|(idx, encoder)| {
+                            let arrow = if encoder.clockwise { "cw" } else { "ccw" };
+                            format!("{}: {} {}", idx, encoder.position, arrow)
+                        })
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                });
+                ui.end_row();
+
+                ui.label("LED count");
+                ui.label(format!("{}", self.kb_config.led_count()));
+                ui.end_row();
+
+                ui.label("HID delta update");
+                ui.label(format!("{:.2}", self.curr_state.delta_update * 1000.0));
+                ui.end_row();
+
+                ui.label("HID delta frame");
+                ui.label(format!("{:.2}", self.curr_state.delta_frame * 1000.0));
+                ui.end_row();
+
+                ui.label("HID FPS");
+                ui.label(format!("{:.2}", 1.0 / self.curr_state.delta_frame));
+                ui.end_row();
+
+                let timing = |summary: &TimingSummary| {
+                    format!(
+                        "{:.2} / {:.2} / {:.2} / {:.2}",
+                        summary.min * 1000.0,
+                        summary.mean * 1000.0,
+                        summary.p99 * 1000.0,
+                        summary.max * 1000.0
+                    )
+                };
+
+                ui.label("Update ms (min / mean / p99 / max)");
+                ui.label(timing(&self.curr_state.update_timing));
+                ui.end_row();
+
+                ui.label("Frame ms (min / mean / p99 / max)");
+                ui.label(timing(&self.curr_state.frame_timing));
+                ui.end_row();
+
+                for (name, summary) in &self.curr_state.effect_timing {
+                    ui.label(format!("{} update ms", name));
+                    ui.label(timing(summary));
                     ui.end_row();
+                }
+            });
+
+        self.timing.show(ui);
+
+        if let Some(layout) = switch_layout {
+            match self.kb_config.with_layout(&layout) {
+                Ok(kb_config) => self.switch_config(kb_config),
+                Err(err) => warn!("can't switch to layout {}: {}", layout, err),
+            }
+        }
+
+        if let Some(keymap) = switch_keymap {
+            match self.source.with_keymap(&keymap) {
+                Ok(kb_config) => {
+                    // stay on the layout picked here if the reread config still has it
+                    let layout = &self.kb_config.host_config.layout;
+                    let kb_config = kb_config.with_layout(layout).unwrap_or(kb_config);
+                    self.switch_config(kb_config);
+                }
+                Err(err) => warn!("can't switch to keymap {}: {}", keymap, err),
+            }
+        }
+
+        if ui.button("Resync LEDs").clicked() {
+            self.tx.send(HIDCommand::ForceRefresh).ok();
+        }
+    }
+
+    // the layer state whose legends are drawn, as if only the previewed layer was held
+    fn shown_layer_state(&self) -> u32 {
+        match self.preview_layer {
+            Some(0) => 0,
+            Some(layer) => 1 << layer,
+            None => self.curr_state.layer_state,
+        }
+    }
+
+    fn keyboard_window(&mut self, ui: &mut Ui, style: &mut RenderStyle, palettes: &Palettes) {
+        // a reload can leave fewer layers than the one picked
+        let names: Vec<String> = self
+            .kb_config
+            .legends
+            .layer_names()
+            .map(str::to_string)
+            .collect();
+        if self.preview_layer.is_some_and(|layer| layer >= names.len()) {
+            self.preview_layer = None;
+        }
+
+        self.connection_status(ui);
+
+        ui.horizontal(|ui| {
+            ui.label("Layer");
+            egui::ComboBox::from_id_source("preview_layer")
+                .selected_text(match self.preview_layer {
+                    Some(layer) => names[layer].as_str(),
+                    None => AUTO_LAYER,
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.preview_layer, None, AUTO_LAYER);
+                    for (idx, name) in names.iter().enumerate() {
+                        ui.selectable_value(&mut self.preview_layer, Some(idx), name);
+                    }
                 });
+
+            ui.separator();
+            ui.checkbox(&mut style.fit, "Fit to window");
+            ui.add_enabled(
+                !style.fit,
+                egui::Slider::new(&mut style.scale, SCALE_RANGE).text("Scale"),
+            );
+        });
+
+        let mut export = false;
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut style.heatmap, "Press heatmap");
+            if ui.button("Reset counts").clicked() {
+                self.tx.send(HIDCommand::ResetTyping).ok();
+            }
+
+            ui.separator();
+            ui.text_edit_singleline(&mut self.export_path);
+            ui.checkbox(&mut self.export_live, "Live leds");
+            export = ui.button("Export SVG").clicked();
+        });
+
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.key_test.enabled, "Key test")
+                .on_hover_text("keys turn green once pressed, the rest stay red");
+            if ui.button("Reset test").clicked() {
+                self.key_test.reset();
+            }
+
+            let layout = &self.kb_config.layout().layout;
+            let tested = layout
+                .iter()
+                .filter(|key| self.key_test.seen.contains(&key.matrix))
+                .count();
+            ui.label(format!("{} of {} keys pressed", tested, layout.len()));
         });
+
+        ui.horizontal(|ui| {
+            ui.add_enabled(
+                self.device_colors.is_some(),
+                egui::Checkbox::new(&mut self.show_device_colors, "Device colors"),
+            )
+            .on_hover_text("draw the leds as the keyboard last reported them, not as sent");
+            if ui.small_button("Read back").clicked() {
+                self.tx.send(HIDCommand::QueryRgb).ok();
+            }
+        });
+        match &self.export_result {
+            Some(Ok(())) => {
+                ui.colored_label(Color32::GREEN, format!("exported {}", self.export_path));
+            }
+            Some(Err(err)) => {
+                ui.colored_label(Color32::RED, format!("not exported: {}", err));
+            }
+            None => {}
+        }
+
+        // the board takes whatever room the window has left below the controls, keeping its
+        // aspect ratio. it can shrink past the slider's range so a small window still shows all
+        // of it
+        let scale = if style.fit {
+            let room = ui.available_size();
+            (room.x / self.kb_config.width())
+                .min(room.y / self.kb_config.height())
+                .clamp(1.0, *SCALE_RANGE.end())
+        } else {
+            style.scale
+        };
+
+        if export {
+            self.export_result = Some(self.export_svg(style, palettes, scale));
+        }
+
+        self.keyboard_render(ui, style, palettes, scale);
+        self.pin_window(ui);
     }
 
-    fn keyboard_render(&self, ui: &mut Ui, scale: f32) {
-        let layout = self.kb_config.layout();
+    fn connection_status(&self, ui: &mut Ui) {
+        let (color, text) = match &self.curr_state.status {
+            ConnectionStatus::Connected => (Color32::GREEN, "Connected".to_string()),
+            ConnectionStatus::Waiting => (
+                Color32::GRAY,
+                format!(
+                    "Waiting for {} to be plugged in…",
+                    self.kb_config.qmk_info.keyboard_name
+                ),
+            ),
+            ConnectionStatus::Reconnecting => (Color32::YELLOW, "Reconnecting…".to_string()),
+            ConnectionStatus::Error(err) => (Color32::RED, format!("Error: {}", err)),
+        };
 
-        let min = ui.next_widget_position();
-        let max = Pos2 {
-            x: min.x + self.kb_config.width() * scale,
-            y: min.y + self.kb_config.height() * scale,
+        ui.horizontal(|ui| {
+            let size = ui.spacing().interact_size.y * 0.5;
+            let (rect, _) = ui.allocate_exact_size(Vec2::splat(size), Sense::hover());
+            ui.painter().circle_filled(rect.center(), size / 2.0, color);
+            ui.label(text);
+        });
+    }
+
+    // every key as the keyboard window draws it, relative to the board's top left. without
+    // `live` it's only the layout, with no leds, presses or heatmap
+    fn key_shapes(
+        &self,
+        style: &RenderStyle,
+        palettes: &Palettes,
+        scale: f32,
+        live: bool,
+    ) -> Vec<KeyShape> {
+        let layout = self.kb_config.layout();
+        let layer_state = self.shown_layer_state();
+        let counts = &self.curr_state.typing.counts;
+        let max_count = counts.values().copied().max().unwrap_or(0).max(1);
+        // what's drawn is stale while the keyboard is gone
+        let shade = |color: Color32| {
+            if !live || self.curr_state.connected {
+                color
+            } else {
+                muted(color)
+            }
         };
 
-        let clip_rect = Rect { min, max };
-        let painter = Painter::new(ui.ctx().clone(), ui.layer_id(), clip_rect);
+        let mut shapes = Vec::with_capacity(layout.layout.len());
+        // layouts can start left of or above 0, the board's corner is drawn at 0, 0
+        let origin = Vec2::from(self.kb_config.origin()) * scale;
 
         for key in &layout.layout {
+            // validation keeps the legends from being empty, info.json's label does otherwise
+            let fallback = KeyDef {
+                usage: KeyUsage::Default,
+                label: Some(key.label.clone()),
+            };
             let key_def = self
                 .kb_config
                 .legends
-                .get_key(self.curr_state.layer_state, key.matrix.0, key.matrix.1)
-                .expect("could not find key definition");
+                .get_key(layer_state, key.matrix.0, key.matrix.1)
+                .unwrap_or(&fallback);
 
-            let (bg_norm, bg_pressed, fg) = get_key_colors(&key_def.usage);
-            let bg = if self.curr_state.matrix[key.matrix.0 as usize][key.matrix.1 as usize]
-                .is_pressed
-            {
-                bg_pressed
+            let theme = palettes.key_colors(style, &self.kb_config.host_config.key_colors);
+            let colors = theme.get(&key_def.usage);
+            let background = if live && self.key_test.enabled {
+                if self.key_test.seen.contains(&key.matrix) {
+                    TESTED_COLOR
+                } else {
+                    UNTESTED_COLOR
+                }
+            } else if live && style.heatmap {
+                let count = counts.get(&key.matrix).copied().unwrap_or(0);
+                heat_color(colors.background.0, count as f32 / max_count as f32)
             } else {
-                bg_norm
+                colors.background.0
             };
+            let (bg_norm, bg_pressed, fg) = (
+                shade(background),
+                shade(colors.pressed.0),
+                shade(colors.foreground.0),
+            );
+            // the thread may still be reporting the previous layout's matrix after a switch
+            let pressed = self
+                .curr_state
+                .matrix
+                .get(key.matrix.0 as usize)
+                .and_then(|row| row.get(key.matrix.1 as usize))
+                .is_some_and(|state| live && state.is_pressed);
+            let bg = if pressed { bg_pressed } else { bg_norm };
 
             // bounds
-            let key_border = 0.04 * scale;
+            let key_border = style.border * scale;
             let key_shrink = Vec2::new(key_border, key_border);
 
-            let key_min = Vec2::new(key.x * scale, key.y * scale) + key_shrink;
+            let key_min = Vec2::new(key.x * scale, key.y * scale) - origin + key_shrink;
             let key_max =
                 Vec2::new(key_min.x + key.w * scale, key_min.y + key.h * scale) - key_shrink;
 
@@ -147,68 +1752,522 @@ impl App {
 
             // led
             let led_index = self.kb_config.matrix[key.matrix.0 as usize][key.matrix.1 as usize];
-            let border_color = if led_index >= 0 {
-                let color = self.curr_state.led_state[led_index as usize];
-                Hsva::new(
+            let shown = match &self.device_colors {
+                Some(colors) if self.show_device_colors => colors,
+                _ => &self.curr_state.led_state,
+            };
+            let led_color = usize::try_from(led_index)
+                .ok()
+                .and_then(|idx| shown.get(idx))
+                .filter(|_| live);
+            let border_color = match led_color {
+                Some(color) => Hsva::new(
                     color.hue.to_degrees() / 360.0,
-                    color.saturation,
-                    color.value * color.alpha,
+                    color.saturation.clamp(0.0, 1.0),
+                    (color.value * color.alpha).clamp(0.0, 1.0),
                     1.0,
-                )
-            } else {
-                Hsva::new(0.0, 0.0, 0.0, 0.0)
+                ),
+                None => Hsva::new(0.0, 0.0, 0.0, 0.0),
             };
 
-            let translate = clip_rect.left_top().to_vec2();
+            let text_margin = style.text_margin * scale;
+            let letter = KeyColorTheme::usage_letter(&key_def.usage);
 
-            painter.add(RectShape {
-                rect: key_rect.translate(translate - key_shrink / 2.0),
-                rounding: Rounding::same(0.1 * scale),
+            shapes.push(KeyShape {
+                matrix: key.matrix,
+                rect: key_rect.translate(-key_shrink / 2.0),
+                rounding: style.rounding * scale,
                 fill: bg,
-                stroke: Stroke::new(key_border, border_color),
+                border_width: key_border,
+                border: shade(border_color.into()),
+                text_color: fg,
+                font_size: style.font_size(scale),
+                legend: key_def.label.clone(),
+                legend_pos: (key_min + Vec2::new(text_margin, text_margin)).to_pos2(),
+                legend_width: key_rect.width() - 2.0 * text_margin,
+                letter: letter.filter(|_| style.usage_letters),
+                text_margin,
+                rotation: key.r.to_radians(),
+                pivot: Pos2::new(key.rx * scale, key.ry * scale) - origin,
             });
+        }
+
+        shapes
+    }
+
+    fn keyboard_render(
+        &mut self,
+        ui: &mut Ui,
+        style: &RenderStyle,
+        palettes: &Palettes,
+        scale: f32,
+    ) {
+        // centered in the window's width, which doesn't shrink by itself when zooming out
+        let size = Vec2::new(self.kb_config.width(), self.kb_config.height()) * scale;
+        let margin = (ui.available_width() - size.x).max(0.0) / 2.0;
+        let min = ui.next_widget_position() + Vec2::new(margin, 0.0);
+
+        let clip_rect = Rect::from_min_size(min, size);
+        let painter = Painter::new(ui.ctx().clone(), ui.layer_id(), clip_rect);
+
+        let sense = if self.paint.enabled || self.paint.pin_on_click {
+            Sense::click_and_drag()
+        } else {
+            Sense::hover()
+        };
+        let response = ui.allocate_rect(clip_rect, sense);
+
+        let layer_state = self.shown_layer_state();
+        let translate = clip_rect.left_top().to_vec2();
+        let shapes = self.key_shapes(style, palettes, scale, true);
+        // rotated keys are hit and selected by the upright rect around them
+        let key_rects: Vec<_> = shapes
+            .iter()
+            .map(|shape| (shape.matrix, shape.bounds().translate(translate)))
+            .collect();
 
-            // legend
-            if let Some(legend) = key_def.label.as_ref() {
-                let text_margin = 0.1 * scale;
+        for shape in &shapes {
+            let rect = shape.rect.translate(translate);
+            let rounding = Rounding::same(shape.rounding);
+            let stroke = Stroke::new(shape.border_width, shape.border);
+            let selected = self.paint.selection.contains(&shape.matrix);
 
+            // egui can't turn a rounded rect, rotated keys get square corners
+            if shape.rotation == 0.0 {
+                painter.add(RectShape {
+                    rect,
+                    rounding,
+                    fill: shape.fill,
+                    stroke,
+                });
+
+                if selected {
+                    painter.rect_stroke(
+                        rect.expand(shape.border_width),
+                        rounding,
+                        Stroke::new(2.0, SELECTION_COLOR),
+                    );
+                }
+            } else {
+                let corners: Vec<_> = shape
+                    .corners()
+                    .iter()
+                    .map(|corner| *corner + translate)
+                    .collect();
+                painter.add(Shape::convex_polygon(corners.clone(), shape.fill, stroke));
+
+                if selected {
+                    painter.add(Shape::closed_line(
+                        corners,
+                        Stroke::new(2.0, SELECTION_COLOR),
+                    ));
+                }
+            }
+
+            if let Some(legend) = &shape.legend {
                 let job = LayoutJob::simple(
-                    legend.to_string(),
-                    FontId::new(14.0, FontFamily::Proportional),
-                    fg,
-                    key_rect.width() - 2.0 * text_margin,
+                    legend.clone(),
+                    FontId::new(shape.font_size, FontFamily::Proportional),
+                    shape.text_color,
+                    shape.legend_width,
                 );
 
                 let galley = ui.fonts().layout_job(job);
 
                 painter.add(TextShape {
-                    pos: (key_min + Vec2::new(text_margin, text_margin) + translate).to_pos2(),
+                    pos: shape.turn(shape.legend_pos) + translate,
+                    galley,
+                    underline: Stroke::none(),
+                    override_text_color: None,
+                    angle: shape.rotation,
+                });
+            }
+
+            if let Some(letter) = shape.letter {
+                let galley = ui.fonts().layout_no_wrap(
+                    letter.to_string(),
+                    FontId::new(shape.font_size * 0.7, FontFamily::Monospace),
+                    shape.text_color,
+                );
+                // its bottom right in the key's, laid out upright
+                let pos =
+                    shape.rect.right_bottom() - Vec2::splat(shape.text_margin) - galley.size();
+
+                painter.add(TextShape {
+                    pos: shape.turn(pos) + translate,
                     galley,
                     underline: Stroke::none(),
                     override_text_color: None,
-                    angle: 0.0,
+                    angle: shape.rotation,
                 });
             }
         }
 
-        ui.expand_to_include_rect(painter.clip_rect());
+        if self.paint.pin_on_click {
+            if let Some(pos) = response
+                .interact_pointer_pos()
+                .filter(|_| response.clicked())
+            {
+                let hit = key_rects.iter().find(|(_, rect)| rect.contains(pos));
+                self.paint.pinning = hit.map(|(matrix, _)| (*matrix, pos));
+            }
+        } else if self.paint.enabled {
+            self.handle_selection(ui, &response, &key_rects);
+        }
+
+        let hovered = response.hover_pos().and_then(|pos| {
+            key_rects
+                .iter()
+                .find(|(_, rect)| rect.contains(pos))
+                .map(|(matrix, _)| *matrix)
+        });
+        if let Some(matrix) = hovered {
+            response.on_hover_ui_at_pointer(|ui| self.key_tooltip(ui, matrix, layer_state));
+        }
+    }
+
+    fn export_svg(
+        &self,
+        style: &RenderStyle,
+        palettes: &Palettes,
+        scale: f32,
+    ) -> Result<(), String> {
+        let shapes = self.key_shapes(style, palettes, scale, self.export_live);
+        let size = Vec2::new(self.kb_config.width(), self.kb_config.height()) * scale;
+
+        let result = fs::write(&self.export_path, export::to_svg(&shapes, size));
+        match &result {
+            Ok(()) => info!("exported the layout to {}", self.export_path),
+            Err(err) => warn!("could not export to {}: {}", self.export_path, err),
+        }
+        result.map_err(|err| err.to_string())
+    }
+
+    // the picker opened by clicking a key with pin on click, every change is sent right away
+    fn pin_window(&mut self, ui: &Ui) {
+        let Some((matrix, pos)) = self.paint.pinning else {
+            return;
+        };
+
+        let mut color = match self.paint.key_colors.get(&matrix) {
+            Some(color) => from_led_color(*color),
+            None => self.paint.color,
+        };
+        let (mut changed, mut unpin, mut close) = (false, false, false);
+
+        egui::Window::new(format!("Pin {}", self.key_name(matrix)))
+            .id(ui.id().with("pin_key"))
+            .collapsible(false)
+            .resizable(false)
+            .current_pos(pos)
+            .show(ui.ctx(), |ui| {
+                changed = egui::color_picker::color_picker_hsva_2d(
+                    ui,
+                    &mut color,
+                    egui::color_picker::Alpha::Opaque,
+                );
+
+                ui.horizontal(|ui| {
+                    unpin = ui.button("Unpin").clicked();
+                    close = ui.button("Done").clicked();
+                });
+            });
+
+        if changed {
+            let color = to_led_color(color);
+            self.paint.key_colors.insert(matrix, color);
+            self.tx
+                .send(HIDCommand::SetKeyColors(vec![(matrix, color)]))
+                .ok();
+        }
+        if unpin {
+            self.paint.key_colors.remove(&matrix);
+            self.tx.send(HIDCommand::ClearKeyColors(vec![matrix])).ok();
+        }
+        if unpin || close {
+            self.paint.pinning = None;
+        }
+    }
+
+    // everything known about a key, for checking the layout, legends and matrix files
+    fn key_tooltip(&self, ui: &mut Ui, (row, col): (u8, u8), layer_state: u32) {
+        let key_def = self.kb_config.legends.get_key(layer_state, row, col);
+        let led_index = self.kb_config.matrix[row as usize][col as usize];
+        let keycode = self
+            .curr_state
+            .matrix
+            .get(row as usize)
+            .and_then(|cols| cols.get(col as usize))
+            .and_then(|state| state.keycode);
+
+        egui::Grid::new("key_tooltip")
+            .num_columns(2)
+            .show(ui, |ui| {
+                ui.label("Matrix");
+                ui.label(format!("({}, {})", row, col));
+                ui.end_row();
+
+                ui.label("Legend");
+                ui.label(
+                    key_def
+                        .and_then(|def| def.label.as_deref())
+                        .unwrap_or("none"),
+                );
+                ui.end_row();
+
+                ui.label("Usage");
+                ui.label(key_def.map_or("unknown".to_string(), |def| format!("{:?}", def.usage)));
+                ui.end_row();
+
+                // the firmware only says once the key is pressed
+                ui.label("Keycode");
+                ui.label(keycode.map_or("not pressed yet".to_string(), keycode::describe));
+                ui.end_row();
+
+                ui.label("LED");
+                ui.label(if led_index >= 0 {
+                    led_index.to_string()
+                } else {
+                    "none".to_string()
+                });
+                ui.end_row();
+            });
     }
 }
 
-impl eframe::App for App {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        if let Some(last) = self.rx.try_iter().last() {
-            self.curr_state = last;
+impl App {
+    pub fn new(boards: Vec<Board>, fps: f32, storage: Option<&dyn eframe::Storage>) -> App {
+        let ui_state = storage
+            .and_then(|storage| eframe::get_value(storage, eframe::APP_KEY))
+            .unwrap_or_default();
+        App {
+            boards,
+            fps,
+            palettes: Palettes::new(&ui_state),
+            ui_state,
+            visuals: None,
+            reset_layout: false,
+        }
+    }
+
+    // with several keyboards their windows are numbered, one keyboard keeps the plain titles
+    fn windows(&self) -> Vec<WindowSpec> {
+        let numbered = self.boards.len() > 1;
+        let mut windows = Vec::new();
+
+        for board in 0..self.boards.len() {
+            for (kind, [x, y]) in WINDOWS {
+                let shared = kind.shared();
+                if shared && board > 0 {
+                    continue;
+                }
+
+                let offset = board as f32;
+                windows.push(WindowSpec {
+                    title: if numbered && !shared {
+                        format!("{} {}", kind.title(), board + 1)
+                    } else {
+                        kind.title().to_string()
+                    },
+                    kind: *kind,
+                    board,
+                    default_pos: [x + BOARD_OFFSET[0] * offset, y + BOARD_OFFSET[1] * offset],
+                });
+            }
         }
 
-        self.info_window(ctx);
+        windows
+    }
+
+    fn show_window(
+        &mut self,
+        ctx: &egui::Context,
+        title: &str,
+        default_pos: [f32; 2],
+        add_contents: impl FnOnce(&mut App, &mut Ui),
+    ) {
+        let state = *self.ui_state.windows.entry(title.to_string()).or_default();
+        let mut open = state.open;
+
+        let window = egui::Window::new(title).open(&mut open);
+        let window = if self.reset_layout {
+            window.current_pos(default_pos)
+        } else {
+            window.default_pos(state.pos.unwrap_or(default_pos))
+        };
 
-        egui::Window::new("Keyboard").show(ctx, |ui| {
-            self.keyboard_render(ui, 45.0);
+        let response = window.show(ctx, |ui| add_contents(self, ui));
+        let pos = response.map(|response| response.response.rect.left_top());
+
+        let state = self.ui_state.windows.entry(title.to_string()).or_default();
+        state.open = open;
+        if let Some(pos) = pos {
+            state.pos = Some([pos.x, pos.y]);
+        }
+    }
+
+    fn menu_bar(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        egui::TopBottomPanel::top("menu_bar").show(ctx, |ui| {
+            egui::menu::bar(ui, |ui| {
+                ui.menu_button("File", |ui| {
+                    if ui.button("Quit").clicked() {
+                        frame.close();
+                    }
+                });
+
+                ui.menu_button("View", |ui| {
+                    for window in self.windows() {
+                        let state = self
+                            .ui_state
+                            .windows
+                            .entry(window.title.clone())
+                            .or_default();
+                        ui.checkbox(&mut state.open, window.title);
+                    }
+
+                    ui.separator();
+
+                    if ui.button("Reset layout").clicked() {
+                        self.ui_state.windows.clear();
+                        self.reset_layout = true;
+                        ui.close_menu();
+                    }
+                });
+
+                ui.menu_button("Help", |ui| {
+                    ui.label(format!("kb_host {}", env!("CARGO_PKG_VERSION")));
+                    ui.hyperlink_to("Source", "https://github.com/voidedWarranties/kb_host");
+                });
+            });
         });
+    }
+
+    fn appearance_window(ui_state: &mut UiState, palettes: &mut Palettes, ui: &mut Ui) {
+        let style = &mut ui_state.style;
+
+        egui::Grid::new("appearance_grid")
+            .num_columns(2)
+            .show(ui, |ui| {
+                ui.label("Rounding");
+                ui.add(egui::Slider::new(&mut style.rounding, 0.0..=0.5));
+                ui.end_row();
+
+                ui.label("Border");
+                ui.add(egui::Slider::new(&mut style.border, 0.0..=0.2));
+                ui.end_row();
+
+                ui.label("Text margin");
+                ui.add(egui::Slider::new(&mut style.text_margin, 0.0..=0.4));
+                ui.end_row();
+
+                let mut fixed_font = style.font_size.is_some();
+                ui.checkbox(&mut fixed_font, "Fixed font size");
+                match (fixed_font, style.font_size.as_mut()) {
+                    (true, Some(size)) => {
+                        ui.add(egui::Slider::new(size, 6.0..=32.0));
+                    }
+                    (true, None) => style.font_size = Some(14.0),
+                    (false, _) => {
+                        style.font_size = None;
+                        ui.add(egui::Slider::new(&mut style.font_scale, 0.1..=0.6).text("per key"));
+                    }
+                }
+                ui.end_row();
+
+                ui.label("Theme");
+                ui.horizontal(|ui| {
+                    ui.radio_value(&mut style.theme, UiTheme::Dark, "Dark");
+                    ui.radio_value(&mut style.theme, UiTheme::Light, "Light");
+                });
+                ui.end_row();
+
+                ui.label("Palette");
+                ui.horizontal(|ui| {
+                    ui.radio_value(&mut style.palette, PaletteMode::Config, "Config");
+                    ui.radio_value(&mut style.palette, PaletteMode::Theme, "Theme");
+                    ui.radio_value(&mut style.palette, PaletteMode::ColorBlind, "Color blind");
+                    ui.radio_value(&mut style.palette, PaletteMode::File, "File");
+                });
+                ui.end_row();
+
+                ui.label("Palette file");
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut ui_state.theme_file);
+                    if ui.button("Load").clicked() {
+                        palettes.load_file(&ui_state.theme_file);
+                        style.palette = PaletteMode::File;
+                    }
+                });
+                ui.end_row();
+
+                ui.label("Usage letters");
+                ui.checkbox(&mut style.usage_letters, "");
+                ui.end_row();
+            });
+
+        if let Some(Err(err)) = &palettes.file {
+            ui.colored_label(Color32::RED, format!("palette file not loaded: {}", err));
+        }
+
+        if ui.button("Reset").clicked() {
+            *style = RenderStyle::default();
+        }
+    }
+}
+
+impl eframe::App for App {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        for board in &mut self.boards {
+            board.poll();
+        }
+
+        let theme = self.ui_state.style.theme;
+        if self.visuals != Some(theme) {
+            ctx.set_visuals(theme.visuals());
+            self.visuals = Some(theme);
+        }
+
+        self.menu_bar(ctx, frame);
+
+        for window in self.windows() {
+            self.show_window(ctx, &window.title, window.default_pos, |app, ui| {
+                let App {
+                    boards,
+                    ui_state,
+                    palettes,
+                    ..
+                } = app;
+                let board = &mut boards[window.board];
+
+                match window.kind {
+                    WindowKind::Information => board.info_window(ui),
+                    WindowKind::Keyboard => {
+                        board.keyboard_window(ui, &mut ui_state.style, palettes)
+                    }
+                    WindowKind::Paint => board.paint_window(ui),
+                    WindowKind::Appearance => App::appearance_window(ui_state, palettes, ui),
+                    WindowKind::Console => board.console_window(ui),
+                    WindowKind::Effects => board.effects_window(ui),
+                    WindowKind::Statistics => board.statistics_window(ui),
+                    WindowKind::Settings => board.settings_window(ui),
+                    WindowKind::Matrix => board.matrix_window(ui),
+                }
+            });
+        }
+        self.reset_layout = false;
 
         egui::CentralPanel::default().show(ctx, |_ui| {});
 
-        ctx.request_repaint();
+        // the thread only has new colors once a frame, input repaints on its own in between
+        ctx.request_repaint_after(Duration::from_secs_f32(1.0 / self.fps));
+    }
+
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, eframe::APP_KEY, &self.ui_state);
+    }
+
+    // window positions are kept in UiState, where Reset layout can clear them
+    fn persist_egui_memory(&self) -> bool {
+        false
     }
 }
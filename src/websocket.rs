@@ -0,0 +1,232 @@
+// a websocket server for external visualizers, broadcasting every `LiveFrame` as a json text
+// message to every client. clients only listen, anything they send is ignored, and a client
+// that can't keep up is dropped
+use crate::threading::LiveFrame;
+use crossbeam::channel::Receiver;
+use log::{debug, info, warn};
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+// appended to the client's key before hashing, see rfc 6455
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const WRITE_TIMEOUT: Duration = Duration::from_millis(200);
+// for the handshake, a client that connects and says nothing is turned away after this
+const READ_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for chunk in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (idx, word) in chunk.chunks(4).enumerate() {
+            w[idx] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for idx in 16..80 {
+            w[idx] = (w[idx - 3] ^ w[idx - 8] ^ w[idx - 14] ^ w[idx - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (idx, word) in w.iter().enumerate() {
+            let (f, k) = match idx {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (state, value) in h.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (bytes, word) in digest.chunks_mut(4).zip(h) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for idx in 0..4 {
+            if idx <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - idx * 6)) as usize & 0x3F] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key.trim(), ACCEPT_GUID).as_bytes()))
+}
+
+// an unmasked text message, which is how servers send them
+fn text_frame(text: &str) -> Vec<u8> {
+    let len = text.len();
+    let mut frame = vec![0x81];
+    match len {
+        0..=125 => frame.push(len as u8),
+        126..=0xFFFF => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        _ => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(text.as_bytes());
+    frame
+}
+
+// reads the upgrade request and answers it, only the key header matters
+fn handshake(stream: &TcpStream) -> Result<(), String> {
+    let mut key = None;
+    for line in BufReader::new(stream).lines() {
+        let line = line.map_err(|err| err.to_string())?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("sec-websocket-key") {
+                key = Some(value.trim().to_string());
+            }
+        }
+    }
+
+    let mut writer = stream;
+    let Some(key) = key else {
+        writer
+            .write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n")
+            .ok();
+        return Err("not a websocket request".to_string());
+    };
+
+    write!(
+        writer,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(&key)
+    )
+    .map_err(|err| err.to_string())
+}
+
+pub fn spawn(addr: &str, frames: Receiver<LiveFrame>) {
+    let listener = match TcpListener::bind(addr) {
+        Ok(listener) => listener,
+        Err(err) => {
+            warn!("could not open websocket server on {}: {}", addr, err);
+            return;
+        }
+    };
+    info!("websocket server listening on {}", addr);
+
+    let clients: Arc<Mutex<Vec<TcpStream>>> = Arc::default();
+
+    // every handshake on its own thread, so a slow client doesn't hold up the next ones
+    let accepted = clients.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let accepted = accepted.clone();
+            thread::spawn(move || {
+                let peer = stream.peer_addr().ok();
+                stream.set_read_timeout(Some(READ_TIMEOUT)).ok();
+                match handshake(&stream) {
+                    Ok(()) => {
+                        debug!("websocket client connected from {:?}", peer);
+                        stream.set_write_timeout(Some(WRITE_TIMEOUT)).ok();
+                        accepted.lock().unwrap().push(stream);
+                    }
+                    Err(err) => debug!("websocket client {:?} turned away: {}", peer, err),
+                }
+            });
+        }
+    });
+
+    // only the newest frame is sent if serializing falls behind, and this stops with the hid thread
+    thread::spawn(move || {
+        while let Ok(frame) = frames.recv() {
+            let frame = frames.try_iter().last().unwrap_or(frame);
+
+            let mut clients = clients.lock().unwrap();
+            if clients.is_empty() {
+                continue;
+            }
+
+            let message = match serde_json::to_string(&frame) {
+                Ok(json) => text_frame(&json),
+                Err(err) => {
+                    warn!("could not serialize the live frame: {}", err);
+                    continue;
+                }
+            };
+            clients.retain_mut(|client| match client.write_all(&message) {
+                Ok(()) => true,
+                Err(err) => {
+                    debug!("websocket client dropped: {}", err);
+                    false
+                }
+            });
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_the_rfc_example() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        assert_eq!(base64(b"ab"), "YWI=");
+        assert_eq!(base64(b"a"), "YQ==");
+    }
+
+    #[test]
+    fn frames_text() {
+        assert_eq!(text_frame("hi"), [0x81, 2, b'h', b'i']);
+
+        let long = "x".repeat(300);
+        assert_eq!(text_frame(&long)[..4], [0x81, 126, 1, 44]);
+    }
+}
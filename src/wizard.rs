@@ -0,0 +1,287 @@
+// `kb_host` without a config file: asks on the terminal which keyboard is plugged in, then writes
+// a config for it that reads like any other
+use crate::{
+    config::{Config, QMKInfo, StartupError, ValidationError},
+    paths::Overrides,
+};
+use hidapi::HidApi;
+use serde_json::json;
+use std::{
+    fs,
+    io::{self, BufRead, Write},
+    path::Path,
+};
+
+// qmk's raw hid interface, unless the firmware changed RAW_USAGE_PAGE and RAW_USAGE
+const RAW_USAGE_PAGE: u16 = 0xFF60;
+
+#[derive(Clone, Debug, PartialEq)]
+struct Interface {
+    vid: u16,
+    pid: u16,
+    usage_page: u16,
+    usage: u16,
+    product: String,
+}
+
+impl Interface {
+    fn label(&self) -> String {
+        format!(
+            "{} ({:04x}:{:04x}, usage page {:#06x} usage {:#04x})",
+            self.product, self.vid, self.pid, self.usage_page, self.usage
+        )
+    }
+}
+
+// interfaces with a vendor defined usage page, where raw hid lives, qmk's own first
+fn interfaces(api: &HidApi) -> Vec<Interface> {
+    let mut interfaces: Vec<Interface> = Vec::new();
+    for device in api
+        .device_list()
+        .filter(|device| device.usage_page() >= 0xFF00)
+    {
+        let interface = Interface {
+            vid: device.vendor_id(),
+            pid: device.product_id(),
+            usage_page: device.usage_page(),
+            usage: device.usage(),
+            product: device
+                .product_string()
+                .unwrap_or("unnamed device")
+                .to_string(),
+        };
+        if !interfaces.contains(&interface) {
+            interfaces.push(interface);
+        }
+    }
+
+    interfaces.sort_by_key(|interface| (interface.usage_page != RAW_USAGE_PAGE, interface.vid));
+    interfaces
+}
+
+// a config with only the keyboard filled in, enough to find its files
+fn draft(kb: &str) -> Config {
+    serde_json::from_value(json!({
+        "kb": kb,
+        "keymap": "default",
+        "layout": "",
+        "usage_page": "0",
+        "usage": "0",
+    }))
+    .expect("a draft config always parses")
+}
+
+// the config file's contents, in toml if that's what the file is called
+fn config_contents(
+    path: &Path,
+    kb: &str,
+    keymap: &str,
+    layout: &str,
+    interface: &Interface,
+) -> String {
+    let config = json!({
+        "kb": kb,
+        "keymap": keymap,
+        "layout": layout,
+        "usage_page": format!("{:#06X}", interface.usage_page),
+        "usage": format!("{:#04X}", interface.usage),
+    });
+
+    if path.extension().is_some_and(|ext| ext == "toml") {
+        toml::to_string(&config).expect("the config is a table of strings")
+    } else {
+        serde_json::to_string_pretty(&config).expect("the config is plain json") + "\n"
+    }
+}
+
+struct Prompt<R, W> {
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> Prompt<R, W> {
+    fn line(&mut self, question: &str) -> io::Result<String> {
+        write!(self.output, "{}: ", question)?;
+        self.output.flush()?;
+
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "no answer, nothing was written",
+            ));
+        }
+        Ok(line.trim().to_string())
+    }
+
+    // asks again until the answer is one of the numbers listed. one option is taken as is
+    fn choose(&mut self, question: &str, options: &[String]) -> io::Result<usize> {
+        if let [option] = options {
+            writeln!(self.output, "{}: {}", question, option)?;
+            return Ok(0);
+        }
+
+        writeln!(self.output, "{}", question)?;
+        for (index, option) in options.iter().enumerate() {
+            writeln!(self.output, "  {}) {}", index + 1, option)?;
+        }
+
+        loop {
+            let answer = self.line(&format!("1-{}", options.len()))?;
+            match answer.parse::<usize>() {
+                Ok(number) if (1..=options.len()).contains(&number) => return Ok(number - 1),
+                _ => writeln!(self.output, "{:?} isn't one of them", answer)?,
+            }
+        }
+    }
+}
+
+pub fn run(overrides: &Overrides) -> Result<(), StartupError> {
+    let path = overrides.config_path();
+    let mut prompt = Prompt {
+        input: io::stdin().lock(),
+        output: io::stdout(),
+    };
+    writeln!(
+        prompt.output,
+        "no config at {}, pick your keyboard to write one (ctrl-c to stop)\n",
+        path.display()
+    )?;
+
+    let interfaces = {
+        let api = HidApi::new()
+            .map_err(|err| StartupError::Hid(format!("could not list devices: {}", err)))?;
+        interfaces(&api)
+    };
+    if interfaces.is_empty() {
+        return Err(StartupError::Hid(
+            "no raw hid interface found, is the keyboard plugged in and built with RAW_ENABLE?"
+                .to_string(),
+        ));
+    }
+    let labels: Vec<String> = interfaces.iter().map(Interface::label).collect();
+    let interface = &interfaces[prompt.choose("which of these is the keyboard?", &labels)?];
+
+    let ids_match = |info: &QMKInfo| info.usb.vid == interface.vid && info.usb.pid == interface.pid;
+    let keyboards: Vec<(String, QMKInfo)> = overrides
+        .keyboard_names()
+        .into_iter()
+        .filter_map(|kb| Some((kb.clone(), crate::read_info(overrides, &draft(&kb)).ok()?)))
+        .filter(|(_, info)| ids_match(info) && !info.layouts.is_empty())
+        .collect();
+
+    let (kb, info) = if keyboards.is_empty() {
+        writeln!(
+            prompt.output,
+            "no qmk keyboard has usb ids {:04x}:{:04x}",
+            interface.vid, interface.pid
+        )?;
+        loop {
+            let kb = prompt.line("its folder under qmk_firmware/keyboards, e.g. foo/rev2")?;
+            match crate::read_info(overrides, &draft(&kb)) {
+                Ok(info) if info.layouts.is_empty() => writeln!(
+                    prompt.output,
+                    "{} has no layouts, is it the folder above the revisions?",
+                    kb
+                )?,
+                Ok(info) if ids_match(&info) => break (kb, info),
+                Ok(info) => writeln!(
+                    prompt.output,
+                    "{} has usb ids {:04x}:{:04x}, not the device's",
+                    kb, info.usb.vid, info.usb.pid
+                )?,
+                Err(err) => writeln!(prompt.output, "{}", err)?,
+            }
+        }
+    } else {
+        let labels: Vec<String> = keyboards
+            .iter()
+            .map(|(kb, info)| format!("{} ({} by {})", kb, info.keyboard_name, info.manufacturer))
+            .collect();
+        let index = prompt.choose("which keyboard is it?", &labels)?;
+        keyboards[index].clone()
+    };
+
+    let mut layouts: Vec<String> = info.layouts.keys().cloned().collect();
+    layouts.sort_unstable();
+    let layout = &layouts[prompt.choose("which layout?", &layouts)?];
+
+    let keymaps = overrides.keymap_names(&draft(&kb));
+    if keymaps.is_empty() {
+        return Err(StartupError::Invalid(ValidationError {
+            problems: vec![format!(
+                "{} has no keymap with a legends.json, keymap.json or via.json, `qmk c2json` can write one",
+                kb
+            )],
+        }));
+    }
+    let keymap = &keymaps[prompt.choose("which keymap?", &keymaps)?];
+
+    // checked the same way as at startup before anything is written
+    let contents = config_contents(&path, &kb, keymap, layout, interface);
+    let config = Config::parse_all(&path, &contents)
+        .map_err(|err| StartupError::parse(&path, err))?
+        .remove(0);
+    crate::read_keyboard(overrides, config)?;
+
+    let write = || {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, &contents)
+    };
+    write().map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("could not write {}: {}", path.display(), err),
+        )
+    })?;
+
+    writeln!(prompt.output, "\nwrote {}\n", path.display())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn asks_until_answered() {
+        let mut prompt = Prompt {
+            input: io::Cursor::new("3\nfoo\n2\n"),
+            output: Vec::new(),
+        };
+        let options = ["a".to_string(), "b".to_string()];
+
+        assert_eq!(prompt.choose("which?", &options).unwrap(), 1);
+        let output = String::from_utf8(prompt.output).unwrap();
+        assert!(output.contains("  2) b"));
+        assert!(output.contains("\"3\" isn't one of them"));
+        assert!(output.contains("\"foo\" isn't one of them"));
+
+        let mut prompt = Prompt {
+            input: io::Cursor::new(""),
+            output: Vec::new(),
+        };
+        assert!(prompt.line("name").is_err());
+    }
+
+    #[test]
+    fn writes_readable_configs() {
+        let interface = Interface {
+            vid: 0x1234,
+            pid: 0x5678,
+            usage_page: 0xFF60,
+            usage: 0x61,
+            product: "test".to_string(),
+        };
+
+        for path in [Path::new("config.json"), Path::new("config.toml")] {
+            let contents = config_contents(path, "foo/rev2", "default", "LAYOUT", &interface);
+            let config = &Config::parse_all(path, &contents).unwrap()[0];
+            assert_eq!(config.kb, "foo/rev2");
+            assert_eq!(config.layout, "LAYOUT");
+            assert_eq!((config.usage_page, config.usage), (0xFF60, 0x61));
+        }
+    }
+}